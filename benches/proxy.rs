@@ -0,0 +1,119 @@
+use std::net::SocketAddr;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+};
+
+// Same data path as the proxy: accept, connect to the backend, then `copy_bidirectional` until
+// either side closes. End-to-end numbers against a running helper come from `proxy-load`.
+async fn spawn_relay(backend: SocketAddr) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut ingress, _) = listener.accept().await.unwrap();
+
+            tokio::spawn(async move {
+                let mut egress = TcpStream::connect(backend).await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut ingress, &mut egress).await;
+            });
+        }
+    });
+
+    address
+}
+
+async fn spawn_echo_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    address
+}
+
+async fn round_trip(relay: SocketAddr, payload: &[u8]) {
+    let mut stream = TcpStream::connect(relay).await.unwrap();
+    let mut received = vec![0; payload.len()];
+
+    let (mut reader, mut writer) = stream.split();
+
+    tokio::try_join!(writer.write_all(payload), reader.read_exact(&mut received)).unwrap();
+}
+
+fn throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let relay = runtime.block_on(async { spawn_relay(spawn_echo_backend().await).await });
+
+    let mut group = c.benchmark_group("relay_throughput");
+
+    for size in [4 << 10, 64 << 10, 1 << 20, 16 << 20] {
+        let payload = vec![0x42; size];
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+            b.to_async(&runtime).iter(|| round_trip(relay, payload))
+        });
+    }
+
+    group.finish();
+}
+
+fn connection_setup(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let relay = runtime.block_on(async { spawn_relay(spawn_echo_backend().await).await });
+
+    c.bench_function("relay_connection_setup", |b| {
+        b.to_async(&runtime).iter(|| round_trip(relay, b"ping"))
+    });
+}
+
+fn concurrent_connections(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let relay = runtime.block_on(async { spawn_relay(spawn_echo_backend().await).await });
+
+    let mut group = c.benchmark_group("relay_concurrent_connections");
+    group.sample_size(10);
+
+    for connections in [100, 1000, 4000] {
+        group.throughput(Throughput::Elements(connections));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(connections),
+            &connections,
+            |b, &connections| {
+                b.to_async(&runtime).iter(|| async move {
+                    let handles = (0..connections)
+                        .map(|_| tokio::spawn(round_trip(relay, &[0x42; 1024])))
+                        .collect::<Vec<_>>();
+
+                    for handle in handles {
+                        handle.await.unwrap();
+                    }
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    throughput,
+    connection_setup,
+    concurrent_connections
+);
+criterion_main!(benches);
@@ -0,0 +1,131 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+
+use crate::{
+    client_certificates::{self, ClientCertificate},
+    discovery, events, logging, CONFIG,
+};
+
+// Only subnets configured explicitly are trusted, the ones Proxmox reports for the discovered
+// members are not. Without any, only the members' own addresses are.
+fn parse_subnets(subnets: &[String]) -> Vec<IpNet> {
+    subnets
+        .iter()
+        .filter_map(|subnet| match subnet.parse() {
+            Ok(subnet) => Some(subnet),
+            Err(err) => {
+                logging::warn!("Ignoring invalid cluster subnet {subnet}: {err}");
+                None
+            }
+        })
+        .collect()
+}
+
+fn in_subnets(ip: IpAddr, subnets: &[IpNet]) -> bool {
+    let ip = ip.to_canonical();
+
+    subnets.iter().any(|subnet| subnet.contains(&ip))
+}
+
+pub(crate) fn warn_unconfigured() {
+    if CONFIG.cluster_subnets.is_empty() {
+        logging::warn!(
+            "cluster_subnets is not set, only the discovered members' own addresses and node certificates are trusted as cluster callers"
+        );
+    }
+}
+
+fn is_cluster_member(ip: IpAddr) -> bool {
+    discovery::subscribe()
+        .borrow()
+        .iter()
+        .any(|ipam| ipam.assignment.is_some() && ipam.ip == ip.to_string())
+}
+
+// A node certificate issued by the helper CA vouches for the caller wherever it connects from.
+async fn has_node_certificate(certificate: Option<ClientCertificate>) -> bool {
+    let Some(certificate) = certificate else {
+        return false;
+    };
+
+    match client_certificates::certificate_vmid(&certificate).await {
+        Ok(_) => true,
+        Err(err) => {
+            logging::warn!("Ignoring client certificate: {err}");
+            false
+        }
+    }
+}
+
+pub(crate) async fn require_cluster_caller(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = addr.ip().to_canonical();
+
+    if is_cluster_member(ip)
+        || in_subnets(ip, &parse_subnets(&CONFIG.cluster_subnets))
+        || has_node_certificate(request.extensions().get().cloned()).await
+    {
+        return next.run(request).await;
+    }
+
+    events::record(
+        "audit",
+        None,
+        format!(
+            "Rejected {} {} from {ip}, outside of the cluster subnets and without a node certificate",
+            request.method(),
+            request.uri().path()
+        ),
+        None,
+    );
+
+    (
+        StatusCode::FORBIDDEN,
+        "Only cluster members are allowed to call this endpoint",
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subnets() -> Vec<IpNet> {
+        parse_subnets(&["10.10.0.0/16".to_string(), "fd00::/64".to_string()])
+    }
+
+    #[test]
+    fn accepts_addresses_inside_the_configured_subnets() {
+        assert!(in_subnets("10.10.4.2".parse().unwrap(), &subnets()));
+        assert!(in_subnets("fd00::42".parse().unwrap(), &subnets()));
+    }
+
+    #[test]
+    fn rejects_addresses_outside_of_them() {
+        assert!(!in_subnets("10.11.0.1".parse().unwrap(), &subnets()));
+        assert!(!in_subnets("fd00:0:0:1::1".parse().unwrap(), &subnets()));
+    }
+
+    #[test]
+    fn matches_ipv4_mapped_addresses() {
+        assert!(in_subnets("::ffff:10.10.0.1".parse().unwrap(), &subnets()));
+    }
+
+    #[test]
+    fn trusts_nothing_without_subnets() {
+        assert!(!in_subnets(
+            "10.10.0.1".parse().unwrap(),
+            &parse_subnets(&[])
+        ));
+    }
+}
@@ -0,0 +1,50 @@
+use serde::Serialize;
+
+use crate::{events, logging, secrets, CONFIG};
+
+#[derive(Debug, Serialize)]
+struct AlertPayload<'a> {
+    alert: &'a str,
+    status: &'a str,
+    message: &'a str,
+    timestamp: i64,
+}
+
+async fn send(alert: &str, status: &str, message: &str) {
+    events::record(
+        "alert",
+        None,
+        format!("[{alert}] {status}: {message}"),
+        None,
+    );
+
+    let Some(webhook_url) = secrets::secret("alert_webhook_url", &CONFIG.alert_webhook_url) else {
+        return;
+    };
+
+    let payload = AlertPayload {
+        alert,
+        status,
+        message,
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+
+    let result = reqwest::Client::new()
+        .post(webhook_url)
+        .json(&payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    if let Err(err) = result {
+        logging::warn!("Unable to deliver alert {alert}: {err}");
+    }
+}
+
+pub(crate) async fn fire(alert: &str, message: &str) {
+    send(alert, "firing", message).await
+}
+
+pub(crate) async fn resolve(alert: &str, message: &str) {
+    send(alert, "resolved", message).await
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    cluster::{get_vm_resources, IpamEntry},
+    discovery, events, kube, logging, proxmox, status, CONFIG, STATE,
+};
+
+const ANNOTATION_INTERVAL: Duration = Duration::from_secs(300);
+const ANNOTATIONS_KEY: &str = "vm_annotations";
+const ROLE_TAG_PREFIX: &str = "k3s.role.";
+const CLUSTER_TAG_PREFIX: &str = "k3s.cluster.";
+// Proxmox renders the notes as markdown, the markers stay hidden in the UI.
+const DESCRIPTION_START: &str = "<!-- k3s-proxmox-helper -->";
+const DESCRIPTION_END: &str = "<!-- /k3s-proxmox-helper -->";
+
+// The Kubernetes details are kept once seen, so the notes still tell which node a VM was when the
+// cluster can't be reached.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct KnownNode {
+    node_name: Option<String>,
+    joined_at: Option<String>,
+    k3s_version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NodeList {
+    items: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    metadata: NodeMetadata,
+    status: NodeStatus,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeMetadata {
+    name: String,
+    creation_timestamp: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeStatus {
+    node_info: Option<NodeInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeInfo {
+    kubelet_version: String,
+}
+
+async fn kubernetes_nodes(client: &reqwest::Client, cluster: &str) -> BTreeMap<String, KnownNode> {
+    let nodes = match kube::kubectl(client, cluster, &["get", "nodes", "-o", "json"]).await {
+        Ok(output) => serde_json::from_str::<NodeList>(&output).map_err(anyhow::Error::from),
+        Err(err) => Err(err),
+    };
+
+    match nodes {
+        Ok(nodes) => nodes
+            .items
+            .into_iter()
+            .map(|node| {
+                (
+                    node.metadata.name.clone(),
+                    KnownNode {
+                        node_name: Some(node.metadata.name),
+                        joined_at: node.metadata.creation_timestamp,
+                        k3s_version: node.status.node_info.map(|info| info.kubelet_version),
+                    },
+                )
+            })
+            .collect(),
+        Err(err) => {
+            logging::warn!(
+                "Unable to list the Kubernetes nodes of cluster {cluster}, using the last known ones: {err}"
+            );
+            BTreeMap::new()
+        }
+    }
+}
+
+// Tags owned by the helper are replaced, anything set by hand is left alone.
+fn annotated_tags(tags: Option<&str>, ipam: &IpamEntry) -> String {
+    let mut tags = tags
+        .unwrap_or_default()
+        .split([';', ',', ' '])
+        .filter(|tag| {
+            !tag.is_empty()
+                && !tag.starts_with(ROLE_TAG_PREFIX)
+                && !tag.starts_with(CLUSTER_TAG_PREFIX)
+        })
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    if let Some(assignment) = &ipam.assignment {
+        tags.push(format!("{ROLE_TAG_PREFIX}{}", assignment.role.as_str()));
+        tags.push(format!("{CLUSTER_TAG_PREFIX}{}", assignment.cluster));
+    }
+
+    tags.join(";")
+}
+
+fn annotated_description(description: Option<&str>, ipam: &IpamEntry, known: &KnownNode) -> String {
+    let description = description.unwrap_or_default();
+
+    // Only the block between the markers belongs to the helper.
+    let user_notes = match (
+        description.find(DESCRIPTION_START),
+        description.find(DESCRIPTION_END),
+    ) {
+        (Some(start), Some(end)) if start < end => format!(
+            "{}{}",
+            &description[..start],
+            &description[end + DESCRIPTION_END.len()..]
+        ),
+        _ => description.to_string(),
+    };
+
+    let mut lines = vec![DESCRIPTION_START.to_string()];
+
+    if let Some(assignment) = &ipam.assignment {
+        lines.push(format!("**k3s cluster:** {}  ", assignment.cluster));
+        lines.push(format!("**k3s role:** {}  ", assignment.role.as_str()));
+    }
+
+    for (label, value) in [
+        ("Kubernetes node", &known.node_name),
+        ("Joined at", &known.joined_at),
+        ("k3s version", &known.k3s_version),
+    ] {
+        if let Some(value) = value {
+            lines.push(format!("**{label}:** {value}  "));
+        }
+    }
+
+    lines.push(DESCRIPTION_END.to_string());
+
+    let user_notes = user_notes.trim();
+
+    if user_notes.is_empty() {
+        lines.join("\n")
+    } else {
+        format!("{user_notes}\n\n{}", lines.join("\n"))
+    }
+}
+
+async fn annotate(client: &reqwest::Client) -> anyhow::Result<()> {
+    let ipams = discovery::subscribe().borrow().clone();
+    let resources = get_vm_resources(client).await?;
+
+    // Node names are only unique within a cluster.
+    let mut nodes = BTreeMap::new();
+
+    for cluster in ipams
+        .iter()
+        .filter_map(|ipam| ipam.assignment.as_ref())
+        .map(|assignment| assignment.cluster.clone())
+        .collect::<BTreeSet<_>>()
+    {
+        let cluster_nodes = kubernetes_nodes(client, &cluster).await;
+        nodes.insert(cluster, cluster_nodes);
+    }
+
+    let mut known_nodes: BTreeMap<String, KnownNode> =
+        STATE.get(ANNOTATIONS_KEY).unwrap_or_default();
+
+    for ipam in ipams.iter().filter(|ipam| ipam.assignment.is_some()) {
+        let Some(vmid) = &ipam.vmid else {
+            continue;
+        };
+
+        let Some(resource) = resources
+            .iter()
+            .find(|resource| &resource.vmid.to_string() == vmid)
+        else {
+            continue;
+        };
+
+        let cluster_nodes = ipam
+            .assignment
+            .as_ref()
+            .and_then(|assignment| nodes.get(&assignment.cluster));
+
+        if let Some(node) = ipam
+            .hostname
+            .as_ref()
+            .and_then(|name| cluster_nodes?.get(name))
+        {
+            known_nodes.insert(vmid.clone(), node.clone());
+        }
+
+        let known = known_nodes.get(vmid).cloned().unwrap_or_default();
+
+        let path = format!("/nodes/{}/{}/{vmid}/config", resource.node, resource.kind);
+        let config: Map<String, Value> = proxmox::get(client, &path).await?;
+
+        let current_tags = config.get("tags").and_then(Value::as_str);
+        let current_description = config.get("description").and_then(Value::as_str);
+
+        let tags = annotated_tags(current_tags, ipam);
+        let description = annotated_description(current_description, ipam, &known);
+
+        // Proxmox may sort the tags, only the set of tags matters.
+        let tag_set = |tags: &str| {
+            tags.split([';', ',', ' '])
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect::<BTreeSet<_>>()
+        };
+        let tags_changed = tag_set(current_tags.unwrap_or_default()) != tag_set(&tags);
+        let description_changed = current_description.unwrap_or_default().trim() != description;
+
+        if !tags_changed && !description_changed {
+            continue;
+        }
+
+        let _: Value = proxmox::put(
+            client,
+            &path,
+            &[
+                ("tags", tags.as_str()),
+                ("description", description.as_str()),
+            ],
+        )
+        .await?;
+
+        events::record(
+            "annotation",
+            Some(vmid),
+            format!("Annotated VM {vmid} with its cluster metadata"),
+            None,
+        );
+    }
+
+    STATE.update(
+        ANNOTATIONS_KEY,
+        |annotations: &mut BTreeMap<String, KnownNode>| {
+            *annotations = known_nodes;
+        },
+    )?;
+
+    Ok(())
+}
+
+pub(crate) async fn annotate_vms(client: reqwest::Client) -> anyhow::Result<()> {
+    if !CONFIG.annotate_vms {
+        return std::future::pending().await;
+    }
+
+    loop {
+        let result = annotate(&client).await;
+        status::record_job("vm-annotations", &result);
+
+        if let Err(err) = result {
+            logging::warn!("Unable to annotate VMs: {err}");
+        }
+
+        tokio::time::sleep(ANNOTATION_INTERVAL).await;
+    }
+}
@@ -0,0 +1,197 @@
+use std::{sync::Mutex, time::Duration};
+
+use axum::Json;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{
+    backups::{self, BackupOptions},
+    cluster::{get_all_vms_for_node, get_nodes, NodeRole},
+    error::AppResult,
+    events, logging,
+    maintenance::{self, OperationClass},
+    proxmox, roles, status, CONFIG,
+};
+
+static COMPLIANCE: Lazy<Mutex<Vec<PolicyCompliance>>> = Lazy::new(|| Mutex::new(vec![]));
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BackupPolicy {
+    pub interval_hours: u64,
+    pub keep_last: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PolicyCompliance {
+    pub vmid: String,
+    pub name: String,
+    pub role: NodeRole,
+    pub policy: BackupPolicy,
+    pub latest_backup: Option<i64>,
+    pub backups: usize,
+    pub compliant: bool,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PolicyReport {
+    pub enabled: bool,
+    pub compliant: bool,
+    pub vms: Vec<PolicyCompliance>,
+}
+
+pub(crate) fn policy_for_role(role: NodeRole) -> BackupPolicy {
+    match role {
+        NodeRole::Server => BackupPolicy {
+            interval_hours: CONFIG.backup_policy_server_interval,
+            keep_last: CONFIG.backup_policy_server_keep,
+        },
+        NodeRole::Agent => BackupPolicy {
+            interval_hours: CONFIG.backup_policy_agent_interval,
+            keep_last: CONFIG.backup_policy_agent_keep,
+        },
+    }
+}
+
+async fn enforce_retention(
+    client: &reqwest::Client,
+    node: &str,
+    storage: &str,
+    vmid: &str,
+    keep_last: usize,
+) -> anyhow::Result<usize> {
+    let backups = backups::list_backups(client, node, storage, vmid).await?;
+
+    let mut kept = 0;
+
+    for backup in backups {
+        if kept < keep_last || backup.protected.unwrap_or_default() == 1 {
+            kept += 1;
+            continue;
+        }
+
+        let _: serde_json::Value = proxmox::delete(
+            client,
+            &format!(
+                "/nodes/{node}/storage/{storage}/content/{}",
+                urlencoding::encode(&backup.volid)
+            ),
+        )
+        .await?;
+
+        events::record(
+            "backup-pruned",
+            Some(vmid),
+            format!("Pruned backup {} of VM {vmid}", backup.volid),
+            None,
+        );
+    }
+
+    Ok(kept)
+}
+
+async fn apply_policy(client: &reqwest::Client) -> anyhow::Result<Vec<PolicyCompliance>> {
+    let storage = backups::default_backup_storage();
+    let now = chrono::Utc::now().timestamp();
+
+    let mut compliance = vec![];
+    let mut deferred = vec![];
+
+    for node in get_nodes(client.clone()).await?.data {
+        let vms = get_all_vms_for_node(client.clone(), &node.node).await?.data;
+
+        for vm in vms
+            .into_iter()
+            .filter(|vm| vm.template.is_none_or(|template| template == 0))
+        {
+            let Some(role) = roles::assign_vm(&vm).map(|assignment| assignment.role) else {
+                continue;
+            };
+
+            let vmid = vm.vmid.to_string();
+            let policy = policy_for_role(role);
+            let due_before = now - policy.interval_hours as i64 * 3600;
+
+            let mut latest_backup = backups::list_backups(client, &node.node, &storage, &vmid)
+                .await?
+                .first()
+                .map(|backup| backup.ctime);
+
+            let mut last_error = None;
+
+            let due = latest_backup.is_none_or(|ctime| ctime < due_before);
+
+            if due && !maintenance::is_open(OperationClass::Backup) {
+                deferred.push(format!("Back up VM {vmid} ({})", vm.name));
+            } else if due {
+                match backups::run_backup(client, &vmid, &BackupOptions::default()).await {
+                    Ok(_) => latest_backup = Some(chrono::Utc::now().timestamp()),
+                    Err(err) => last_error = Some(err.to_string()),
+                }
+            }
+
+            let backups = match enforce_retention(
+                client,
+                &node.node,
+                &storage,
+                &vmid,
+                policy.keep_last,
+            )
+            .await
+            {
+                Ok(kept) => kept,
+                Err(err) => {
+                    last_error = Some(err.to_string());
+                    0
+                }
+            };
+
+            compliance.push(PolicyCompliance {
+                vmid,
+                name: vm.name,
+                role,
+                compliant: latest_backup.is_some_and(|ctime| ctime >= due_before),
+                policy,
+                latest_backup,
+                backups,
+                last_error,
+            });
+        }
+    }
+
+    maintenance::defer(OperationClass::Backup, deferred);
+
+    Ok(compliance)
+}
+
+pub(crate) async fn run_backup_policy(client: reqwest::Client) -> anyhow::Result<()> {
+    if !CONFIG.backup_policy {
+        return std::future::pending().await;
+    }
+
+    loop {
+        let result = apply_policy(&client).await;
+        status::record_job("backup-policy", &result);
+
+        match result {
+            Ok(compliance) => *COMPLIANCE.lock().unwrap() = compliance,
+            Err(err) => logging::warn!("Unable to apply backup policy: {err}"),
+        }
+
+        tokio::time::sleep(maintenance::next_pass_in(
+            OperationClass::Backup,
+            Duration::from_secs(900),
+        ))
+        .await;
+    }
+}
+
+pub(crate) async fn get_policy_report() -> AppResult<Json<PolicyReport>> {
+    let vms = COMPLIANCE.lock().unwrap().clone();
+
+    Ok(Json(PolicyReport {
+        enabled: CONFIG.backup_policy,
+        compliant: vms.iter().all(|vm| vm.compliant),
+        vms,
+    }))
+}
@@ -0,0 +1,373 @@
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    response::Response,
+    Json,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alerts,
+    cluster::{find_vm_node, get_all_vms_for_node, get_nodes, NodeRole},
+    clusters,
+    error::AppResult,
+    events,
+    health::HealthCheck,
+    jobs::{self, JobHandle},
+    logging, proxmox, roles, status, tasks, CONFIG,
+};
+
+static BACKUP_FRESHNESS: Lazy<Mutex<Vec<BackupFreshness>>> = Lazy::new(|| Mutex::new(vec![]));
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct BackupOptions {
+    storage: Option<String>,
+    mode: Option<String>,
+    compress: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BulkBackupRequest {
+    vmids: Option<Vec<String>>,
+    #[serde(flatten)]
+    options: BackupOptions,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupResult {
+    pub vmid: String,
+    pub node: Option<String>,
+    pub upid: Option<String>,
+    pub archive: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BackupVerification {
+    pub state: String,
+    pub upid: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BackupEntry {
+    pub volid: String,
+    pub ctime: i64,
+    pub size: Option<i64>,
+    pub format: Option<String>,
+    pub notes: Option<String>,
+    pub protected: Option<u8>,
+    pub verification: Option<BackupVerification>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BackupFreshness {
+    pub vmid: String,
+    pub name: String,
+    pub control_plane: bool,
+    pub latest_backup: Option<i64>,
+    pub fresh: bool,
+}
+
+pub(crate) fn default_backup_storage() -> String {
+    CONFIG
+        .pbs_storage
+        .clone()
+        .unwrap_or_else(|| CONFIG.backup_storage.clone())
+}
+
+fn find_archive(log: &[tasks::TaskLogLine]) -> Option<String> {
+    log.iter().find_map(|line| {
+        line.t
+            .split_once("archive '")
+            .and_then(|(_, rest)| rest.split_once('\''))
+            .map(|(archive, _)| archive.to_string())
+    })
+}
+
+pub(crate) async fn run_backup(
+    client: &reqwest::Client,
+    vmid: &str,
+    options: &BackupOptions,
+) -> anyhow::Result<BackupResult> {
+    let node = find_vm_node(client.clone(), vmid).await?;
+
+    let storage = options
+        .storage
+        .clone()
+        .unwrap_or_else(default_backup_storage);
+    let mode = options.mode.as_deref().unwrap_or("snapshot");
+    let compress = options.compress.as_deref().unwrap_or("zstd");
+
+    let upid: String = proxmox::post(
+        client,
+        &format!("/nodes/{node}/vzdump"),
+        &[
+            ("vmid", vmid),
+            ("storage", &storage),
+            ("mode", mode),
+            ("compress", compress),
+        ],
+    )
+    .await?;
+
+    events::record(
+        "backup-started",
+        Some(vmid),
+        format!("Backup of VM {vmid} to {storage} started ({upid})"),
+        None,
+    );
+
+    if let Err(err) = tasks::wait_for_task(
+        client,
+        &node,
+        &upid,
+        Duration::from_secs(CONFIG.backup_timeout),
+    )
+    .await
+    {
+        events::record(
+            "backup-failed",
+            Some(vmid),
+            format!("Backup of VM {vmid} failed: {err}"),
+            Some(serde_json::json!({ "upid": upid })),
+        );
+
+        return Err(err);
+    }
+
+    let archive = find_archive(&tasks::get_task_log(client, &node, &upid).await?);
+
+    events::record(
+        "backup-completed",
+        Some(vmid),
+        format!(
+            "Backup of VM {vmid} completed: {}",
+            archive.as_deref().unwrap_or("unknown archive")
+        ),
+        Some(serde_json::json!({
+            "upid": upid,
+            "node": node,
+            "storage": storage,
+            "archive": archive,
+        })),
+    );
+
+    Ok(BackupResult {
+        vmid: vmid.to_string(),
+        node: Some(node),
+        upid: Some(upid),
+        archive,
+        error: None,
+    })
+}
+
+pub(crate) async fn backup_vm(
+    Path(vm_id): Path<String>,
+    State(client): State<reqwest::Client>,
+    options: Option<Json<BackupOptions>>,
+) -> AppResult<Response> {
+    let options = options.map(|Json(options)| options).unwrap_or_default();
+
+    Ok(jobs::accepted(jobs::spawn(
+        "backup",
+        clusters::cluster_of_vmid(&vm_id),
+        |_| async move { run_backup(&client, &vm_id, &options).await },
+    )?))
+}
+
+async fn run_backups(
+    client: reqwest::Client,
+    request: BulkBackupRequest,
+    job: JobHandle,
+) -> anyhow::Result<Vec<BackupResult>> {
+    let vmids = match request.vmids {
+        Some(vmids) => vmids,
+        None => {
+            let mut vmids = vec![];
+
+            for node in get_nodes(client.clone()).await?.data {
+                vmids.extend(
+                    get_all_vms_for_node(client.clone(), &node.node)
+                        .await?
+                        .data
+                        .into_iter()
+                        .filter(|vm| vm.template.is_none_or(|template| template == 0))
+                        .filter(|vm| roles::assign_vm(vm).is_some())
+                        .map(|vm| vm.vmid.to_string()),
+                );
+            }
+
+            vmids
+        }
+    };
+
+    let mut results = vec![];
+
+    // Backups are run one after the other to avoid saturating the backup storage.
+    for vmid in vmids {
+        let result = match run_backup(&client, &vmid, &request.options).await {
+            Ok(result) => {
+                job.log(format!("Backed up VM {vmid}"));
+                result
+            }
+            Err(err) => {
+                job.log(format!("Unable to back up VM {vmid}: {err}"));
+
+                BackupResult {
+                    vmid,
+                    node: None,
+                    upid: None,
+                    archive: None,
+                    error: Some(err.to_string()),
+                }
+            }
+        };
+
+        results.push(result);
+    }
+
+    Ok(results)
+}
+
+pub(crate) async fn backup_vms(
+    State(client): State<reqwest::Client>,
+    Json(request): Json<BulkBackupRequest>,
+) -> AppResult<Response> {
+    Ok(jobs::accepted(jobs::spawn("backup", None, |job| {
+        run_backups(client, request, job)
+    })?))
+}
+
+pub(crate) async fn list_backups(
+    client: &reqwest::Client,
+    node: &str,
+    storage: &str,
+    vmid: &str,
+) -> anyhow::Result<Vec<BackupEntry>> {
+    let mut backups: Vec<BackupEntry> = proxmox::get_with_query(
+        client,
+        &format!("/nodes/{node}/storage/{storage}/content"),
+        &[("content", "backup"), ("vmid", vmid)],
+    )
+    .await?;
+
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.ctime));
+
+    Ok(backups)
+}
+
+pub(crate) async fn get_vm_backups(
+    Path(vm_id): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<Vec<BackupEntry>>> {
+    let node = find_vm_node(client.clone(), &vm_id).await?;
+
+    Ok(Json(
+        list_backups(&client, &node, &default_backup_storage(), &vm_id).await?,
+    ))
+}
+
+async fn check_freshness(client: &reqwest::Client) -> anyhow::Result<Vec<BackupFreshness>> {
+    let storage = default_backup_storage();
+    let oldest_allowed = chrono::Utc::now().timestamp() - CONFIG.backup_max_age as i64 * 3600;
+
+    let mut freshness = vec![];
+
+    for node in get_nodes(client.clone()).await?.data {
+        let vms = get_all_vms_for_node(client.clone(), &node.node).await?.data;
+
+        for vm in vms
+            .into_iter()
+            .filter(|vm| vm.template.is_none_or(|template| template == 0))
+            .filter(|vm| roles::assign_vm(vm).is_some())
+        {
+            let vmid = vm.vmid.to_string();
+            let assignment = roles::assign_vm(&vm);
+
+            let latest_backup = list_backups(client, &node.node, &storage, &vmid)
+                .await?
+                .first()
+                .map(|backup| backup.ctime);
+
+            freshness.push(BackupFreshness {
+                vmid,
+                control_plane: assignment
+                    .is_some_and(|assignment| assignment.role == NodeRole::Server),
+                name: vm.name,
+                latest_backup,
+                fresh: latest_backup.is_some_and(|ctime| ctime >= oldest_allowed),
+            });
+        }
+    }
+
+    Ok(freshness)
+}
+
+pub(crate) fn freshness_checks() -> Vec<HealthCheck> {
+    BACKUP_FRESHNESS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|freshness| HealthCheck {
+            name: format!("backup/{}", freshness.vmid),
+            healthy: freshness.fresh,
+            message: if freshness.fresh {
+                format!("{} has a recent backup", freshness.name)
+            } else {
+                format!(
+                    "{} {} has no backup newer than {} hours",
+                    if freshness.control_plane {
+                        "Control-plane node"
+                    } else {
+                        "Node"
+                    },
+                    freshness.name,
+                    CONFIG.backup_max_age
+                )
+            },
+        })
+        .collect()
+}
+
+pub(crate) async fn monitor_backup_freshness(client: reqwest::Client) -> anyhow::Result<()> {
+    let mut stale_vms = HashSet::new();
+
+    loop {
+        let result = check_freshness(&client).await;
+        status::record_job("backup-freshness", &result);
+
+        match result {
+            Ok(freshness) => {
+                for entry in &freshness {
+                    let alert = format!("backup-stale-{}", entry.vmid);
+
+                    if !entry.fresh && stale_vms.insert(entry.vmid.clone()) {
+                        alerts::fire(
+                            &alert,
+                            &format!(
+                                "{} ({}) has no backup newer than {} hours",
+                                entry.name, entry.vmid, CONFIG.backup_max_age
+                            ),
+                        )
+                        .await;
+                    } else if entry.fresh && stale_vms.remove(&entry.vmid) {
+                        alerts::resolve(
+                            &alert,
+                            &format!("{} ({}) has a recent backup", entry.name, entry.vmid),
+                        )
+                        .await;
+                    }
+                }
+
+                *BACKUP_FRESHNESS.lock().unwrap() = freshness;
+            }
+            Err(err) => {
+                logging::warn!("Unable to check backup freshness: {err}");
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(900)).await;
+    }
+}
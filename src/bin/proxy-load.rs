@@ -0,0 +1,186 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+// Drives a running helper: fake k3s servers are started on loopback addresses, which the helper
+// must know through its static entries, and are stopped and restarted in turn to churn the pool.
+#[derive(Parser)]
+struct Args {
+    /// Address of the helper's k3s API proxy
+    #[arg(long, default_value = "127.0.0.1:6443")]
+    target: SocketAddr,
+    /// Connections kept open at the same time
+    #[arg(long, default_value_t = 100)]
+    connections: usize,
+    /// Bytes sent and echoed back on every connection
+    #[arg(long, default_value_t = 16384)]
+    payload_size: usize,
+    /// Duration of the run, in seconds
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+    /// Echo backends to start on 127.0.0.2, 127.0.0.3, ... port 6443, none when 0
+    #[arg(long, default_value_t = 0)]
+    backends: u8,
+    /// Seconds between two backend restarts, no churn when 0
+    #[arg(long, default_value_t = 0)]
+    churn_interval: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+    completed: AtomicU64,
+    failed: AtomicU64,
+    bytes: AtomicU64,
+    connect_latencies: Mutex<Vec<Duration>>,
+}
+
+fn spawn_backend(ip: String) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind((ip.as_str(), 6443)).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                println!("Unable to start backend {ip}: {err}");
+                return;
+            }
+        };
+
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    })
+}
+
+async fn churn_backends(backends: u8, interval: Duration) {
+    let ips = (0..backends)
+        .map(|index| format!("127.0.0.{}", index + 2))
+        .collect::<Vec<_>>();
+
+    let mut handles = ips
+        .iter()
+        .map(|ip| spawn_backend(ip.clone()))
+        .collect::<Vec<_>>();
+
+    if interval.is_zero() || ips.is_empty() {
+        return std::future::pending().await;
+    }
+
+    for index in (0..ips.len()).cycle() {
+        tokio::time::sleep(interval).await;
+
+        println!("Stopping backend {}", ips[index]);
+        handles[index].abort();
+
+        tokio::time::sleep(interval).await;
+
+        println!("Restarting backend {}", ips[index]);
+        handles[index] = spawn_backend(ips[index].clone());
+    }
+}
+
+async fn round_trip(target: SocketAddr, payload: &[u8], stats: &Stats) -> anyhow::Result<()> {
+    let started_at = Instant::now();
+    let mut stream = TcpStream::connect(target).await?;
+
+    stats
+        .connect_latencies
+        .lock()
+        .unwrap()
+        .push(started_at.elapsed());
+
+    let mut received = vec![0; payload.len()];
+    let (mut reader, mut writer) = stream.split();
+
+    tokio::try_join!(writer.write_all(payload), reader.read_exact(&mut received))?;
+
+    stats
+        .bytes
+        .fetch_add(payload.len() as u64 * 2, Ordering::Relaxed);
+
+    Ok(())
+}
+
+async fn worker(args: Arc<Args>, stats: Arc<Stats>, deadline: Instant) {
+    let payload = vec![0x42; args.payload_size];
+
+    while Instant::now() < deadline {
+        match round_trip(args.target, &payload, &stats).await {
+            Ok(()) => stats.completed.fetch_add(1, Ordering::Relaxed),
+            Err(_) => stats.failed.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+}
+
+fn percentile(sorted: &[Duration], percentile: usize) -> Duration {
+    sorted
+        .get((sorted.len() * percentile / 100).min(sorted.len().saturating_sub(1)))
+        .copied()
+        .unwrap_or_default()
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Arc::new(Args::parse());
+    let stats = Arc::new(Stats::default());
+
+    let churn = tokio::spawn(churn_backends(
+        args.backends,
+        Duration::from_secs(args.churn_interval),
+    ));
+
+    let started_at = Instant::now();
+    let deadline = started_at + Duration::from_secs(args.duration);
+
+    let workers = (0..args.connections)
+        .map(|_| tokio::spawn(worker(args.clone(), stats.clone(), deadline)))
+        .collect::<Vec<_>>();
+
+    for worker in workers {
+        worker.await?;
+    }
+
+    churn.abort();
+
+    let elapsed = started_at.elapsed().as_secs_f64();
+    let mut latencies = std::mem::take(&mut *stats.connect_latencies.lock().unwrap());
+    latencies.sort();
+
+    let completed = stats.completed.load(Ordering::Relaxed);
+    let failed = stats.failed.load(Ordering::Relaxed);
+    let bytes = stats.bytes.load(Ordering::Relaxed);
+
+    println!(
+        "Connections completed: {completed} ({:.0}/s)",
+        completed as f64 / elapsed
+    );
+    println!("Connections failed: {failed}");
+    println!(
+        "Throughput: {:.1} MiB/s",
+        bytes as f64 / elapsed / (1 << 20) as f64
+    );
+
+    for p in [50, 90, 99] {
+        println!("Connect latency p{p}: {:?}", percentile(&latencies, p));
+    }
+
+    println!(
+        "Connect latency max: {:?}",
+        latencies.last().copied().unwrap_or_default()
+    );
+
+    Ok(())
+}
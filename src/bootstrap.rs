@@ -0,0 +1,513 @@
+use std::{
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    certificates,
+    cloud_init::{self, Join},
+    cluster::{NodeRole, VmResource},
+    cluster_restore,
+    clusters::{self, K3sCluster, K8S_API_PORT},
+    discovery, permissions, proxmox,
+    roles::{self, NodeAssignment},
+    signer,
+    ssh::{self, SshTarget},
+    vm_lifecycle, vms,
+};
+
+const NODE_READY_TIMEOUT: Duration = Duration::from_secs(900);
+
+#[derive(Deserialize)]
+struct BootstrapManifest {
+    #[serde(default = "clusters::default_cluster_name")]
+    cluster: String,
+    template: u32,
+    // Proxmox nodes the servers are spread over, the template's node by default.
+    #[serde(default)]
+    nodes: Vec<String>,
+    #[serde(default = "default_servers")]
+    servers: usize,
+    #[serde(default = "default_hostname_prefix")]
+    hostname_prefix: String,
+    // Reserved in the vnet's IPAM before the servers boot, they get theirs from DHCP otherwise.
+    #[serde(default)]
+    addresses: Vec<String>,
+    #[serde(default)]
+    ssh_keys: Vec<String>,
+    // Generated when unset.
+    token: Option<String>,
+    #[serde(default = "default_kubeconfig")]
+    kubeconfig: String,
+}
+
+#[derive(Deserialize)]
+struct VnetConfig {
+    zone: String,
+}
+
+struct Server {
+    hostname: String,
+    node: String,
+    vmid: String,
+    address: Option<String>,
+    assignment: NodeAssignment,
+}
+
+fn default_servers() -> usize {
+    3
+}
+
+fn default_hostname_prefix() -> String {
+    "k3s-server-".to_string()
+}
+
+fn default_kubeconfig() -> String {
+    "kubeconfig.yaml".to_string()
+}
+
+fn step(message: String) {
+    eprintln!("==> {message}");
+}
+
+fn load_manifest(path: &str) -> anyhow::Result<BootstrapManifest> {
+    let content =
+        std::fs::read_to_string(path).context(format!("Unable to read manifest {path}"))?;
+
+    Ok(if path.ends_with(".json") {
+        serde_json::from_str(&content)?
+    } else if path.ends_with(".yaml") || path.ends_with(".yml") {
+        serde_yaml::from_str(&content)?
+    } else {
+        toml::from_str(&content)?
+    })
+}
+
+fn ask(question: &str, default: Option<&str>) -> anyhow::Result<String> {
+    match default {
+        Some(default) => eprint!("{question} [{default}]: "),
+        None => eprint!("{question}: "),
+    }
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    match (answer.trim(), default) {
+        ("", Some(default)) => Ok(default.to_string()),
+        ("", None) => anyhow::bail!("{question} is required"),
+        (answer, _) => Ok(answer.to_string()),
+    }
+}
+
+fn ask_list(question: &str) -> anyhow::Result<Vec<String>> {
+    Ok(ask(question, Some(""))?
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn ask_manifest() -> anyhow::Result<BootstrapManifest> {
+    let token = ask("Cluster token, generated when empty", Some(""))?;
+
+    Ok(BootstrapManifest {
+        cluster: ask("Cluster", Some(&clusters::default_cluster_name()))?,
+        template: ask("Template vmid", None)?
+            .parse()
+            .context("Invalid template vmid")?,
+        nodes: ask_list("Proxmox nodes, comma separated, the template's node when empty")?,
+        servers: ask("Servers", Some(&default_servers().to_string()))?
+            .parse()
+            .context("Invalid number of servers")?,
+        hostname_prefix: ask("Hostname prefix", Some(&default_hostname_prefix()))?,
+        addresses: ask_list("Addresses to reserve, comma separated, DHCP when empty")?,
+        ssh_keys: ask_list("SSH public keys, comma separated")?,
+        token: (!token.is_empty()).then_some(token),
+        kubeconfig: ask("Write the kubeconfig to", Some(&default_kubeconfig()))?,
+    })
+}
+
+fn hostname(manifest: &BootstrapManifest, index: usize) -> String {
+    format!("{}{}", manifest.hostname_prefix, index + 1)
+}
+
+// Nothing is created before the whole manifest checks out, bootstrap only ever creates clusters
+// that have no server yet.
+async fn validate(
+    client: &reqwest::Client,
+    manifest: &BootstrapManifest,
+) -> anyhow::Result<(&'static K3sCluster, VmResource, Vec<NodeAssignment>)> {
+    let cluster = clusters::find(&manifest.cluster)
+        .context(format!("Unknown cluster {}", manifest.cluster))?;
+
+    if manifest.servers.is_multiple_of(2) {
+        anyhow::bail!(
+            "etcd needs an odd number of servers to keep its quorum, got {}",
+            manifest.servers
+        );
+    }
+
+    if !manifest.addresses.is_empty() && manifest.addresses.len() != manifest.servers {
+        anyhow::bail!(
+            "{} addresses given for {} servers",
+            manifest.addresses.len(),
+            manifest.servers
+        );
+    }
+
+    let missing = permissions::check(client)
+        .await?
+        .into_iter()
+        .filter(|check| !check.granted)
+        .map(|check| format!("{} on {}", check.privilege, check.path))
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty() {
+        anyhow::bail!("Missing Proxmox privileges: {}", missing.join(", "));
+    }
+
+    let template = vm_lifecycle::find_template(client, manifest.template).await?;
+
+    let has_servers = discovery::discover_ipams(client)
+        .await?
+        .iter()
+        .filter_map(|ipam| ipam.assignment.as_ref())
+        .any(|assignment| {
+            assignment.cluster == cluster.name && assignment.role == NodeRole::Server
+        });
+
+    if has_servers {
+        anyhow::bail!("Cluster {} already has servers", cluster.name);
+    }
+
+    // The helper only proxies and manages the servers if the role mapping recognizes them.
+    (0..manifest.servers)
+        .map(|index| {
+            let hostname = hostname(manifest, index);
+
+            roles::assign(
+                Some(&hostname),
+                Some(cluster.vnet()),
+                template.tags.as_deref(),
+            )
+            .filter(|assignment| {
+                assignment.cluster == cluster.name && assignment.role == NodeRole::Server
+            })
+            .context(format!(
+                "The role mapping doesn't make {hostname} a server of cluster {}",
+                cluster.name
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map(|assignments| (cluster, template, assignments))
+}
+
+// e.g. `virtio=BC:24:11:00:00:01,bridge=vnet1,firewall=1`, the model comes first.
+async fn reserve_address(
+    client: &reqwest::Client,
+    node: &str,
+    vmid: &str,
+    ip: &str,
+) -> anyhow::Result<()> {
+    let config: Map<String, Value> =
+        proxmox::get(client, &format!("/nodes/{node}/qemu/{vmid}/config")).await?;
+
+    let net0 = config
+        .get("net0")
+        .and_then(Value::as_str)
+        .context(format!("VM {vmid} has no net0 interface"))?;
+
+    let fields = net0
+        .split(',')
+        .filter_map(|field| field.split_once('='))
+        .collect::<Vec<_>>();
+
+    let mac = fields
+        .first()
+        .map(|(_, mac)| *mac)
+        .context(format!("VM {vmid} has no MAC address"))?;
+    let vnet = fields
+        .iter()
+        .find(|(name, _)| *name == "bridge")
+        .map(|(_, bridge)| *bridge)
+        .context(format!("VM {vmid} isn't attached to a bridge"))?;
+
+    let vnet_config: VnetConfig =
+        proxmox::get(client, &format!("/cluster/sdn/vnets/{vnet}")).await?;
+
+    let _: Value = proxmox::post(
+        client,
+        &format!("/cluster/sdn/vnets/{vnet}/ips"),
+        &[
+            ("zone", vnet_config.zone.as_str()),
+            ("ip", ip),
+            ("mac", mac),
+        ],
+    )
+    .await?;
+
+    Ok(())
+}
+
+// Asked on the first server, the helper's discovery may not have picked the new ones up yet.
+async fn wait_for_ready(
+    client: &reqwest::Client,
+    first: &SshTarget,
+    cluster: &K3sCluster,
+    hostname: &str,
+) -> anyhow::Result<()> {
+    let deadline = tokio::time::Instant::now() + NODE_READY_TIMEOUT;
+    let command = format!(
+        "{} get node {} -o {}",
+        cluster.distro.kubectl(),
+        ssh::shell_quote(&hostname.to_lowercase()),
+        ssh::shell_quote(r#"jsonpath={.status.conditions[?(@.type=="Ready")].status}"#)
+    );
+
+    loop {
+        if let Ok(output) = ssh::run(client, first, &command).await {
+            if String::from_utf8_lossy(&output.stdout).trim() == "True" {
+                return Ok(());
+            }
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("Timed out waiting for {hostname} to become ready");
+        }
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+fn rename(value: &mut serde_yaml::Value, key: &str, name: &str) {
+    if let Some(mapping) = value.as_mapping_mut() {
+        mapping.insert(key.into(), name.into());
+    }
+}
+
+// k3s names everything `default` and points at its loopback address, the copy reaches the cluster
+// through the helper's proxy under the cluster's own name.
+fn rewrite_kubeconfig(
+    kubeconfig: &mut serde_yaml::Value,
+    cluster: &K3sCluster,
+) -> anyhow::Result<()> {
+    let server = cluster.proxy_url()?;
+
+    for (section, inner) in [
+        ("clusters", "cluster"),
+        ("users", "user"),
+        ("contexts", "context"),
+    ] {
+        for entry in kubeconfig
+            .get_mut(section)
+            .and_then(serde_yaml::Value::as_sequence_mut)
+            .into_iter()
+            .flatten()
+        {
+            rename(entry, "name", &cluster.name);
+
+            if let Some(inner) = entry.get_mut(inner) {
+                match section {
+                    "clusters" => rename(inner, "server", &server),
+                    "contexts" => {
+                        rename(inner, "cluster", &cluster.name);
+                        rename(inner, "user", &cluster.name);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    rename(kubeconfig, "current-context", &cluster.name);
+
+    Ok(())
+}
+
+async fn write_kubeconfig(
+    client: &reqwest::Client,
+    first: &SshTarget,
+    cluster: &K3sCluster,
+    path: &str,
+) -> anyhow::Result<()> {
+    let output = ssh::run(
+        client,
+        first,
+        &format!("cat {}", cluster.distro.kubeconfig_path()),
+    )
+    .await
+    .context("Unable to read the kubeconfig of the first server")?;
+
+    let mut kubeconfig: serde_yaml::Value = serde_yaml::from_slice(&output.stdout)?;
+    rewrite_kubeconfig(&mut kubeconfig, cluster)?;
+
+    let content = serde_yaml::to_string(&kubeconfig)?;
+
+    // Created private, the credentials are never readable by others even for a moment. An existing
+    // file is made private before it gets them.
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .context(format!("Unable to write {path}"))?;
+
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(content.as_bytes())
+        .context(format!("Unable to write {path}"))?;
+
+    Ok(())
+}
+
+// The first server initializes etcd and the others join it directly, the proxy only learns about
+// it once the helper's discovery picks it up. Servers are started one at a time so etcd never
+// adds two members at once.
+pub(crate) async fn bootstrap(
+    client: &reqwest::Client,
+    manifest: Option<&str>,
+) -> anyhow::Result<String> {
+    let manifest = match manifest {
+        Some(path) => load_manifest(path)?,
+        None => ask_manifest()?,
+    };
+
+    let (cluster, template, assignments) = validate(client, &manifest).await?;
+    step(format!(
+        "Validated the configuration and the manifest of cluster {}",
+        cluster.name
+    ));
+
+    if signer::bootstrap_ca(cluster).await? {
+        step(format!(
+            "Created the cluster CA in {}",
+            cluster.ca_path().display()
+        ));
+    } else {
+        step("Using the existing cluster CA".to_string());
+    }
+
+    // k3s would generate a CA of its own otherwise, the helper couldn't issue certificates it
+    // trusts.
+    let ca_files = certificates::cluster_ca_files(&cluster.name).await?;
+    step(format!(
+        "Issued the k3s CA certificates of cluster {} from the cluster CA",
+        cluster.name
+    ));
+
+    let token = manifest
+        .token
+        .clone()
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+
+    let nodes = if manifest.nodes.is_empty() {
+        vec![template.node.clone()]
+    } else {
+        manifest.nodes.clone()
+    };
+
+    let mut servers = vec![];
+
+    for (index, assignment) in assignments.into_iter().enumerate() {
+        let hostname = hostname(&manifest, index);
+        let node = nodes[index % nodes.len()].clone();
+
+        let vmid =
+            vm_lifecycle::clone_template(client, &template, &node, &hostname, cluster).await?;
+        step(format!(
+            "Cloned template {} into VM {vmid} ({hostname}) on {node}",
+            manifest.template
+        ));
+
+        let address = manifest.addresses.get(index).cloned();
+
+        if let Some(ip) = &address {
+            reserve_address(client, &node, &vmid, ip).await?;
+            step(format!("Reserved {ip} for {hostname}"));
+        }
+
+        servers.push(Server {
+            hostname,
+            node,
+            vmid,
+            address,
+            assignment,
+        });
+    }
+
+    let join_port = cluster
+        .distro
+        .supervisor_backend_port()
+        .unwrap_or(K8S_API_PORT);
+    let mut first: Option<SshTarget> = None;
+
+    for server in &servers {
+        let join = match &first {
+            None => Join::ClusterInit {
+                token: token.clone(),
+                ca_files: ca_files.clone(),
+            },
+            Some(first) => Join::Existing {
+                server: format!("https://{}:{join_port}", first.ip),
+                token: token.clone(),
+            },
+        };
+
+        cloud_init::attach(
+            client,
+            &server.node,
+            &server.vmid,
+            &server.assignment,
+            &server.hostname,
+            &manifest.ssh_keys,
+            &join,
+        )
+        .await?;
+        vms::change_vm_status(client, &server.node, &server.vmid, "start").await?;
+
+        let target = match &server.address {
+            Some(ip) => SshTarget {
+                vmid: server.vmid.clone(),
+                ip: ip.clone(),
+            },
+            None => cluster_restore::wait_for_ipam(client, &server.node, &server.vmid).await?,
+        };
+        cluster_restore::wait_for_ssh(client, &target).await?;
+        step(format!(
+            "{} booted with address {}",
+            server.hostname, target.ip
+        ));
+
+        let first = first.get_or_insert(target);
+        wait_for_ready(client, first, cluster, &server.hostname).await?;
+
+        if matches!(join, Join::ClusterInit { .. }) {
+            step(format!("{} initialized the cluster", server.hostname));
+        } else {
+            step(format!("{} joined the cluster", server.hostname));
+        }
+    }
+
+    let first = first.context("No server was created")?;
+    write_kubeconfig(client, &first, cluster, &manifest.kubeconfig).await?;
+    step(format!("Wrote the kubeconfig to {}", manifest.kubeconfig));
+
+    Ok(format!(
+        "Cluster {name} is up with {servers} servers.\n\n\
+         Next steps:\n  \
+         export KUBECONFIG={kubeconfig}\n  \
+         kubectl get nodes\n  \
+         k3s-proxmox-helper nodes, to check the helper picked the servers up\n  \
+         POST /cluster/vms/clone with \"cloud_init\": true to add agents\n",
+        name = cluster.name,
+        servers = servers.len(),
+        kubeconfig = manifest.kubeconfig,
+    ))
+}
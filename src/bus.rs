@@ -0,0 +1,71 @@
+use std::sync::Mutex;
+
+use tokio::sync::{mpsc, watch};
+
+// Consumers only interested in the current value, like the proxy, read the latest snapshot and
+// don't mind skipping intermediate values. Consumers that must see every transition subscribe to
+// the bounded event stream instead, publishers wait for them when they fall behind.
+pub(crate) struct Bus<T> {
+    capacity: usize,
+    latest: watch::Sender<T>,
+    subscribers: Mutex<Vec<mpsc::Sender<T>>>,
+    // Publications are serialized so that the snapshot and the stream agree on the order.
+    publishing: tokio::sync::Mutex<()>,
+}
+
+impl<T: Clone + Send + Sync + 'static> Bus<T> {
+    pub(crate) fn new(initial: T, capacity: usize) -> Self {
+        Self {
+            capacity,
+            latest: watch::channel(initial).0,
+            subscribers: Mutex::new(vec![]),
+            publishing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub(crate) fn latest(&self) -> watch::Receiver<T> {
+        self.latest.subscribe()
+    }
+
+    pub(crate) fn borrow(&self) -> watch::Ref<'_, T> {
+        self.latest.borrow()
+    }
+
+    pub(crate) fn events(&self) -> mpsc::Receiver<T> {
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub(crate) async fn publish(&self, value: T) {
+        self.publish_if(|current| {
+            *current = value;
+            true
+        })
+        .await;
+    }
+
+    // Nothing is published when `modify` returns false.
+    pub(crate) async fn publish_if<F: FnOnce(&mut T) -> bool>(&self, modify: F) -> bool {
+        let _publishing = self.publishing.lock().await;
+
+        if !self.latest.send_if_modified(modify) {
+            return false;
+        }
+
+        let value = self.latest.borrow().clone();
+
+        let subscribers = {
+            let mut subscribers = self.subscribers.lock().unwrap();
+            subscribers.retain(|subscriber| !subscriber.is_closed());
+            subscribers.clone()
+        };
+
+        // Nothing is dropped, a consumer that closed its stream meanwhile is simply skipped.
+        for subscriber in subscribers {
+            let _ = subscriber.send(value.clone()).await;
+        }
+
+        true
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::{get_all_vms_for_node, get_nodes, VirtualMachineEntry},
+    error::AppResult,
+    ha,
+    models::ProxmoxData,
+    proxmox, roles,
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StorageEntry {
+    pub storage: String,
+    #[serde(rename = "type")]
+    pub storage_type: String,
+    pub active: Option<u8>,
+    pub enabled: Option<u8>,
+    pub shared: Option<u8>,
+    pub total: Option<i64>,
+    pub used: Option<i64>,
+    pub avail: Option<i64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ResourceUsage {
+    pub total: i64,
+    pub allocated: i64,
+    pub k3s_allocated: i64,
+    pub headroom: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HypervisorCapacity {
+    pub node: String,
+    pub status: String,
+    pub schedulable: bool,
+    pub cpu: ResourceUsage,
+    pub memory: ResourceUsage,
+    pub storage: ResourceUsage,
+    pub k3s_vms: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClusterCapacity {
+    pub cpu: ResourceUsage,
+    pub memory: ResourceUsage,
+    pub storage: ResourceUsage,
+    pub k3s_vms: usize,
+    pub nodes: Vec<HypervisorCapacity>,
+}
+
+pub(crate) async fn get_storages_for_node<S: AsRef<str>>(
+    client: reqwest::Client,
+    node: S,
+) -> anyhow::Result<ProxmoxData<Vec<StorageEntry>>> {
+    Ok(ProxmoxData {
+        data: proxmox::get(&client, &format!("/nodes/{}/storage", node.as_ref())).await?,
+    })
+}
+
+fn is_k3s_vm(vm: &VirtualMachineEntry) -> bool {
+    roles::assign_vm(vm).is_some()
+}
+
+impl ResourceUsage {
+    fn add(&mut self, other: &ResourceUsage) {
+        self.total += other.total;
+        self.allocated += other.allocated;
+        self.k3s_allocated += other.k3s_allocated;
+        self.headroom += other.headroom;
+    }
+}
+
+pub(crate) async fn get_capacity(
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<ClusterCapacity>> {
+    let nodes = get_nodes(client.clone()).await?.data;
+
+    let mut capacity = ClusterCapacity {
+        cpu: ResourceUsage::default(),
+        memory: ResourceUsage::default(),
+        storage: ResourceUsage::default(),
+        k3s_vms: 0,
+        nodes: vec![],
+    };
+
+    // Shared storages are reported by every node, only count them once cluster-wide.
+    let mut seen_shared_storages = HashSet::new();
+
+    for node in nodes {
+        if node.status != "online" {
+            capacity.nodes.push(HypervisorCapacity {
+                node: node.node,
+                status: node.status,
+                schedulable: false,
+                cpu: ResourceUsage::default(),
+                memory: ResourceUsage::default(),
+                storage: ResourceUsage::default(),
+                k3s_vms: 0,
+            });
+            continue;
+        }
+
+        let vms: Vec<VirtualMachineEntry> = get_all_vms_for_node(client.clone(), &node.node)
+            .await?
+            .data
+            .into_iter()
+            .filter(|vm| vm.template.is_none_or(|template| template == 0))
+            .collect();
+
+        let storages = get_storages_for_node(client.clone(), &node.node)
+            .await?
+            .data;
+
+        let running_vms = vms.iter().filter(|vm| vm.status == "running");
+
+        let mut cpu = ResourceUsage {
+            total: node.maxcpu as i64,
+            ..Default::default()
+        };
+        let mut memory = ResourceUsage {
+            total: node.maxmem,
+            ..Default::default()
+        };
+
+        for vm in running_vms {
+            let vm_cpus = vm.cpus.unwrap_or_default() as i64;
+            let vm_memory = vm.maxmem.unwrap_or_default();
+
+            cpu.allocated += vm_cpus;
+            memory.allocated += vm_memory;
+
+            if is_k3s_vm(vm) {
+                cpu.k3s_allocated += vm_cpus;
+                memory.k3s_allocated += vm_memory;
+            }
+        }
+
+        cpu.headroom = cpu.total - cpu.allocated;
+        memory.headroom = memory.total - memory.allocated;
+
+        let mut storage = ResourceUsage::default();
+        let mut cluster_storage = ResourceUsage::default();
+
+        for entry in storages
+            .iter()
+            .filter(|entry| entry.active.unwrap_or(1) == 1 && entry.enabled.unwrap_or(1) == 1)
+        {
+            let usage = ResourceUsage {
+                total: entry.total.unwrap_or_default(),
+                allocated: entry.used.unwrap_or_default(),
+                k3s_allocated: 0,
+                headroom: entry.avail.unwrap_or_default(),
+            };
+
+            storage.add(&usage);
+
+            if entry.shared.unwrap_or_default() == 0
+                || seen_shared_storages.insert(entry.storage.clone())
+            {
+                cluster_storage.add(&usage);
+            }
+        }
+
+        storage.k3s_allocated = vms
+            .iter()
+            .filter(|vm| is_k3s_vm(vm))
+            .map(|vm| vm.maxdisk.unwrap_or_default())
+            .sum();
+        cluster_storage.k3s_allocated = storage.k3s_allocated;
+
+        let k3s_vms = vms.iter().filter(|vm| is_k3s_vm(vm)).count();
+
+        capacity.cpu.add(&cpu);
+        capacity.memory.add(&memory);
+        capacity.storage.add(&cluster_storage);
+        capacity.k3s_vms += k3s_vms;
+
+        capacity.nodes.push(HypervisorCapacity {
+            schedulable: ha::is_schedulable(&node.node),
+            node: node.node,
+            status: node.status,
+            cpu,
+            memory,
+            storage,
+            k3s_vms,
+        });
+    }
+
+    Ok(Json(capacity))
+}
@@ -0,0 +1,288 @@
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use mktemp::Temp;
+use tokio::process::Command;
+
+use crate::{
+    alerts, clusters::default_cluster_name, commands, logging, metrics, signer::SIGNERS, status,
+    CONFIG,
+};
+
+const EXPIRY_METRIC: &str = "k3s_helper_certificate_expiry_seconds";
+const EXPIRY_METRIC_HELP: &str = "Seconds until the certificate expires";
+const OUTSTANDING_METRIC: &str = "k3s_helper_outstanding_certificates_expiry_seconds";
+const OUTSTANDING_BUCKETS: &[f64] = &[
+    86400.0,
+    7.0 * 86400.0,
+    30.0 * 86400.0,
+    90.0 * 86400.0,
+    180.0 * 86400.0,
+    365.0 * 86400.0,
+    3650.0 * 86400.0,
+];
+
+#[derive(Clone, Debug)]
+pub struct TrackedCertificate {
+    pub kind: &'static str,
+    pub cluster: String,
+    pub name: String,
+    pub not_after: i64,
+}
+
+pub(crate) async fn certificate_not_after(path: &str) -> anyhow::Result<i64> {
+    let output =
+        commands::output(Command::new("openssl").args(["x509", "-noout", "-enddate", "-in", path]))
+            .await?;
+
+    parse_openssl_enddate(&String::from_utf8_lossy(&output.stdout))
+        .context(format!("Unable to read expiry date of {path}"))
+}
+
+pub(crate) async fn certificate_pem_not_after(pem: &str) -> anyhow::Result<i64> {
+    let temp_file = Temp::new_file()?;
+
+    std::fs::write(&temp_file, pem)?;
+
+    certificate_not_after(&temp_file.as_path().display().to_string()).await
+}
+
+pub(crate) fn parse_openssl_enddate(output: &str) -> anyhow::Result<i64> {
+    let not_after = output
+        .trim()
+        .strip_prefix("notAfter=")
+        .context("Missing notAfter field")?;
+
+    parse_openssl_date(not_after)
+}
+
+pub(crate) fn parse_openssl_date(date: &str) -> anyhow::Result<i64> {
+    Ok(
+        NaiveDateTime::parse_from_str(date.trim(), "%b %e %H:%M:%S %Y GMT")?
+            .and_utc()
+            .timestamp(),
+    )
+}
+
+// The openssl index stores expiry dates as UTCTime (YYMMDDHHMMSSZ) or, after 2049, as
+// GeneralizedTime (YYYYMMDDHHMMSSZ).
+fn parse_index_date(date: &str) -> Option<i64> {
+    let format = if date.len() == 15 {
+        "%Y%m%d%H%M%SZ"
+    } else {
+        "%y%m%d%H%M%SZ"
+    };
+
+    NaiveDateTime::parse_from_str(date, format)
+        .ok()
+        .map(|date| date.and_utc().timestamp())
+}
+
+// Certificates of the first cluster keep their plain names so existing alerts and dashboards don't
+// change when more clusters are added.
+fn tracked_name(cluster: &str, name: String) -> String {
+    if cluster == default_cluster_name() {
+        name
+    } else {
+        format!("{cluster}/{name}")
+    }
+}
+
+// The configured index path overrides the one of the first cluster's signer.
+pub(crate) fn ca_index_path(cluster: &str) -> Option<PathBuf> {
+    CONFIG
+        .ca_index_path
+        .as_ref()
+        .filter(|_| cluster == default_cluster_name())
+        .map(PathBuf::from)
+        .or_else(|| SIGNERS.get(cluster)?.ca_index_path())
+}
+
+pub(crate) fn issued_certificates() -> anyhow::Result<Vec<TrackedCertificate>> {
+    let mut certificates = vec![];
+
+    for cluster in SIGNERS.keys() {
+        certificates.extend(cluster_issued_certificates(cluster)?);
+    }
+
+    Ok(certificates)
+}
+
+fn cluster_issued_certificates(cluster: &str) -> anyhow::Result<Vec<TrackedCertificate>> {
+    // Only openssl based signers keep an index of the certificates they issued.
+    let Some(index_path) = ca_index_path(cluster) else {
+        return Ok(vec![]);
+    };
+
+    let index = std::fs::read_to_string(&index_path)
+        .context(format!("Unable to read CA index {}", index_path.display()))?;
+
+    Ok(index
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            match fields.as_slice() {
+                ["V", expiry, _, serial, _, subject, ..] => Some(TrackedCertificate {
+                    kind: "issued",
+                    cluster: cluster.to_string(),
+                    name: tracked_name(cluster, format!("{subject} ({serial})")),
+                    not_after: parse_index_date(expiry)?,
+                }),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+pub(crate) async fn ca_certificates() -> anyhow::Result<Vec<TrackedCertificate>> {
+    let mut certificates = vec![];
+
+    for (cluster, signer) in SIGNERS.iter() {
+        for ca_certificate in signer.ca_chain().await? {
+            certificates.push(TrackedCertificate {
+                kind: if ca_certificate.name == "root-ca" {
+                    "root-ca"
+                } else {
+                    "intermediate-ca"
+                },
+                cluster: cluster.clone(),
+                not_after: certificate_pem_not_after(&ca_certificate.pem).await?,
+                name: tracked_name(cluster, ca_certificate.name),
+            });
+        }
+    }
+
+    Ok(certificates)
+}
+
+fn crl_path() -> PathBuf {
+    CONFIG
+        .crl_path
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(&CONFIG.certificates_path).join("crl.pem"))
+}
+
+async fn update_crl_metrics() -> anyhow::Result<()> {
+    let path = crl_path();
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let path = path.as_path().display().to_string();
+
+    let output =
+        commands::output(Command::new("openssl").args(["crl", "-noout", "-text", "-in", &path]))
+            .await?;
+
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    let last_update = text
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Last Update:"))
+        .context(format!("Unable to read last update of {path}"))?;
+
+    metrics::set_gauge(
+        "k3s_helper_crl_revoked_certificates",
+        "Certificates listed in the CRL",
+        &[],
+        text.matches("Serial Number:").count() as f64,
+    );
+    metrics::set_gauge(
+        "k3s_helper_crl_age_seconds",
+        "Seconds since the CRL was last updated",
+        &[],
+        (chrono::Utc::now().timestamp() - parse_openssl_date(last_update)?) as f64,
+    );
+
+    Ok(())
+}
+
+pub(crate) async fn tracked_certificates() -> anyhow::Result<Vec<TrackedCertificate>> {
+    let mut certificates = ca_certificates().await?;
+
+    certificates.extend(issued_certificates()?);
+
+    Ok(certificates)
+}
+
+pub(crate) async fn monitor_certificate_expiry() -> anyhow::Result<()> {
+    let mut expiring = HashSet::new();
+
+    loop {
+        let result = tracked_certificates().await;
+        status::record_job("certificate-expiry", &result);
+
+        match result {
+            Ok(certificates) => {
+                let now = chrono::Utc::now().timestamp();
+                let warning_threshold = now + CONFIG.certificate_expiry_warning_days * 86400;
+
+                metrics::clear_gauge(EXPIRY_METRIC);
+                metrics::clear_gauge(OUTSTANDING_METRIC);
+
+                let mut still_expiring = HashSet::new();
+
+                for certificate in &certificates {
+                    metrics::set_gauge(
+                        EXPIRY_METRIC,
+                        EXPIRY_METRIC_HELP,
+                        &[("kind", certificate.kind), ("name", &certificate.name)],
+                        (certificate.not_after - now) as f64,
+                    );
+
+                    if certificate.kind == "issued" {
+                        metrics::observe_histogram(
+                            OUTSTANDING_METRIC,
+                            "Time until expiry of the certificates issued by the helper CA",
+                            OUTSTANDING_BUCKETS,
+                            &[],
+                            (certificate.not_after - now) as f64,
+                        );
+                    }
+
+                    if certificate.not_after > warning_threshold {
+                        continue;
+                    }
+
+                    let alert = format!("certificate-expiry/{}", certificate.name);
+
+                    if !expiring.contains(&alert) {
+                        alerts::fire(
+                            &alert,
+                            &format!(
+                                "{} certificate {} expires at {}",
+                                certificate.kind,
+                                certificate.name,
+                                chrono::DateTime::from_timestamp(certificate.not_after, 0)
+                                    .map(|date| date.to_rfc3339())
+                                    .unwrap_or_default()
+                            ),
+                        )
+                        .await;
+                    }
+
+                    still_expiring.insert(alert);
+                }
+
+                for alert in expiring.difference(&still_expiring) {
+                    alerts::resolve(alert, "Certificate is no longer about to expire").await;
+                }
+
+                expiring = still_expiring;
+            }
+            Err(err) => {
+                logging::warn!("Unable to check certificate expiry: {err}");
+            }
+        }
+
+        if let Err(err) = update_crl_metrics().await {
+            logging::warn!("Unable to inspect the CRL: {err}");
+        }
+
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+    }
+}
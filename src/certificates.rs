@@ -1,15 +1,36 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, time::Instant};
 
-use axum::{routing::post, Json, Router};
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use base64::Engine;
 use mktemp::Temp;
 use serde::{Deserialize, Serialize};
-use tokio::process::Command;
 
-use crate::{error::AppResult, CONFIG};
+use crate::{
+    certificate_expiry::{certificate_pem_not_after, parse_openssl_date},
+    client_certificates::ClientCertificate,
+    cluster::resolve_caller,
+    clusters,
+    credentials::{self, ClusterScope},
+    error::AppResult,
+    metrics, registrations,
+    signer::{self, openssl_output, signer, CaCertificate, CertificateUsage},
+    CONFIG,
+};
 
 #[derive(Deserialize)]
 pub(crate) struct GenerateCertificateRequest {
     certificate_type: String,
+    #[serde(default = "clusters::default_cluster_name")]
+    cluster: String,
+    validity_hours: Option<i64>,
 }
 
 #[derive(Serialize)]
@@ -17,145 +38,858 @@ pub(crate) struct GenerateCertificateResponse {
     private_key: String,
     certificate_pem: String,
     certificate_chain: String,
+    not_after: i64,
+    renew_after: i64,
 }
 
-#[axum::debug_handler]
-pub(crate) async fn generate_certificate(
-    Json(request): Json<GenerateCertificateRequest>,
-) -> AppResult<Json<GenerateCertificateResponse>> {
+#[derive(Default, Deserialize)]
+pub(crate) struct GenerateSvidRequest {
+    validity_hours: Option<i64>,
+}
+
+#[derive(Default, Deserialize)]
+pub(crate) struct GenerateKubeletCertificateRequest {
+    validity_hours: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RenewCertificateRequest {
+    certificate_pem: String,
+    #[serde(default = "clusters::default_cluster_name")]
+    cluster: String,
+    timestamp: i64,
+    // Base64 encoded SHA-256 signature of `renew:<timestamp>` made with the certificate's key.
+    signature: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GenerateCertificateBatchRequest {
+    certificates: Vec<GenerateCertificateRequest>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct GenerateCertificateBatchItem {
+    certificate_type: String,
+    #[serde(flatten)]
+    certificate: Option<GenerateCertificateResponse>,
+    error: Option<String>,
+}
+
+pub(crate) struct CertificateSpec {
+    pub cluster: String,
+    pub profile: String,
+    pub subject: String,
+    pub validity: chrono::Duration,
+    pub usage: CertificateUsage,
+}
+
+async fn sign_certificate(spec: &CertificateSpec) -> anyhow::Result<GenerateCertificateResponse> {
+    let started_at = Instant::now();
+
+    let result = signer::serialized(sign_certificate_with_signer(spec)).await;
+
+    metrics::observe_histogram(
+        "k3s_helper_certificate_sign_duration_seconds",
+        "Time spent signing certificates",
+        metrics::DURATION_BUCKETS,
+        &[("profile", &spec.profile)],
+        started_at.elapsed().as_secs_f64(),
+    );
+
+    match &result {
+        Ok(_) => metrics::increment_counter(
+            "k3s_helper_certificates_issued_total",
+            "Certificates issued by the helper CA",
+            &[("profile", &spec.profile)],
+        ),
+        Err(_) => metrics::increment_counter(
+            "k3s_helper_certificate_sign_failures_total",
+            "Certificate signing attempts which failed",
+            &[("profile", &spec.profile)],
+        ),
+    }
+
+    result
+}
+
+async fn sign_certificate_with_signer(
+    spec: &CertificateSpec,
+) -> anyhow::Result<GenerateCertificateResponse> {
     let temp_dir = Temp::new_dir()?;
 
-    let ca_paths = PathBuf::from(&CONFIG.certificates_path);
-
-    let private_key_path = temp_dir
-        .join("private.key")
-        .as_path()
-        .display()
-        .to_string()
-        .clone();
-
-    Command::new("openssl")
-        .args(&[
-            "ecparam",
-            "-name",
-            "prime256v1",
-            "-genkey",
-            "-noout",
-            "-out",
-            &private_key_path,
-        ])
-        .output()
-        .await?;
+    let temp_path = |name: &str| temp_dir.join(name).as_path().display().to_string();
+    let private_key_path = temp_path("private.key");
+    let csr_path = temp_path("certificate.csr");
+
+    openssl_output(&[
+        "ecparam",
+        "-name",
+        "prime256v1",
+        "-genkey",
+        "-noout",
+        "-out",
+        &private_key_path,
+    ])
+    .await?;
 
     let private_key = std::fs::read_to_string(&private_key_path)?;
 
-    let certificate_type = request.certificate_type.replace("/", "-");
-    let timestamp = chrono::Utc::now().timestamp();
-    let subject_name = format!("/CN=k3s-{certificate_type}@{timestamp}");
-
-    let csr_path = temp_dir
-        .join("certificate.csr")
-        .as_path()
-        .display()
-        .to_string()
-        .clone();
-
-    Command::new("openssl")
-        .args(&[
-            "req",
-            "-new",
-            "-nodes",
-            "-subj",
-            &subject_name,
-            "-key",
-            &private_key_path,
-            "-out",
-            &csr_path,
-        ])
-        .output()
-        .await?;
+    let mut csr_args = vec![
+        "req".to_string(),
+        "-new".to_string(),
+        "-nodes".to_string(),
+        "-utf8".to_string(),
+        "-subj".to_string(),
+        spec.subject.clone(),
+        "-key".to_string(),
+        private_key_path,
+        "-out".to_string(),
+        csr_path.clone(),
+    ];
 
-    let certificate_path = temp_dir
-        .join("certificate.pem")
-        .as_path()
-        .display()
-        .to_string()
-        .clone();
-
-    let intermediate_private_key_path = ca_paths
-        .join("intermediate-ca.key")
-        .as_path()
-        .display()
-        .to_string()
-        .clone();
-
-    let intermediate_certificate_path = ca_paths
-        .join("intermediate-ca.pem")
-        .as_path()
-        .display()
-        .to_string()
-        .clone();
-
-    let ca_config_path = ca_paths
-        .join(".ca")
-        .join("config")
-        .as_path()
-        .display()
-        .to_string()
-        .clone();
-
-    Command::new("openssl")
-        .args(&[
-            "ca",
-            "-batch",
-            "-notext",
-            "-days",
-            "3700",
-            "-in",
-            &csr_path,
-            "-out",
-            &certificate_path,
-            "-keyfile",
-            &intermediate_private_key_path,
-            "-cert",
-            &intermediate_certificate_path,
-            "-config",
-            &ca_config_path,
-            "-extensions",
-            "v3_ca",
-        ])
-        .output()
-        .await?;
+    // Signers which copy extensions from the request (e.g. Vault) need the names in the CSR.
+    if let CertificateUsage::Leaf { subject_alt_names } = &spec.usage {
+        csr_args.extend([
+            "-addext".to_string(),
+            format!("subjectAltName={}", subject_alt_names.join(",")),
+        ]);
+    }
 
-    let root_ca_pem_path = ca_paths
-        .join("root-ca.pem")
-        .as_path()
-        .display()
-        .to_string()
-        .clone();
+    openssl_output(&csr_args.iter().map(String::as_str).collect::<Vec<_>>()).await?;
 
-    let root_ca_pem = std::fs::read_to_string(&root_ca_pem_path)?;
+    let csr = std::fs::read_to_string(&csr_path)?;
 
-    let intermediate_ca_pem_path = ca_paths
-        .join("intermediate-ca.pem")
-        .as_path()
-        .display()
-        .to_string()
-        .clone();
+    // Backdate slightly so clients with a lagging clock accept the certificate right away.
+    let issued_at = chrono::Utc::now();
+    let not_before = issued_at - chrono::Duration::minutes(5);
+
+    let signer = signer(&spec.cluster)?;
+
+    let certificate_pem = signer
+        .sign(&csr, &spec.usage, not_before, issued_at + spec.validity)
+        .await?;
 
-    let intermediate_ca_pem = std::fs::read_to_string(&intermediate_ca_pem_path)?;
+    let mut certificate_chain = certificate_pem.clone();
 
-    let certificate_pem = std::fs::read_to_string(&certificate_path)?;
+    for ca_certificate in signer.ca_chain().await? {
+        certificate_chain.push_str(&ca_certificate.pem);
+    }
 
-    let certificate_chain = format!("{certificate_pem}{intermediate_ca_pem}{root_ca_pem}");
+    // The signer may cap the requested lifetime, trust the certificate rather than the request.
+    let not_after = certificate_pem_not_after(&certificate_pem).await?;
 
-    Ok(Json(GenerateCertificateResponse {
+    Ok(GenerateCertificateResponse {
         private_key,
         certificate_pem,
         certificate_chain,
-    }))
+        not_after,
+        // Renewing after two thirds of the lifetime leaves room for a few failed attempts.
+        renew_after: issued_at.timestamp() + (not_after - issued_at.timestamp()) * 2 / 3,
+    })
+}
+
+// Anything outside of the allowlist is rejected rather than sanitized, a type must never be able to
+// sneak extra attributes into the subject.
+fn validate_certificate_type(certificate_type: &str) -> anyhow::Result<()> {
+    let valid_characters = !certificate_type.is_empty()
+        && certificate_type.chars().all(|character| {
+            character.is_ascii_lowercase()
+                || character.is_ascii_digit()
+                || character == '-'
+                || character == '/'
+        });
+
+    if !valid_characters {
+        anyhow::bail!(
+            "Invalid certificate type {certificate_type:?}, only lowercase letters, digits, dashes and slashes are allowed"
+        );
+    }
+
+    if !CONFIG
+        .certificate_types
+        .iter()
+        .any(|allowed| allowed == certificate_type)
+    {
+        anyhow::bail!("Certificate type {certificate_type} is not allowed");
+    }
+
+    Ok(())
+}
+
+// Requested validities are capped rather than refused, a certificate that is already expired never
+// makes sense.
+fn checked_validity(hours: i64, max_hours: i64) -> anyhow::Result<chrono::Duration> {
+    if hours <= 0 {
+        anyhow::bail!("Certificate validity must be positive, got {hours} hours");
+    }
+
+    Ok(chrono::Duration::hours(hours.min(max_hours)))
+}
+
+fn subject_attribute(name: &str, value: &str) -> anyhow::Result<String> {
+    if value.is_empty() || value.contains(['/', '=', '\\']) {
+        anyhow::bail!("Invalid {name} {value:?} for a certificate subject");
+    }
+
+    Ok(format!("/{name}={value}"))
+}
+
+// The O and OU attributes come first so the subject reads from the organization down to the CN.
+fn certificate_subject(
+    certificate_type: &str,
+    cluster: &str,
+    timestamp: i64,
+) -> anyhow::Result<String> {
+    let mut subject = String::new();
+
+    if let Some(organization) = &CONFIG.certificate_subject_organization {
+        subject.push_str(&subject_attribute("O", organization)?);
+    }
+
+    if let Some(organizational_unit) = &CONFIG.certificate_subject_organizational_unit {
+        subject.push_str(&subject_attribute("OU", organizational_unit)?);
+    }
+
+    subject.push_str(
+        &CONFIG
+            .certificate_subject_template
+            .replace("{certificate_type}", certificate_type)
+            .replace("{cluster}", cluster)
+            .replace("{timestamp}", &timestamp.to_string()),
+    );
+
+    Ok(subject)
+}
+
+async fn issue_certificate(
+    request: &GenerateCertificateRequest,
+) -> anyhow::Result<GenerateCertificateResponse> {
+    let validity =
+        validate_certificate_type(&request.certificate_type).and_then(|()| {
+            match request.validity_hours {
+                Some(hours) => checked_validity(hours, CONFIG.certificate_max_validity_hours),
+                None => Ok(chrono::Duration::days(3700)),
+            }
+        });
+
+    let validity = match validity {
+        Ok(validity) => validity,
+        Err(err) => {
+            metrics::increment_counter(
+                "k3s_helper_certificate_validation_failures_total",
+                "Certificate requests rejected during validation",
+                &[("profile", "ca")],
+            );
+
+            return Err(err);
+        }
+    };
+
+    let cluster =
+        clusters::find(&request.cluster).context(format!("Unknown cluster {}", request.cluster))?;
+
+    let certificate_type = request.certificate_type.replace('/', "-");
+    let timestamp = chrono::Utc::now().timestamp();
+
+    sign_certificate(&CertificateSpec {
+        cluster: cluster.name.clone(),
+        profile: certificate_type.clone(),
+        subject: certificate_subject(&certificate_type, &cluster.name, timestamp)?,
+        validity,
+        usage: CertificateUsage::Ca,
+    })
+    .await
+}
+
+// k3s uses the CA files it finds in its `server/tls` directory instead of generating its own, the
+// first server of a new cluster gets them before it starts. Paths are relative to that directory.
+pub(crate) async fn cluster_ca_files(cluster: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut files = vec![];
+
+    for certificate_type in &CONFIG.certificate_types {
+        let issued = issue_certificate(&GenerateCertificateRequest {
+            certificate_type: certificate_type.clone(),
+            cluster: cluster.to_string(),
+            validity_hours: None,
+        })
+        .await
+        .context(format!(
+            "Unable to issue the {certificate_type} certificate"
+        ))?;
+
+        files.push((format!("{certificate_type}.crt"), issued.certificate_chain));
+        files.push((format!("{certificate_type}.key"), issued.private_key));
+    }
+
+    Ok(files)
+}
+
+pub(crate) async fn issue_svid(
+    cluster: &str,
+    trust_domain: &str,
+    vmid: &str,
+    hostname: Option<&str>,
+    ip: &str,
+    validity: chrono::Duration,
+) -> anyhow::Result<GenerateCertificateResponse> {
+    let spiffe_id = format!("spiffe://{trust_domain}/node/{vmid}");
+
+    let mut subject_alt_names = vec![format!("URI:{spiffe_id}"), format!("IP:{ip}")];
+
+    if let Some(hostname) = hostname {
+        subject_alt_names.push(format!("DNS:{hostname}"));
+    }
+
+    sign_certificate(&CertificateSpec {
+        cluster: cluster.to_string(),
+        profile: "svid".to_string(),
+        subject: format!("/CN={}", hostname.unwrap_or(vmid)),
+        validity,
+        usage: CertificateUsage::Leaf { subject_alt_names },
+    })
+    .await
+}
+
+pub(crate) async fn generate_svid(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    certificate: Option<Extension<ClientCertificate>>,
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+    request: Option<Json<GenerateSvidRequest>>,
+) -> AppResult<Json<GenerateCertificateResponse>> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+
+    // The SPIFFE ID is derived from the caller's IPAM entry, never from the request itself.
+    let caller = resolve_caller(client, addr, certificate).await?;
+
+    let vmid = caller.vmid.clone().context("Caller has no VM id")?;
+
+    registrations::ensure_approved(&vmid)?;
+
+    let cluster = clusters::cluster_of(&caller).context("Caller is not part of any cluster")?;
+
+    credentials::authorize(&scope, &cluster)?;
+
+    Ok(Json(
+        issue_svid(
+            &cluster,
+            &CONFIG.spiffe_trust_domain,
+            &vmid,
+            caller.hostname.as_deref(),
+            &caller.ip,
+            // Callers may ask for shorter lived SVIDs, never longer lived ones.
+            checked_validity(
+                request.validity_hours.unwrap_or(CONFIG.svid_validity_hours),
+                CONFIG.svid_validity_hours,
+            )?,
+        )
+        .await?,
+    ))
+}
+
+// Follows the Kubernetes conventions so the Node authorizer and the NodeRestriction admission plugin
+// recognize the holder, the node name is the caller's hostname from the IPAM.
+pub(crate) async fn generate_kubelet_certificate(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    certificate: Option<Extension<ClientCertificate>>,
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+    request: Option<Json<GenerateKubeletCertificateRequest>>,
+) -> AppResult<Json<GenerateCertificateResponse>> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+
+    let caller = resolve_caller(client, addr, certificate).await?;
+
+    let vmid = caller.vmid.clone().context("Caller has no VM id")?;
+
+    registrations::ensure_approved(&vmid)?;
+
+    let cluster = clusters::cluster_of(&caller).context("Caller is not part of any cluster")?;
+
+    credentials::authorize(&scope, &cluster)?;
+
+    // Kubernetes node names are the lowercase hostnames, the Node authorizer compares them as is.
+    let hostname = caller
+        .hostname
+        .as_deref()
+        .context("Caller has no hostname")?
+        .to_lowercase();
+
+    let validity_hours = request
+        .validity_hours
+        .unwrap_or(CONFIG.kubelet_certificate_validity_hours);
+
+    if validity_hours <= 0 {
+        return Err(anyhow::Error::msg(format!(
+            "Certificate validity must be positive, got {validity_hours} hours"
+        ))
+        .into());
+    }
+
+    Ok(Json(
+        sign_certificate(&CertificateSpec {
+            cluster,
+            profile: "kubelet".to_string(),
+            subject: format!(
+                "{}{}",
+                subject_attribute("O", "system:nodes")?,
+                subject_attribute("CN", &format!("system:node:{hostname}"))?
+            ),
+            validity: chrono::Duration::hours(
+                validity_hours.min(CONFIG.kubelet_certificate_max_validity_hours),
+            ),
+            usage: CertificateUsage::Client,
+        })
+        .await?,
+    ))
+}
+
+// Splits on the separator characters which are not escaped, the escapes are kept for unescaping.
+fn split_unescaped(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (index, character) in value.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if character == separator => {
+                parts.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&value[start..]);
+    parts
+}
+
+fn unescape_rfc2253(value: &str) -> anyhow::Result<String> {
+    if value.starts_with('#') {
+        anyhow::bail!("Hex encoded subject attributes cannot be renewed");
+    }
+
+    let mut bytes = vec![];
+    let mut characters = value.chars();
+
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            bytes.extend(character.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+
+        let escaped = characters.next().context("Dangling escape in subject")?;
+
+        // Either a special character or the two hex digits of a byte.
+        match escaped.to_digit(16) {
+            Some(high) => {
+                let low = characters
+                    .next()
+                    .and_then(|low| low.to_digit(16))
+                    .context("Invalid hex escape in subject")?;
+
+                bytes.push((high * 16 + low) as u8);
+            }
+            None => bytes.extend(escaped.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+// RFC 2253 lists the most specific RDN first, openssl -subj expects the opposite with `/` between
+// RDNs and `+` between the attributes of a multi-valued one.
+fn rfc2253_to_subj(subject: &str) -> anyhow::Result<String> {
+    let mut subj = String::new();
+
+    for rdn in split_unescaped(subject, ',').into_iter().rev() {
+        let mut attributes = vec![];
+
+        for attribute in split_unescaped(rdn, '+') {
+            let (name, value) = attribute
+                .split_once('=')
+                .context(format!("Invalid subject attribute {attribute:?}"))?;
+
+            let value = unescape_rfc2253(value)?
+                .replace('\\', "\\\\")
+                .replace('/', "\\/")
+                .replace('+', "\\+");
+
+            attributes.push(format!("{}={value}", name.trim()));
+        }
+
+        subj.push('/');
+        subj.push_str(&attributes.join("+"));
+    }
+
+    Ok(subj)
+}
+
+// Renewal only requires proving possession of a valid certificate's key, the new certificate keeps
+// the subject, subject alternative names and lifetime of the current one.
+async fn validate_renewal(request: &RenewCertificateRequest) -> anyhow::Result<CertificateSpec> {
+    if (chrono::Utc::now().timestamp() - request.timestamp).abs() > 300 {
+        anyhow::bail!("Renewal request timestamp is too far from the current time");
+    }
+
+    let temp_dir = Temp::new_dir()?;
+
+    let temp_path = |name: &str| temp_dir.join(name).as_path().display().to_string();
+    let certificate_path = temp_path("certificate.pem");
+    let public_key_path = temp_path("public.pem");
+    let message_path = temp_path("message");
+    let signature_path = temp_path("signature");
+    let root_ca_path = temp_path("root-ca.pem");
+    let intermediate_ca_path = temp_path("intermediate-ca.pem");
+
+    std::fs::write(&certificate_path, &request.certificate_pem)?;
+    std::fs::write(&message_path, format!("renew:{}", request.timestamp))?;
+    std::fs::write(
+        &signature_path,
+        base64::engine::general_purpose::STANDARD.decode(&request.signature)?,
+    )?;
+
+    let mut ca_chain = signer(&request.cluster)?.ca_chain().await?;
+    let root_ca = ca_chain.pop().context("The signer has no CA certificate")?;
+
+    std::fs::write(&root_ca_path, root_ca.pem)?;
+    std::fs::write(
+        &intermediate_ca_path,
+        ca_chain
+            .into_iter()
+            .map(|ca_certificate| ca_certificate.pem)
+            .collect::<String>(),
+    )?;
+
+    let mut verify_args = vec![
+        "verify",
+        "-CAfile",
+        &root_ca_path,
+        "-untrusted",
+        &intermediate_ca_path,
+    ];
+
+    // A revoked certificate must not be able to renew itself into a fresh one.
+    if let Some(crl_path) = &CONFIG.crl_path {
+        verify_args.extend(["-crl_check", "-CRLfile", crl_path]);
+    }
+
+    verify_args.push(&certificate_path);
+
+    openssl_output(&verify_args)
+        .await
+        .context("Certificate was not issued by this CA, is expired or is revoked")?;
+
+    let public_key =
+        openssl_output(&["x509", "-pubkey", "-noout", "-in", &certificate_path]).await?;
+    std::fs::write(&public_key_path, public_key)?;
+
+    openssl_output(&[
+        "dgst",
+        "-sha256",
+        "-verify",
+        &public_key_path,
+        "-signature",
+        &signature_path,
+        &message_path,
+    ])
+    .await
+    .context("Invalid proof of possession signature")?;
+
+    let basic_constraints = openssl_output(&[
+        "x509",
+        "-noout",
+        "-ext",
+        "basicConstraints",
+        "-in",
+        &certificate_path,
+    ])
+    .await?;
+
+    let is_ca = basic_constraints.contains("CA:TRUE");
+
+    let details = openssl_output(&[
+        "x509",
+        "-noout",
+        "-subject",
+        "-startdate",
+        "-enddate",
+        "-nameopt",
+        "RFC2253,-esc_msb",
+        "-in",
+        &certificate_path,
+    ])
+    .await?;
+
+    let field = |prefix: &str| {
+        details
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix))
+            .map(|value| value.trim().to_string())
+            .context(format!("Unable to read {prefix} of the certificate"))
+    };
+
+    let subject = rfc2253_to_subj(&field("subject=")?)?;
+
+    let lifetime = parse_openssl_date(&field("notAfter=")?)?
+        - parse_openssl_date(&field("notBefore=")?)?
+        - 300;
+
+    // Short-lived CAs from `/generate` are renewed as CAs, with the same subject and lifetime.
+    if is_ca {
+        return Ok(CertificateSpec {
+            cluster: request.cluster.clone(),
+            profile: "renewal".to_string(),
+            subject,
+            validity: chrono::Duration::seconds(lifetime),
+            usage: CertificateUsage::Ca,
+        });
+    }
+
+    let extended_key_usage = openssl_output(&[
+        "x509",
+        "-noout",
+        "-ext",
+        "extendedKeyUsage",
+        "-in",
+        &certificate_path,
+    ])
+    .await?;
+
+    // Client only certificates (e.g. kubelet ones) must not come back usable as server ones.
+    if !extended_key_usage.contains("TLS Web Server Authentication") {
+        return Ok(CertificateSpec {
+            cluster: request.cluster.clone(),
+            profile: "renewal".to_string(),
+            subject,
+            validity: chrono::Duration::seconds(lifetime),
+            usage: CertificateUsage::Client,
+        });
+    }
+
+    let subject_alt_names = openssl_output(&[
+        "x509",
+        "-noout",
+        "-ext",
+        "subjectAltName",
+        "-in",
+        &certificate_path,
+    ])
+    .await?
+    .lines()
+    .skip(1)
+    .flat_map(|line| line.split(','))
+    .map(|name| name.trim().replace("IP Address:", "IP:"))
+    .filter(|name| !name.is_empty())
+    .collect::<Vec<_>>();
+
+    Ok(CertificateSpec {
+        cluster: request.cluster.clone(),
+        profile: "renewal".to_string(),
+        subject,
+        validity: chrono::Duration::seconds(lifetime),
+        usage: CertificateUsage::Leaf { subject_alt_names },
+    })
+}
+
+async fn renew_certificate(
+    request: &RenewCertificateRequest,
+) -> anyhow::Result<GenerateCertificateResponse> {
+    let spec = match validate_renewal(request).await {
+        Ok(spec) => spec,
+        Err(err) => {
+            metrics::increment_counter(
+                "k3s_helper_certificate_validation_failures_total",
+                "Certificate requests rejected during validation",
+                &[("profile", "renewal")],
+            );
+
+            return Err(err);
+        }
+    };
+
+    sign_certificate(&spec).await
+}
+
+pub(crate) async fn renew(
+    scope: Option<Extension<ClusterScope>>,
+    Json(request): Json<RenewCertificateRequest>,
+) -> AppResult<Json<GenerateCertificateResponse>> {
+    credentials::authorize(&scope, &request.cluster)?;
+
+    Ok(Json(renew_certificate(&request).await?))
+}
+
+#[axum::debug_handler]
+pub(crate) async fn generate_certificate(
+    scope: Option<Extension<ClusterScope>>,
+    Json(request): Json<GenerateCertificateRequest>,
+) -> AppResult<Json<GenerateCertificateResponse>> {
+    credentials::authorize(&scope, &request.cluster)?;
+
+    Ok(Json(issue_certificate(&request).await?))
+}
+
+pub(crate) async fn generate_certificate_batch(
+    scope: Option<Extension<ClusterScope>>,
+    Json(request): Json<GenerateCertificateBatchRequest>,
+) -> AppResult<Json<Vec<GenerateCertificateBatchItem>>> {
+    let mut items = vec![];
+
+    for certificate_request in &request.certificates {
+        let result = match credentials::authorize(&scope, &certificate_request.cluster) {
+            Ok(()) => issue_certificate(certificate_request).await,
+            Err(err) => Err(err),
+        };
+
+        let (certificate, error) = match result {
+            Ok(certificate) => (Some(certificate), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        items.push(GenerateCertificateBatchItem {
+            certificate_type: certificate_request.certificate_type.clone(),
+            certificate,
+            error,
+        });
+    }
+
+    Ok(Json(items))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CaBundleQuery {
+    cluster: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CaBundleEntry {
+    // The fingerprint, stable for as long as the certificate stays in the bundle.
+    id: String,
+    name: String,
+    subject: String,
+    sha256_fingerprint: String,
+    not_before: i64,
+    not_after: i64,
+    pem: String,
+}
+
+async fn describe_ca_certificate(ca_certificate: CaCertificate) -> anyhow::Result<CaBundleEntry> {
+    let temp_file = Temp::new_file()?;
+    let path = &temp_file.as_path().display().to_string();
+
+    std::fs::write(path, &ca_certificate.pem)?;
+
+    let stdout = openssl_output(&[
+        "x509",
+        "-noout",
+        "-fingerprint",
+        "-sha256",
+        "-startdate",
+        "-enddate",
+        "-subject",
+        "-nameopt",
+        "RFC2253",
+        "-in",
+        path,
+    ])
+    .await?;
+
+    let field = |prefix: &str| {
+        stdout
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix))
+            .map(|value| value.trim().to_string())
+            .context(format!(
+                "Unable to read {prefix} of {}",
+                ca_certificate.name
+            ))
+    };
+
+    let sha256_fingerprint =
+        field("sha256 Fingerprint=").or_else(|_| field("SHA256 Fingerprint="))?;
+
+    Ok(CaBundleEntry {
+        id: sha256_fingerprint.replace(':', "").to_lowercase(),
+        name: ca_certificate.name.clone(),
+        subject: field("subject=")?,
+        sha256_fingerprint,
+        not_before: parse_openssl_date(&field("notBefore=")?)?,
+        not_after: parse_openssl_date(&field("notAfter=")?)?,
+        pem: ca_certificate.pem.clone(),
+    })
+}
+
+pub(crate) async fn get_ca_bundle(
+    scope: Option<Extension<ClusterScope>>,
+    Query(query): Query<CaBundleQuery>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let mut bundle = vec![];
+
+    let cluster = query
+        .cluster
+        .clone()
+        .unwrap_or_else(clusters::default_cluster_name);
+
+    credentials::authorize(&scope, &cluster)?;
+
+    for ca_certificate in signer(&cluster)?.ca_chain().await? {
+        bundle.push(describe_ca_certificate(ca_certificate).await?);
+    }
+
+    let wants_json = query.format.as_deref() == Some("json")
+        || headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json"));
+
+    if wants_json {
+        return Ok(Json(bundle).into_response());
+    }
+
+    let pem = bundle
+        .into_iter()
+        .map(|entry| entry.pem)
+        .collect::<Vec<_>>()
+        .join("");
+
+    Ok(([(header::CONTENT_TYPE, "application/x-pem-file")], pem).into_response())
 }
 
 pub(crate) fn create_router() -> Router<reqwest::Client> {
-    Router::new().route("/generate", post(generate_certificate))
+    Router::new()
+        .route("/generate", post(generate_certificate))
+        .route("/generate-batch", post(generate_certificate_batch))
+        .route("/ca-bundle", get(get_ca_bundle))
+        .route("/svid", post(generate_svid))
+        .route("/kubelet", post(generate_kubelet_certificate))
+        .route("/renew", post(renew))
+        .layer(middleware::from_fn(credentials::require_credentials))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_non_positive_validities() {
+        assert!(checked_validity(0, 24).is_err());
+        assert!(checked_validity(-1, 24).is_err());
+    }
+
+    #[test]
+    fn caps_validities_at_the_maximum() {
+        assert_eq!(
+            checked_validity(12, 24).unwrap(),
+            chrono::Duration::hours(12)
+        );
+        assert_eq!(
+            checked_validity(100_000, 24).unwrap(),
+            chrono::Duration::hours(24)
+        );
+    }
 }
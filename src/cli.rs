@@ -0,0 +1,259 @@
+use std::fmt::Write;
+
+use clap::{Subcommand, ValueEnum};
+use serde::Serialize;
+
+#[cfg(feature = "provisioning")]
+use crate::bootstrap;
+#[cfg(feature = "pki")]
+use crate::certificate_expiry;
+use crate::{config_file, discovery, events, permissions, support_bundle};
+
+#[derive(Debug, Clone, Subcommand)]
+pub(crate) enum Command {
+    /// List the discovered cluster members and their roles
+    Nodes,
+    /// List the tracked certificates and their expiry
+    #[cfg(feature = "pki")]
+    Certificates,
+    /// List the recorded events
+    Events,
+    /// Check the Proxmox privileges required by the enabled features
+    Doctor,
+    /// Gather status, logs, redacted configuration and diagnostics into a tarball for bug reports
+    SupportBundle {
+        /// Where to write the tarball, in the current directory by default
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Create a new cluster: its CA, servers cloned from a template and a kubeconfig
+    #[cfg(feature = "provisioning")]
+    BootstrapCluster {
+        /// TOML, YAML or JSON manifest, the values are asked for interactively when omitted
+        #[arg(long)]
+        manifest: Option<String>,
+    },
+    /// Inspect the configuration file format
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub(crate) enum ConfigCommand {
+    /// Print the JSON Schema of the configuration file
+    Schema,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum OutputFormat {
+    Json,
+    Yaml,
+    Table,
+}
+
+#[derive(Serialize)]
+struct NodeRow {
+    hostname: Option<String>,
+    ip: String,
+    vmid: Option<String>,
+    vnet: String,
+    role: Option<&'static str>,
+    pool: Option<String>,
+    proxy: bool,
+}
+
+#[cfg(feature = "pki")]
+#[derive(Serialize)]
+struct CertificateRow {
+    kind: &'static str,
+    name: String,
+    not_after: i64,
+    days_left: i64,
+}
+
+#[derive(Serialize)]
+struct EventRow {
+    timestamp: i64,
+    kind: String,
+    vmid: Option<String>,
+    message: String,
+}
+
+fn format_cell(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "-".to_string(),
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Columns follow the field order of the row struct, which is also what the json and yaml outputs
+// use, so every format exposes the same field names.
+fn render_table<T: Serialize>(rows: &[T]) -> anyhow::Result<String> {
+    let rows = rows
+        .iter()
+        .map(|row| {
+            let value = serde_json::to_value(row)?;
+            let object = value
+                .as_object()
+                .ok_or_else(|| anyhow::Error::msg("Table rows must be objects"))?;
+
+            Ok(object
+                .iter()
+                .map(|(name, value)| (name.clone(), format_cell(value)))
+                .collect::<Vec<_>>())
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let Some(first) = rows.first() else {
+        return Ok(String::new());
+    };
+
+    let headers = first
+        .iter()
+        .map(|(name, _)| name.to_uppercase())
+        .collect::<Vec<_>>();
+
+    let widths = headers
+        .iter()
+        .enumerate()
+        .map(|(index, header)| {
+            rows.iter()
+                .map(|row| row[index].1.chars().count())
+                .chain(std::iter::once(header.len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let mut output = String::new();
+
+    let mut write_line = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+
+        let _ = writeln!(output, "{}", line.trim_end());
+    };
+
+    write_line(headers.iter().map(String::as_str).collect());
+
+    for row in &rows {
+        write_line(row.iter().map(|(_, cell)| cell.as_str()).collect());
+    }
+
+    Ok(output)
+}
+
+fn render<T: Serialize>(rows: &[T], output: OutputFormat) -> anyhow::Result<String> {
+    Ok(match output {
+        OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(rows)?),
+        OutputFormat::Yaml => serde_yaml::to_string(rows)?,
+        OutputFormat::Table => render_table(rows)?,
+    })
+}
+
+pub(crate) async fn run(
+    command: &Command,
+    output: OutputFormat,
+    client: &reqwest::Client,
+) -> anyhow::Result<()> {
+    let mut failure = None;
+
+    let rendered = match command {
+        Command::Config {
+            command: ConfigCommand::Schema,
+        } => format!(
+            "{}\n",
+            serde_json::to_string_pretty(&config_file::schema())?
+        ),
+        Command::Nodes => {
+            let rows = discovery::discover_ipams(client)
+                .await?
+                .into_iter()
+                .filter(|ipam| ipam.assignment.is_some())
+                .map(|ipam| NodeRow {
+                    role: ipam
+                        .assignment
+                        .as_ref()
+                        .map(|assignment| assignment.role.as_str()),
+                    pool: ipam
+                        .assignment
+                        .as_ref()
+                        .and_then(|assignment| assignment.pool.clone()),
+                    proxy: discovery::is_proxy_member(&ipam),
+                    hostname: ipam.hostname,
+                    ip: ipam.ip,
+                    vmid: ipam.vmid,
+                    vnet: ipam.vnet,
+                })
+                .collect::<Vec<_>>();
+
+            render(&rows, output)?
+        }
+        #[cfg(feature = "pki")]
+        Command::Certificates => {
+            let now = chrono::Utc::now().timestamp();
+
+            let rows = certificate_expiry::tracked_certificates()
+                .await?
+                .into_iter()
+                .map(|certificate| CertificateRow {
+                    kind: certificate.kind,
+                    name: certificate.name,
+                    not_after: certificate.not_after,
+                    days_left: (certificate.not_after - now) / 86400,
+                })
+                .collect::<Vec<_>>();
+
+            render(&rows, output)?
+        }
+        Command::Events => {
+            let rows = events::list()
+                .into_iter()
+                .map(|event| EventRow {
+                    timestamp: event.timestamp,
+                    kind: event.kind,
+                    vmid: event.vmid,
+                    message: event.message,
+                })
+                .collect::<Vec<_>>();
+
+            render(&rows, output)?
+        }
+        Command::Doctor => {
+            let checks = permissions::check(client).await?;
+            let missing = checks.iter().filter(|check| !check.granted).count();
+
+            if missing > 0 {
+                failure = Some(format!("{missing} Proxmox privileges are missing"));
+            }
+
+            render(&checks, output)?
+        }
+        #[cfg(feature = "provisioning")]
+        Command::BootstrapCluster { manifest } => {
+            bootstrap::bootstrap(client, manifest.as_deref()).await?
+        }
+        Command::SupportBundle { path } => {
+            let (file_name, archive) = support_bundle::create(client).await?;
+            let path = path.clone().unwrap_or(file_name);
+
+            std::fs::write(&path, archive)?;
+
+            format!("{path}\n")
+        }
+    };
+
+    print!("{rendered}");
+
+    match failure {
+        Some(failure) => anyhow::bail!(failure),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,170 @@
+use std::net::SocketAddr;
+
+#[cfg(feature = "pki")]
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Request},
+    middleware::Next,
+    response::Response,
+};
+use base64::Engine;
+#[cfg(feature = "pki")]
+use mktemp::Temp;
+
+#[cfg(feature = "pki")]
+use crate::{discovery, signer, signer::openssl_output};
+use crate::{forwarded, CONFIG};
+
+// PEM certificate the caller presented to the TLS-terminating reverse proxy.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientCertificate(pub String);
+
+// nginx sends the URL-encoded PEM (`$ssl_client_escaped_cert`), Traefik the base64 DER without
+// armor.
+fn decode_certificate(value: &str) -> Option<String> {
+    let value = urlencoding::decode(value).ok()?;
+
+    if value.contains("-----BEGIN CERTIFICATE-----") {
+        return Some(value.into_owned());
+    }
+
+    let der = base64::engine::general_purpose::STANDARD
+        .decode(value.trim())
+        .ok()?;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+
+    let body = encoded
+        .as_bytes()
+        .chunks(64)
+        .map(|line| format!("{}\n", String::from_utf8_lossy(line)))
+        .collect::<String>();
+
+    Some(format!(
+        "-----BEGIN CERTIFICATE-----\n{body}-----END CERTIFICATE-----\n"
+    ))
+}
+
+// Must run before the forwarded client address is resolved, only the proxy itself is trusted to
+// vouch for a certificate.
+pub(crate) async fn extract_certificate(mut request: Request, next: Next) -> Response {
+    let Some(header) = &CONFIG.client_certificate_header else {
+        return next.run(request).await;
+    };
+
+    let trusted = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_some_and(|ConnectInfo(peer)| forwarded::is_trusted_proxy(peer.ip()));
+
+    let certificate = request
+        .headers()
+        .get(header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .and_then(decode_certificate);
+
+    request.extensions_mut().remove::<ClientCertificate>();
+
+    if let (true, Some(certificate)) = (trusted, certificate) {
+        request
+            .extensions_mut()
+            .insert(ClientCertificate(certificate));
+    }
+
+    next.run(request).await
+}
+
+// Only the CA of the cluster the node belongs to vouches for it, a certificate from another
+// cluster's CA identifies nobody here.
+#[cfg(feature = "pki")]
+async fn verify(certificate_path: &str, cluster: &str) -> anyhow::Result<()> {
+    let temp_dir = Temp::new_dir()?;
+    let temp_path = |name: &str| temp_dir.join(name).as_path().display().to_string();
+
+    let mut ca_chain = signer::signer(cluster)?.ca_chain().await?;
+    let root_ca = ca_chain
+        .pop()
+        .context(format!("Cluster {cluster} has no CA"))?;
+
+    let root_ca_path = temp_path("root-ca.pem");
+    let intermediate_ca_path = temp_path("intermediate-ca.pem");
+
+    std::fs::write(&root_ca_path, root_ca.pem)?;
+    std::fs::write(
+        &intermediate_ca_path,
+        ca_chain
+            .into_iter()
+            .map(|ca_certificate| ca_certificate.pem)
+            .collect::<String>(),
+    )?;
+
+    let mut verify_args = vec![
+        "verify",
+        "-CAfile",
+        &root_ca_path,
+        "-untrusted",
+        &intermediate_ca_path,
+    ];
+
+    if let Some(crl_path) = &CONFIG.crl_path {
+        verify_args.extend(["-crl_check", "-CRLfile", crl_path]);
+    }
+
+    verify_args.push(certificate_path);
+
+    openssl_output(&verify_args).await.context(format!(
+        "Client certificate was not issued by the CA of cluster {cluster}, is expired or is revoked"
+    ))?;
+
+    Ok(())
+}
+
+// The vmid comes from the SPIFFE ID the helper put in the certificate's SAN when issuing it, and
+// is only trusted once the CA of that VM's cluster verified the certificate.
+#[cfg(feature = "pki")]
+pub(crate) async fn certificate_vmid(certificate: &ClientCertificate) -> anyhow::Result<String> {
+    let temp_dir = Temp::new_dir()?;
+    let certificate_path = temp_dir
+        .join("certificate.pem")
+        .as_path()
+        .display()
+        .to_string();
+
+    std::fs::write(&certificate_path, &certificate.0)?;
+
+    let prefix = format!("URI:spiffe://{}/node/", CONFIG.spiffe_trust_domain);
+
+    let vmid = openssl_output(&[
+        "x509",
+        "-noout",
+        "-ext",
+        "subjectAltName",
+        "-in",
+        &certificate_path,
+    ])
+    .await?
+    .lines()
+    .flat_map(|line| line.split(','))
+    .find_map(|name| name.trim().strip_prefix(&prefix).map(str::to_string))
+    .context("Client certificate carries no node SPIFFE ID")?;
+
+    let cluster = discovery::subscribe()
+        .borrow()
+        .iter()
+        .find(|ipam| ipam.vmid.as_deref() == Some(vmid.as_str()))
+        .and_then(|ipam| ipam.assignment.as_ref())
+        .map(|assignment| assignment.cluster.clone())
+        .context(format!(
+            "VM {vmid} of the client certificate is not part of any cluster"
+        ))?;
+
+    verify(&certificate_path, &cluster).await?;
+
+    Ok(vmid)
+}
+
+// Without the PKI the helper never issued any certificate to trust.
+#[cfg(not(feature = "pki"))]
+pub(crate) async fn certificate_vmid(_certificate: &ClientCertificate) -> anyhow::Result<String> {
+    anyhow::bail!("Client certificates are only accepted when built with the pki feature")
+}
@@ -0,0 +1,230 @@
+use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+use anyhow::Context;
+use mktemp::Temp;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    cluster::NodeRole,
+    clusters::{self, K3sCluster},
+    lxc, proxmox,
+    roles::{NodeAssignment, NodeOs},
+    ssh, tls_san, token_rotation, CONFIG,
+};
+
+#[derive(Deserialize)]
+struct StorageConfig {
+    path: Option<String>,
+    content: Option<String>,
+}
+
+fn snippet_name(vmid: &str) -> String {
+    format!("k3s-proxmox-helper-{vmid}-user.yaml")
+}
+
+fn volume_id(vmid: &str) -> String {
+    format!(
+        "{}:snippets/{}",
+        CONFIG.cloud_init_snippets_storage,
+        snippet_name(vmid)
+    )
+}
+
+// Keys given with the request come on top of the ones every node gets.
+fn authorized_keys(extra: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut keys = match &CONFIG.cloud_init_ssh_keys_path {
+        Some(path) => std::fs::read_to_string(path)
+            .context(format!("Unable to read SSH keys {path}"))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect(),
+        None => vec![],
+    };
+
+    keys.extend(extra.iter().cloned());
+    keys.dedup();
+
+    Ok(keys)
+}
+
+// How a node gets into its cluster, the first server of a new cluster initializes it with the
+// token the others then join with, and with the CA files issued by the helper.
+pub(crate) enum Join {
+    Existing {
+        server: String,
+        token: String,
+    },
+    ClusterInit {
+        token: String,
+        ca_files: Vec<(String, String)>,
+    },
+}
+
+// Nodes join through the helper's proxy, or the cluster's API name, with the token read from a
+// server.
+pub(crate) async fn join_existing(
+    client: &reqwest::Client,
+    cluster: &K3sCluster,
+) -> anyhow::Result<Join> {
+    Ok(Join::Existing {
+        server: cluster.server_url()?,
+        token: token_rotation::cluster_token(client, &cluster.name).await?,
+    })
+}
+
+// The install script starts k3s with the configuration written beforehand.
+fn user_data(
+    cluster: &K3sCluster,
+    assignment: &NodeAssignment,
+    hostname: &str,
+    ssh_keys: &[String],
+    join: &Join,
+) -> anyhow::Result<String> {
+    if assignment.os != NodeOs::Linux {
+        anyhow::bail!("Only Linux nodes are provisioned with cloud-init user-data");
+    }
+
+    let config_path = format!("/etc/rancher/{}/config.yaml", cluster.distro.as_str());
+
+    let (mut config, ca_files) = match join {
+        Join::Existing { server, token } => (json!({ "server": server, "token": token }), &[][..]),
+        Join::ClusterInit { token, ca_files } => (
+            json!({ "cluster-init": true, "token": token }),
+            ca_files.as_slice(),
+        ),
+    };
+    config["node-name"] = json!(hostname.to_lowercase());
+
+    if assignment.role == NodeRole::Server {
+        config["tls-san"] = json!(tls_san::cluster_names(cluster)?);
+    }
+
+    let mut write_files = vec![json!({
+        "path": config_path,
+        "permissions": "0600",
+        "content": serde_yaml::to_string(&config)?,
+    })];
+
+    write_files.extend(ca_files.iter().map(|(name, content)| {
+        json!({
+            "path": format!("{}/server/tls/{name}", cluster.distro.data_dir()),
+            "permissions": "0600",
+            "content": content,
+        })
+    }));
+
+    let document = json!({
+        "hostname": hostname,
+        "preserve_hostname": false,
+        "ssh_authorized_keys": authorized_keys(ssh_keys)?,
+        "write_files": write_files,
+        "runcmd": [cluster.distro.install_command(assignment.role)],
+    });
+
+    Ok(format!(
+        "#cloud-config\n{}",
+        serde_yaml::to_string(&document)?
+    ))
+}
+
+async fn snippets_directory(client: &reqwest::Client) -> anyhow::Result<String> {
+    let storage = &CONFIG.cloud_init_snippets_storage;
+    let config: StorageConfig = proxmox::get(client, &format!("/storage/{storage}")).await?;
+
+    if !config
+        .content
+        .unwrap_or_default()
+        .split(',')
+        .any(|content| content == "snippets")
+    {
+        anyhow::bail!("Storage {storage} doesn't allow snippets");
+    }
+
+    let path = config
+        .path
+        .context(format!("Storage {storage} is not a directory storage"))?;
+
+    Ok(format!("{path}/snippets"))
+}
+
+// Proxmox has no API to upload snippets, the file is copied to the node over SSH. It holds the join
+// token, only root may read it and it never shows up on a command line.
+pub(crate) async fn attach(
+    client: &reqwest::Client,
+    node: &str,
+    vmid: &str,
+    assignment: &NodeAssignment,
+    hostname: &str,
+    ssh_keys: &[String],
+    join: &Join,
+) -> anyhow::Result<String> {
+    let cluster = clusters::find(&assignment.cluster)
+        .context(format!("Unknown cluster {}", assignment.cluster))?;
+    let user_data = user_data(cluster, assignment, hostname, ssh_keys, join)?;
+    let directory = snippets_directory(client).await?;
+    let path = format!("{directory}/{}", snippet_name(vmid));
+    let host = lxc::node_target(client, node).await?;
+
+    let temp_dir = Temp::new_dir()?;
+    let local_path = temp_dir.join("user-data").as_path().display().to_string();
+
+    // scp creates the remote file with the mode of the local one.
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&local_path)?
+        .write_all(user_data.as_bytes())?;
+
+    let context = format!("Unable to write the user-data of VM {vmid} on {node}");
+
+    ssh::run(client, &host, &format!("mkdir -p {directory}"))
+        .await
+        .context(context.clone())?;
+    ssh::scp_to(client, &host, &local_path, &path)
+        .await
+        .context(context)?;
+
+    let volume = volume_id(vmid);
+
+    let _: serde_json::Value = proxmox::put(
+        client,
+        &format!("/nodes/{node}/qemu/{vmid}/config"),
+        &[("cicustom", format!("user={volume}"))],
+    )
+    .await?;
+
+    Ok(volume)
+}
+
+pub(crate) async fn is_attached(
+    client: &reqwest::Client,
+    node: &str,
+    vmid: &str,
+) -> anyhow::Result<bool> {
+    let config: serde_json::Map<String, serde_json::Value> =
+        proxmox::get(client, &format!("/nodes/{node}/qemu/{vmid}/config")).await?;
+
+    Ok(config
+        .get("cicustom")
+        .and_then(serde_json::Value::as_str)
+        .is_some_and(|cicustom| cicustom.contains(&volume_id(vmid))))
+}
+
+// The snippet outlives the VM otherwise, along with the join token it holds.
+pub(crate) async fn remove(client: &reqwest::Client, node: &str, vmid: &str) -> anyhow::Result<()> {
+    let _: serde_json::Value = proxmox::delete(
+        client,
+        &format!(
+            "/nodes/{node}/storage/{}/content/{}",
+            CONFIG.cloud_init_snippets_storage,
+            urlencoding::encode(&volume_id(vmid))
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
@@ -1,18 +1,55 @@
-use std::net::SocketAddr;
+use std::{collections::HashMap, net::SocketAddr};
 
+use anyhow::Context;
 use axum::{
-    extract::{ConnectInfo, Path, State},
-    routing::get,
-    Json, Router,
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
 };
 use mktemp::Temp;
-use serde::{Deserialize, Serialize};
-use tokio::process::Command;
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::{error::AppResult, models::ProxmoxData, CONFIG};
+use crate::{
+    access, capacity,
+    client_certificates::{self, ClientCertificate},
+    clusters, connectivity,
+    credentials::{self, ClusterScope},
+    discovery,
+    error::AppResult,
+    ipam_gc,
+    models::ProxmoxData,
+    node_deltas::{self, NodesQuery},
+    node_history, placement, proxmox, proxmox_auth, proxmox_cache, registrations, response_cache,
+    roles::NodeAssignment,
+    route_limits, sdn, tasks, vms,
+};
+
+#[cfg(feature = "operator")]
+use axum::routing::delete;
+
+#[cfg(feature = "provisioning")]
+use crate::lxc;
+#[cfg(feature = "operator")]
+use crate::{
+    backup_policy, backups, cluster_restore, etcd, etcd_snapshots, k3s_certificates, maintenance,
+    preflight, restore,
+    ssh::{self, PinnedHostKey, SshTarget},
+    tls_san, token_rotation, vm_lifecycle,
+};
+#[cfg(feature = "proxy")]
+use crate::{
+    lb_export,
+    proxy::{self, BackendHealth},
+};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct IpamEntry {
+    // Stays the same across synchronizations and address changes, see `IpamEntry::stable_id`.
+    #[serde(default)]
+    pub id: String,
     pub zone: String,
     pub hostname: Option<String>,
     pub vmid: Option<String>,
@@ -20,9 +57,23 @@ pub struct IpamEntry {
     pub ip: String,
     pub mac: Option<String>,
     pub subnet: String,
+    #[serde(default)]
+    pub tags: Option<String>,
+    #[serde(default)]
+    pub assignment: Option<NodeAssignment>,
+}
+
+impl IpamEntry {
+    pub fn stable_id(&self) -> String {
+        match (&self.vmid, &self.hostname) {
+            (Some(vmid), _) => format!("vm-{vmid}-{}", self.vnet),
+            (None, Some(hostname)) => format!("{}-{hostname}", self.zone),
+            (None, None) => format!("{}-{}", self.zone, self.ip),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NodeEntry {
     pub cpu: f64,
     pub maxcpu: i32,
@@ -37,100 +88,302 @@ pub struct NodeEntry {
     pub ssl_fingerprint: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize)]
+struct NodeStatus {
+    loadavg: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProxmoxNode {
+    #[serde(flatten)]
+    pub node: NodeEntry,
+    pub loadavg: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct VirtualMachineEntry {
     pub status: String,
+    #[serde(deserialize_with = "deserialize_vmid")]
     pub vmid: i64,
     pub name: String,
     pub template: Option<u8>,
+    pub cpus: Option<f64>,
+    pub maxmem: Option<i64>,
+    pub maxdisk: Option<i64>,
+    pub tags: Option<String>,
+}
+
+// Container listings give the VMID as a string where qemu ones give a number.
+fn deserialize_vmid<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Vmid {
+        Number(i64),
+        String(String),
+    }
+
+    match Vmid::deserialize(deserializer)? {
+        Vmid::Number(vmid) => Ok(vmid),
+        Vmid::String(vmid) => vmid.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GuestKind {
+    Qemu,
+    Lxc,
+}
+
+// A qemu VM or an LXC container, Proxmox lists both with the same fields.
+#[derive(Clone, Debug, Serialize)]
+pub struct GuestEntry {
+    #[serde(flatten)]
+    pub guest: VirtualMachineEntry,
+    pub kind: GuestKind,
+}
+
+// Cluster-wide view of guests, covering both qemu VMs and LXC containers.
+#[derive(Debug, Deserialize)]
+pub(crate) struct VmResource {
+    pub vmid: u32,
+    pub node: String,
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status: Option<String>,
+    pub template: Option<u8>,
+    pub tags: Option<String>,
+    pub pool: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    Server,
+    Agent,
+}
+
+impl NodeRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeRole::Server => "server",
+            NodeRole::Agent => "agent",
+        }
+    }
 }
 
 pub(crate) async fn get_nodes(
     client: reqwest::Client,
 ) -> anyhow::Result<ProxmoxData<Vec<NodeEntry>>> {
-    Ok(client
-        .get(format!("{}/api2/json/nodes", &CONFIG.proxmox_api_url))
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?)
+    Ok(ProxmoxData {
+        data: proxmox_cache::NODES
+            .get_or_fetch("", || proxmox::get(&client, "/nodes"))
+            .await?,
+    })
 }
 
 pub(crate) async fn get_ipams_for_node<S: AsRef<str>>(
     client: reqwest::Client,
     node: S,
 ) -> anyhow::Result<ProxmoxData<Vec<IpamEntry>>> {
-    Ok(client
-        .get(format!(
-            "{}/api2/json/cluster/sdn/ipams/{}/status",
-            &CONFIG.proxmox_api_url,
-            node.as_ref()
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?)
+    let node = node.as_ref();
+    let path = format!("/cluster/sdn/ipams/{node}/status");
+
+    Ok(ProxmoxData {
+        data: proxmox_cache::IPAMS
+            .get_or_fetch(node, || proxmox::get(&client, &path))
+            .await?,
+    })
 }
 
 pub(crate) async fn get_all_vms_for_node<S: AsRef<str>>(
     client: reqwest::Client,
     node: S,
 ) -> anyhow::Result<ProxmoxData<Vec<VirtualMachineEntry>>> {
-    Ok(client
-        .get(format!(
-            "{}/api2/json/nodes/{}/qemu",
-            &CONFIG.proxmox_api_url,
-            node.as_ref()
-        ))
-        .send()
+    let node = node.as_ref();
+    let path = format!("/nodes/{node}/qemu");
+
+    Ok(ProxmoxData {
+        data: proxmox_cache::VMS
+            .get_or_fetch(node, || proxmox::get(&client, &path))
+            .await?,
+    })
+}
+
+pub(crate) async fn get_all_containers_for_node<S: AsRef<str>>(
+    client: reqwest::Client,
+    node: S,
+) -> anyhow::Result<ProxmoxData<Vec<VirtualMachineEntry>>> {
+    let node = node.as_ref();
+    let path = format!("/nodes/{node}/lxc");
+
+    Ok(ProxmoxData {
+        data: proxmox_cache::CONTAINERS
+            .get_or_fetch(node, || proxmox::get(&client, &path))
+            .await?,
+    })
+}
+
+// Members may be VMs or containers, whatever hosts them is looked up in both listings.
+pub(crate) async fn get_all_guests_for_node<S: AsRef<str>>(
+    client: reqwest::Client,
+    node: S,
+) -> anyhow::Result<Vec<GuestEntry>> {
+    let vms = get_all_vms_for_node(client.clone(), node.as_ref())
+        .await?
+        .data;
+    let containers = get_all_containers_for_node(client, node.as_ref())
         .await?
-        .error_for_status()?
-        .json()
-        .await?)
+        .data;
+
+    Ok(vms
+        .into_iter()
+        .map(|guest| GuestEntry {
+            guest,
+            kind: GuestKind::Qemu,
+        })
+        .chain(containers.into_iter().map(|guest| GuestEntry {
+            guest,
+            kind: GuestKind::Lxc,
+        }))
+        .collect())
+}
+
+pub(crate) async fn get_vm_resources(client: &reqwest::Client) -> anyhow::Result<Vec<VmResource>> {
+    proxmox::get_with_query(client, "/cluster/resources", &[("type", "vm")]).await
+}
+
+pub(crate) async fn find_vm<S: AsRef<str>>(
+    client: reqwest::Client,
+    vmid: S,
+) -> anyhow::Result<(String, VirtualMachineEntry)> {
+    let nodes = get_nodes(client.clone()).await?.data;
+
+    for node in nodes {
+        let vms = get_all_vms_for_node(client.clone(), &node.node).await?.data;
+
+        if let Some(vm) = vms
+            .into_iter()
+            .find(|vm| vm.vmid.to_string() == vmid.as_ref())
+        {
+            return Ok((node.node, vm));
+        }
+    }
+
+    Err(anyhow::Error::msg("VM not found"))
+}
+
+pub(crate) async fn find_vm_node<S: AsRef<str>>(
+    client: reqwest::Client,
+    vmid: S,
+) -> anyhow::Result<String> {
+    placement::resolve_node(&client, vmid.as_ref()).await
 }
 
 async fn get_nodes_infos(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    scope: Option<Extension<ClusterScope>>,
     State(client): State<reqwest::Client>,
-) -> AppResult<Json<Vec<IpamEntry>>> {
+    Query(query): Query<NodesQuery>,
+) -> AppResult<Response> {
+    // Members of other clusters are never handed out, a node must only ever join its own cluster.
+    // A caller that is part of no cluster gets none of them.
+    let caller_cluster = match scope {
+        Some(Extension(ClusterScope(cluster))) => Some(cluster),
+        None => clusters::cluster_of_ip(&addr.ip().to_canonical().to_string()),
+    };
+
+    let Some(caller_cluster) = caller_cluster else {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "The caller is not part of any cluster",
+        )
+            .into_response());
+    };
+
+    let same_cluster = |entry: &IpamEntry| {
+        entry
+            .assignment
+            .as_ref()
+            .is_some_and(|assignment| assignment.cluster == caller_cluster)
+    };
+
     let nodes = get_nodes(client.clone()).await?.data;
     let mut ipams = vec![];
 
     for node in nodes {
-        let vms = get_all_vms_for_node(client.clone(), &node.node).await?.data;
+        let guests = get_all_guests_for_node(client.clone(), &node.node).await?;
 
         ipams.extend(
-            get_ipams_for_node(client.clone(), &node.node)
+            discovery::discover_node_ipams(&client, &node.node)
                 .await?
-                .data
                 .into_iter()
-                .filter(
-                    |entry| entry.vnet == "vnet1", /*CONFIG.k3s_internal_network_interface*/
-                )
+                .filter(|entry| entry.assignment.is_some())
+                .filter(same_cluster)
                 .filter(|entry| addr.ip().to_string() != entry.ip)
                 .filter(|entry| entry.vmid.is_some())
                 .filter(|entry| {
-                    vms.iter()
-                        .find(|v| {
+                    guests
+                        .iter()
+                        .find(|g| {
                             entry
                                 .vmid
                                 .clone()
-                                .is_some_and(|vmid| vmid == v.vmid.to_string())
+                                .is_some_and(|vmid| vmid == g.guest.vmid.to_string())
+                        })
+                        .is_some_and(|g| {
+                            g.guest.template.is_none_or(|template| template == 0)
+                                && g.guest.status == "running"
                         })
-                        .is_some_and(|v| v.template.is_none() && v.status == "running")
                 }),
         );
     }
 
-    Ok(Json(ipams))
+    ipams.extend(
+        discovery::static_entries()?
+            .into_iter()
+            .filter(|entry| entry.assignment.is_some())
+            .filter(same_cluster)
+            .filter(|entry| addr.ip().to_string() != entry.ip),
+    );
+
+    Ok(node_deltas::respond(query, ipams)?)
 }
 
+async fn get_proxmox_nodes(
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<Vec<ProxmoxNode>>> {
+    let mut nodes = vec![];
+
+    for node in get_nodes(client.clone()).await?.data {
+        // Offline nodes can't report their status, they are listed without a load average.
+        let loadavg = if node.status == "online" {
+            proxmox::get::<NodeStatus>(&client, &format!("/nodes/{}/status", node.node))
+                .await?
+                .loadavg
+        } else {
+            None
+        };
+
+        nodes.push(ProxmoxNode { node, loadavg });
+    }
+
+    Ok(Json(nodes))
+}
+
+#[cfg(feature = "operator")]
 async fn get_node_token(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    certificate: Option<Extension<ClientCertificate>>,
     Path(vm_id): Path<String>,
+    scope: Option<Extension<ClusterScope>>,
     State(client): State<reqwest::Client>,
 ) -> AppResult<String> {
+    let caller = resolve_caller(client.clone(), addr, certificate).await?;
+
+    registrations::ensure_approved(caller.vmid.as_deref().unwrap_or_default())?;
+
     let nodes = get_nodes(client.clone()).await?.data;
 
     for node in nodes {
@@ -138,25 +391,47 @@ async fn get_node_token(
 
         for ipam in ipams {
             if ipam.vmid.is_some_and(|ipam_vmid| ipam_vmid == vm_id) {
+                let cluster = clusters::cluster_of_ip(&ipam.ip)
+                    .context(format!("VM {vm_id} is not part of any cluster"))?;
+
+                credentials::authorize(&scope, &cluster)?;
+
+                // A node only ever gets the token of its own cluster, whatever vouched for it.
+                if clusters::cluster_of(&caller).as_deref() != Some(cluster.as_str()) {
+                    return Err(anyhow::Error::msg(format!(
+                        "VM {vm_id} is not part of the caller's cluster"
+                    ))
+                    .into());
+                }
+
                 let temp = Temp::new_dir()?;
 
                 let token_path = temp.join("token").as_path().display().to_string().clone();
 
-                Command::new("scp")
-                    .arg("-o")
-                    .arg("StrictHostKeyChecking=no")
-                    .arg("-o")
-                    .arg("UserKnownHostsFile=/dev/null")
-                    .arg(format!(
-                        "root@{}:/var/lib/rancher/k3s/server/token",
-                        ipam.ip
-                    ))
-                    .arg(&token_path)
-                    .output()
-                    .await?;
+                let target = SshTarget {
+                    vmid: vm_id.clone(),
+                    ip: ipam.ip.clone(),
+                };
+
+                let distro = clusters::find(&cluster)
+                    .map(|cluster| cluster.distro)
+                    .unwrap_or_default();
+
+                ssh::scp_from(&client, &target, &distro.token_path(), &token_path).await?;
 
                 let token = std::fs::read_to_string(&token_path)?;
 
+                // Only a token actually handed out counts as a join.
+                token_rotation::record_join(&caller);
+
+                if let Some(vmid) = &caller.vmid {
+                    node_history::record(
+                        vmid,
+                        "joined",
+                        format!("Fetched the join token of VM {vm_id}"),
+                    );
+                }
+
                 return Ok(token);
             }
         }
@@ -165,30 +440,213 @@ async fn get_node_token(
     Err(anyhow::Error::msg("VM not found").into())
 }
 
-async fn get_current_node_id(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    State(client): State<reqwest::Client>,
-) -> AppResult<String> {
+// A client certificate issued by the helper identifies the caller even behind NAT, a VPN or on a
+// multi-homed guest, the source address is only used without one.
+pub(crate) async fn resolve_caller(
+    client: reqwest::Client,
+    addr: SocketAddr,
+    certificate: Option<Extension<ClientCertificate>>,
+) -> anyhow::Result<IpamEntry> {
+    let certificate_vmid = match certificate {
+        Some(Extension(certificate)) => {
+            Some(client_certificates::certificate_vmid(&certificate).await?)
+        }
+        None => None,
+    };
+
     let nodes = get_nodes(client.clone()).await?.data;
 
     for node in nodes {
         let ipams = get_ipams_for_node(client.clone(), &node.node).await?.data;
 
         if let Some(ip) = ipams
-            .iter()
+            .into_iter()
             .filter(|ipam| ipam.vmid.is_some())
-            .find(|ipam| addr.ip().to_string() == ipam.ip)
+            .find(|ipam| match &certificate_vmid {
+                Some(vmid) => ipam.vmid.as_ref() == Some(vmid),
+                None => addr.ip().to_string() == ipam.ip,
+            })
         {
-            return Ok(ip.vmid.clone().unwrap());
+            return Ok(ip);
         }
     }
 
-    Err(anyhow::Error::msg("VM not found").into())
+    Err(anyhow::Error::msg("VM not found"))
+}
+
+async fn get_current_node_id(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    certificate: Option<Extension<ClientCertificate>>,
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<String> {
+    let caller = resolve_caller(client, addr, certificate).await?;
+
+    credentials::authorize(
+        &scope,
+        &clusters::cluster_of(&caller).context("Caller is not part of any cluster")?,
+    )?;
+
+    Ok(caller.vmid.unwrap())
+}
+
+#[cfg(feature = "proxy")]
+async fn get_backends() -> AppResult<Json<HashMap<String, BackendHealth>>> {
+    Ok(Json(proxy::backend_health()))
+}
+
+#[cfg(feature = "operator")]
+async fn get_host_key(Path(vm_id): Path<String>) -> AppResult<Json<Option<PinnedHostKey>>> {
+    Ok(Json(ssh::get_pinned_host_key(&vm_id)))
+}
+
+#[cfg(feature = "operator")]
+async fn forget_host_key(Path(vm_id): Path<String>) -> AppResult<Json<Option<PinnedHostKey>>> {
+    Ok(Json(ssh::forget_host_key(&vm_id)?))
 }
 
 pub(crate) fn create_router() -> Router<reqwest::Client> {
-    Router::new()
-        .route("/nodes", get(get_nodes_infos))
-        .route("/current", get(get_current_node_id))
-        .route("/:vmid/token", get(get_node_token))
+    let router = Router::new()
+        .route(
+            "/proxmox-nodes",
+            get(get_proxmox_nodes).layer(middleware::from_fn(response_cache::cache)),
+        )
+        .route(
+            "/vms",
+            get(vms::get_vms).layer(middleware::from_fn(response_cache::cache)),
+        )
+        .route("/sync", post(discovery::sync_ipams))
+        .route("/registrations", get(registrations::get_registrations))
+        .route(
+            "/sdn",
+            get(sdn::get_sdn).layer(middleware::from_fn(response_cache::cache)),
+        )
+        .route("/ipam/stale", get(ipam_gc::get_stale_entries))
+        .route(
+            "/capacity",
+            get(capacity::get_capacity).layer(middleware::from_fn(response_cache::cache)),
+        )
+        .route("/connectivity", get(connectivity::get_connectivity))
+        .route("/tasks/:upid", get(tasks::get_task))
+        .route("/:vmid", get(vms::get_vm))
+        .route("/:vmid/history", get(node_history::get_node_history));
+
+    #[cfg(feature = "proxy")]
+    let router = router
+        .route("/backends", get(get_backends))
+        .route("/lb-config", get(lb_export::get_lb_config));
+
+    #[cfg(feature = "operator")]
+    let router = router
+        .route(
+            "/etcd/snapshots",
+            get(etcd_snapshots::get_remote_snapshots).post(etcd_snapshots::create_snapshot),
+        )
+        .route("/restore", get(cluster_restore::get_restore_status))
+        .route("/backup", post(backups::backup_vms))
+        .route("/backups/policy", get(backup_policy::get_policy_report))
+        .route("/maintenance", get(maintenance::get_maintenance))
+        .route(
+            "/k3s-certificates",
+            get(k3s_certificates::get_rotation_status),
+        )
+        .route("/tls-san", get(tls_san::get_tls_sans))
+        .route("/token/rotation", get(token_rotation::get_rotation_status))
+        .route("/:vmid/host-key", get(get_host_key))
+        .route("/:vmid/preflight", get(preflight::get_preflight))
+        .route("/:vmid/backup", post(backups::backup_vm))
+        .route("/:vmid/backups", get(backups::get_vm_backups));
+
+    #[cfg(feature = "provisioning")]
+    let router = router.route("/lxc", post(lxc::provision_lxc));
+
+    // `route_layer` only covers the routes above, the ones below order the check among their own
+    // layers.
+    let router = router
+        .route_layer(middleware::from_fn(credentials::require_credentials))
+        // A VM held back for approval must not be able to approve itself.
+        .route(
+            "/:vmid/approve",
+            post(registrations::approve).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/proxmox-credentials/reload",
+            post(proxmox_auth::reload_credentials)
+                .layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/nodes",
+            get(get_nodes_infos)
+                .layer(middleware::from_fn(route_limits::limit_nodes))
+                .layer(middleware::from_fn(credentials::require_credentials))
+                .layer(middleware::from_fn(discovery::data_age_headers)),
+        )
+        .route(
+            "/current",
+            get(get_current_node_id)
+                .layer(middleware::from_fn(credentials::require_credentials))
+                .layer(middleware::from_fn(access::require_cluster_caller)),
+        );
+
+    // Powering off, deleting or replacing VMs is kept for the administrator, a cluster credential
+    // is not enough.
+    #[cfg(feature = "operator")]
+    let router = router
+        .route(
+            "/:vmid/token",
+            get(get_node_token)
+                .layer(middleware::from_fn(route_limits::limit_token))
+                .layer(middleware::from_fn(credentials::require_credentials))
+                .layer(middleware::from_fn(access::require_cluster_caller)),
+        )
+        .route(
+            "/token/rotate",
+            post(token_rotation::rotate_token)
+                .layer(middleware::from_fn(credentials::require_admin)),
+        )
+        // Adding names restarts every server in turn.
+        .route(
+            "/tls-san",
+            post(tls_san::add_tls_sans).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/restore",
+            post(cluster_restore::start_restore)
+                .layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/:vmid/etcd/remove",
+            post(etcd::remove_vm_member).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        // The next connection trusts whatever key the guest presents, like the very first one.
+        .route(
+            "/:vmid/host-key",
+            delete(forget_host_key).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/:vmid/restore",
+            post(restore::restore_vm).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/vms/clone",
+            post(vm_lifecycle::clone_vm).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/:vmid/start",
+            post(vm_lifecycle::start_vm).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/:vmid/stop",
+            post(vm_lifecycle::stop_vm).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/:vmid/reboot",
+            post(vm_lifecycle::reboot_vm).layer(middleware::from_fn(credentials::require_admin)),
+        )
+        .route(
+            "/:vmid/delete",
+            post(vm_lifecycle::delete_vm).layer(middleware::from_fn(credentials::require_admin)),
+        );
+
+    router
 }
@@ -0,0 +1,455 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{extract::State, Extension, Json};
+use mktemp::Temp;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clusters,
+    credentials::{self, ClusterScope},
+    discovery,
+    error::AppResult,
+    etcd_snapshots, events, kube, logging, node_history, placement, proxmox,
+    ssh::{self, SshTarget},
+    tasks, vms, STATE,
+};
+
+const RESTORE_KEY: &str = "cluster_restore";
+const CONFIRMATION: &str = "restore-cluster";
+const CLONE_TIMEOUT: Duration = Duration::from_secs(1800);
+const BOOT_TIMEOUT: Duration = Duration::from_secs(600);
+const NODE_READY_TIMEOUT: Duration = Duration::from_secs(900);
+
+#[derive(Deserialize)]
+pub(crate) struct ClusterRestoreRequest {
+    // Only the servers of this cluster are wiped or replaced, it's never guessed.
+    cluster: String,
+    snapshot: String,
+    template: String,
+    node: String,
+    hostname: String,
+    confirm: String,
+    // The token the cluster had when the snapshot was taken, k3s can't decrypt its bootstrap data
+    // without it.
+    token: Option<String>,
+    // Replaces the remaining servers with fresh clones instead of wiping their etcd data.
+    #[serde(default)]
+    reprovision: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RestoreStep {
+    pub timestamp: i64,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClusterRestore {
+    // Restores recorded before clusters were introduced belong to the first one.
+    #[serde(default = "clusters::default_cluster_name")]
+    pub cluster: String,
+    pub snapshot: String,
+    pub hostname: String,
+    pub status: RestoreStatus,
+    pub started_at: i64,
+    pub completed_at: Option<i64>,
+    pub first_server: Option<String>,
+    pub steps: Vec<RestoreStep>,
+    pub error: Option<String>,
+}
+
+fn update(f: impl FnOnce(&mut ClusterRestore)) {
+    let result = STATE.update(RESTORE_KEY, |restore: &mut Option<ClusterRestore>| {
+        if let Some(restore) = restore {
+            f(restore);
+        }
+    });
+
+    if let Err(err) = result {
+        logging::warn!("Unable to persist the cluster restore status: {err}");
+    }
+}
+
+fn step(message: String) {
+    logging::info!("Cluster restore: {message}");
+
+    update(|restore| {
+        restore.steps.push(RestoreStep {
+            timestamp: chrono::Utc::now().timestamp(),
+            message,
+        })
+    });
+}
+
+pub(crate) async fn wait_for_ipam(
+    client: &reqwest::Client,
+    node: &str,
+    vmid: &str,
+) -> anyhow::Result<SshTarget> {
+    let deadline = tokio::time::Instant::now() + BOOT_TIMEOUT;
+
+    loop {
+        let ip = discovery::discover_node_ipams(client, node)
+            .await?
+            .into_iter()
+            .find(|ipam| ipam.vmid.as_deref() == Some(vmid))
+            .map(|ipam| ipam.ip);
+
+        if let Some(ip) = ip {
+            return Ok(SshTarget {
+                vmid: vmid.to_string(),
+                ip,
+            });
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("VM {vmid} got no address from the IPAM");
+        }
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+pub(crate) async fn wait_for_ssh(
+    client: &reqwest::Client,
+    target: &SshTarget,
+) -> anyhow::Result<()> {
+    let deadline = tokio::time::Instant::now() + BOOT_TIMEOUT;
+
+    loop {
+        match ssh::run(client, target, "true").await {
+            Ok(_) => return Ok(()),
+            Err(err) if tokio::time::Instant::now() >= deadline => {
+                return Err(err.context(format!("VM {} is not reachable over SSH", target.vmid)))
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(10)).await,
+        }
+    }
+}
+
+// Clones the template and boots it, the template's own bootstrap joins the VM to the cluster.
+async fn provision_server(
+    client: &reqwest::Client,
+    cluster: &str,
+    node: &str,
+    template: &str,
+    hostname: &str,
+) -> anyhow::Result<SshTarget> {
+    let vmid: String = proxmox::get(client, "/cluster/nextid").await?;
+
+    let mut params = vec![("newid", vmid.as_str()), ("name", hostname), ("full", "1")];
+
+    if let Some(pool) = clusters::find(cluster).and_then(|cluster| cluster.proxmox_pool()) {
+        params.push(("pool", pool));
+    }
+
+    let upid: String = proxmox::post(
+        client,
+        &format!("/nodes/{node}/qemu/{template}/clone"),
+        &params,
+    )
+    .await?;
+
+    tasks::wait_for_task(client, node, &upid, CLONE_TIMEOUT).await?;
+    step(format!(
+        "Cloned template {template} into VM {vmid} ({hostname}) on {node}"
+    ));
+
+    vms::change_vm_status(client, node, &vmid, "start").await?;
+
+    let target = wait_for_ipam(client, node, &vmid).await?;
+    wait_for_ssh(client, &target).await?;
+    step(format!("VM {vmid} booted with address {}", target.ip));
+
+    Ok(target)
+}
+
+async fn restore_snapshot(
+    client: &reqwest::Client,
+    cluster: &str,
+    target: &SshTarget,
+    snapshot: &str,
+    token: Option<&str>,
+) -> anyhow::Result<()> {
+    let temp_dir = Temp::new_dir()?;
+    let local_path = temp_dir.join("snapshot").as_path().display().to_string();
+
+    let name = etcd_snapshots::fetch_snapshot(snapshot, &local_path).await?;
+    step(format!("Downloaded and decrypted snapshot {snapshot}"));
+
+    // The new server isn't discovered yet, its address doesn't tell the distro.
+    let distro = clusters::find(cluster)
+        .context(format!("Unknown cluster {cluster}"))?
+        .distro;
+    let snapshots_path = distro.snapshots_path();
+    let remote_path = format!("{snapshots_path}/{name}");
+
+    ssh::run(client, target, &format!("mkdir -p {snapshots_path}")).await?;
+    ssh::scp_to(client, target, &local_path, &remote_path).await?;
+    step(format!("Copied the snapshot to {remote_path}"));
+
+    let token = token
+        .map(|token| format!(" --token={}", ssh::shell_quote(token)))
+        .unwrap_or_default();
+
+    ssh::run(
+        client,
+        target,
+        &format!(
+            "systemctl stop {service} && {} server --cluster-reset --cluster-reset-restore-path={}{token}",
+            distro.as_str(),
+            ssh::shell_quote(&remote_path),
+            service = distro.server_service()
+        ),
+    )
+    .await
+    .context("k3s cluster reset failed")?;
+    step("Reset the cluster from the snapshot".to_string());
+
+    ssh::run(
+        client,
+        target,
+        &format!("systemctl start {}", distro.server_service()),
+    )
+    .await?;
+    step("Started k3s on the restored server".to_string());
+
+    Ok(())
+}
+
+// The etcd data of the old servers belongs to the cluster that was lost, they come back as new
+// members once it's gone.
+async fn rejoin_server(
+    client: &reqwest::Client,
+    cluster: &str,
+    hostname: &str,
+    target: &SshTarget,
+) -> anyhow::Result<()> {
+    let distro = clusters::distro_of_ip(&target.ip);
+
+    ssh::run(
+        client,
+        target,
+        &format!(
+            "systemctl stop {service} && rm -rf {}/server/db && systemctl start {service}",
+            distro.data_dir(),
+            service = distro.server_service()
+        ),
+    )
+    .await?;
+    step(format!(
+        "Wiped the etcd data of {hostname} and restarted k3s"
+    ));
+
+    kube::wait_for_node_ready(client, cluster, hostname, NODE_READY_TIMEOUT).await?;
+    step(format!("Server {hostname} rejoined the cluster"));
+
+    Ok(())
+}
+
+async fn reprovision_server(
+    client: &reqwest::Client,
+    cluster: &str,
+    request: &ClusterRestoreRequest,
+    hostname: &str,
+    target: &SshTarget,
+) -> anyhow::Result<()> {
+    // The old VM is only stopped, it's left for the operator to remove once the cluster is healthy.
+    if let Some(node) = placement::last_known_node(&target.vmid) {
+        vms::change_vm_status(client, &node, &target.vmid, "stop").await?;
+        step(format!("Stopped VM {} ({hostname})", target.vmid));
+    }
+
+    provision_server(client, cluster, &request.node, &request.template, hostname).await?;
+
+    node_history::record(
+        &target.vmid,
+        "rebuilt",
+        format!("Re-provisioned as a new VM during a cluster restore of {hostname}"),
+    );
+
+    kube::wait_for_node_ready(client, cluster, hostname, NODE_READY_TIMEOUT).await?;
+    step(format!(
+        "Server {hostname} was re-provisioned and joined the cluster"
+    ));
+
+    Ok(())
+}
+
+async fn restore_cluster(
+    client: &reqwest::Client,
+    request: &ClusterRestoreRequest,
+) -> anyhow::Result<()> {
+    let cluster = &request.cluster;
+
+    // Listed before the new server exists, so it's not rejoined with the others. Not knowing them
+    // would leave them running with the lost cluster's etcd data.
+    let remaining_servers = kube::find_servers(client, cluster)
+        .await
+        .context(format!("Unable to list the servers of cluster {cluster}"))?;
+
+    step(format!(
+        "Found {} remaining servers in cluster {cluster}",
+        remaining_servers.len()
+    ));
+
+    let first_server = provision_server(
+        client,
+        cluster,
+        &request.node,
+        &request.template,
+        &request.hostname,
+    )
+    .await?;
+
+    update(|restore| restore.first_server = Some(first_server.vmid.clone()));
+
+    restore_snapshot(
+        client,
+        cluster,
+        &first_server,
+        &request.snapshot,
+        request.token.as_deref(),
+    )
+    .await?;
+
+    kube::wait_for_node_ready(client, cluster, &request.hostname, NODE_READY_TIMEOUT).await?;
+    step(format!("Server {} is ready", request.hostname));
+
+    for (hostname, target) in remaining_servers {
+        if hostname == request.hostname {
+            continue;
+        }
+
+        if request.reprovision {
+            reprovision_server(client, cluster, request, &hostname, &target).await?;
+        } else {
+            rejoin_server(client, cluster, &hostname, &target).await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn start_restore(
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+    Json(request): Json<ClusterRestoreRequest>,
+) -> AppResult<Json<ClusterRestore>> {
+    if request.confirm != CONFIRMATION {
+        return Err(anyhow::Error::msg(format!(
+            "Restoring the cluster replaces its whole state, set \"confirm\" to \"{CONFIRMATION}\" to proceed"
+        ))
+        .into());
+    }
+
+    clusters::find(&request.cluster).context(format!("Unknown cluster {}", request.cluster))?;
+    credentials::authorize(&scope, &request.cluster)?;
+
+    // A snapshot of another cluster would take this one's servers over.
+    if !request
+        .snapshot
+        .starts_with(&etcd_snapshots::prefix(&request.cluster))
+    {
+        return Err(anyhow::Error::msg(format!(
+            "Snapshot {} wasn't taken of cluster {}",
+            request.snapshot, request.cluster
+        ))
+        .into());
+    }
+
+    let restore = ClusterRestore {
+        cluster: request.cluster.clone(),
+        snapshot: request.snapshot.clone(),
+        hostname: request.hostname.clone(),
+        status: RestoreStatus::Running,
+        started_at: chrono::Utc::now().timestamp(),
+        completed_at: None,
+        first_server: None,
+        steps: vec![],
+        error: None,
+    };
+
+    let mut already_running = false;
+
+    STATE.update(RESTORE_KEY, |current: &mut Option<ClusterRestore>| {
+        if current
+            .as_ref()
+            .is_some_and(|current| current.status == RestoreStatus::Running)
+        {
+            already_running = true;
+        } else {
+            *current = Some(restore.clone());
+        }
+    })?;
+
+    if already_running {
+        return Err(anyhow::Error::msg("A cluster restore is already running").into());
+    }
+
+    events::record(
+        "cluster-restore-started",
+        None,
+        format!(
+            "Restoring cluster {} from {} onto a new server {}",
+            request.cluster, request.snapshot, request.hostname
+        ),
+        None,
+    );
+
+    tokio::spawn(async move {
+        let result = restore_cluster(&client, &request).await;
+
+        update(|restore| {
+            restore.completed_at = Some(chrono::Utc::now().timestamp());
+
+            match &result {
+                Ok(()) => restore.status = RestoreStatus::Completed,
+                Err(err) => {
+                    restore.status = RestoreStatus::Failed;
+                    restore.error = Some(format!("{err:#}"));
+                }
+            }
+        });
+
+        match result {
+            Ok(()) => events::record(
+                "cluster-restore-completed",
+                None,
+                format!("Restored the cluster from {}", request.snapshot),
+                None,
+            ),
+            Err(err) => events::record(
+                "cluster-restore-failed",
+                None,
+                format!("Cluster restore from {} failed: {err:#}", request.snapshot),
+                None,
+            ),
+        }
+    });
+
+    Ok(Json(restore))
+}
+
+pub(crate) async fn get_restore_status(
+    scope: Option<Extension<ClusterScope>>,
+) -> AppResult<Json<Option<ClusterRestore>>> {
+    let restore = STATE
+        .get::<Option<ClusterRestore>>(RESTORE_KEY)
+        .unwrap_or_default();
+
+    if let Some(restore) = &restore {
+        credentials::authorize(&scope, &restore.cluster)?;
+    }
+
+    Ok(Json(restore))
+}
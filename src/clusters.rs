@@ -0,0 +1,311 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::{IpamEntry, NodeRole},
+    discovery, get_exposed_address, roles, CONFIG,
+};
+
+pub(crate) const DEFAULT_CLUSTER: &str = "default";
+pub(crate) const K8S_API_PORT: u16 = 6443;
+pub(crate) const RKE2_SUPERVISOR_PORT: u16 = 9345;
+
+pub(crate) static CLUSTERS: Lazy<Vec<K3sCluster>> =
+    Lazy::new(|| load_clusters().expect("Unable to load the cluster definitions"));
+
+#[derive(Deserialize)]
+struct ClusterDefinitions {
+    clusters: Vec<K3sCluster>,
+}
+
+// One k3s cluster served by the helper, VMs belong to the first cluster they match.
+#[derive(Debug, Deserialize)]
+pub(crate) struct K3sCluster {
+    pub name: String,
+    hostname: Option<String>,
+    tag: Option<String>,
+    vnet: Option<String>,
+    #[serde(default = "default_proxy_port")]
+    pub proxy_port: u16,
+    // Relative to `certificates_path`, the cluster CA lives at its root when unset.
+    ca_subdirectory: Option<String>,
+    #[serde(default)]
+    pub distro: Distro,
+    // RKE2 agents register through a supervisor port of their own, proxied like the API.
+    supervisor_port: Option<u16>,
+    // Only VMs in this Proxmox resource pool are members, new nodes are created in it.
+    proxmox_pool: Option<String>,
+    // Nodes reach the API through this name rather than the helper's address when set.
+    api_fqdn: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Distro {
+    #[default]
+    K3s,
+    Rke2,
+}
+
+impl Distro {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Distro::K3s => "k3s",
+            Distro::Rke2 => "rke2",
+        }
+    }
+
+    pub fn data_dir(&self) -> String {
+        format!("/var/lib/rancher/{}", self.as_str())
+    }
+
+    pub fn token_path(&self) -> String {
+        format!("{}/server/token", self.data_dir())
+    }
+
+    pub fn snapshots_path(&self) -> String {
+        format!("{}/server/db/snapshots", self.data_dir())
+    }
+
+    pub fn kubeconfig_path(&self) -> String {
+        format!("/etc/rancher/{0}/{0}.yaml", self.as_str())
+    }
+
+    // RKE2 doesn't put kubectl on the path.
+    pub fn kubectl(&self) -> String {
+        match self {
+            Distro::K3s => "k3s kubectl".to_string(),
+            Distro::Rke2 => format!(
+                "{}/bin/kubectl --kubeconfig {}",
+                self.data_dir(),
+                self.kubeconfig_path()
+            ),
+        }
+    }
+
+    pub fn server_service(&self) -> &'static str {
+        match self {
+            Distro::K3s => "k3s",
+            Distro::Rke2 => "rke2-server",
+        }
+    }
+
+    pub fn install_command(&self, role: NodeRole) -> String {
+        match self {
+            Distro::K3s => format!(
+                "curl -sfL https://get.k3s.io | INSTALL_K3S_EXEC={} sh -",
+                role.as_str()
+            ),
+            Distro::Rke2 => format!(
+                "curl -sfL https://get.rke2.io | INSTALL_RKE2_TYPE={} sh -",
+                role.as_str()
+            ),
+        }
+    }
+
+    // Only RKE2 ships a Windows agent, installed as a service from PowerShell.
+    pub fn windows_install_command(&self) -> anyhow::Result<String> {
+        match self {
+            Distro::K3s => anyhow::bail!("k3s has no Windows agent, mixed clusters need RKE2"),
+            Distro::Rke2 => Ok([
+                "Invoke-WebRequest -Uri https://raw.githubusercontent.com/rancher/rke2/master/install.ps1 -OutFile install.ps1",
+                "./install.ps1",
+                r#"$env:PATH += ";C:\var\lib\rancher\rke2\bin;C:\usr\local\bin""#,
+                "rke2.exe agent service --add",
+            ]
+            .join("\n")),
+        }
+    }
+
+    pub fn windows_config_path(&self) -> String {
+        format!(r"C:\etc\rancher\{}\config.yaml", self.as_str())
+    }
+
+    // k3s serves the supervisor on the API port.
+    pub fn supervisor_backend_port(&self) -> Option<u16> {
+        match self {
+            Distro::K3s => None,
+            Distro::Rke2 => Some(RKE2_SUPERVISOR_PORT),
+        }
+    }
+}
+
+fn default_proxy_port() -> u16 {
+    K8S_API_PORT
+}
+
+impl K3sCluster {
+    pub(crate) fn vnet(&self) -> &str {
+        self.vnet
+            .as_deref()
+            .unwrap_or(&CONFIG.k3s_internal_network_interface)
+    }
+
+    pub(crate) fn proxmox_pool(&self) -> Option<&str> {
+        self.proxmox_pool
+            .as_deref()
+            .or(CONFIG.proxmox_pool.as_deref())
+    }
+
+    // Clusters without a pool take guests from anywhere.
+    pub(crate) fn includes_pool(&self, pool: Option<&str>) -> bool {
+        match self.proxmox_pool() {
+            Some(expected) => pool == Some(expected),
+            None => true,
+        }
+    }
+
+    pub(crate) fn supervisor_port(&self) -> Option<u16> {
+        self.distro
+            .supervisor_backend_port()
+            .map(|port| self.supervisor_port.unwrap_or(port))
+    }
+
+    // Where nodes join, through the helper's proxy.
+    pub(crate) fn join_port(&self) -> u16 {
+        self.supervisor_port().unwrap_or(self.proxy_port)
+    }
+
+    pub(crate) fn api_fqdn(&self) -> Option<&str> {
+        self.api_fqdn
+            .as_deref()
+            .or(CONFIG.cluster_api_fqdn.as_deref())
+    }
+
+    // The name survives the helper moving to another address, which is embedded otherwise.
+    pub(crate) fn api_host(&self) -> anyhow::Result<String> {
+        match self.api_fqdn() {
+            Some(fqdn) => Ok(fqdn.to_string()),
+            None => Ok(get_exposed_address()?.0.to_string()),
+        }
+    }
+
+    pub(crate) fn server_url(&self) -> anyhow::Result<String> {
+        Ok(format!("https://{}:{}", self.api_host()?, self.join_port()))
+    }
+
+    pub(crate) fn proxy_url(&self) -> anyhow::Result<String> {
+        Ok(format!("https://{}:{}", self.api_host()?, self.proxy_port))
+    }
+
+    pub(crate) fn ca_path(&self) -> PathBuf {
+        let path = PathBuf::from(&CONFIG.certificates_path);
+
+        match &self.ca_subdirectory {
+            Some(subdirectory) => path.join(subdirectory),
+            None => path,
+        }
+    }
+
+    // Same semantics as the role mapping, an unknown vnet or tag list doesn't exclude a cluster.
+    fn matches(&self, hostname: Option<&str>, vnet: Option<&str>, tags: Option<&str>) -> bool {
+        let hostname_matches = match &self.hostname {
+            Some(pattern) => hostname.is_some_and(|hostname| roles::glob_match(pattern, hostname)),
+            None => true,
+        };
+
+        let vnet_matches = vnet.is_none_or(|vnet| vnet == self.vnet());
+
+        let tag_matches = match (&self.tag, tags) {
+            (Some(expected), Some(tags)) => tags.split([';', ',', ' ']).any(|tag| tag == expected),
+            _ => true,
+        };
+
+        hostname_matches && vnet_matches && tag_matches
+    }
+}
+
+fn default_clusters() -> Vec<K3sCluster> {
+    vec![K3sCluster {
+        name: DEFAULT_CLUSTER.to_string(),
+        hostname: None,
+        tag: None,
+        vnet: None,
+        proxy_port: K8S_API_PORT,
+        ca_subdirectory: None,
+        distro: Distro::default(),
+        supervisor_port: None,
+        proxmox_pool: None,
+        api_fqdn: None,
+    }]
+}
+
+fn load_clusters() -> anyhow::Result<Vec<K3sCluster>> {
+    let Some(path) = &CONFIG.clusters_path else {
+        return Ok(default_clusters());
+    };
+
+    let definitions = std::fs::read_to_string(path)
+        .context(format!("Unable to read cluster definitions {path}"))?;
+
+    let clusters = toml::from_str::<ClusterDefinitions>(&definitions)
+        .context(format!("Invalid cluster definitions {path}"))?
+        .clusters;
+
+    if clusters.is_empty() {
+        anyhow::bail!("No cluster defined in {path}");
+    }
+
+    Ok(clusters)
+}
+
+pub(crate) fn cluster_for(
+    hostname: Option<&str>,
+    vnet: Option<&str>,
+    tags: Option<&str>,
+) -> Option<&'static K3sCluster> {
+    CLUSTERS
+        .iter()
+        .find(|cluster| cluster.matches(hostname, vnet, tags))
+}
+
+// Callers resolved straight from the IPAM carry no assignment, the discovery snapshot knows their
+// tags.
+pub(crate) fn cluster_of(ipam: &IpamEntry) -> Option<String> {
+    match &ipam.assignment {
+        Some(assignment) => Some(assignment.cluster.clone()),
+        None => cluster_of_ip(&ipam.ip),
+    }
+}
+
+pub(crate) fn cluster_of_ip(ip: &str) -> Option<String> {
+    discovery::subscribe()
+        .borrow()
+        .iter()
+        .find(|entry| entry.ip == ip)
+        .and_then(|entry| entry.assignment.as_ref())
+        .map(|assignment| assignment.cluster.clone())
+}
+
+pub(crate) fn cluster_of_vmid(vmid: &str) -> Option<String> {
+    discovery::subscribe()
+        .borrow()
+        .iter()
+        .find(|entry| entry.vmid.as_deref() == Some(vmid))
+        .and_then(|entry| entry.assignment.as_ref())
+        .map(|assignment| assignment.cluster.clone())
+}
+
+pub(crate) fn find(name: &str) -> Option<&'static K3sCluster> {
+    CLUSTERS.iter().find(|cluster| cluster.name == name)
+}
+
+// Servers reached over SSH are only known by their address, the default cluster's distro applies
+// to ones not discovered yet.
+pub(crate) fn distro_of_ip(ip: &str) -> Distro {
+    cluster_for_ip(ip).distro
+}
+
+pub(crate) fn cluster_for_ip(ip: &str) -> &'static K3sCluster {
+    cluster_of_ip(ip)
+        .and_then(|name| find(&name))
+        .unwrap_or(&CLUSTERS[0])
+}
+
+// Assignments persisted before clusters were introduced belong to the first one.
+pub(crate) fn default_cluster_name() -> String {
+    CLUSTERS[0].name.clone()
+}
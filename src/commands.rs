@@ -0,0 +1,61 @@
+use std::{fmt, process::Output};
+
+use tokio::process::Command;
+
+use crate::metrics;
+
+#[derive(Debug)]
+pub(crate) struct CommandError {
+    pub program: String,
+    pub status: std::process::ExitStatus,
+    pub stderr: String,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed ({})", self.program, self.status)?;
+
+        if !self.stderr.is_empty() {
+            write!(f, ": {}", self.stderr)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+fn record_failure(program: &str) {
+    metrics::increment_counter(
+        "k3s_helper_external_command_failures_total",
+        "External commands which could not be run or exited with an error",
+        &[("program", program)],
+    );
+}
+
+// Runs the command to completion, a non-zero exit is turned into a `CommandError` carrying stderr.
+pub(crate) async fn output(command: &mut Command) -> anyhow::Result<Output> {
+    let program = command.as_std().get_program().to_string_lossy().to_string();
+
+    // A caller giving up (e.g. on a timeout) must not leave the process behind.
+    let output = match command.kill_on_drop(true).output().await {
+        Ok(output) => output,
+        Err(err) => {
+            record_failure(&program);
+            return Err(anyhow::Error::new(err).context(format!("Unable to run {program}")));
+        }
+    };
+
+    if !output.status.success() {
+        record_failure(&program);
+
+        return Err(CommandError {
+            program,
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    Ok(output)
+}
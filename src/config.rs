@@ -1,22 +1,401 @@
 use clap::Parser;
 
+use crate::cli::{Command, OutputFormat};
+
 #[derive(Debug, Clone, Parser)]
 pub(crate) struct Config {
+    #[clap(long, env, hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    #[clap(long, env)]
+    pub alert_webhook_url: Option<String>,
+
+    #[clap(long, env)]
+    pub annotate_vms: bool,
+
+    #[clap(long, env, default_value = "3")]
+    pub backend_fall: u32,
+
+    #[clap(long, env)]
+    pub backend_fallback_dns: Option<String>,
+
+    #[clap(long, env, default_value = "3")]
+    pub backend_rise: u32,
+
+    #[clap(long, env, default_value = "26")]
+    pub backup_max_age: u64,
+
+    #[clap(long, env)]
+    pub backup_policy: bool,
+
+    #[clap(long, env, default_value = "168")]
+    pub backup_policy_agent_interval: u64,
+
+    #[clap(long, env, default_value = "4")]
+    pub backup_policy_agent_keep: usize,
+
+    #[clap(long, env, default_value = "24")]
+    pub backup_policy_server_interval: u64,
+
+    #[clap(long, env, default_value = "7")]
+    pub backup_policy_server_keep: usize,
+
+    #[clap(long, env, default_value = "local")]
+    pub backup_storage: String,
+
+    #[clap(long, env, default_value = "3600")]
+    pub backup_timeout: u64,
+
+    #[clap(long, env)]
+    pub ca_index_path: Option<String>,
+
+    #[clap(long, env, default_value = "30")]
+    pub certificate_expiry_warning_days: i64,
+
+    #[clap(long, env, default_value = "88800")]
+    pub certificate_max_validity_hours: i64,
+
+    #[clap(long, env)]
+    pub certificate_subject_organization: Option<String>,
+
+    #[clap(long, env)]
+    pub certificate_subject_organizational_unit: Option<String>,
+
+    #[clap(long, env, default_value = "/CN=k3s-{certificate_type}@{timestamp}")]
+    pub certificate_subject_template: String,
+
+    #[clap(
+        long,
+        env,
+        value_delimiter = ',',
+        default_value = "server-ca,client-ca,request-header-ca,etcd/peer-ca,etcd/server-ca"
+    )]
+    pub certificate_types: Vec<String>,
+
     #[clap(long, env, default_value = "/srv/k8s/certificates")]
     pub certificates_path: String,
 
+    #[clap(long, env)]
+    pub client_certificate_header: Option<String>,
+
+    #[clap(long, env, default_value = "local")]
+    pub cloud_init_snippets_storage: String,
+
+    #[clap(long, env)]
+    pub cloud_init_ssh_keys_path: Option<String>,
+
+    #[clap(long, env)]
+    pub cluster_api_fqdn: Option<String>,
+
+    #[clap(long, env, value_delimiter = ',')]
+    pub cluster_subnets: Vec<String>,
+
+    #[clap(long, env)]
+    pub clusters_path: Option<String>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    #[clap(long, env, hide_env_values = true)]
+    pub config_age_key: Option<String>,
+
+    #[clap(long, env)]
+    pub config_age_key_file: Option<String>,
+
+    #[clap(long, env)]
+    pub config_file: Option<String>,
+
+    #[clap(long, env)]
+    pub crl_path: Option<String>,
+
+    #[clap(long, env, default_value = "300")]
+    pub dns_cache_max_ttl: u64,
+
+    #[clap(long, env, default_value = "30")]
+    pub dns_cache_negative_ttl: u64,
+
+    #[clap(long, env)]
+    pub error_report_webhook_url: Option<String>,
+
+    #[clap(long, env, default_value = "3")]
+    pub etcd_min_members: usize,
+
+    #[clap(long, env)]
+    pub etcd_snapshot_encryption_key_path: Option<String>,
+
+    #[clap(long, env, default_value = "12")]
+    pub etcd_snapshot_interval: u64,
+
+    #[clap(long, env, default_value = "14")]
+    pub etcd_snapshot_retention: usize,
+
+    #[clap(long, env)]
+    pub etcd_snapshot_s3_access_key: Option<String>,
+
+    #[clap(long, env)]
+    pub etcd_snapshot_s3_bucket: Option<String>,
+
+    #[clap(long, env)]
+    pub etcd_snapshot_s3_endpoint: Option<String>,
+
+    #[clap(long, env, default_value = "k3s-etcd-snapshots")]
+    pub etcd_snapshot_s3_folder: String,
+
+    #[clap(long, env, default_value = "us-east-1")]
+    pub etcd_snapshot_s3_region: String,
+
+    #[clap(long, env, hide_env_values = true)]
+    pub etcd_snapshot_s3_secret_key: Option<String>,
+
+    #[clap(long, env, default_value = "60")]
+    pub guest_agent_timeout: u64,
+
+    #[clap(long, env)]
+    pub ipam_gc: bool,
+
+    #[clap(long, env)]
+    pub ipam_gc_delete: bool,
+
+    #[clap(long, env, default_value = "900")]
+    pub ipam_gc_grace_period: i64,
+
+    #[clap(long, env)]
+    pub ipam_max_age: Option<i64>,
+
+    #[clap(long, env)]
+    pub ipxe_initrd_url: Option<String>,
+
+    #[clap(long, env, default_value = "ip=dhcp autoinstall")]
+    pub ipxe_kernel_args: String,
+
+    #[clap(long, env)]
+    pub ipxe_kernel_url: Option<String>,
+
+    #[clap(long, env)]
+    pub k3s_certificate_rotation: bool,
+
+    #[clap(long, env, default_value = "02:00-05:00")]
+    pub k3s_certificate_rotation_window: String,
+
     #[clap(long, env, default_value = "vnet1")]
     pub k3s_internal_network_interface: String,
 
+    #[clap(long, env)]
+    pub k8s_ca_cert: Option<String>,
+
+    #[clap(long, env)]
+    pub k8s_client_cert: Option<String>,
+
+    #[clap(long, env)]
+    pub k8s_client_key: Option<String>,
+
+    #[clap(long, env, default_value = "8760")]
+    pub kubelet_certificate_max_validity_hours: i64,
+
+    #[clap(long, env, default_value = "8760")]
+    pub kubelet_certificate_validity_hours: i64,
+
+    #[clap(long, env)]
+    pub lb_export_format: Option<String>,
+
+    #[clap(long, env)]
+    pub lb_export_path: Option<String>,
+
+    #[clap(long, env)]
+    pub lb_export_reload_command: Option<String>,
+
+    #[clap(long, env, default_value = "info")]
+    pub log_levels: String,
+
+    #[clap(long, env, default_value = "8")]
+    pub lxc_disk_size: u32,
+
+    #[clap(long, env)]
+    pub lxc_ostemplate: Option<String>,
+
+    #[clap(long, env, default_value = "local-lvm")]
+    pub lxc_storage: String,
+
+    #[clap(long, env)]
+    pub maintenance_window_backup: Option<String>,
+
+    #[clap(long, env)]
+    pub maintenance_window_certificate_rotation: Option<String>,
+
+    #[clap(long, env)]
+    pub maintenance_window_etcd_snapshot: Option<String>,
+
+    #[clap(long, env)]
+    pub maintenance_window_ipam_gc: Option<String>,
+
+    #[clap(long, env)]
+    pub maintenance_window_node_reaper: Option<String>,
+
+    #[clap(long, env)]
+    pub maintenance_window_server_restart: Option<String>,
+
+    #[clap(long, env)]
+    pub maintenance_window_token_rotation: Option<String>,
+
+    #[clap(long, env)]
+    pub nocloud_templates_path: Option<String>,
+
+    #[clap(long, env)]
+    pub node_reaper: bool,
+
+    #[clap(long, env, default_value = "600")]
+    pub node_reaper_grace_period: i64,
+
+    #[clap(long, global = true, value_enum, default_value = "table")]
+    pub output: OutputFormat,
+
+    #[clap(long, env)]
+    pub pbs_storage: Option<String>,
+
     #[clap(long, env, default_value = "3000")]
     pub port: u16,
 
     #[clap(long, env, default_value = "https://localhost:8006")]
     pub proxmox_api_url: String,
 
+    #[clap(long, env, hide_env_values = true)]
+    pub proxmox_api_token: Option<String>,
+
+    #[clap(long, env)]
+    pub proxmox_api_token_file: Option<String>,
+
     #[clap(long, env)]
-    pub proxmox_api_user: String,
+    pub proxmox_api_user: Option<String>,
 
     #[clap(env)]
-    pub proxmox_api_password: String,
+    pub proxmox_api_password: Option<String>,
+
+    #[clap(long, env)]
+    pub proxmox_api_password_file: Option<String>,
+
+    #[clap(long, env)]
+    pub proxmox_ca_cert: Option<String>,
+
+    #[clap(long, env, default_value = "10")]
+    pub proxmox_cache_ttl: u64,
+
+    #[clap(long, env, hide_env_values = true)]
+    pub proxmox_hook_secret: Option<String>,
+
+    #[clap(long, env)]
+    pub proxmox_http_proxy: Option<String>,
+
+    #[clap(long, env)]
+    pub proxmox_insecure_skip_verify: bool,
+
+    #[clap(long, env)]
+    pub proxmox_no_proxy: Option<String>,
+
+    #[clap(long, env)]
+    pub proxmox_pool: Option<String>,
+
+    #[clap(long, env, default_value = "500")]
+    pub proxmox_retry_initial_delay_ms: u64,
+
+    #[clap(long, env, default_value = "5")]
+    pub proxmox_retry_max_attempts: u32,
+
+    #[clap(long, env, default_value = "30")]
+    pub proxmox_timeout: u64,
+
+    #[clap(long, env)]
+    pub proxmox_tls_fingerprint: Option<String>,
+
+    #[clap(long, env)]
+    pub proxy_access_log: bool,
+
+    #[clap(long, env, default_value = "1")]
+    pub proxy_access_log_sample_every: u64,
+
+    #[clap(long, env)]
+    pub registration_approval: bool,
+
+    #[clap(long, env)]
+    pub registration_auto_approve_tag: Option<String>,
+
+    #[clap(long, env, default_value = "900")]
+    pub replication_max_lag: i64,
+
+    #[clap(long, env, default_value = "5")]
+    pub response_cache_max_age: u64,
+
+    #[clap(long, env, default_value = "60")]
+    pub response_cache_stale_while_revalidate: u64,
+
+    #[clap(long, env)]
+    pub role_mapping_path: Option<String>,
+
+    #[clap(long, env, default_value = "8")]
+    pub route_concurrency_limit: usize,
+
+    #[clap(long, env, default_value = "64")]
+    pub route_queue_size: usize,
+
+    #[clap(long, env, default_value = "30")]
+    pub route_queue_timeout: u64,
+
+    #[clap(long, env)]
+    pub run_as_group: Option<String>,
+
+    #[clap(long, env)]
+    pub run_as_user: Option<String>,
+
+    #[clap(long, env)]
+    pub scoped_credentials: bool,
+
+    #[clap(long, env, hide_env_values = true)]
+    pub sentry_dsn: Option<String>,
+
+    #[clap(long, env, default_value = "local")]
+    pub signer: String,
+
+    #[clap(long, env, default_value = "pkcs11")]
+    pub signer_hsm_engine: String,
+
+    #[clap(long, env)]
+    pub signer_hsm_key: Option<String>,
+
+    #[clap(long, env, default_value = "64")]
+    pub signing_queue_size: usize,
+
+    #[clap(long, env, default_value = "30")]
+    pub signing_timeout: u64,
+
+    #[clap(long, env, default_value = "cluster")]
+    pub spiffe_trust_domain: String,
+
+    #[clap(long, env, default_value = "600")]
+    pub ssh_timeout: u64,
+
+    #[clap(long, env, default_value = "/var/lib/k3s-proxmox-helper/state.json")]
+    pub state_path: String,
+
+    #[clap(long, env)]
+    pub static_entries_path: Option<String>,
+
+    #[clap(long, env, default_value = "24")]
+    pub svid_validity_hours: i64,
+
+    #[clap(long, env, value_delimiter = ',')]
+    pub trusted_proxies: Vec<String>,
+
+    #[clap(long, env)]
+    pub vault_addr: Option<String>,
+
+    #[clap(long, env, default_value = "pki")]
+    pub vault_pki_mount: String,
+
+    #[clap(long, env)]
+    pub vault_pki_role: Option<String>,
+
+    #[clap(long, env, hide_env_values = true)]
+    pub vault_token: Option<String>,
+
+    #[clap(long, env)]
+    pub wait_for_proxmox: bool,
 }
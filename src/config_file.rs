@@ -0,0 +1,311 @@
+use std::any::TypeId;
+
+use anyhow::Context;
+use base64::Engine;
+use clap::{Arg, ArgMatches, CommandFactory, FromArgMatches};
+use mktemp::Temp;
+use once_cell::sync::OnceCell;
+use serde_json::{json, Map, Value};
+use tokio::process::Command;
+
+use crate::{commands, config::Config, secrets};
+
+// Only meaningful on the command line, they can't be set from the file.
+const CLI_ONLY: &[&str] = &[
+    "config_age_key",
+    "config_age_key_file",
+    "config_file",
+    "help",
+    "output",
+];
+const AGE_PREFIX: &str = "age:";
+
+static FILE_SETTINGS: OnceCell<Vec<(String, String)>> = OnceCell::new();
+
+fn file_settings() -> Vec<Arg> {
+    Config::command()
+        .get_arguments()
+        .filter(|arg| !CLI_ONLY.contains(&arg.get_id().as_str()))
+        .cloned()
+        .collect()
+}
+
+fn value_type(arg: &Arg) -> &'static str {
+    let type_id = arg.get_value_parser().type_id();
+
+    if type_id == TypeId::of::<bool>() {
+        "boolean"
+    } else if [
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<usize>(),
+        TypeId::of::<i64>(),
+    ]
+    .iter()
+    .any(|id| type_id == *id)
+    {
+        "integer"
+    } else if type_id == TypeId::of::<f64>() {
+        "number"
+    } else {
+        "string"
+    }
+}
+
+fn typed_value(value_type: &str, value: &str) -> Value {
+    match value_type {
+        "boolean" => value.parse().map(Value::Bool).unwrap_or(json!(value)),
+        "integer" => value
+            .parse::<i64>()
+            .map(Value::from)
+            .unwrap_or(json!(value)),
+        "number" => value
+            .parse::<f64>()
+            .map(Value::from)
+            .unwrap_or(json!(value)),
+        _ => json!(value),
+    }
+}
+
+fn property(arg: &Arg) -> Value {
+    let value_type = value_type(arg);
+    let mut property = Map::new();
+
+    property.insert("type".to_string(), json!(value_type));
+
+    let possible_values = arg
+        .get_possible_values()
+        .iter()
+        .map(|value| json!(value.get_name()))
+        .collect::<Vec<_>>();
+
+    if !possible_values.is_empty() && value_type != "boolean" {
+        property.insert("enum".to_string(), Value::Array(possible_values));
+    }
+
+    let defaults = arg
+        .get_default_values()
+        .iter()
+        .map(|value| typed_value(value_type, &value.to_string_lossy()))
+        .collect::<Vec<_>>();
+
+    if arg.get_value_delimiter().is_some() {
+        let mut array = Map::new();
+        array.insert("type".to_string(), json!("array"));
+        array.insert("items".to_string(), Value::Object(property));
+
+        if !defaults.is_empty() {
+            array.insert("default".to_string(), Value::Array(defaults));
+        }
+
+        return Value::Object(array);
+    }
+
+    if let Some(default) = defaults.into_iter().next() {
+        property.insert("default".to_string(), default);
+    }
+
+    if let Some(env) = arg.get_env() {
+        property.insert(
+            "description".to_string(),
+            json!(format!(
+                "Overridden by the {} environment variable",
+                env.to_string_lossy()
+            )),
+        );
+    }
+
+    Value::Object(property)
+}
+
+pub(crate) fn schema() -> Value {
+    let properties = file_settings()
+        .iter()
+        .map(|arg| (arg.get_id().to_string(), property(arg)))
+        .collect::<Map<_, _>>();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "k3s-proxmox-helper configuration",
+        "type": "object",
+        "additionalProperties": false,
+        "properties": properties,
+    })
+}
+
+// Checked before the configuration is parsed, printing the schema doesn't need a valid one.
+pub(crate) fn is_schema_requested() -> bool {
+    cli_matches()
+        .and_then(|matches| {
+            let (name, config) = matches.subcommand()?;
+            Some(name == "config" && config.subcommand_name() == Some("schema"))
+        })
+        .unwrap_or(false)
+}
+
+fn cli_matches() -> Option<ArgMatches> {
+    Config::command().ignore_errors(true).try_get_matches().ok()
+}
+
+fn parse(path: &str, content: &str) -> anyhow::Result<Value> {
+    Ok(if path.ends_with(".json") {
+        serde_json::from_str(content)?
+    } else if path.ends_with(".yaml") || path.ends_with(".yml") {
+        serde_yaml::from_str(content)?
+    } else {
+        toml::from_str(content)?
+    })
+}
+
+fn age_key(matches: &ArgMatches) -> Option<String> {
+    secrets::secret(
+        "config_age_key",
+        &matches.get_one::<String>("config_age_key").cloned(),
+    )
+}
+
+// SOPS keeps its metadata next to the encrypted values, it only handles the yaml and json files.
+async fn decrypt_sops(path: &str, matches: &ArgMatches) -> anyhow::Result<String> {
+    let mut command = Command::new("sops");
+    command.args(["--decrypt", path]);
+
+    if let Some(key_file) = matches.get_one::<String>("config_age_key_file") {
+        command.env("SOPS_AGE_KEY_FILE", key_file);
+    }
+
+    if let Some(key) = age_key(matches) {
+        command.env("SOPS_AGE_KEY", key);
+    }
+
+    let output = commands::output(&mut command).await?;
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// Single values can be encrypted with `age` in any format, stored base64 encoded after `age:`.
+async fn decrypt_age(value: &str, matches: &ArgMatches) -> anyhow::Result<String> {
+    let temp_dir = Temp::new_dir()?;
+    let temp_path = |name: &str| temp_dir.join(name).as_path().display().to_string();
+
+    let identity_path = match (
+        matches.get_one::<String>("config_age_key_file"),
+        age_key(matches),
+    ) {
+        (Some(key_file), _) => key_file.clone(),
+        (None, Some(key)) => {
+            let path = temp_path("identity");
+            std::fs::write(&path, key)?;
+            path
+        }
+        (None, None) => {
+            anyhow::bail!("config_age_key_file or config_age_key is required to decrypt values")
+        }
+    };
+
+    let encrypted_path = temp_path("value.age");
+    std::fs::write(
+        &encrypted_path,
+        base64::engine::general_purpose::STANDARD.decode(value.trim())?,
+    )?;
+
+    let output = commands::output(Command::new("age").args([
+        "--decrypt",
+        "--identity",
+        &identity_path,
+        &encrypted_path,
+    ]))
+    .await?;
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+fn env_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Array(values) => values.iter().map(env_value).collect::<Vec<_>>().join(","),
+        value => value.to_string(),
+    }
+}
+
+// Settings from the file become the defaults of their arguments, so the command line and the
+// environment keep precedence over the file. Decrypted values only ever live in memory, they are
+// never exported to the environment inherited by the commands the helper runs, nor shown as
+// defaults by --help.
+pub(crate) fn parse_config() -> Config {
+    let mut command = Config::command();
+
+    for (id, value) in FILE_SETTINGS.get().into_iter().flatten() {
+        command = command.mut_arg(id, |arg| {
+            arg.default_value(value.clone()).hide_default_value(true)
+        });
+    }
+
+    Config::from_arg_matches_mut(&mut command.get_matches()).unwrap_or_else(|err| err.exit())
+}
+
+pub(crate) async fn apply() -> anyhow::Result<()> {
+    let Some(matches) = cli_matches() else {
+        return Ok(());
+    };
+
+    let Some(path) = matches.get_one::<String>("config_file") else {
+        return Ok(());
+    };
+
+    let mut content = std::fs::read_to_string(path)
+        .context(format!("Unable to read the configuration file {path}"))?;
+
+    let mut settings =
+        parse(path, &content).context(format!("Invalid configuration file {path}"))?;
+
+    if settings.get("sops").is_some() {
+        content = decrypt_sops(path, &matches)
+            .await
+            .context(format!("Unable to decrypt {path} with sops"))?;
+
+        settings = parse(path, &content)?;
+        settings
+            .as_object_mut()
+            .map(|settings| settings.remove("sops"));
+    }
+
+    let Value::Object(settings) = settings else {
+        anyhow::bail!("Invalid configuration file {path}, settings must be a table");
+    };
+
+    let args = file_settings();
+    let mut file_settings = vec![];
+
+    for (key, value) in settings {
+        let arg = args
+            .iter()
+            .find(|arg| arg.get_id() == key.as_str())
+            .context(format!("Unknown setting {key} in {path}"))?;
+
+        if arg
+            .get_env()
+            .is_some_and(|env| std::env::var_os(env).is_some())
+        {
+            continue;
+        }
+
+        let value = match value
+            .as_str()
+            .and_then(|value| value.strip_prefix(AGE_PREFIX))
+        {
+            Some(encrypted) => decrypt_age(encrypted, &matches)
+                .await
+                .context(format!("Unable to decrypt {key}"))?,
+            None => env_value(&value),
+        };
+
+        file_settings.push((key, value));
+    }
+
+    FILE_SETTINGS
+        .set(file_settings)
+        .map_err(|_| anyhow::anyhow!("The configuration file was already applied"))?;
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpStream, task::JoinSet};
+
+use crate::{cluster::NodeRole, clusters, discovery, dns_cache, error::AppResult, proxmox, CONFIG};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const PROXMOX_API_PORT: u16 = 8006;
+const SSH_PORT: u16 = 22;
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PortState {
+    Open,
+    // Something answered with a reset, the host is up but nothing listens on the port.
+    Refused,
+    // Nothing answered before the timeout, the usual sign of a dropping firewall.
+    Filtered,
+    Unreachable,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Diagnosis {
+    Healthy,
+    // The host answers on some ports and silently drops others.
+    Firewall,
+    ServiceDown,
+    // Nothing answers at all, the host is down or cut off entirely.
+    Unreachable,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PortCheck {
+    pub port: u16,
+    pub service: &'static str,
+    pub state: PortState,
+    pub latency_ms: Option<u128>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TargetCheck {
+    pub kind: &'static str,
+    pub name: String,
+    pub address: String,
+    pub diagnosis: Diagnosis,
+    pub ports: Vec<PortCheck>,
+}
+
+#[derive(Deserialize)]
+struct ClusterStatusEntry {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    ip: Option<String>,
+}
+
+struct Target {
+    kind: &'static str,
+    name: String,
+    address: String,
+    ports: Vec<(u16, &'static str)>,
+}
+
+async fn probe(address: &str, port: u16, service: &'static str) -> PortCheck {
+    let started_at = Instant::now();
+
+    let result = match dns_cache::resolve(address).await {
+        Ok(ips) => match ips.first() {
+            Some(ip) => tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect((*ip, port)))
+                .await
+                .map_err(|_| (PortState::Filtered, "Timed out".to_string()))
+                .and_then(|result| {
+                    result.map_err(|err| match err.kind() {
+                        ErrorKind::ConnectionRefused => (PortState::Refused, err.to_string()),
+                        _ => (PortState::Unreachable, err.to_string()),
+                    })
+                }),
+            None => Err((PortState::Unreachable, format!("{address} has no address"))),
+        },
+        Err(err) => Err((PortState::Unreachable, err.to_string())),
+    };
+
+    match result {
+        Ok(_) => PortCheck {
+            port,
+            service,
+            state: PortState::Open,
+            latency_ms: Some(started_at.elapsed().as_millis()),
+            error: None,
+        },
+        Err((state, error)) => PortCheck {
+            port,
+            service,
+            state,
+            latency_ms: None,
+            error: Some(error),
+        },
+    }
+}
+
+fn diagnose(ports: &[PortCheck]) -> Diagnosis {
+    let answered = ports
+        .iter()
+        .any(|check| matches!(check.state, PortState::Open | PortState::Refused));
+
+    if ports.iter().all(|check| check.state == PortState::Open) {
+        Diagnosis::Healthy
+    } else if answered && ports.iter().any(|check| check.state == PortState::Filtered) {
+        Diagnosis::Firewall
+    } else if answered {
+        Diagnosis::ServiceDown
+    } else {
+        Diagnosis::Unreachable
+    }
+}
+
+async fn check(target: Target) -> TargetCheck {
+    let mut ports = vec![];
+
+    for (port, service) in target.ports {
+        ports.push(probe(&target.address, port, service).await);
+    }
+
+    TargetCheck {
+        kind: target.kind,
+        name: target.name,
+        address: target.address,
+        diagnosis: diagnose(&ports),
+        ports,
+    }
+}
+
+// k3s serves the supervisor on the API port, only RKE2 servers are expected on 9345.
+fn server_targets() -> Vec<Target> {
+    discovery::subscribe()
+        .borrow()
+        .iter()
+        .filter_map(|ipam| {
+            let assignment = ipam.assignment.as_ref()?;
+
+            if assignment.role != NodeRole::Server {
+                return None;
+            }
+
+            let mut ports = vec![
+                (SSH_PORT, "ssh"),
+                (clusters::K8S_API_PORT, "kubernetes-api"),
+            ];
+
+            if let Some(port) = clusters::find(&assignment.cluster)
+                .and_then(|cluster| cluster.distro.supervisor_backend_port())
+            {
+                ports.push((port, "supervisor"));
+            }
+
+            Some(Target {
+                kind: "k3s-server",
+                name: ipam.hostname.clone().unwrap_or_else(|| ipam.ip.clone()),
+                address: ipam.ip.clone(),
+                ports,
+            })
+        })
+        .collect()
+}
+
+// The API address is always checked, the nodes only when the API answers with their addresses.
+// SSH is checked next to the API so a dropped port stands out from a node that is down.
+async fn proxmox_targets(client: &reqwest::Client) -> Vec<Target> {
+    let api = reqwest::Url::parse(&CONFIG.proxmox_api_url)
+        .ok()
+        .and_then(|url| Some((url.host_str()?.to_string(), url.port_or_known_default()?)));
+
+    let mut targets = api
+        .into_iter()
+        .map(|(host, port)| Target {
+            kind: "proxmox-api",
+            name: host.clone(),
+            address: host,
+            ports: vec![(port, "proxmox-api")],
+        })
+        .collect::<Vec<_>>();
+
+    let nodes = proxmox::get::<Vec<ClusterStatusEntry>>(client, "/cluster/status")
+        .await
+        .unwrap_or_default();
+
+    targets.extend(
+        nodes
+            .into_iter()
+            .filter(|entry| entry.kind == "node")
+            .filter_map(|entry| {
+                Some(Target {
+                    kind: "proxmox-node",
+                    name: entry.name,
+                    address: entry.ip?,
+                    ports: vec![(PROXMOX_API_PORT, "proxmox-api"), (SSH_PORT, "ssh")],
+                })
+            }),
+    );
+
+    targets
+}
+
+pub(crate) async fn self_test(client: &reqwest::Client) -> Vec<TargetCheck> {
+    let mut tasks = JoinSet::new();
+
+    for target in proxmox_targets(client)
+        .await
+        .into_iter()
+        .chain(server_targets())
+    {
+        tasks.spawn(check(target));
+    }
+
+    let mut checks = tasks.join_all().await;
+    checks.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+
+    checks
+}
+
+pub(crate) async fn get_connectivity(
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<Vec<TargetCheck>>> {
+    Ok(Json(self_test(&client).await))
+}
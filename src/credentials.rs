@@ -0,0 +1,226 @@
+use std::{collections::BTreeMap, io::Read};
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, Request},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Extension, Json, Router,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{clusters::CLUSTERS, error::AppResult, events, secrets, CONFIG, STATE};
+
+const CREDENTIALS_KEY: &str = "api_credentials";
+
+// Only the hash of a token is kept, the token itself is returned once when issued.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiCredential {
+    pub id: String,
+    pub cluster: String,
+    pub token_sha256: String,
+    pub created_at: i64,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct IssueCredentialRequest {
+    cluster: String,
+}
+
+#[derive(Serialize)]
+pub struct IssuedCredential {
+    pub id: String,
+    pub cluster: String,
+    pub token: String,
+}
+
+// The cluster the caller's credential is scoped to, absent when scoped credentials are disabled.
+#[derive(Clone, Debug)]
+pub(crate) struct ClusterScope(pub String);
+
+// Read straight from the kernel, issuing credentials doesn't need openssl.
+fn random_hex(bytes: usize) -> anyhow::Result<String> {
+    let mut buffer = vec![0; bytes];
+
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut buffer))
+        .context("Unable to read /dev/urandom")?;
+
+    Ok(buffer.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn hash_token(token: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(Sha256::digest(token.as_bytes()))
+}
+
+fn find_credential(token: &str) -> Option<ApiCredential> {
+    let token_sha256 = hash_token(token);
+
+    STATE
+        .get::<BTreeMap<String, ApiCredential>>(CREDENTIALS_KEY)
+        .unwrap_or_default()
+        .into_values()
+        .find(|credential| credential.token_sha256 == token_sha256)
+}
+
+// Rejects callers holding a credential of another cluster, always passes when scoped credentials
+// are disabled.
+pub(crate) fn authorize(
+    scope: &Option<Extension<ClusterScope>>,
+    cluster: &str,
+) -> anyhow::Result<()> {
+    match scope {
+        Some(Extension(ClusterScope(scope))) if scope != cluster => {
+            anyhow::bail!("This credential is not allowed to access cluster {cluster}")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn bearer_token(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+fn is_admin(token: &str) -> bool {
+    secrets::secret("admin_token", &CONFIG.admin_token)
+        .is_some_and(|admin_token| secrets::secrets_match(&admin_token, token))
+}
+
+fn reject(request: &Request, status: StatusCode, missing: &str) -> Response {
+    events::record(
+        "audit",
+        None,
+        format!(
+            "Rejected {} {} without a valid {missing}",
+            request.method(),
+            request.uri().path()
+        ),
+        None,
+    );
+
+    (status, format!("A valid {missing} is required")).into_response()
+}
+
+// Credentials are managed with the admin token, a cluster credential can neither mint nor revoke
+// others. The admin endpoints are disabled until it is set.
+pub(crate) async fn require_admin(request: Request, next: Next) -> Response {
+    if secrets::secret("admin_token", &CONFIG.admin_token).is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            "Admin endpoints are disabled, set admin_token to enable them",
+        )
+            .into_response();
+    }
+
+    if !bearer_token(&request).is_some_and(is_admin) {
+        return reject(&request, StatusCode::FORBIDDEN, "admin token");
+    }
+
+    next.run(request).await
+}
+
+// The admin token is accepted everywhere and isn't scoped to any cluster.
+pub(crate) async fn require_credentials(mut request: Request, next: Next) -> Response {
+    if !CONFIG.scoped_credentials || bearer_token(&request).is_some_and(is_admin) {
+        return next.run(request).await;
+    }
+
+    let credential = bearer_token(&request).and_then(find_credential);
+
+    let Some(credential) = credential else {
+        return reject(&request, StatusCode::UNAUTHORIZED, "API credential");
+    };
+
+    request
+        .extensions_mut()
+        .insert(ClusterScope(credential.cluster));
+
+    next.run(request).await
+}
+
+pub(crate) async fn issue_credential(
+    Json(request): Json<IssueCredentialRequest>,
+) -> AppResult<Json<IssuedCredential>> {
+    let cluster = request.cluster;
+
+    CLUSTERS
+        .iter()
+        .find(|candidate| candidate.name == cluster)
+        .context(format!("Unknown cluster {cluster}"))?;
+
+    let token = random_hex(32)?;
+
+    let credential = ApiCredential {
+        id: random_hex(8)?,
+        cluster: cluster.clone(),
+        token_sha256: hash_token(&token),
+        created_at: chrono::Utc::now().timestamp(),
+    };
+
+    STATE.update(
+        CREDENTIALS_KEY,
+        |credentials: &mut BTreeMap<String, ApiCredential>| {
+            credentials.insert(credential.id.clone(), credential.clone());
+        },
+    )?;
+
+    events::record(
+        "audit",
+        None,
+        format!(
+            "Issued API credential {} for cluster {cluster}",
+            credential.id
+        ),
+        None,
+    );
+
+    Ok(Json(IssuedCredential {
+        id: credential.id,
+        cluster,
+        token,
+    }))
+}
+
+pub(crate) async fn get_credentials() -> AppResult<Json<Vec<ApiCredential>>> {
+    Ok(Json(
+        STATE
+            .get::<BTreeMap<String, ApiCredential>>(CREDENTIALS_KEY)
+            .unwrap_or_default()
+            .into_values()
+            .collect(),
+    ))
+}
+
+pub(crate) async fn revoke_credential(
+    Path(id): Path<String>,
+) -> AppResult<Json<Option<ApiCredential>>> {
+    let mut revoked = None;
+
+    STATE.update(
+        CREDENTIALS_KEY,
+        |credentials: &mut BTreeMap<String, ApiCredential>| {
+            revoked = credentials.remove(&id);
+        },
+    )?;
+
+    if revoked.is_some() {
+        events::record("audit", None, format!("Revoked API credential {id}"), None);
+    }
+
+    Ok(Json(revoked))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/", get(get_credentials).post(issue_credential))
+        .route("/:id", delete(revoke_credential))
+        .route_layer(middleware::from_fn(require_admin))
+}
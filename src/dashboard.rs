@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use axum::{extract::State, middleware, routing::get, Extension, Router};
+use maud::{html, Markup, DOCTYPE};
+
+#[cfg(feature = "proxy")]
+use crate::proxy;
+#[cfg(feature = "pki")]
+use crate::{certificate_expiry, CONFIG};
+use crate::{
+    cluster::{get_all_vms_for_node, get_nodes, IpamEntry},
+    credentials::{self, ClusterScope},
+    discovery,
+    error::AppResult,
+    events,
+};
+
+const RECENT_EVENTS: usize = 25;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; width: 100%; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.3em 0.6em; text-align: left; }
+th { background: #f4f4f4; }
+.ok { color: #1a7f37; }
+.warn { color: #9a6700; }
+.bad { color: #cf222e; }
+";
+
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|date| date.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_default()
+}
+
+async fn vm_statuses(client: &reqwest::Client) -> anyhow::Result<HashMap<String, String>> {
+    let mut statuses = HashMap::new();
+
+    for node in get_nodes(client.clone()).await?.data {
+        for vm in get_all_vms_for_node(client.clone(), &node.node).await?.data {
+            statuses.insert(vm.vmid.to_string(), vm.status);
+        }
+    }
+
+    Ok(statuses)
+}
+
+// A scoped credential only sees its own cluster's nodes, backends, certificates and events.
+fn visible_ipams(scope: &Option<Extension<ClusterScope>>) -> Vec<IpamEntry> {
+    discovery::subscribe()
+        .borrow()
+        .iter()
+        .filter(|ipam| {
+            ipam.assignment.as_ref().is_some_and(|assignment| {
+                credentials::authorize(scope, &assignment.cluster).is_ok()
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+fn nodes_section(statuses: &HashMap<String, String>, ipams: &[IpamEntry]) -> Markup {
+    html! {
+        h2 { "Nodes" }
+        table {
+            tr { th { "Hostname" } th { "IP" } th { "VM" } th { "Status" } th { "Role" } th { "Pool" } th { "Proxy" } }
+            @for ipam in ipams {
+                @let assignment = ipam.assignment.as_ref();
+                @let status = ipam.vmid.as_ref().and_then(|vmid| statuses.get(vmid));
+                tr {
+                    td { (ipam.hostname.as_deref().unwrap_or("-")) }
+                    td { (ipam.ip) }
+                    td { (ipam.vmid.as_deref().unwrap_or("static")) }
+                    td class=(if status.is_some_and(|status| status == "running") { "ok" } else { "warn" }) {
+                        (status.map(String::as_str).unwrap_or("-"))
+                    }
+                    td { (assignment.map(|assignment| assignment.role.as_str()).unwrap_or_default()) }
+                    td { (assignment.and_then(|assignment| assignment.pool.as_deref()).unwrap_or("-")) }
+                    td { (if assignment.is_some_and(|assignment| assignment.proxy) { "yes" } else { "no" }) }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "proxy")]
+fn backends_section(ipams: &[IpamEntry]) -> Markup {
+    let mut backends = proxy::backend_health()
+        .into_iter()
+        .filter(|(ip, _)| ipams.iter().any(|ipam| &ipam.ip == ip))
+        .collect::<Vec<_>>();
+    backends.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    html! {
+        h2 { "Proxy backends" }
+        table {
+            tr { th { "Backend" } th { "Health" } th { "Published" } th { "Last check" } th { "Last error" } }
+            @for (ip, health) in &backends {
+                tr {
+                    td { (ip) }
+                    td class=(if health.healthy { "ok" } else { "bad" }) {
+                        (if health.healthy { "healthy" } else { "unhealthy" })
+                    }
+                    td { (if health.published { "yes" } else { "no" }) }
+                    td { (format_timestamp(health.last_check)) }
+                    td { (health.last_error.as_deref().unwrap_or("")) }
+                }
+            }
+        }
+    }
+}
+
+// Sections of subsystems that aren't built in are left out.
+#[cfg(not(feature = "proxy"))]
+fn backends_section(_ipams: &[IpamEntry]) -> Markup {
+    html! {}
+}
+
+#[cfg(feature = "pki")]
+async fn certificates_section(scope: &Option<Extension<ClusterScope>>) -> Markup {
+    let now = chrono::Utc::now().timestamp();
+
+    match certificate_expiry::tracked_certificates().await {
+        Ok(mut certificates) => {
+            certificates
+                .retain(|certificate| credentials::authorize(scope, &certificate.cluster).is_ok());
+            certificates.sort_by_key(|certificate| certificate.not_after);
+
+            html! {
+                h2 { "Certificates" }
+                table {
+                    tr { th { "Kind" } th { "Name" } th { "Expires" } th { "Days left" } }
+                    @for certificate in &certificates {
+                        @let days_left = (certificate.not_after - now) / 86400;
+                        tr {
+                            td { (certificate.kind) }
+                            td { (certificate.name) }
+                            td { (format_timestamp(certificate.not_after)) }
+                            td class=(if days_left < 0 { "bad" } else if days_left < CONFIG.certificate_expiry_warning_days { "warn" } else { "ok" }) {
+                                (days_left)
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Err(err) => html! {
+            h2 { "Certificates" }
+            p class="bad" { "Unable to read certificates: " (err) }
+        },
+    }
+}
+
+#[cfg(not(feature = "pki"))]
+async fn certificates_section(_scope: &Option<Extension<ClusterScope>>) -> Markup {
+    html! {}
+}
+
+fn events_section(scope: &Option<Extension<ClusterScope>>) -> Markup {
+    let events = events::list()
+        .into_iter()
+        .filter(|event| events::is_visible(event, scope))
+        .collect::<Vec<_>>();
+
+    html! {
+        h2 { "Recent events" }
+        table {
+            tr { th { "Time" } th { "Kind" } th { "VM" } th { "Message" } }
+            @for event in events.iter().rev().take(RECENT_EVENTS) {
+                tr {
+                    td { (format_timestamp(event.timestamp)) }
+                    td { (event.kind) }
+                    td { (event.vmid.as_deref().unwrap_or("-")) }
+                    td { (event.message) }
+                }
+            }
+        }
+    }
+}
+
+async fn get_dashboard(
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Markup> {
+    let statuses = vm_statuses(&client).await.unwrap_or_default();
+    let ipams = visible_ipams(&scope);
+
+    Ok(html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                meta http-equiv="refresh" content="30";
+                title { "k3s-proxmox-helper" }
+                style { (STYLE) }
+            }
+            body {
+                h1 { "k3s-proxmox-helper" }
+                (nodes_section(&statuses, &ipams))
+                (backends_section(&ipams))
+                (certificates_section(&scope).await)
+                (events_section(&scope))
+            }
+        }
+    })
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/dashboard", get(get_dashboard))
+        .route_layer(middleware::from_fn(credentials::require_credentials))
+}
@@ -0,0 +1,459 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hickory_resolver::proto::rr::RData;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    bus::Bus,
+    cluster::{self, IpamEntry, NodeRole},
+    clusters, dns_cache,
+    error::AppResult,
+    events, logging, metrics, node_history, registrations,
+    roles::{self, NodeAssignment, NodeOs, Provisioning},
+    status, CONFIG,
+};
+
+const SYNCHRONIZATION_INTERVAL: Duration = Duration::from_secs(10);
+// Publications of a burst of hook notifications, beyond that publishers wait for the consumers.
+const EVENT_CAPACITY: usize = 16;
+
+static IPAMS: Lazy<Bus<Vec<IpamEntry>>> = Lazy::new(|| Bus::new(Vec::new(), EVENT_CAPACITY));
+// Seeded entries don't count, only a synchronization with Proxmox makes the data fresh.
+static LAST_SYNCHRONIZATION: Lazy<RwLock<Option<i64>>> = Lazy::new(|| RwLock::new(None));
+
+pub(crate) fn subscribe() -> watch::Receiver<Vec<IpamEntry>> {
+    IPAMS.latest()
+}
+
+// Every published list in order, unlike `subscribe` which only ever holds the latest one.
+pub(crate) fn events() -> mpsc::Receiver<Vec<IpamEntry>> {
+    IPAMS.events()
+}
+
+#[derive(Deserialize)]
+struct StaticEntries {
+    entries: Vec<StaticEntry>,
+}
+
+// Cluster members living outside of Proxmox, e.g. bare-metal nodes.
+#[derive(Deserialize)]
+struct StaticEntry {
+    hostname: String,
+    ip: String,
+    // Lets bare-metal machines be recognized while they boot from the network.
+    mac: Option<String>,
+    vnet: Option<String>,
+    subnet: Option<String>,
+    tags: Option<String>,
+    // Bypasses the role mapping when set, along with `cluster`.
+    cluster: Option<String>,
+    role: Option<NodeRole>,
+    pool: Option<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    proxy: Option<bool>,
+    #[serde(default)]
+    provisioning: Provisioning,
+    #[serde(default)]
+    os: NodeOs,
+}
+
+impl From<StaticEntry> for IpamEntry {
+    fn from(entry: StaticEntry) -> Self {
+        let vnet = entry
+            .vnet
+            .unwrap_or_else(|| CONFIG.k3s_internal_network_interface.clone());
+
+        let assignment = match entry.role {
+            Some(role) => Some(NodeAssignment {
+                cluster: entry.cluster.unwrap_or_else(|| {
+                    clusters::cluster_for(Some(&entry.hostname), Some(&vnet), entry.tags.as_deref())
+                        .map(|cluster| cluster.name.clone())
+                        .unwrap_or_else(clusters::default_cluster_name)
+                }),
+                role,
+                pool: entry.pool,
+                labels: entry.labels,
+                proxy: roles::is_proxied(role, entry.os, entry.proxy),
+                provisioning: entry.provisioning,
+                os: entry.os,
+            }),
+            None => roles::assign(Some(&entry.hostname), Some(&vnet), entry.tags.as_deref()),
+        };
+
+        let mut ipam = IpamEntry {
+            id: String::new(),
+            zone: "static".to_string(),
+            hostname: Some(entry.hostname),
+            vmid: None,
+            vnet,
+            ip: entry.ip,
+            mac: entry.mac,
+            subnet: entry.subnet.unwrap_or_default(),
+            tags: entry.tags,
+            assignment,
+        };
+
+        ipam.id = ipam.stable_id();
+        ipam
+    }
+}
+
+// The file is read on every pass so edits are picked up without a restart.
+pub(crate) fn static_entries() -> anyhow::Result<Vec<IpamEntry>> {
+    let Some(path) = &CONFIG.static_entries_path else {
+        return Ok(vec![]);
+    };
+
+    let content =
+        std::fs::read_to_string(path).context(format!("Unable to read static entries {path}"))?;
+
+    let entries: StaticEntries = if path.ends_with(".json") {
+        serde_json::from_str(&content).context(format!("Invalid static entries {path}"))?
+    } else {
+        toml::from_str(&content).context(format!("Invalid static entries {path}"))?
+    };
+
+    Ok(entries.entries.into_iter().map(IpamEntry::from).collect())
+}
+
+pub(crate) fn is_proxy_member(ipam: &IpamEntry) -> bool {
+    ipam.assignment
+        .as_ref()
+        .is_some_and(|assignment| assignment.proxy)
+}
+
+// Returns the IPAM entries of a Proxmox node along with the role mapping they fall under.
+pub(crate) async fn discover_node_ipams(
+    client: &reqwest::Client,
+    node: &str,
+) -> anyhow::Result<Vec<IpamEntry>> {
+    let guests = cluster::get_all_guests_for_node(client.clone(), node).await?;
+
+    let mut ipams = cluster::get_ipams_for_node(client.clone(), node)
+        .await?
+        .data;
+
+    // The node's guest list doesn't say which pool a guest is in, the cluster-wide one does.
+    let pools = if clusters::CLUSTERS
+        .iter()
+        .any(|cluster| cluster.proxmox_pool().is_some())
+    {
+        cluster::get_vm_resources(client)
+            .await?
+            .into_iter()
+            .map(|resource| (resource.vmid.to_string(), resource.pool))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    for ipam in &mut ipams {
+        let tags = guests
+            .iter()
+            .find(|guest| ipam.vmid.as_ref() == Some(&guest.guest.vmid.to_string()))
+            .and_then(|guest| guest.guest.tags.clone());
+
+        let pool = ipam
+            .vmid
+            .as_ref()
+            .and_then(|vmid| pools.get(vmid).cloned().flatten());
+
+        // A guest outside of its cluster's pool isn't a member, the pool bounds what the helper
+        // may touch.
+        ipam.assignment =
+            roles::assign(ipam.hostname.as_deref(), Some(&ipam.vnet), tags.as_deref()).filter(
+                |assignment| {
+                    clusters::find(&assignment.cluster)
+                        .is_none_or(|cluster| cluster.includes_pool(pool.as_deref()))
+                },
+            );
+        ipam.tags = tags;
+        ipam.id = ipam.stable_id();
+    }
+
+    Ok(ipams)
+}
+
+pub(crate) async fn discover_ipams(client: &reqwest::Client) -> anyhow::Result<Vec<IpamEntry>> {
+    let nodes = cluster::get_nodes(client.clone()).await?.data;
+
+    let mut ipams = vec![];
+
+    for node in nodes {
+        ipams.extend(discover_node_ipams(client, &node.node).await?);
+    }
+
+    ipams.extend(static_entries()?);
+
+    Ok(ipams)
+}
+
+async fn synchronize(client: &reqwest::Client) -> anyhow::Result<Vec<IpamEntry>> {
+    let ipams = discover_ipams(client).await?;
+
+    registrations::register(&ipams)?;
+    node_history::seen(&ipams);
+
+    IPAMS.publish(ipams.clone()).await;
+
+    let now = chrono::Utc::now().timestamp();
+    *LAST_SYNCHRONIZATION.write().unwrap() = Some(now);
+
+    metrics::set_gauge(
+        "k3s_helper_ipam_last_sync_timestamp_seconds",
+        "Time of the last successful IPAM synchronization",
+        &[],
+        now as f64,
+    );
+
+    Ok(ipams)
+}
+
+pub(crate) fn last_synchronization() -> Option<i64> {
+    *LAST_SYNCHRONIZATION.read().unwrap()
+}
+
+pub(crate) fn data_age() -> Option<i64> {
+    last_synchronization().map(|timestamp| chrono::Utc::now().timestamp() - timestamp)
+}
+
+// Never stale without a threshold, never synchronized data is always stale otherwise.
+pub(crate) fn is_stale() -> bool {
+    match (CONFIG.ipam_max_age, data_age()) {
+        (None, _) => false,
+        (Some(max_age), Some(age)) => age > max_age,
+        (Some(_), None) => true,
+    }
+}
+
+// Tells consumers how old the IPAM data behind the response is, and refuses to answer past
+// `ipam_max_age`.
+pub(crate) async fn data_age_headers(request: Request, next: Next) -> Response {
+    if is_stale() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            match data_age() {
+                Some(age) => format!("IPAM data is {age} seconds old"),
+                None => "IPAM data was never synchronized".to_string(),
+            },
+        )
+            .into_response();
+    }
+
+    let mut response = next.run(request).await;
+
+    if let Some(age) = data_age() {
+        response
+            .headers_mut()
+            .insert("X-Data-Age", HeaderValue::from(age));
+    }
+
+    response
+}
+
+async fn resolve_fallback_backends(name: &str) -> anyhow::Result<Vec<IpamEntry>> {
+    let resolver = dns_cache::resolver()?;
+
+    // Names starting with an underscore are SRV records (e.g. `_k3s._tcp.internal`), anything else
+    // is resolved as a plain host name.
+    let hosts = if name.starts_with('_') {
+        resolver
+            .srv_lookup(name)
+            .await?
+            .answers()
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::SRV(srv) => Some(srv.target.to_string()),
+                _ => None,
+            })
+            .collect()
+    } else {
+        vec![name.to_string()]
+    };
+
+    let mut ipams = vec![];
+
+    for host in hosts {
+        for ip in dns_cache::resolve(&host).await? {
+            let mut ipam = IpamEntry {
+                id: String::new(),
+                zone: "dns".to_string(),
+                hostname: Some(host.trim_end_matches('.').to_string()),
+                vmid: None,
+                vnet: CONFIG.k3s_internal_network_interface.clone(),
+                ip: ip.to_string(),
+                mac: None,
+                subnet: String::new(),
+                tags: None,
+                assignment: Some(NodeAssignment {
+                    cluster: clusters::default_cluster_name(),
+                    role: NodeRole::Server,
+                    pool: None,
+                    labels: BTreeMap::new(),
+                    proxy: true,
+                    provisioning: Provisioning::default(),
+                    os: NodeOs::default(),
+                }),
+            };
+
+            ipam.id = ipam.stable_id();
+            ipams.push(ipam);
+        }
+    }
+
+    Ok(ipams)
+}
+
+// Seeds are only used while there is no IPAM data at all, the last snapshot is kept as is
+// otherwise.
+pub(crate) async fn seed(mut ipams: Vec<IpamEntry>) -> bool {
+    // Snapshots persisted before entries had an id get one now.
+    for ipam in ipams.iter_mut().filter(|ipam| ipam.id.is_empty()) {
+        ipam.id = ipam.stable_id();
+    }
+
+    IPAMS
+        .publish_if(|current| {
+            if current.is_empty() && !ipams.is_empty() {
+                *current = ipams;
+                true
+            } else {
+                false
+            }
+        })
+        .await
+}
+
+async fn seed_from_dns() {
+    let Some(name) = &CONFIG.backend_fallback_dns else {
+        return;
+    };
+
+    if !IPAMS.borrow().is_empty() {
+        return;
+    }
+
+    match resolve_fallback_backends(name).await {
+        Ok(ipams) if !ipams.is_empty() => {
+            let count = ipams.len();
+
+            if seed(ipams).await {
+                logging::info!("Seeding {count} proxy backends from {name}");
+            }
+        }
+        Ok(_) => logging::info!("No proxy backend found in {name}"),
+        Err(err) => logging::warn!("Unable to resolve fallback backends from {name}: {err}"),
+    }
+}
+
+pub(crate) async fn synchronize_ipams(client: reqwest::Client) -> anyhow::Result<()> {
+    loop {
+        let result = synchronize(&client).await;
+        status::record_job("ipam-sync", &result);
+
+        if let Err(err) = result {
+            logging::warn!("Unable to synchronize IPAMs: {err}");
+
+            seed_from_dns().await;
+        }
+
+        tokio::time::sleep(SYNCHRONIZATION_INTERVAL).await;
+    }
+}
+
+fn describe(ipam: &IpamEntry) -> String {
+    match &ipam.hostname {
+        Some(hostname) => format!("{hostname} ({})", ipam.ip),
+        None => ipam.ip.clone(),
+    }
+}
+
+// Records nodes showing up, going away or changing address in the event log, every transition is
+// seen even when several updates are published in a row.
+pub(crate) async fn record_changes() -> anyhow::Result<()> {
+    loop {
+        let mut changes = events();
+        let mut previous = IPAMS.borrow().clone();
+
+        while let Some(ipams) = changes.recv().await {
+            for ipam in &ipams {
+                match previous.iter().find(|known| known.id == ipam.id) {
+                    None => events::record(
+                        "discovery",
+                        ipam.vmid.as_deref(),
+                        format!("Discovered {}", describe(ipam)),
+                        None,
+                    ),
+                    Some(known) if known.ip != ipam.ip => events::record(
+                        "discovery",
+                        ipam.vmid.as_deref(),
+                        format!("{} moved to {}", describe(known), ipam.ip),
+                        None,
+                    ),
+                    Some(_) => {}
+                }
+            }
+
+            for known in previous
+                .iter()
+                .filter(|known| !ipams.iter().any(|ipam| ipam.id == known.id))
+            {
+                events::record(
+                    "discovery",
+                    known.vmid.as_deref(),
+                    format!("Lost {}", describe(known)),
+                    None,
+                );
+            }
+
+            previous = ipams;
+        }
+
+        logging::warn!("The discovery event stream closed, subscribing again");
+    }
+}
+
+// Refreshes the entries of a single VM, e.g. when Proxmox reports it started or moved, without
+// waiting for the next synchronization.
+pub(crate) async fn resync_vm(
+    client: &reqwest::Client,
+    vmid: &str,
+    node: &str,
+) -> anyhow::Result<Vec<IpamEntry>> {
+    let entries = discover_node_ipams(client, node)
+        .await?
+        .into_iter()
+        .filter(|ipam| ipam.vmid.as_deref() == Some(vmid))
+        .collect::<Vec<_>>();
+
+    IPAMS
+        .publish_if(|ipams| {
+            ipams.retain(|ipam| ipam.vmid.as_deref() != Some(vmid));
+            ipams.extend(entries.clone());
+            true
+        })
+        .await;
+
+    Ok(entries)
+}
+
+pub(crate) async fn sync_ipams(
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<Vec<IpamEntry>>> {
+    Ok(Json(synchronize(&client).await?))
+}
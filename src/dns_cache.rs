@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hickory_resolver::TokioResolver;
+use once_cell::sync::Lazy;
+
+use crate::{logging, metrics, CONFIG};
+
+static RESOLVER: Lazy<Result<TokioResolver, String>> = Lazy::new(|| {
+    TokioResolver::builder_tokio()
+        .and_then(|builder| builder.build())
+        .map_err(|err| err.to_string())
+});
+
+static CACHE: Lazy<Mutex<HashMap<String, CachedLookup>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone)]
+struct CachedLookup {
+    // The last addresses the name resolved to, kept after they expire in case the resolver fails.
+    addresses: Vec<IpAddr>,
+    expires_at: Instant,
+    error: Option<String>,
+}
+
+pub(crate) fn resolver() -> anyhow::Result<&'static TokioResolver> {
+    RESOLVER
+        .as_ref()
+        .map_err(|err| anyhow::Error::msg(format!("Unable to set up the DNS resolver: {err}")))
+}
+
+fn record(result: &'static str) {
+    metrics::increment_counter(
+        "k3s_helper_dns_cache_lookups_total",
+        "Backend names resolved from the cache, from DNS or not at all",
+        &[("result", result)],
+    );
+}
+
+// Backends may be given by name, resolving them on every connection would put the resolver on the
+// path of every kubectl call. Addresses are kept for their DNS TTL, up to `dns_cache_max_ttl`, and
+// failures for `dns_cache_negative_ttl`. While the resolver fails, the last known addresses keep
+// being used.
+pub(crate) async fn resolve(host: &str) -> anyhow::Result<Vec<IpAddr>> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![ip]);
+    }
+
+    let cached = CACHE.lock().unwrap().get(host).cloned();
+
+    if let Some(cached) = &cached {
+        if cached.expires_at > Instant::now() {
+            return match &cached.error {
+                None => {
+                    record("hit");
+                    Ok(cached.addresses.clone())
+                }
+                Some(err) => {
+                    record("negative");
+                    anyhow::bail!("Unable to resolve {host}: {err}")
+                }
+            };
+        }
+    }
+
+    let now = Instant::now();
+
+    let lookup = match resolver() {
+        Ok(resolver) => resolver
+            .lookup_ip(host)
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    let entry = match lookup {
+        Ok(lookup) if lookup.iter().next().is_some() => {
+            record("miss");
+
+            CachedLookup {
+                addresses: lookup.iter().collect(),
+                expires_at: lookup
+                    .valid_until()
+                    .min(now + Duration::from_secs(CONFIG.dns_cache_max_ttl)),
+                error: None,
+            }
+        }
+        result => {
+            let err = result
+                .err()
+                .unwrap_or_else(|| "no address found".to_string());
+
+            match cached.filter(|cached| !cached.addresses.is_empty()) {
+                Some(cached) => {
+                    record("stale");
+                    logging::warn!(
+                        "Unable to resolve {host}, using its last known addresses: {err}"
+                    );
+
+                    CachedLookup {
+                        expires_at: now + Duration::from_secs(CONFIG.dns_cache_negative_ttl),
+                        ..cached
+                    }
+                }
+                None => {
+                    record("failed");
+
+                    CachedLookup {
+                        addresses: vec![],
+                        expires_at: now + Duration::from_secs(CONFIG.dns_cache_negative_ttl),
+                        error: Some(err),
+                    }
+                }
+            }
+        }
+    };
+
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(host.to_string(), entry.clone());
+
+    match entry.error {
+        None => Ok(entry.addresses),
+        Some(err) => anyhow::bail!("Unable to resolve {host}: {err}"),
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+use axum::{
+    extract::Request,
+    http::{Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+
+use crate::{credentials, error::AppResult, events, jobs};
+
+// When the drain started, zero while the helper serves normally.
+static DRAINING_SINCE: AtomicI64 = AtomicI64::new(0);
+static PROXIED_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static MUTATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Serialize)]
+pub struct DrainStatus {
+    pub draining: bool,
+    pub since: Option<i64>,
+    pub proxied_connections: usize,
+    pub mutations: usize,
+    pub running_jobs: Vec<String>,
+    // Nothing left in flight, the helper can be restarted.
+    pub drained: bool,
+}
+
+// Counts a piece of work as in flight for as long as it is held.
+pub(crate) struct InFlight(&'static AtomicUsize);
+
+impl InFlight {
+    fn new(counter: &'static AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        InFlight(counter)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub(crate) fn is_draining() -> bool {
+    DRAINING_SINCE.load(Ordering::SeqCst) != 0
+}
+
+pub(crate) fn track_connection() -> InFlight {
+    InFlight::new(&PROXIED_CONNECTIONS)
+}
+
+fn status() -> DrainStatus {
+    let since = Some(DRAINING_SINCE.load(Ordering::SeqCst)).filter(|since| *since != 0);
+    let proxied_connections = PROXIED_CONNECTIONS.load(Ordering::SeqCst);
+    let mutations = MUTATIONS.load(Ordering::SeqCst);
+    let running_jobs = jobs::running();
+
+    DrainStatus {
+        draining: since.is_some(),
+        since,
+        proxied_connections,
+        mutations,
+        drained: since.is_some()
+            && proxied_connections == 0
+            && mutations == 0
+            && running_jobs.is_empty(),
+        running_jobs,
+    }
+}
+
+// Reads keep being answered while draining, only what would start new work is refused. The drain
+// itself can always be lifted, and a support bundle gathered.
+pub(crate) async fn reject_mutations(request: Request, next: Next) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) || matches!(
+        request.uri().path(),
+        "/admin/drain" | "/admin/support-bundle"
+    ) {
+        return next.run(request).await;
+    }
+
+    if is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The helper is draining for maintenance",
+        )
+            .into_response();
+    }
+
+    let _mutation = InFlight::new(&MUTATIONS);
+
+    next.run(request).await
+}
+
+async fn get_drain() -> AppResult<Json<DrainStatus>> {
+    Ok(Json(status()))
+}
+
+async fn start_drain() -> AppResult<Json<DrainStatus>> {
+    let now = chrono::Utc::now().timestamp();
+
+    if DRAINING_SINCE
+        .compare_exchange(0, now, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+    {
+        events::record(
+            "drain",
+            None,
+            "Draining, new proxied connections and API mutations are refused",
+            None,
+        );
+    }
+
+    Ok(Json(status()))
+}
+
+async fn stop_drain() -> AppResult<Json<DrainStatus>> {
+    if DRAINING_SINCE.swap(0, Ordering::SeqCst) != 0 {
+        events::record("drain", None, "Drain lifted, serving normally", None);
+    }
+
+    Ok(Json(status()))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route(
+            "/drain",
+            get(get_drain).post(start_drain).delete(stop_drain),
+        )
+        .route_layer(middleware::from_fn(credentials::require_admin))
+}
@@ -3,6 +3,8 @@ use axum::{
     response::{IntoResponse, Response},
 };
 
+use crate::error_reporting;
+
 pub type AppResult<T> = Result<T, AppError>;
 
 pub(crate) struct AppError(anyhow::Error);
@@ -10,6 +12,8 @@ pub(crate) struct AppError(anyhow::Error);
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        error_reporting::capture(&self.0);
+
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Something went wrong: {}", self.0),
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{logging, secrets, CONFIG};
+
+const DEDUPLICATION_WINDOW: Duration = Duration::from_secs(600);
+const REPORT_TIMEOUT: Duration = Duration::from_secs(10);
+const RELEASE: &str = concat!("k3s-proxmox-helper@", env!("CARGO_PKG_VERSION"));
+
+// A failing loop would otherwise report the same error every few seconds.
+static LAST_REPORTS: Lazy<Mutex<HashMap<String, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static EVENT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize)]
+struct ErrorReport<'a> {
+    kind: &'a str,
+    message: &'a str,
+    release: &'a str,
+    timestamp: i64,
+}
+
+fn sentry_dsn() -> Option<String> {
+    secrets::secret("sentry_dsn", &CONFIG.sentry_dsn)
+}
+
+fn webhook_url() -> Option<String> {
+    secrets::secret("error_report_webhook_url", &CONFIG.error_report_webhook_url)
+}
+
+fn is_enabled() -> bool {
+    sentry_dsn().is_some() || webhook_url().is_some()
+}
+
+fn is_duplicate(message: &str) -> bool {
+    let mut last_reports = LAST_REPORTS.lock().unwrap();
+    let now = Instant::now();
+
+    last_reports.retain(|_, reported_at| now.duration_since(*reported_at) < DEDUPLICATION_WINDOW);
+
+    last_reports.insert(message.to_string(), now).is_some()
+}
+
+fn event_id() -> String {
+    let seed = format!(
+        "{}-{}-{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        EVENT_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+
+    Sha256::digest(seed.as_bytes())
+        .iter()
+        .take(16)
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+// `https://<key>@<host>/<project>` is posted to `https://<host>/api/<project>/envelope/`.
+fn sentry_envelope_url(dsn: &str) -> anyhow::Result<(String, String)> {
+    let url = reqwest::Url::parse(dsn).context("Invalid Sentry DSN")?;
+    let key = url.username().to_string();
+    let host = url.host_str().context("Sentry DSN has no host")?;
+
+    let (prefix, project) = url
+        .path()
+        .trim_end_matches('/')
+        .rsplit_once('/')
+        .context("Sentry DSN has no project")?;
+
+    let port = url
+        .port()
+        .map(|port| format!(":{port}"))
+        .unwrap_or_default();
+
+    Ok((
+        format!(
+            "{}://{host}{port}{prefix}/api/{project}/envelope/",
+            url.scheme()
+        ),
+        key,
+    ))
+}
+
+async fn send_to_sentry(
+    client: &reqwest::Client,
+    dsn: &str,
+    kind: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let (url, key) = sentry_envelope_url(dsn)?;
+    let event_id = event_id();
+
+    let event = json!({
+        "event_id": event_id,
+        "timestamp": chrono::Utc::now().timestamp(),
+        "platform": "other",
+        "level": if kind == "panic" { "fatal" } else { "error" },
+        "logger": "k3s-proxmox-helper",
+        "release": RELEASE,
+        "message": { "formatted": message },
+        "tags": { "kind": kind },
+    });
+
+    let envelope = format!(
+        "{}\n{}\n{}\n",
+        json!({ "event_id": event_id }),
+        json!({ "type": "event" }),
+        event
+    );
+
+    client
+        .post(url)
+        .header(
+            "X-Sentry-Auth",
+            format!("Sentry sentry_version=7, sentry_client={RELEASE}, sentry_key={key}"),
+        )
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            "application/x-sentry-envelope",
+        )
+        .body(envelope)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+async fn report(kind: &str, message: &str) {
+    let message = secrets::redact(message);
+
+    if is_duplicate(&message) {
+        return;
+    }
+
+    let client = match reqwest::Client::builder().timeout(REPORT_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => {
+            logging::warn!("Unable to report error: {err}");
+            return;
+        }
+    };
+
+    if let Some(dsn) = sentry_dsn() {
+        if let Err(err) = send_to_sentry(&client, &dsn, kind, &message).await {
+            logging::warn!("Unable to report error to Sentry: {err}");
+        }
+    }
+
+    if let Some(webhook_url) = webhook_url() {
+        let payload = ErrorReport {
+            kind,
+            message: &message,
+            release: RELEASE,
+            timestamp: chrono::Utc::now().timestamp(),
+        };
+
+        let result = client
+            .post(webhook_url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        if let Err(err) = result {
+            logging::warn!("Unable to report error to the webhook: {err}");
+        }
+    }
+}
+
+// Reported in the background, the caller doesn't wait for the error tracker.
+pub(crate) fn capture(err: &anyhow::Error) {
+    if !is_enabled() {
+        return;
+    }
+
+    let message = format!("{err:#}");
+
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        handle.spawn(async move { report("error", &message).await });
+    }
+}
+
+// The report is sent from its own thread and runtime, the panicking one may be a runtime worker.
+pub(crate) fn install_panic_hook() {
+    if !is_enabled() {
+        return;
+    }
+
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let message = info.to_string();
+
+        let _ = std::thread::spawn(move || {
+            if let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                runtime.block_on(report("panic", &message));
+            }
+        })
+        .join();
+    }));
+}
@@ -0,0 +1,62 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+// Every response of the API is a small JSON or text document, anything larger isn't worth hashing.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == "*" || candidate == etag)
+}
+
+// The ETag is a hash of the body, clients polling with `If-None-Match` get a 304 without the
+// document while nothing changed.
+pub(crate) async fn etag(request: Request, next: Next) -> Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+
+    let if_none_match = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let response = next.run(request).await;
+
+    if response.status() != StatusCode::OK || response.headers().contains_key(header::ETAG) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    let Ok(body) = axum::body::to_bytes(body, MAX_BODY_SIZE).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let etag = format!(
+        "\"{}\"",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(&body))
+    );
+
+    let Ok(etag_value) = HeaderValue::from_str(&etag) else {
+        return Response::from_parts(parts, Body::from(body));
+    };
+
+    if if_none_match.is_some_and(|if_none_match| matches(&if_none_match, &etag)) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag_value)]).into_response();
+    }
+
+    parts.headers.insert(header::ETAG, etag_value);
+
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,218 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alerts,
+    cluster::find_vm,
+    clusters,
+    error::AppResult,
+    events, kube, roles,
+    ssh::{self, SshTarget},
+    CONFIG,
+};
+
+fn etcdctl_command(server: &SshTarget) -> String {
+    let tls = format!(
+        "{}/server/tls/etcd",
+        clusters::distro_of_ip(&server.ip).data_dir()
+    );
+
+    format!(
+        "ETCDCTL_API=3 etcdctl --endpoints https://127.0.0.1:2379 \
+         --cacert {tls}/server-ca.crt --cert {tls}/client.crt --key {tls}/client.key"
+    )
+}
+
+#[derive(Deserialize)]
+struct MemberList {
+    #[serde(default)]
+    members: Vec<EtcdMember>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EtcdMember {
+    #[serde(rename = "ID")]
+    pub id: u64,
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "peerURLs", default)]
+    pub peer_urls: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EndpointHealth {
+    pub endpoint: String,
+    pub health: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberRemoval {
+    pub removed: Option<EtcdMember>,
+    pub remaining_members: usize,
+    pub quorum_at_risk: bool,
+    pub health: Vec<EndpointHealth>,
+}
+
+#[derive(Default, Deserialize)]
+pub(crate) struct RemoveMemberQuery {
+    #[serde(default)]
+    force: bool,
+}
+
+async fn etcdctl(
+    client: &reqwest::Client,
+    server: &SshTarget,
+    args: &str,
+) -> anyhow::Result<String> {
+    let output = ssh::run(
+        client,
+        server,
+        &format!("{} {args}", etcdctl_command(server)),
+    )
+    .await
+    .context(format!("etcdctl {args} failed on {}", server.ip))?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+async fn member_list(
+    client: &reqwest::Client,
+    server: &SshTarget,
+) -> anyhow::Result<Vec<EtcdMember>> {
+    let output = etcdctl(client, server, "member list -w json").await?;
+
+    Ok(serde_json::from_str::<MemberList>(&output)
+        .context("Invalid etcd member list")?
+        .members)
+}
+
+async fn endpoint_health(
+    client: &reqwest::Client,
+    server: &SshTarget,
+) -> anyhow::Result<Vec<EndpointHealth>> {
+    // Unhealthy endpoints make etcdctl exit with an error while still printing the report.
+    let output = ssh::run(
+        client,
+        server,
+        &format!(
+            "{} endpoint health --cluster -w json || true",
+            etcdctl_command(server)
+        ),
+    )
+    .await?;
+
+    serde_json::from_slice(&output.stdout).context("Invalid etcd endpoint health report")
+}
+
+// k3s names members after the server hostname followed by a random suffix.
+fn is_member_of(member: &EtcdMember, hostname: &str, ip: &str) -> bool {
+    member.name.starts_with(&format!("{hostname}-"))
+        || member
+            .peer_urls
+            .iter()
+            .any(|url| url.contains(&format!("//{ip}:")))
+}
+
+// Removes the etcd member of a decommissioned server through one of the remaining servers. A removal
+// leaving less than `etcd_min_members` members is refused unless forced, e.g. when the VM is already
+// gone and its member can never come back anyway.
+pub(crate) async fn remove_member(
+    client: &reqwest::Client,
+    cluster: &str,
+    hostname: &str,
+    ip: &str,
+    force: bool,
+) -> anyhow::Result<MemberRemoval> {
+    let (_, server) = kube::find_servers(client, cluster)
+        .await?
+        .into_iter()
+        .find(|(server_hostname, server)| server_hostname != hostname && server.ip != ip)
+        .context("No other k3s server left to remove the etcd member from")?;
+
+    let members = member_list(client, &server).await?;
+
+    let Some(member) = members
+        .iter()
+        .find(|member| is_member_of(member, hostname, ip))
+        .cloned()
+    else {
+        return Ok(MemberRemoval {
+            removed: None,
+            remaining_members: members.len(),
+            quorum_at_risk: members.len() < CONFIG.etcd_min_members,
+            health: endpoint_health(client, &server).await?,
+        });
+    };
+
+    let remaining_members = members.len() - 1;
+    let quorum_at_risk = remaining_members < CONFIG.etcd_min_members;
+
+    if quorum_at_risk {
+        let message = format!(
+            "Removing the etcd member of {hostname} leaves {remaining_members} members, less than the \
+             {} required for a safe quorum",
+            CONFIG.etcd_min_members
+        );
+
+        if !force {
+            anyhow::bail!("{message}, refusing without force");
+        }
+
+        alerts::fire("etcd-quorum", &message).await;
+    }
+
+    etcdctl(client, &server, &format!("member remove {:x}", member.id)).await?;
+
+    events::record(
+        "etcd",
+        None,
+        format!("Removed etcd member {} of {hostname}", member.name),
+        None,
+    );
+
+    let health = endpoint_health(client, &server).await?;
+
+    if health.iter().any(|endpoint| !endpoint.health) {
+        alerts::fire(
+            "etcd-health",
+            &format!("Unhealthy etcd members remain after removing the member of {hostname}"),
+        )
+        .await;
+    }
+
+    Ok(MemberRemoval {
+        removed: Some(member),
+        remaining_members,
+        quorum_at_risk,
+        health,
+    })
+}
+
+pub(crate) async fn remove_vm_member(
+    Path(vm_id): Path<String>,
+    Query(query): Query<RemoveMemberQuery>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<MemberRemoval>> {
+    let (_, vm) = find_vm(client.clone(), &vm_id).await?;
+
+    let cluster = roles::assign_vm(&vm)
+        .context(format!("VM {vm_id} isn't a member of any k3s cluster"))?
+        .cluster;
+
+    let ip = kube::find_servers(&client, &cluster)
+        .await?
+        .into_iter()
+        .find(|(_, server)| server.vmid == vm_id)
+        .map(|(_, server)| server.ip)
+        .unwrap_or_default();
+
+    Ok(Json(
+        remove_member(&client, &cluster, &vm.name, &ip, query.force).await?,
+    ))
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::HashSet,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    Extension, Json,
+};
+use hmac::{Hmac, KeyInit, Mac};
+use mktemp::Temp;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    alerts, clusters,
+    credentials::{self, ClusterScope},
+    error::AppResult,
+    events, kube, logging,
+    maintenance::{self, OperationClass},
+    s3::{S3Bucket, S3Object},
+    secrets,
+    signer::openssl_output,
+    ssh, status, CONFIG,
+};
+
+// Marks snapshots sealed with an HMAC, the tag follows it and the ciphertext comes last.
+const SEALED_HEADER: &[u8] = b"k3s-proxmox-helper-hmac-sha256\n";
+const TAG_LENGTH: usize = 32;
+
+#[derive(Debug, Serialize)]
+pub struct RemoteSnapshot {
+    pub cluster: String,
+    pub key: String,
+    pub last_modified: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SnapshotsQuery {
+    cluster: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+pub(crate) struct CreateSnapshotRequest {
+    cluster: Option<String>,
+}
+
+impl RemoteSnapshot {
+    fn new(cluster: &str, object: S3Object) -> Self {
+        RemoteSnapshot {
+            cluster: cluster.to_string(),
+            key: object.key,
+            last_modified: object.last_modified,
+            size: object.size,
+        }
+    }
+}
+
+fn bucket() -> anyhow::Result<S3Bucket> {
+    Ok(S3Bucket {
+        endpoint: CONFIG
+            .etcd_snapshot_s3_endpoint
+            .clone()
+            .context("etcd_snapshot_s3_endpoint is not configured")?,
+        bucket: CONFIG
+            .etcd_snapshot_s3_bucket
+            .clone()
+            .context("etcd_snapshot_s3_bucket is not configured")?,
+        region: CONFIG.etcd_snapshot_s3_region.clone(),
+        access_key: secrets::secret(
+            "etcd_snapshot_s3_access_key",
+            &CONFIG.etcd_snapshot_s3_access_key,
+        )
+        .context("etcd_snapshot_s3_access_key is not configured")?,
+        secret_key: secrets::secret(
+            "etcd_snapshot_s3_secret_key",
+            &CONFIG.etcd_snapshot_s3_secret_key,
+        )
+        .context("etcd_snapshot_s3_secret_key is not configured")?,
+    })
+}
+
+// Every cluster has its own folder, so retention never prunes the snapshots of another one.
+pub(crate) fn prefix(cluster: &str) -> String {
+    format!(
+        "{}/{cluster}/",
+        CONFIG.etcd_snapshot_s3_folder.trim_end_matches('/')
+    )
+}
+
+fn encryption_key_path() -> anyhow::Result<&'static str> {
+    CONFIG
+        .etcd_snapshot_encryption_key_path
+        .as_deref()
+        .context("etcd_snapshot_encryption_key_path is not configured")
+}
+
+// Derived from the passphrase openssl encrypts with, i.e. the first line of the key file, so a
+// single key file keeps working.
+fn mac() -> anyhow::Result<Hmac<Sha256>> {
+    let key_path = encryption_key_path()?;
+    let key = std::fs::read_to_string(key_path)
+        .context(format!("Unable to read the snapshot key {key_path}"))?;
+    let passphrase = key.lines().next().unwrap_or_default();
+
+    let mut derivation = Hmac::<Sha256>::new_from_slice(passphrase.as_bytes())?;
+    derivation.update(b"etcd-snapshot-authentication");
+
+    Ok(Hmac::<Sha256>::new_from_slice(
+        &derivation.finalize().into_bytes(),
+    )?)
+}
+
+// Snapshots hold every secret of the cluster, they only leave the server encrypted with a key the
+// object store never sees. CBC alone doesn't detect tampering, the ciphertext is sealed with an
+// HMAC which is checked before anything gets decrypted.
+async fn encrypt(path: &str) -> anyhow::Result<Vec<u8>> {
+    let temp_file = Temp::new_file()?;
+    let encrypted_path = temp_file.as_path().display().to_string();
+
+    cipher(&["-salt", "-in", path, "-out", &encrypted_path]).await?;
+
+    let ciphertext = std::fs::read(&encrypted_path)?;
+
+    let mut mac = mac()?;
+    mac.update(&ciphertext);
+
+    Ok([SEALED_HEADER, &mac.finalize().into_bytes(), &ciphertext].concat())
+}
+
+async fn decrypt(sealed: &[u8], path: &str) -> anyhow::Result<()> {
+    let sealed = sealed.strip_prefix(SEALED_HEADER).context(
+        "The snapshot carries no integrity tag, it was shipped before snapshots were sealed",
+    )?;
+
+    if sealed.len() < TAG_LENGTH {
+        anyhow::bail!("The snapshot is truncated");
+    }
+
+    let (tag, ciphertext) = sealed.split_at(TAG_LENGTH);
+
+    let mut mac = mac()?;
+    mac.update(ciphertext);
+    mac.verify_slice(tag)
+        .map_err(|_| anyhow::Error::msg("The snapshot failed its integrity check"))?;
+
+    let temp_file = Temp::new_file()?;
+    let encrypted_path = temp_file.as_path().display().to_string();
+
+    std::fs::write(&encrypted_path, ciphertext)?;
+
+    cipher(&["-d", "-in", &encrypted_path, "-out", path]).await
+}
+
+async fn cipher(args: &[&str]) -> anyhow::Result<()> {
+    let pass = format!("file:{}", encryption_key_path()?);
+
+    openssl_output(
+        &["enc", "-aes-256-cbc", "-pbkdf2"]
+            .into_iter()
+            .chain(args.iter().copied())
+            .chain(["-pass", &pass])
+            .collect::<Vec<_>>(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn apply_retention(
+    client: &reqwest::Client,
+    bucket: &S3Bucket,
+    cluster: &str,
+) -> anyhow::Result<()> {
+    let mut snapshots = bucket.list(client, &prefix(cluster)).await?;
+
+    // Keys embed the snapshot timestamp, the oldest sort first.
+    snapshots.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let excess = snapshots
+        .len()
+        .saturating_sub(CONFIG.etcd_snapshot_retention);
+
+    for snapshot in snapshots.into_iter().take(excess) {
+        bucket.delete(client, &snapshot.key).await?;
+
+        events::record(
+            "etcd-snapshot",
+            None,
+            format!("Pruned remote etcd snapshot {}", snapshot.key),
+            None,
+        );
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn ship_snapshot(
+    client: &reqwest::Client,
+    cluster: &str,
+) -> anyhow::Result<RemoteSnapshot> {
+    let bucket = bucket()?;
+
+    let (hostname, server) = kube::find_servers(client, cluster)
+        .await?
+        .into_iter()
+        .next()
+        .context(format!("No k3s server found in cluster {cluster}"))?;
+
+    let name = format!("helper-{}", chrono::Utc::now().format("%Y%m%d%H%M%S"));
+    let distro = clusters::distro_of_ip(&server.ip);
+
+    ssh::run(
+        client,
+        &server,
+        &format!("{} etcd-snapshot save --name {name}", distro.as_str()),
+    )
+    .await
+    .context(format!("Unable to take an etcd snapshot on {hostname}"))?;
+
+    // k3s appends the node name and a timestamp to the requested name.
+    let output = ssh::run(
+        client,
+        &server,
+        &format!("ls -1t {}/{name}* | head -n 1", distro.snapshots_path()),
+    )
+    .await?;
+    let remote_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if remote_path.is_empty() {
+        anyhow::bail!("etcd snapshot {name} not found on {hostname}");
+    }
+
+    // The plaintext only ever sits in a directory private to the helper, which goes away along with
+    // it whichever way this returns.
+    let temp_dir = Temp::new_dir()?;
+    std::fs::set_permissions(&temp_dir, std::fs::Permissions::from_mode(0o700))?;
+    let snapshot_path = temp_dir.join("snapshot").as_path().display().to_string();
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&snapshot_path)?;
+
+    ssh::scp_from(client, &server, &remote_path, &snapshot_path).await?;
+    let body = encrypt(&snapshot_path).await;
+    std::fs::remove_file(&snapshot_path)?;
+    let body = body?;
+
+    let file_name = remote_path.rsplit('/').next().unwrap_or(&name);
+    let key = format!("{}{file_name}.enc", prefix(cluster));
+    let size = body.len() as u64;
+
+    let s3_client = reqwest::Client::new();
+
+    bucket.put(&s3_client, &key, body).await?;
+
+    // The local copy only takes space on the server once shipped.
+    if let Err(err) = ssh::run(
+        client,
+        &server,
+        &format!(
+            "{} etcd-snapshot delete {}",
+            distro.as_str(),
+            ssh::shell_quote(file_name)
+        ),
+    )
+    .await
+    {
+        logging::warn!("Unable to delete local etcd snapshot {file_name}: {err}");
+    }
+
+    events::record(
+        "etcd-snapshot",
+        None,
+        format!("Shipped etcd snapshot of {hostname} to {key}"),
+        None,
+    );
+
+    apply_retention(&s3_client, &bucket, cluster).await?;
+
+    Ok(RemoteSnapshot {
+        cluster: cluster.to_string(),
+        key,
+        last_modified: chrono::Utc::now().to_rfc3339(),
+        size,
+    })
+}
+
+// Downloads and decrypts a shipped snapshot, returns the name k3s gave it.
+pub(crate) async fn fetch_snapshot(key: &str, path: &str) -> anyhow::Result<String> {
+    let body = bucket()?.get(&reqwest::Client::new(), key).await?;
+
+    decrypt(&body, path)
+        .await
+        .context(format!("Unable to decrypt snapshot {key}"))?;
+
+    let file_name = key.rsplit('/').next().unwrap_or(key);
+
+    Ok(file_name
+        .strip_suffix(".enc")
+        .unwrap_or(file_name)
+        .to_string())
+}
+
+async fn ship_all(client: &reqwest::Client, failing: &mut HashSet<String>) -> anyhow::Result<()> {
+    let mut failures = vec![];
+
+    for cluster in clusters::CLUSTERS.iter() {
+        match ship_snapshot(client, &cluster.name).await {
+            Ok(snapshot) if failing.remove(&cluster.name) => {
+                alerts::resolve(
+                    "etcd-snapshot",
+                    &format!("etcd snapshot shipped to {}", snapshot.key),
+                )
+                .await
+            }
+            Ok(_) => {}
+            Err(err) => {
+                failing.insert(cluster.name.clone());
+                alerts::fire(
+                    "etcd-snapshot",
+                    &format!(
+                        "Unable to ship an etcd snapshot of cluster {}: {err}",
+                        cluster.name
+                    ),
+                )
+                .await;
+                failures.push(format!("cluster {}: {err:#}", cluster.name));
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::Error::msg(failures.join("; ")))
+    }
+}
+
+pub(crate) async fn ship_snapshots(client: reqwest::Client) -> anyhow::Result<()> {
+    if CONFIG.etcd_snapshot_s3_endpoint.is_none() {
+        return std::future::pending().await;
+    }
+
+    let mut failing = HashSet::new();
+
+    // The first snapshot is taken right away, a helper restarted more often than the interval
+    // would otherwise never ship any.
+    loop {
+        // Snapshots due outside of the window are taken once it opens.
+        if maintenance::is_open(OperationClass::EtcdSnapshot) {
+            maintenance::defer(OperationClass::EtcdSnapshot, vec![]);
+
+            let result = ship_all(&client, &mut failing).await;
+            status::record_job("etcd-snapshots", &result);
+        } else {
+            maintenance::defer(
+                OperationClass::EtcdSnapshot,
+                clusters::CLUSTERS
+                    .iter()
+                    .map(|cluster| format!("ship an etcd snapshot of cluster {}", cluster.name))
+                    .collect(),
+            );
+        }
+
+        tokio::time::sleep(maintenance::next_pass_in(
+            OperationClass::EtcdSnapshot,
+            Duration::from_secs(CONFIG.etcd_snapshot_interval * 3600),
+        ))
+        .await;
+    }
+}
+
+// Scoped credentials only list the snapshots of their own cluster.
+pub(crate) async fn get_remote_snapshots(
+    scope: Option<Extension<ClusterScope>>,
+    Query(query): Query<SnapshotsQuery>,
+) -> AppResult<Json<Vec<RemoteSnapshot>>> {
+    let bucket = bucket()?;
+    let client = reqwest::Client::new();
+    let mut snapshots = vec![];
+
+    for cluster in clusters::CLUSTERS.iter().filter(|cluster| {
+        query
+            .cluster
+            .as_ref()
+            .is_none_or(|name| *name == cluster.name)
+            && credentials::authorize(&scope, &cluster.name).is_ok()
+    }) {
+        snapshots.extend(
+            bucket
+                .list(&client, &prefix(&cluster.name))
+                .await?
+                .into_iter()
+                .map(|object| RemoteSnapshot::new(&cluster.name, object)),
+        );
+    }
+
+    Ok(Json(snapshots))
+}
+
+pub(crate) async fn create_snapshot(
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+    request: Option<Json<CreateSnapshotRequest>>,
+) -> AppResult<Json<RemoteSnapshot>> {
+    let cluster = request
+        .and_then(|Json(request)| request.cluster)
+        .unwrap_or_else(clusters::default_cluster_name);
+
+    credentials::authorize(&scope, &cluster)?;
+
+    Ok(Json(ship_snapshot(&client, &cluster).await?))
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{extract::Query, middleware, routing::get, Extension, Json, Router};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clusters,
+    credentials::{self, ClusterScope},
+    error::AppResult,
+    logging, STATE,
+};
+
+const EVENTS_KEY: &str = "events";
+const MAX_EVENTS: usize = 1000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Recording an event only touches memory, the log is written to the state store in the background
+// rather than rewriting the whole store for every event.
+static EVENTS: Lazy<Mutex<VecDeque<Event>>> =
+    Lazy::new(|| Mutex::new(STATE.get(EVENTS_KEY).unwrap_or_default()));
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Event {
+    pub timestamp: i64,
+    pub kind: String,
+    pub vmid: Option<String>,
+    pub message: String,
+    pub data: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EventsQuery {
+    kind: Option<String>,
+    vmid: Option<String>,
+}
+
+pub(crate) fn record<S: Into<String>>(
+    kind: &str,
+    vmid: Option<&str>,
+    message: S,
+    data: Option<serde_json::Value>,
+) {
+    let event = Event {
+        timestamp: chrono::Utc::now().timestamp(),
+        kind: kind.to_string(),
+        vmid: vmid.map(|vmid| vmid.to_string()),
+        message: message.into(),
+        data,
+    };
+
+    logging::info!("[{}] {}", event.kind, event.message);
+
+    let mut events = EVENTS.lock().unwrap();
+    events.push_back(event);
+
+    while events.len() > MAX_EVENTS {
+        events.pop_front();
+    }
+
+    DIRTY.store(true, Ordering::SeqCst);
+}
+
+pub(crate) fn list() -> Vec<Event> {
+    EVENTS.lock().unwrap().iter().cloned().collect()
+}
+
+pub(crate) fn flush() {
+    if !DIRTY.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    let events = EVENTS.lock().unwrap().clone();
+
+    if let Err(err) = STATE.update(EVENTS_KEY, |persisted: &mut VecDeque<Event>| {
+        *persisted = events;
+    }) {
+        DIRTY.store(true, Ordering::SeqCst);
+        logging::warn!("Unable to persist the events: {err}");
+    }
+}
+
+pub(crate) async fn persist_events() -> anyhow::Result<()> {
+    loop {
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        tokio::task::spawn_blocking(flush).await?;
+    }
+}
+
+// A scoped credential only sees the events of its cluster's VMs, the ones about the helper itself
+// are for the administrator.
+pub(crate) fn is_visible(event: &Event, scope: &Option<Extension<ClusterScope>>) -> bool {
+    scope.is_none()
+        || event
+            .vmid
+            .as_deref()
+            .and_then(clusters::cluster_of_vmid)
+            .is_some_and(|cluster| credentials::authorize(scope, &cluster).is_ok())
+}
+
+async fn get_events(
+    scope: Option<Extension<ClusterScope>>,
+    Query(query): Query<EventsQuery>,
+) -> AppResult<Json<Vec<Event>>> {
+    Ok(Json(
+        list()
+            .into_iter()
+            .filter(|event| is_visible(event, &scope))
+            .filter(|event| query.kind.as_ref().is_none_or(|kind| &event.kind == kind))
+            .filter(|event| {
+                query
+                    .vmid
+                    .as_ref()
+                    .is_none_or(|vmid| event.vmid.as_ref() == Some(vmid))
+            })
+            .collect(),
+    ))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/", get(get_events))
+        .route_layer(middleware::from_fn(credentials::require_credentials))
+}
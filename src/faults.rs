@@ -0,0 +1,95 @@
+use std::{sync::RwLock, time::Duration};
+
+use axum::{middleware, routing::get, Json, Router};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{credentials, error::AppResult, events, roles::glob_match};
+
+static FAULTS: Lazy<RwLock<Faults>> = Lazy::new(|| RwLock::new(Faults::default()));
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ProxmoxFault {
+    // Glob matched against the API path, e.g. `/nodes/*/qemu`.
+    pub path: String,
+    #[serde(default)]
+    pub delay_ms: u64,
+    pub error: Option<String>,
+}
+
+// Ratios are probabilities between 0 and 1, checked independently on every call.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Faults {
+    #[serde(default)]
+    pub proxmox: Vec<ProxmoxFault>,
+    #[serde(default)]
+    pub unhealthy_backend_ratio: f64,
+    #[serde(default)]
+    pub dropped_connection_ratio: f64,
+}
+
+fn roll(ratio: f64) -> bool {
+    ratio > 0.0 && rand::random::<f64>() < ratio
+}
+
+pub(crate) async fn inject_proxmox_fault(path: &str) -> anyhow::Result<()> {
+    let fault = FAULTS
+        .read()
+        .unwrap()
+        .proxmox
+        .iter()
+        .find(|fault| glob_match(&fault.path, path))
+        .cloned();
+
+    let Some(fault) = fault else {
+        return Ok(());
+    };
+
+    tokio::time::sleep(Duration::from_millis(fault.delay_ms)).await;
+
+    match fault.error {
+        Some(error) => anyhow::bail!("Injected fault on {path}: {error}"),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn inject_backend_fault(ip: &str, result: anyhow::Result<()>) -> anyhow::Result<()> {
+    if roll(FAULTS.read().unwrap().unhealthy_backend_ratio) {
+        anyhow::bail!("Injected fault on backend {ip}");
+    }
+
+    result
+}
+
+pub(crate) fn should_drop_connection() -> bool {
+    roll(FAULTS.read().unwrap().dropped_connection_ratio)
+}
+
+async fn get_faults() -> AppResult<Json<Faults>> {
+    Ok(Json(FAULTS.read().unwrap().clone()))
+}
+
+async fn set_faults(Json(faults): Json<Faults>) -> AppResult<Json<Faults>> {
+    events::record(
+        "fault-injection",
+        None,
+        format!("Injecting faults: {}", serde_json::to_string(&faults)?),
+        None,
+    );
+
+    *FAULTS.write().unwrap() = faults.clone();
+
+    Ok(Json(faults))
+}
+
+async fn clear_faults() -> AppResult<Json<Faults>> {
+    events::record("fault-injection", None, "Cleared injected faults", None);
+
+    Ok(Json(std::mem::take(&mut *FAULTS.write().unwrap())))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/", get(get_faults).put(set_faults).delete(clear_faults))
+        .route_layer(middleware::from_fn(credentials::require_admin))
+}
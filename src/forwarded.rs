@@ -0,0 +1,188 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+
+use crate::{logging, CONFIG};
+
+static TRUSTED_PROXIES: Lazy<Vec<IpNet>> = Lazy::new(|| parse_proxies(&CONFIG.trusted_proxies));
+
+fn parse_proxies(proxies: &[String]) -> Vec<IpNet> {
+    proxies
+        .iter()
+        .filter_map(|proxy| {
+            match proxy
+                .parse::<IpNet>()
+                .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+            {
+                Ok(proxy) => Some(proxy),
+                Err(err) => {
+                    logging::warn!("Ignoring invalid trusted proxy {proxy}: {err}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn is_trusted(ip: IpAddr, trusted: &[IpNet]) -> bool {
+    trusted
+        .iter()
+        .any(|proxy| proxy.contains(&ip.to_canonical()))
+}
+
+pub(crate) fn is_trusted_proxy(ip: IpAddr) -> bool {
+    is_trusted(ip, &TRUSTED_PROXIES)
+}
+
+// Accepts `192.0.2.1`, `"[2001:db8::1]:4711"` and the other node forms of RFC 7239.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+// Hops in the order they were appended, the client first.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<IpAddr> {
+    let forwarded = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim().eq_ignore_ascii_case("for").then_some(value)
+            })
+        })
+        .filter_map(parse_node)
+        .collect::<Vec<_>>();
+
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_node)
+        .collect()
+}
+
+// Only hops appended by trusted proxies are believed, the client is the last address before them.
+fn client_behind(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> Option<IpAddr> {
+    if !is_trusted(peer, trusted) {
+        return None;
+    }
+
+    forwarded_hops(headers)
+        .into_iter()
+        .rev()
+        .find(|hop| !is_trusted(*hop, trusted))
+}
+
+// Everything downstream, identity resolution and access checks included, sees the client's address.
+pub(crate) async fn resolve_client(mut request: Request, next: Next) -> Response {
+    let Some(ConnectInfo(peer)) = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .cloned()
+    else {
+        return next.run(request).await;
+    };
+
+    if let Some(client) = client_behind(peer.ip(), request.headers(), &TRUSTED_PROXIES) {
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(client.to_canonical(), 0)));
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+
+        headers
+    }
+
+    fn trusted() -> Vec<IpNet> {
+        parse_proxies(&["10.0.0.0/24".to_string(), "192.0.2.10".to_string()])
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let headers = headers(&[("x-forwarded-for", "198.51.100.7")]);
+
+        assert_eq!(client_behind(ip("203.0.113.5"), &headers, &trusted()), None);
+    }
+
+    #[test]
+    fn takes_the_last_hop_before_the_trusted_proxies() {
+        // The first address is whatever the client claimed, only the one our proxies saw counts.
+        let headers = headers(&[("x-forwarded-for", "10.0.0.99, 198.51.100.7, 192.0.2.10")]);
+
+        assert_eq!(
+            client_behind(ip("10.0.0.1"), &headers, &trusted()),
+            Some(ip("198.51.100.7"))
+        );
+    }
+
+    #[test]
+    fn prefers_the_forwarded_header() {
+        let headers = headers(&[
+            ("forwarded", "for=\"[2001:db8::1]:4711\";proto=https"),
+            ("x-forwarded-for", "198.51.100.7"),
+        ]);
+
+        assert_eq!(
+            client_behind(ip("10.0.0.1"), &headers, &trusted()),
+            Some(ip("2001:db8::1"))
+        );
+    }
+
+    #[test]
+    fn keeps_the_peer_when_every_hop_is_trusted() {
+        let headers = headers(&[("x-forwarded-for", "10.0.0.2, 192.0.2.10")]);
+
+        assert_eq!(client_behind(ip("10.0.0.1"), &headers, &trusted()), None);
+    }
+
+    #[test]
+    fn trusts_ipv4_mapped_peers() {
+        let headers = headers(&[("x-forwarded-for", "198.51.100.7")]);
+
+        assert_eq!(
+            client_behind(ip("::ffff:10.0.0.1"), &headers, &trusted()),
+            Some(ip("198.51.100.7"))
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{placement, proxmox, ssh::shell_quote, CONFIG};
+
+// Proxmox refuses file-write contents over 60 KiB, which the base64 of a chunk must fit in.
+const CHUNK_SIZE: usize = 45 * 1024;
+
+#[derive(Deserialize)]
+struct ExecStarted {
+    pid: u64,
+}
+
+#[derive(Deserialize)]
+struct ExecStatus {
+    exited: u8,
+    exitcode: Option<i32>,
+    #[serde(rename = "out-data")]
+    out_data: Option<String>,
+    #[serde(rename = "err-data")]
+    err_data: Option<String>,
+}
+
+async fn exec(
+    client: &reqwest::Client,
+    node: &str,
+    vmid: &str,
+    command: &str,
+) -> anyhow::Result<String> {
+    let path = format!("/nodes/{node}/qemu/{vmid}/agent");
+
+    let started: ExecStarted = proxmox::post(
+        client,
+        &format!("{path}/exec"),
+        &[("command", "sh"), ("command", "-c"), ("command", command)],
+    )
+    .await?;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(CONFIG.guest_agent_timeout);
+
+    loop {
+        let status: ExecStatus = proxmox::get_with_query(
+            client,
+            &format!("{path}/exec-status"),
+            &[("pid", started.pid)],
+        )
+        .await?;
+
+        if status.exited != 0 {
+            if status.exitcode != Some(0) {
+                anyhow::bail!(
+                    "Command failed in VM {vmid} with exit code {}: {}",
+                    status.exitcode.unwrap_or(-1),
+                    status.err_data.unwrap_or_default().trim()
+                );
+            }
+
+            return Ok(status.out_data.unwrap_or_default());
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("Command in VM {vmid} did not complete in time");
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+// Writes a file into a guest through the QEMU guest agent, for when SSH can't reach it. Large
+// contents go in chunks reassembled in the guest, the result is checked against its hash before it
+// replaces the file.
+pub(crate) async fn write_file(
+    client: &reqwest::Client,
+    vmid: &str,
+    path: &str,
+    content: &[u8],
+) -> anyhow::Result<()> {
+    let node = placement::resolve_node(client, vmid).await?;
+    let temp_path = format!("{path}.tmp");
+
+    let mut chunk_paths = vec![];
+
+    for (index, chunk) in content.chunks(CHUNK_SIZE).enumerate() {
+        let chunk_path = format!("{temp_path}.{index}");
+
+        let _: serde_json::Value = proxmox::post(
+            client,
+            &format!("/nodes/{node}/qemu/{vmid}/agent/file-write"),
+            &[
+                ("file", chunk_path.as_str()),
+                (
+                    "content",
+                    &base64::engine::general_purpose::STANDARD.encode(chunk),
+                ),
+                ("encode", "0"),
+            ],
+        )
+        .await
+        .context(format!("Unable to write {chunk_path} in VM {vmid}"))?;
+
+        chunk_paths.push(shell_quote(&chunk_path));
+    }
+
+    let temp_path = shell_quote(&temp_path);
+
+    // An empty content has no chunk at all.
+    exec(
+        client,
+        &node,
+        vmid,
+        &format!(
+            "cat {} > {temp_path} && rm -f {}",
+            if chunk_paths.is_empty() {
+                "/dev/null".to_string()
+            } else {
+                chunk_paths.join(" ")
+            },
+            chunk_paths.join(" ")
+        ),
+    )
+    .await?;
+
+    let written = exec(client, &node, vmid, &format!("sha256sum {temp_path}")).await?;
+    let expected = Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+
+    if written.split_whitespace().next() != Some(expected.as_str()) {
+        let _ = exec(client, &node, vmid, &format!("rm -f {temp_path}")).await;
+        anyhow::bail!("Content of {path} in VM {vmid} doesn't match what was written");
+    }
+
+    exec(
+        client,
+        &node,
+        vmid,
+        &format!("mv -f {temp_path} {}", shell_quote(path)),
+    )
+    .await?;
+
+    Ok(())
+}
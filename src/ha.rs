@@ -0,0 +1,112 @@
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{cluster::IpamEntry, events, logging, placement, proxmox, status};
+
+const NODE_CONDITION_INTERVAL: Duration = Duration::from_secs(30);
+
+static NODE_CONDITIONS: Lazy<RwLock<HashMap<String, NodeCondition>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// Ordered by preference, backends on healthy nodes are tried first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeCondition {
+    #[default]
+    Healthy,
+    Degraded,
+    Maintenance,
+}
+
+#[derive(Deserialize)]
+struct HaManagerStatus {
+    #[serde(default)]
+    manager_status: Option<HaManager>,
+}
+
+#[derive(Deserialize)]
+struct HaManager {
+    #[serde(default)]
+    node_status: HashMap<String, String>,
+}
+
+impl NodeCondition {
+    fn from_ha_status(status: &str) -> Self {
+        match status {
+            "online" => NodeCondition::Healthy,
+            "maintenance" => NodeCondition::Maintenance,
+            // unknown, fence, gone...
+            _ => NodeCondition::Degraded,
+        }
+    }
+}
+
+// Nodes without HA are not reported by the HA manager and are considered healthy.
+async fn current_conditions(
+    client: &reqwest::Client,
+) -> anyhow::Result<HashMap<String, NodeCondition>> {
+    let status: HaManagerStatus = proxmox::get(client, "/cluster/ha/status/manager_status").await?;
+
+    Ok(status
+        .manager_status
+        .map(|manager| manager.node_status)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(node, status)| (node, NodeCondition::from_ha_status(&status)))
+        .collect())
+}
+
+fn update(conditions: HashMap<String, NodeCondition>) {
+    let previous = std::mem::replace(&mut *NODE_CONDITIONS.write().unwrap(), conditions.clone());
+
+    for (node, condition) in &conditions {
+        let previous_condition = previous.get(node).copied().unwrap_or_default();
+
+        if previous_condition != *condition {
+            events::record(
+                "node-condition",
+                None,
+                format!("Proxmox node {node} is now {condition:?} (was {previous_condition:?})"),
+                Some(serde_json::json!({ "node": node, "condition": condition })),
+            );
+        }
+    }
+}
+
+pub(crate) fn node_condition(node: &str) -> NodeCondition {
+    NODE_CONDITIONS
+        .read()
+        .unwrap()
+        .get(node)
+        .copied()
+        .unwrap_or_default()
+}
+
+// New VMs must not land on a node in maintenance or with a degraded HA status.
+pub(crate) fn is_schedulable(node: &str) -> bool {
+    node_condition(node) == NodeCondition::Healthy
+}
+
+pub(crate) fn backend_condition(ipam: &IpamEntry) -> NodeCondition {
+    ipam.vmid
+        .as_deref()
+        .and_then(placement::last_known_node)
+        .map(|node| node_condition(&node))
+        .unwrap_or_default()
+}
+
+pub(crate) async fn monitor_node_conditions(client: reqwest::Client) -> anyhow::Result<()> {
+    loop {
+        let result = current_conditions(&client).await;
+        status::record_job("node-conditions", &result);
+
+        match result {
+            Ok(conditions) => update(conditions),
+            Err(err) => logging::warn!("Unable to read the HA status of Proxmox nodes: {err}"),
+        }
+
+        tokio::time::sleep(NODE_CONDITION_INTERVAL).await;
+    }
+}
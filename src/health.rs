@@ -0,0 +1,119 @@
+use axum::{http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+
+#[cfg(feature = "operator")]
+use crate::backups;
+use crate::{discovery, drain};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthCheck {
+    pub name: String,
+    pub healthy: bool,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: &'static str,
+    pub checks: Vec<HealthCheck>,
+}
+
+async fn get_health() -> (StatusCode, Json<HealthReport>) {
+    #[cfg(feature = "operator")]
+    let checks = backups::freshness_checks();
+    #[cfg(not(feature = "operator"))]
+    let checks: Vec<HealthCheck> = vec![];
+
+    if checks.iter().all(|check| check.healthy) {
+        (
+            StatusCode::OK,
+            Json(HealthReport {
+                status: "ok",
+                checks,
+            }),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(HealthReport {
+                status: "degraded",
+                checks,
+            }),
+        )
+    }
+}
+
+async fn get_readiness() -> (StatusCode, Json<HealthReport>) {
+    let healthy = discovery::last_synchronization().is_some() && !discovery::is_stale();
+
+    let message = match discovery::last_synchronization() {
+        Some(timestamp) => format!(
+            "IPAMs last synchronized at {} ({} seconds ago)",
+            chrono::DateTime::from_timestamp(timestamp, 0)
+                .map(|date| date.to_rfc3339())
+                .unwrap_or_default(),
+            chrono::Utc::now().timestamp() - timestamp
+        ),
+        None => "IPAMs were never synchronized".to_string(),
+    };
+
+    let mut checks = vec![HealthCheck {
+        name: "ipam-sync".to_string(),
+        healthy,
+        message,
+    }];
+
+    // Lets the load balancer or the VRRP peer take over before a planned restart.
+    if drain::is_draining() {
+        checks.push(HealthCheck {
+            name: "drain".to_string(),
+            healthy: false,
+            message: "Draining for maintenance".to_string(),
+        });
+    }
+
+    if checks.iter().all(|check| check.healthy) {
+        (
+            StatusCode::OK,
+            Json(HealthReport {
+                status: "ready",
+                checks,
+            }),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(HealthReport {
+                status: "not-ready",
+                checks,
+            }),
+        )
+    }
+}
+
+async fn get_startup_health() -> (StatusCode, Json<HealthReport>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(HealthReport {
+            status: "starting",
+            checks: vec![HealthCheck {
+                name: "proxmox".to_string(),
+                healthy: false,
+                message: "Waiting for the Proxmox API".to_string(),
+            }],
+        }),
+    )
+}
+
+// Served while the helper waits for Proxmox to come up, before anything else is available.
+pub(crate) fn create_startup_router() -> Router {
+    Router::new()
+        .route("/healthz", get(get_startup_health))
+        .route("/readyz", get(get_startup_health))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/healthz", get(get_health))
+        .route("/readyz", get(get_readiness))
+}
@@ -0,0 +1,98 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use serde::Deserialize;
+
+#[cfg(feature = "proxy")]
+use crate::proxy;
+use crate::{
+    discovery, error::AppResult, events, logging, placement, proxmox_cache, secrets, CONFIG,
+};
+
+const SECRET_HEADER: &str = "x-hook-secret";
+
+// Sent by a hookscript attached to the VMs, e.g.
+// `curl -H "X-Hook-Secret: $SECRET" -d "{\"vmid\": $1, \"phase\": \"$2\", \"node\": \"$(hostname)\"}"`.
+#[derive(Deserialize)]
+pub(crate) struct ProxmoxHook {
+    vmid: u32,
+    // `pre-start`, `post-start`, `pre-stop`, `post-stop` or `migrate`.
+    phase: String,
+    node: Option<String>,
+}
+
+async fn handle_hook(client: &reqwest::Client, hook: &ProxmoxHook) -> anyhow::Result<()> {
+    let vmid = hook.vmid.to_string();
+
+    #[cfg(feature = "proxy")]
+    if hook.phase == "pre-stop" || hook.phase == "post-stop" {
+        for ipam in discovery::subscribe()
+            .borrow()
+            .iter()
+            .filter(|ipam| ipam.vmid.as_deref() == Some(&vmid))
+        {
+            proxy::unpublish_backend(&ipam.ip);
+        }
+    }
+
+    // The hookscript runs on the VM's node, except after a migration where it may not be known.
+    let node = match &hook.node {
+        Some(node) if hook.phase != "migrate" => node.clone(),
+        _ => placement::resolve_node(client, &vmid).await?,
+    };
+
+    // The VM changed behind the cache's back.
+    proxmox_cache::invalidate();
+
+    let entries = discovery::resync_vm(client, &vmid, &node).await?;
+
+    logging::info!(
+        "VM {vmid} reported {} on {node}, refreshed {} IPAM entries",
+        hook.phase,
+        entries.len()
+    );
+
+    events::record(
+        "proxmox-hook",
+        Some(&vmid),
+        format!("VM {vmid} reported {} on {node}", hook.phase),
+        None,
+    );
+
+    Ok(())
+}
+
+async fn receive_proxmox_hook(
+    State(client): State<reqwest::Client>,
+    headers: HeaderMap,
+    Json(hook): Json<ProxmoxHook>,
+) -> AppResult<Response> {
+    let Some(secret) = secrets::secret("proxmox_hook_secret", &CONFIG.proxmox_hook_secret) else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            "Proxmox hooks are disabled, set proxmox_hook_secret to enable them",
+        )
+            .into_response());
+    };
+
+    let provided = headers
+        .get(SECRET_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    if !secrets::secrets_match(&secret, provided) {
+        return Ok((StatusCode::UNAUTHORIZED, "Invalid hook secret").into_response());
+    }
+
+    handle_hook(&client, &hook).await?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new().route("/proxmox", post(receive_proxmox_hook))
+}
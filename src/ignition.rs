@@ -0,0 +1,116 @@
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::header,
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use base64::Engine;
+use mktemp::Temp;
+use tokio::process::Command;
+
+use crate::{
+    cluster::IpamEntry, clusters::CLUSTERS, commands, error::AppResult, nocloud, registrations,
+    roles::Provisioning, signer, token_rotation,
+};
+
+// Secrets only an Ignition config needs, it can't fetch them itself before the first boot.
+async fn variables(
+    client: &reqwest::Client,
+    ipam: &IpamEntry,
+) -> anyhow::Result<Vec<(&'static str, String)>> {
+    let assignment = ipam.assignment.as_ref().context("Caller has no role")?;
+    let cluster = CLUSTERS
+        .iter()
+        .find(|cluster| cluster.name == assignment.cluster)
+        .context(format!("Unknown cluster {}", assignment.cluster))?;
+
+    let ca_certificate = signer::signer(&cluster.name)?
+        .ca_chain()
+        .await?
+        .into_iter()
+        .map(|ca_certificate| ca_certificate.pem)
+        .collect::<String>();
+
+    Ok(vec![
+        (
+            "token",
+            token_rotation::cluster_token(client, &cluster.name).await?,
+        ),
+        (
+            "ca_certificate_base64",
+            base64::engine::general_purpose::STANDARD.encode(&ca_certificate),
+        ),
+        ("ca_certificate", ca_certificate),
+        ("proxy_url", cluster.proxy_url()?),
+    ])
+}
+
+// Butane templates live next to the NoCloud ones, as `config.bu` or `config.bu.<role>`.
+async fn ignition_config(client: &reqwest::Client, ipam: &IpamEntry) -> anyhow::Result<String> {
+    if let Some(vmid) = &ipam.vmid {
+        registrations::ensure_approved(vmid)?;
+    }
+
+    let provisioning = ipam
+        .assignment
+        .as_ref()
+        .map(|assignment| assignment.provisioning)
+        .unwrap_or_default();
+
+    if provisioning != Provisioning::Ignition {
+        anyhow::bail!("The pool of this node is not provisioned with Ignition");
+    }
+
+    let template = nocloud::template("config.bu", ipam)?.context("No config.bu template")?;
+
+    let butane = variables(client, ipam)
+        .await?
+        .into_iter()
+        .fold(template, |rendered, (variable, value)| {
+            rendered.replace(&format!("{{{{{variable}}}}}"), &value)
+        });
+    let butane = nocloud::render_template(butane, ipam)?;
+
+    let temp_dir = Temp::new_dir()?;
+    let butane_path = temp_dir.join("config.bu").as_path().display().to_string();
+
+    std::fs::write(&butane_path, butane)?;
+
+    let output = commands::output(Command::new("butane").args(["--strict", &butane_path]))
+        .await
+        .context("Unable to transpile the Butane config")?;
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+fn json(config: String) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], config)
+}
+
+async fn get_config(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<impl IntoResponse> {
+    let ipam = nocloud::find_member(Some(&addr.ip().to_string()), None)?;
+
+    Ok(json(ignition_config(&client, &ipam).await?))
+}
+
+async fn get_mac_config(
+    Path(mac): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<impl IntoResponse> {
+    let ipam = nocloud::find_member(None, Some(&mac))?;
+
+    Ok(json(ignition_config(&client, &ipam).await?))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/ignition", get(get_config))
+        .route("/ignition/:mac", get(get_mac_config))
+}
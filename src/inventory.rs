@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::State, middleware, routing::get, Extension, Json, Router};
+use serde::Serialize;
+
+use crate::{
+    cluster::{get_nodes, IpamEntry, NodeRole},
+    credentials::{self, ClusterScope},
+    discovery,
+    error::AppResult,
+    response_cache,
+};
+
+#[derive(Default, Serialize)]
+pub struct AnsibleGroup {
+    pub hosts: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct AnsibleHostVars {
+    pub ansible_host: String,
+    pub ip: String,
+    pub vmid: Option<String>,
+    pub proxmox_node: Option<String>,
+    pub vnet: String,
+    pub k3s_role: &'static str,
+    pub k3s_pool: Option<String>,
+    pub k3s_labels: BTreeMap<String, String>,
+}
+
+#[derive(Default, Serialize)]
+pub struct AnsibleMeta {
+    pub hostvars: BTreeMap<String, AnsibleHostVars>,
+}
+
+#[derive(Default, Serialize)]
+pub struct AnsibleInventory {
+    #[serde(rename = "_meta")]
+    pub meta: AnsibleMeta,
+    #[serde(flatten)]
+    pub groups: BTreeMap<String, AnsibleGroup>,
+}
+
+fn group_name(role: NodeRole) -> String {
+    format!("k3s_{}", role.as_str())
+}
+
+fn add_host(inventory: &mut AnsibleInventory, ipam: IpamEntry, proxmox_node: Option<String>) {
+    let Some(assignment) = ipam.assignment else {
+        return;
+    };
+
+    let host = ipam.hostname.clone().unwrap_or_else(|| ipam.ip.clone());
+
+    inventory
+        .groups
+        .entry(group_name(assignment.role))
+        .or_default()
+        .hosts
+        .push(host.clone());
+
+    inventory.meta.hostvars.insert(
+        host,
+        AnsibleHostVars {
+            ansible_host: ipam.ip.clone(),
+            ip: ipam.ip,
+            vmid: ipam.vmid,
+            proxmox_node,
+            vnet: ipam.vnet,
+            k3s_role: assignment.role.as_str(),
+            k3s_pool: assignment.pool,
+            k3s_labels: assignment.labels,
+        },
+    );
+}
+
+fn in_scope(ipam: &IpamEntry, scope: &Option<Extension<ClusterScope>>) -> bool {
+    scope.is_none()
+        || ipam
+            .assignment
+            .as_ref()
+            .is_some_and(|assignment| credentials::authorize(scope, &assignment.cluster).is_ok())
+}
+
+async fn get_ansible_inventory(
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<AnsibleInventory>> {
+    let mut inventory = AnsibleInventory::default();
+
+    for role in [NodeRole::Server, NodeRole::Agent] {
+        inventory
+            .groups
+            .insert(group_name(role), AnsibleGroup::default());
+    }
+
+    for node in get_nodes(client.clone()).await?.data {
+        for ipam in discovery::discover_node_ipams(&client, &node.node)
+            .await?
+            .into_iter()
+            .filter(|ipam| in_scope(ipam, &scope))
+        {
+            add_host(&mut inventory, ipam, Some(node.node.clone()));
+        }
+    }
+
+    for ipam in discovery::static_entries()?
+        .into_iter()
+        .filter(|ipam| in_scope(ipam, &scope))
+    {
+        add_host(&mut inventory, ipam, None);
+    }
+
+    inventory.groups.insert(
+        "all".to_string(),
+        AnsibleGroup {
+            hosts: vec![],
+            children: vec![group_name(NodeRole::Server), group_name(NodeRole::Agent)],
+        },
+    );
+
+    Ok(Json(inventory))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route(
+            "/ansible",
+            get(get_ansible_inventory).layer(middleware::from_fn(response_cache::cache)),
+        )
+        .route_layer(middleware::from_fn(credentials::require_credentials))
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::Duration,
+};
+
+use axum::Json;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{
+    cluster::{get_ipams_for_node, get_nodes, get_vm_resources, IpamEntry},
+    error::AppResult,
+    events, logging,
+    maintenance::{self, OperationClass},
+    proxmox, status, CONFIG,
+};
+
+const GC_INTERVAL: Duration = Duration::from_secs(300);
+
+// Stale entries by vnet and IP, along with when they were first seen without a VM.
+static STALE_ENTRIES: Lazy<Mutex<HashMap<(String, String), StaleIpamEntry>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StaleIpamEntry {
+    pub entry: IpamEntry,
+    pub first_seen: i64,
+    pub deleted: bool,
+}
+
+// Both qemu VMs and LXC containers take addresses from the IPAM.
+async fn existing_vmids(client: &reqwest::Client) -> anyhow::Result<HashSet<String>> {
+    Ok(get_vm_resources(client)
+        .await?
+        .into_iter()
+        .map(|resource| resource.vmid.to_string())
+        .collect())
+}
+
+async fn ipam_entries(client: &reqwest::Client) -> anyhow::Result<Vec<IpamEntry>> {
+    let mut seen = HashSet::new();
+    let mut entries = vec![];
+
+    for node in get_nodes(client.clone()).await?.data {
+        for entry in get_ipams_for_node(client.clone(), &node.node).await?.data {
+            if seen.insert((entry.vnet.clone(), entry.ip.clone())) {
+                entries.push(entry);
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+async fn delete_entry(client: &reqwest::Client, entry: &IpamEntry) -> anyhow::Result<()> {
+    let mut query = vec![("zone", entry.zone.as_str()), ("ip", entry.ip.as_str())];
+
+    if let Some(mac) = &entry.mac {
+        query.push(("mac", mac));
+    }
+
+    proxmox::delete_with_query::<serde_json::Value, _>(
+        client,
+        &format!("/cluster/sdn/vnets/{}/ips", entry.vnet),
+        &query,
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn collect_garbage(client: &reqwest::Client) -> anyhow::Result<()> {
+    // Listing the VMs first means a VM created in between is never considered gone.
+    let vmids = existing_vmids(client).await?;
+    let entries = ipam_entries(client).await?;
+
+    let now = chrono::Utc::now().timestamp();
+
+    let mut stale = HashMap::new();
+    let mut deferred = vec![];
+
+    for entry in entries {
+        // Gateways and manual reservations have no VM.
+        let Some(vmid) = &entry.vmid else {
+            continue;
+        };
+
+        if vmids.contains(vmid) {
+            continue;
+        }
+
+        let key = (entry.vnet.clone(), entry.ip.clone());
+
+        let first_seen = STALE_ENTRIES
+            .lock()
+            .unwrap()
+            .get(&key)
+            .map(|stale| stale.first_seen)
+            .unwrap_or(now);
+
+        let mut deleted = false;
+
+        // Entries are only deleted once they stayed stale for the whole grace period, a VM being
+        // restored or migrated briefly disappears from the resource list.
+        let due = CONFIG.ipam_gc_delete && now - first_seen >= CONFIG.ipam_gc_grace_period;
+
+        if due && !maintenance::is_open(OperationClass::IpamGc) {
+            deferred.push(format!("delete IPAM entry {} on {}", entry.ip, entry.vnet));
+        } else if due {
+            match delete_entry(client, &entry).await {
+                Ok(()) => {
+                    events::record(
+                        "ipam-gc",
+                        Some(vmid),
+                        format!(
+                            "Deleted stale IPAM entry {} on {} of deleted VM {vmid}",
+                            entry.ip, entry.vnet
+                        ),
+                        None,
+                    );
+                    deleted = true;
+                }
+                Err(err) => logging::warn!("Unable to delete stale IPAM entry {}: {err}", entry.ip),
+            }
+        }
+
+        stale.insert(
+            key,
+            StaleIpamEntry {
+                entry,
+                first_seen,
+                deleted,
+            },
+        );
+    }
+
+    *STALE_ENTRIES.lock().unwrap() = stale;
+    maintenance::defer(OperationClass::IpamGc, deferred);
+
+    Ok(())
+}
+
+pub(crate) async fn run_ipam_gc(client: reqwest::Client) -> anyhow::Result<()> {
+    if !CONFIG.ipam_gc {
+        return std::future::pending().await;
+    }
+
+    loop {
+        let result = collect_garbage(&client).await;
+        status::record_job("ipam-gc", &result);
+
+        if let Err(err) = result {
+            logging::warn!("Unable to collect stale IPAM entries: {err}");
+        }
+
+        tokio::time::sleep(maintenance::next_pass_in(
+            OperationClass::IpamGc,
+            GC_INTERVAL,
+        ))
+        .await;
+    }
+}
+
+pub(crate) async fn get_stale_entries() -> AppResult<Json<Vec<StaleIpamEntry>>> {
+    Ok(Json(
+        STALE_ENTRIES.lock().unwrap().values().cloned().collect(),
+    ))
+}
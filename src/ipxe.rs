@@ -0,0 +1,71 @@
+use anyhow::Context;
+use axum::{extract::Path, routing::get, Router};
+
+use crate::{
+    cluster::IpamEntry, error::AppResult, get_exposed_address, logging, nocloud,
+    roles::Provisioning, CONFIG,
+};
+
+fn default_script(ipam: &IpamEntry, mac: &str) -> anyhow::Result<String> {
+    let kernel = CONFIG
+        .ipxe_kernel_url
+        .as_ref()
+        .context("ipxe_kernel_url is not configured")?;
+    let initrd = CONFIG
+        .ipxe_initrd_url
+        .as_ref()
+        .context("ipxe_initrd_url is not configured")?;
+
+    let (helper_ip, helper_port) = get_exposed_address()?;
+    let kernel_args = nocloud::render_template(CONFIG.ipxe_kernel_args.clone(), ipam)?;
+
+    let helper_url = format!("http://{helper_ip}:{helper_port}");
+
+    // The installer fetches its answers from the NoCloud or Ignition endpoints of this machine.
+    let provisioning_args = match ipam
+        .assignment
+        .as_ref()
+        .map(|assignment| assignment.provisioning)
+        .unwrap_or_default()
+    {
+        Provisioning::CloudInit => format!("ds=nocloud-net;s={helper_url}/nocloud/{mac}/"),
+        Provisioning::Ignition => format!(
+            "ignition.firstboot ignition.platform.id=metal ignition.config.url={helper_url}/ignition/{mac}"
+        ),
+    };
+
+    Ok(format!(
+        "#!ipxe\n\
+         kernel {kernel} initrd=initrd {kernel_args} {provisioning_args}\n\
+         initrd --name initrd {initrd}\n\
+         boot\n"
+    ))
+}
+
+fn script(mac: &str) -> anyhow::Result<String> {
+    let ipam = nocloud::find_member(None, Some(mac))?;
+
+    match nocloud::template("ipxe", &ipam)? {
+        Some(template) => nocloud::render_template(template, &ipam),
+        None => default_script(&ipam, mac),
+    }
+}
+
+// Chained from the network's boot script with `chain http://<helper>:<port>/ipxe/${net0/mac}`.
+async fn get_script(Path(mac): Path<String>) -> AppResult<String> {
+    match script(&mac) {
+        Ok(script) => Ok(script),
+        // Unknown machines fall through to their next boot device instead of hanging on an error.
+        Err(err) => {
+            logging::info!("Not network booting {mac}: {err}");
+
+            Ok(format!(
+                "#!ipxe\necho Not enrolled by k3s-proxmox-helper: {err}\nexit\n"
+            ))
+        }
+    }
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new().route("/ipxe/:mac", get(get_script))
+}
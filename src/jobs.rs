@@ -0,0 +1,276 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    credentials::{self, ClusterScope},
+    error::AppResult,
+    logging, STATE,
+};
+
+const JOBS_KEY: &str = "jobs";
+// Finished jobs are only kept around for their result, the oldest ones go first.
+const MAX_FINISHED_JOBS: usize = 200;
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+// The lines logged by running jobs, written along with their result rather than rewriting the
+// state store for every line.
+static LOGS: Lazy<Mutex<HashMap<String, Vec<JobLogEntry>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Completed,
+    Failed,
+    // The helper stopped while the job was running.
+    Interrupted,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JobLogEntry {
+    pub timestamp: i64,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub kind: String,
+    // Jobs spanning several clusters, or none, are only shown to the administrator.
+    #[serde(default)]
+    pub cluster: Option<String>,
+    pub state: JobState,
+    pub started_at: i64,
+    pub completed_at: Option<i64>,
+    pub logs: Vec<JobLogEntry>,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct JobHandle {
+    id: String,
+}
+
+fn update(id: &str, f: impl FnOnce(&mut Job)) {
+    let result = STATE.update(JOBS_KEY, |jobs: &mut BTreeMap<String, Job>| {
+        if let Some(job) = jobs.get_mut(id) {
+            f(job);
+        }
+    });
+
+    if let Err(err) = result {
+        logging::warn!("Unable to persist job {id}: {err}");
+    }
+}
+
+impl JobHandle {
+    pub(crate) fn log<S: Into<String>>(&self, message: S) {
+        let message = message.into();
+
+        logging::info!("Job {}: {message}", self.id);
+
+        LOGS.lock()
+            .unwrap()
+            .entry(self.id.clone())
+            .or_default()
+            .push(JobLogEntry {
+                timestamp: chrono::Utc::now().timestamp(),
+                message,
+            });
+    }
+
+    // For results listing their own steps, the job logs them as they happen.
+    pub(crate) fn step(&self, steps: &mut Vec<String>, message: String) {
+        self.log(message.clone());
+        steps.push(message);
+    }
+}
+
+fn prune(jobs: &mut BTreeMap<String, Job>) {
+    let mut finished = jobs
+        .values()
+        .filter(|job| job.state != JobState::Running)
+        .map(|job| (job.started_at, job.id.clone()))
+        .collect::<Vec<_>>();
+
+    finished.sort();
+
+    for (_, id) in finished
+        .iter()
+        .take(finished.len().saturating_sub(MAX_FINISHED_JOBS))
+    {
+        jobs.remove(id);
+    }
+}
+
+// Runs the operation in the background, the caller gets the job to poll right away.
+pub(crate) fn spawn<T, F, Fut>(
+    kind: &str,
+    cluster: Option<String>,
+    operation: F,
+) -> anyhow::Result<Job>
+where
+    T: Serialize,
+    F: FnOnce(JobHandle) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>> + Send + 'static,
+{
+    let now = chrono::Utc::now();
+
+    let job = Job {
+        id: format!(
+            "{kind}-{}-{}",
+            now.format("%Y%m%d%H%M%S"),
+            SEQUENCE.fetch_add(1, Ordering::SeqCst)
+        ),
+        kind: kind.to_string(),
+        cluster,
+        state: JobState::Running,
+        started_at: now.timestamp(),
+        completed_at: None,
+        logs: vec![],
+        result: None,
+        error: None,
+    };
+
+    STATE.update(JOBS_KEY, |jobs: &mut BTreeMap<String, Job>| {
+        jobs.insert(job.id.clone(), job.clone());
+        prune(jobs);
+    })?;
+
+    let handle = JobHandle { id: job.id.clone() };
+    let operation = operation(handle.clone());
+
+    tokio::spawn(async move {
+        let result = operation
+            .await
+            .and_then(|result| Ok(serde_json::to_value(result)?));
+
+        if let Err(err) = &result {
+            logging::warn!("Job {} failed: {err:#}", handle.id);
+        }
+
+        let logs = LOGS.lock().unwrap().remove(&handle.id).unwrap_or_default();
+
+        update(&handle.id, |job| {
+            job.logs.extend(logs);
+            job.completed_at = Some(chrono::Utc::now().timestamp());
+
+            match result {
+                Ok(result) => {
+                    job.state = JobState::Completed;
+                    job.result = Some(result);
+                }
+                Err(err) => {
+                    job.state = JobState::Failed;
+                    job.error = Some(format!("{err:#}"));
+                }
+            }
+        });
+    });
+
+    Ok(job)
+}
+
+// Nothing resumes a job after a restart, they are marked as such so pollers stop waiting.
+pub(crate) fn mark_interrupted() -> anyhow::Result<()> {
+    STATE.update(JOBS_KEY, |jobs: &mut BTreeMap<String, Job>| {
+        for job in jobs.values_mut() {
+            if job.state == JobState::Running {
+                job.state = JobState::Interrupted;
+                job.completed_at = Some(chrono::Utc::now().timestamp());
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+pub(crate) fn running() -> Vec<String> {
+    STATE
+        .get::<BTreeMap<String, Job>>(JOBS_KEY)
+        .unwrap_or_default()
+        .into_values()
+        .filter(|job| job.state == JobState::Running)
+        .map(|job| job.id)
+        .collect()
+}
+
+pub(crate) fn accepted(job: Job) -> Response {
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/jobs/{}", job.id))],
+        Json(job),
+    )
+        .into_response()
+}
+
+fn with_buffered_logs(mut job: Job) -> Job {
+    if let Some(logs) = LOGS.lock().unwrap().get(&job.id) {
+        job.logs.extend(logs.iter().cloned());
+    }
+
+    job
+}
+
+fn is_visible(job: &Job, scope: &Option<Extension<ClusterScope>>) -> bool {
+    scope.is_none()
+        || job
+            .cluster
+            .as_ref()
+            .is_some_and(|cluster| credentials::authorize(scope, cluster).is_ok())
+}
+
+async fn get_jobs(scope: Option<Extension<ClusterScope>>) -> AppResult<Json<Vec<Job>>> {
+    let mut jobs = STATE
+        .get::<BTreeMap<String, Job>>(JOBS_KEY)
+        .unwrap_or_default()
+        .into_values()
+        .filter(|job| is_visible(job, &scope))
+        .map(with_buffered_logs)
+        .collect::<Vec<_>>();
+
+    jobs.sort_by_key(|job| std::cmp::Reverse(job.started_at));
+
+    Ok(Json(jobs))
+}
+
+async fn get_job(
+    scope: Option<Extension<ClusterScope>>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Job>> {
+    Ok(Json(
+        STATE
+            .get::<BTreeMap<String, Job>>(JOBS_KEY)
+            .and_then(|mut jobs| jobs.remove(&id))
+            .filter(|job| is_visible(job, &scope))
+            .map(with_buffered_logs)
+            .context(format!("Job {id} not found"))?,
+    ))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/", get(get_jobs))
+        .route("/:id", get(get_job))
+        .route_layer(middleware::from_fn(credentials::require_credentials))
+}
@@ -0,0 +1,301 @@
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use anyhow::Context;
+use axum::Json;
+use chrono::Timelike;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{
+    certificate_expiry::parse_openssl_enddate,
+    clusters,
+    error::AppResult,
+    events, kube, logging,
+    maintenance::{self, OperationClass},
+    ssh::{self, SshTarget},
+    status, tls_san, CONFIG,
+};
+
+// k3s renews any of its internal certificates expiring within 90 days when it starts.
+const K3S_RENEWAL_THRESHOLD_DAYS: i64 = 90;
+const SERVING_CERTIFICATE: &str = "server/tls/serving-kube-apiserver.crt";
+
+static ROTATION_STATUS: Lazy<Mutex<BTreeMap<String, ServerCertificateStatus>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationState {
+    Valid,
+    Pending,
+    Rotating,
+    Rotated,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerCertificateStatus {
+    pub hostname: String,
+    pub vmid: String,
+    pub ip: String,
+    pub not_after: Option<i64>,
+    pub state: RotationState,
+    pub message: Option<String>,
+    pub updated_at: i64,
+}
+
+async fn serving_certificate_not_after(
+    client: &reqwest::Client,
+    target: &SshTarget,
+) -> anyhow::Result<i64> {
+    let output = ssh::run(
+        client,
+        target,
+        &format!(
+            "openssl x509 -noout -enddate -in {}/{SERVING_CERTIFICATE}",
+            clusters::distro_of_ip(&target.ip).data_dir()
+        ),
+    )
+    .await
+    .context(format!("Unable to read k3s certificate on {}", target.ip))?;
+
+    parse_openssl_enddate(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn update_status(
+    hostname: &str,
+    target: &SshTarget,
+    not_after: Option<i64>,
+    state: RotationState,
+    message: Option<String>,
+) {
+    ROTATION_STATUS.lock().unwrap().insert(
+        hostname.to_string(),
+        ServerCertificateStatus {
+            hostname: hostname.to_string(),
+            vmid: target.vmid.clone(),
+            ip: target.ip.clone(),
+            not_after,
+            state,
+            message,
+            updated_at: chrono::Utc::now().timestamp(),
+        },
+    );
+}
+
+async fn rotate_server(
+    client: &reqwest::Client,
+    cluster: &str,
+    hostname: &str,
+    target: &SshTarget,
+    not_after: i64,
+    expiring: bool,
+    missing_sans: &[String],
+) -> anyhow::Result<i64> {
+    update_status(
+        hostname,
+        target,
+        Some(not_after),
+        RotationState::Rotating,
+        None,
+    );
+    events::record(
+        "k3s-certificate-rotation",
+        Some(&target.vmid),
+        format!("Restarting k3s on {hostname} to rotate its internal certificates"),
+        None,
+    );
+
+    if !missing_sans.is_empty() {
+        tls_san::add_required_names(client, target).await?;
+    }
+
+    ssh::run(
+        client,
+        target,
+        &format!(
+            "systemctl restart {}",
+            clusters::distro_of_ip(&target.ip).server_service()
+        ),
+    )
+    .await
+    .context("Unable to restart k3s")?;
+
+    kube::wait_for_node_ready(client, cluster, hostname, Duration::from_secs(600)).await?;
+
+    let renewed_not_after = serving_certificate_not_after(client, target).await?;
+
+    if expiring && renewed_not_after <= not_after {
+        anyhow::bail!("k3s restarted but its certificates were not renewed");
+    }
+
+    let missing_sans = tls_san::missing_names(client, target).await?;
+
+    if !missing_sans.is_empty() {
+        anyhow::bail!(
+            "k3s restarted but its serving certificate still lacks {}",
+            missing_sans.join(", ")
+        );
+    }
+
+    Ok(renewed_not_after)
+}
+
+// Servers are handled one at a time so the control plane keeps its quorum.
+async fn rotate_cluster(
+    client: &reqwest::Client,
+    cluster: &str,
+    threshold: i64,
+    deferred: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    for (hostname, target) in kube::find_servers(client, cluster).await? {
+        let not_after = match serving_certificate_not_after(client, &target).await {
+            Ok(not_after) => not_after,
+            Err(err) => {
+                update_status(
+                    &hostname,
+                    &target,
+                    None,
+                    RotationState::Failed,
+                    Some(err.to_string()),
+                );
+                continue;
+            }
+        };
+
+        let missing_sans = match tls_san::missing_names(client, &target).await {
+            Ok(missing_sans) => missing_sans,
+            Err(err) => {
+                update_status(
+                    &hostname,
+                    &target,
+                    Some(not_after),
+                    RotationState::Failed,
+                    Some(err.to_string()),
+                );
+                continue;
+            }
+        };
+        let expiring = not_after <= threshold;
+
+        if !expiring && missing_sans.is_empty() {
+            update_status(
+                &hostname,
+                &target,
+                Some(not_after),
+                RotationState::Valid,
+                None,
+            );
+            continue;
+        }
+
+        if !maintenance::is_open(OperationClass::CertificateRotation) {
+            update_status(
+                &hostname,
+                &target,
+                Some(not_after),
+                RotationState::Pending,
+                Some("Waiting for the maintenance window".to_string()),
+            );
+            deferred.push(format!("Rotate the k3s certificates of {hostname}"));
+            continue;
+        }
+
+        match rotate_server(
+            client,
+            cluster,
+            &hostname,
+            &target,
+            not_after,
+            expiring,
+            &missing_sans,
+        )
+        .await
+        {
+            Ok(renewed_not_after) => {
+                update_status(
+                    &hostname,
+                    &target,
+                    Some(renewed_not_after),
+                    RotationState::Rotated,
+                    None,
+                );
+                events::record(
+                    "k3s-certificate-rotation",
+                    Some(&target.vmid),
+                    format!("Rotated internal k3s certificates of {hostname}"),
+                    None,
+                );
+            }
+            Err(err) => {
+                update_status(
+                    &hostname,
+                    &target,
+                    Some(not_after),
+                    RotationState::Failed,
+                    Some(err.to_string()),
+                );
+                events::record(
+                    "k3s-certificate-rotation",
+                    Some(&target.vmid),
+                    format!("Rotation of k3s certificates failed on {hostname}: {err}"),
+                    None,
+                );
+
+                // Stop here rather than risk taking down another server.
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Every cluster has its own quorum, one failing doesn't hold back the others.
+async fn run_rotation_pass(client: &reqwest::Client) -> anyhow::Result<()> {
+    let threshold = chrono::Utc::now().timestamp() + K3S_RENEWAL_THRESHOLD_DAYS * 86400;
+    let mut deferred = vec![];
+    let mut failures = vec![];
+
+    for cluster in clusters::CLUSTERS.iter() {
+        if let Err(err) = rotate_cluster(client, &cluster.name, threshold, &mut deferred).await {
+            failures.push(format!("cluster {}: {err:#}", cluster.name));
+        }
+    }
+
+    maintenance::defer(OperationClass::CertificateRotation, deferred);
+
+    if !failures.is_empty() {
+        anyhow::bail!("{}", failures.join("; "));
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn rotate_k3s_certificates(client: reqwest::Client) -> anyhow::Result<()> {
+    if !CONFIG.k3s_certificate_rotation {
+        return std::future::pending().await;
+    }
+
+    loop {
+        let result = run_rotation_pass(&client).await;
+        status::record_job("k3s-certificates", &result);
+
+        if let Err(err) = result {
+            logging::warn!("Unable to rotate k3s certificates: {err}");
+        }
+
+        let wait = 900 - (chrono::Utc::now().time().num_seconds_from_midnight() % 900);
+        tokio::time::sleep(maintenance::next_pass_in(
+            OperationClass::CertificateRotation,
+            Duration::from_secs(wait as u64),
+        ))
+        .await;
+    }
+}
+
+pub(crate) async fn get_rotation_status() -> AppResult<Json<Vec<ServerCertificateStatus>>> {
+    Ok(Json(
+        ROTATION_STATUS.lock().unwrap().values().cloned().collect(),
+    ))
+}
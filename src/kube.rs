@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::{
+    cluster::{get_nodes, NodeRole},
+    clusters, discovery,
+    ssh::{self, SshTarget},
+};
+
+// Returns the hostname and SSH target of every server of the cluster.
+pub(crate) async fn find_servers(
+    client: &reqwest::Client,
+    cluster: &str,
+) -> anyhow::Result<Vec<(String, SshTarget)>> {
+    let mut servers = vec![];
+
+    for node in get_nodes(client.clone()).await?.data {
+        servers.extend(
+            discovery::discover_node_ipams(client, &node.node)
+                .await?
+                .into_iter()
+                .filter(|ipam| {
+                    ipam.assignment.as_ref().is_some_and(|assignment| {
+                        assignment.cluster == cluster && assignment.role == NodeRole::Server
+                    })
+                })
+                .filter_map(|ipam| {
+                    Some((
+                        ipam.hostname?,
+                        SshTarget {
+                            vmid: ipam.vmid?,
+                            ip: ipam.ip,
+                        },
+                    ))
+                }),
+        );
+    }
+
+    Ok(servers)
+}
+
+// Runs the distro's kubectl on the first server of the cluster that answers, so the helper doesn't
+// need its own kubeconfig.
+pub(crate) async fn kubectl(
+    client: &reqwest::Client,
+    cluster: &str,
+    args: &[&str],
+) -> anyhow::Result<String> {
+    let distro = clusters::find(cluster)
+        .context(format!("Unknown cluster {cluster}"))?
+        .distro;
+
+    let mut last_error = anyhow::Error::msg(format!("No k3s server found in cluster {cluster}"));
+
+    for (_, server) in find_servers(client, cluster).await? {
+        let command = std::iter::once(distro.kubectl())
+            .chain(args.iter().map(|arg| ssh::shell_quote(arg)))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        match ssh::run(client, &server, &command).await {
+            Ok(output) => return Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+            Err(err) => last_error = err.context(format!("kubectl failed on {}", server.ip)),
+        }
+    }
+
+    Err(last_error)
+}
+
+pub(crate) async fn cordon_and_drain(
+    client: &reqwest::Client,
+    cluster: &str,
+    node: &str,
+) -> anyhow::Result<()> {
+    kubectl(client, cluster, &["cordon", node]).await?;
+    kubectl(
+        client,
+        cluster,
+        &[
+            "drain",
+            node,
+            "--ignore-daemonsets",
+            "--delete-emptydir-data",
+            "--timeout=300s",
+        ],
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn uncordon(
+    client: &reqwest::Client,
+    cluster: &str,
+    node: &str,
+) -> anyhow::Result<()> {
+    kubectl(client, cluster, &["uncordon", node]).await?;
+
+    Ok(())
+}
+
+pub(crate) async fn is_node_ready(
+    client: &reqwest::Client,
+    cluster: &str,
+    node: &str,
+) -> anyhow::Result<bool> {
+    let status = kubectl(
+        client,
+        cluster,
+        &[
+            "get",
+            "node",
+            node,
+            "-o",
+            r#"jsonpath={.status.conditions[?(@.type=="Ready")].status}"#,
+        ],
+    )
+    .await?;
+
+    Ok(status.trim() == "True")
+}
+
+pub(crate) async fn wait_for_node_ready(
+    client: &reqwest::Client,
+    cluster: &str,
+    node: &str,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        if is_node_ready(client, cluster, node).await.unwrap_or(false) {
+            return Ok(());
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("Timed out waiting for node {node} to become ready");
+        }
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
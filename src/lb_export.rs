@@ -0,0 +1,227 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    extract::Query,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{
+    cluster::IpamEntry,
+    clusters::{K3sCluster, CLUSTERS, K8S_API_PORT},
+    commands, discovery,
+    error::AppResult,
+    events,
+    ha::{self, NodeCondition},
+    logging, proxy, status, CONFIG,
+};
+
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+pub(crate) struct ExportQuery {
+    format: Option<String>,
+}
+
+struct Frontend {
+    name: String,
+    port: u16,
+    backend_port: u16,
+    backends: Vec<(IpamEntry, NodeCondition)>,
+}
+
+// The external load balancer takes over the proxy ports, the built-in proxy stays out of its way.
+pub(crate) fn replaces_proxy() -> bool {
+    CONFIG.lb_export_format.is_some()
+}
+
+fn is_backend(ipam: &IpamEntry, cluster: &K3sCluster) -> bool {
+    ipam.assignment
+        .as_ref()
+        .is_some_and(|assignment| assignment.cluster == cluster.name)
+        && discovery::is_proxy_member(ipam)
+        && proxy::is_backend_published(&ipam.ip)
+}
+
+fn frontends(ipams: &[IpamEntry]) -> Vec<Frontend> {
+    let mut frontends = vec![];
+
+    for cluster in CLUSTERS.iter() {
+        let backends = ipams
+            .iter()
+            .filter(|ipam| is_backend(ipam, cluster))
+            .map(|ipam| (ipam.clone(), ha::backend_condition(ipam)))
+            .collect::<Vec<_>>();
+
+        frontends.push(Frontend {
+            name: format!("k3s_{}_api", cluster.name),
+            port: cluster.proxy_port,
+            backend_port: K8S_API_PORT,
+            backends: backends.clone(),
+        });
+
+        if let (Some(port), Some(backend_port)) = (
+            cluster.supervisor_port(),
+            cluster.distro.supervisor_backend_port(),
+        ) {
+            frontends.push(Frontend {
+                name: format!("k3s_{}_supervisor", cluster.name),
+                port,
+                backend_port,
+                backends,
+            });
+        }
+    }
+
+    frontends
+}
+
+fn server_name(ipam: &IpamEntry) -> String {
+    ipam.hostname
+        .clone()
+        .unwrap_or_else(|| ipam.ip.replace('.', "-"))
+}
+
+// Servers on a node in maintenance or with a degraded HA status are backups, like in the built-in
+// proxy.
+fn render_haproxy(frontends: &[Frontend]) -> String {
+    let mut config = vec!["# Generated by k3s-proxmox-helper, do not edit.".to_string()];
+
+    for frontend in frontends {
+        config.push(format!(
+            "\nfrontend {name}\n    bind *:{port}\n    mode tcp\n    default_backend {name}\n\nbackend {name}\n    mode tcp\n    balance roundrobin\n    option tcp-check",
+            name = frontend.name,
+            port = frontend.port
+        ));
+
+        for (ipam, condition) in &frontend.backends {
+            config.push(format!(
+                "    server {} {}:{} check{}",
+                server_name(ipam),
+                ipam.ip,
+                frontend.backend_port,
+                if *condition == NodeCondition::Healthy {
+                    ""
+                } else {
+                    " backup"
+                }
+            ));
+        }
+    }
+
+    config.join("\n") + "\n"
+}
+
+// Meant to be included from the `stream` block of nginx.conf. nginx refuses an empty upstream, a
+// cluster without any published server gets a placeholder marked as down.
+fn render_nginx(frontends: &[Frontend]) -> String {
+    let mut config = vec!["# Generated by k3s-proxmox-helper, do not edit.".to_string()];
+
+    for frontend in frontends {
+        config.push(format!("\nupstream {} {{", frontend.name));
+
+        for (ipam, condition) in &frontend.backends {
+            config.push(format!(
+                "    server {}:{}{};",
+                ipam.ip,
+                frontend.backend_port,
+                if *condition == NodeCondition::Healthy {
+                    ""
+                } else {
+                    " backup"
+                }
+            ));
+        }
+
+        if frontend.backends.is_empty() {
+            config.push(format!(
+                "    server 127.0.0.1:{} down;",
+                frontend.backend_port
+            ));
+        }
+
+        config.push(format!(
+            "}}\n\nserver {{\n    listen {};\n    proxy_pass {};\n}}",
+            frontend.port, frontend.name
+        ));
+    }
+
+    config.join("\n") + "\n"
+}
+
+fn render(format: &str) -> anyhow::Result<String> {
+    let frontends = frontends(&discovery::subscribe().borrow());
+
+    match format {
+        "haproxy" => Ok(render_haproxy(&frontends)),
+        "nginx" => Ok(render_nginx(&frontends)),
+        format => anyhow::bail!("Unknown load balancer format {format}, expected haproxy or nginx"),
+    }
+}
+
+// Only a changed configuration is written and reloaded, the file is replaced atomically so the
+// load balancer never reads half of it.
+async fn export(format: &str, last_export: &mut Option<String>) -> anyhow::Result<()> {
+    let config = render(format)?;
+
+    if last_export.as_ref() == Some(&config) {
+        return Ok(());
+    }
+
+    let path = CONFIG
+        .lb_export_path
+        .as_ref()
+        .context("lb_export_path is required to export a load balancer configuration")?;
+
+    let temp_path = format!("{path}.tmp");
+    std::fs::write(&temp_path, &config).context(format!("Unable to write {temp_path}"))?;
+    std::fs::rename(&temp_path, path).context(format!("Unable to replace {path}"))?;
+
+    if let Some(reload_command) = &CONFIG.lb_export_reload_command {
+        commands::output(Command::new("sh").args(["-c", reload_command]))
+            .await
+            .context("Unable to reload the load balancer")?;
+    }
+
+    events::record(
+        "lb-export",
+        None,
+        format!("Exported the {format} configuration to {path}"),
+        None,
+    );
+
+    *last_export = Some(config);
+
+    Ok(())
+}
+
+pub(crate) async fn export_lb_config() -> anyhow::Result<()> {
+    let Some(format) = &CONFIG.lb_export_format else {
+        return std::future::pending().await;
+    };
+
+    let mut last_export = None;
+
+    loop {
+        let result = export(format, &mut last_export).await;
+        status::record_job("lb-export", &result);
+
+        if let Err(err) = result {
+            logging::warn!("Unable to export the load balancer configuration: {err}");
+        }
+
+        tokio::time::sleep(EXPORT_INTERVAL).await;
+    }
+}
+
+pub(crate) async fn get_lb_config(Query(query): Query<ExportQuery>) -> AppResult<Response> {
+    let format = query
+        .format
+        .or_else(|| CONFIG.lb_export_format.clone())
+        .unwrap_or_else(|| "haproxy".to_string());
+
+    Ok(([(header::CONTENT_TYPE, "text/plain")], render(&format)?).into_response())
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::TcpListener,
+    os::fd::{FromRawFd, RawFd},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use nix::unistd::{self, Group, User};
+use once_cell::sync::Lazy;
+
+use crate::{
+    clusters::{K3sCluster, CLUSTERS},
+    get_exposed_address, logging, CONFIG,
+};
+
+#[cfg(feature = "proxy")]
+use crate::lb_export;
+
+// systemd hands activated sockets over starting at this file descriptor.
+const LISTEN_FDS_START: RawFd = 3;
+
+pub(crate) const HTTP_LISTENER: &str = "http";
+
+// Sockets are bound once at startup, before privileges are dropped, and cloned for every server
+// using them afterwards.
+static LISTENERS: Lazy<Mutex<HashMap<String, TcpListener>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn activated_listeners() -> anyhow::Result<Vec<TcpListener>> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+
+    if !for_us {
+        return Ok(vec![]);
+    }
+
+    let count = std::env::var("LISTEN_FDS")
+        .context("LISTEN_PID is set without LISTEN_FDS")?
+        .parse::<RawFd>()
+        .context("Invalid LISTEN_FDS")?;
+
+    let mut listeners = vec![];
+
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        // SAFETY: systemd passes these descriptors to us and nothing else in the process owns them.
+        listeners.push(unsafe { TcpListener::from_raw_fd(fd) });
+    }
+
+    Ok(listeners)
+}
+
+fn bind(address: (std::net::IpAddr, u16)) -> anyhow::Result<TcpListener> {
+    TcpListener::bind(address).context(format!("Unable to bind {}:{}", address.0, address.1))
+}
+
+pub(crate) fn api_listener(cluster: &K3sCluster) -> String {
+    format!("api:{}", cluster.name)
+}
+
+pub(crate) fn supervisor_listener(cluster: &K3sCluster) -> String {
+    format!("supervisor:{}", cluster.name)
+}
+
+// Takes over the sockets passed by systemd socket activation, matched by port, and binds whatever
+// is left.
+pub(crate) fn prepare() -> anyhow::Result<()> {
+    let mut listeners = LISTENERS.lock().unwrap();
+
+    for listener in activated_listeners()? {
+        let port = listener.local_addr()?.port();
+
+        let name = if let Some(cluster) = CLUSTERS.iter().find(|cluster| cluster.proxy_port == port)
+        {
+            api_listener(cluster)
+        } else if let Some(cluster) = CLUSTERS
+            .iter()
+            .find(|cluster| cluster.supervisor_port() == Some(port))
+        {
+            supervisor_listener(cluster)
+        } else if port == CONFIG.port {
+            HTTP_LISTENER.to_string()
+        } else {
+            logging::warn!("Ignoring activated socket on unexpected port {port}");
+            continue;
+        };
+
+        logging::info!("Using activated socket for {name} on port {port}");
+        listeners.insert(name, listener);
+    }
+
+    // The external load balancer listens on the proxy ports instead.
+    #[cfg(feature = "proxy")]
+    let proxied_clusters = if lb_export::replaces_proxy() {
+        &[][..]
+    } else {
+        &CLUSTERS[..]
+    };
+    #[cfg(not(feature = "proxy"))]
+    let proxied_clusters: &[K3sCluster] = &[];
+
+    for cluster in proxied_clusters {
+        if let Entry::Vacant(entry) = listeners.entry(api_listener(cluster)) {
+            entry.insert(bind((
+                std::net::Ipv4Addr::UNSPECIFIED.into(),
+                cluster.proxy_port,
+            ))?);
+        }
+
+        if let Some(port) = cluster.supervisor_port() {
+            if let Entry::Vacant(entry) = listeners.entry(supervisor_listener(cluster)) {
+                entry.insert(bind((std::net::Ipv4Addr::UNSPECIFIED.into(), port))?);
+            }
+        }
+    }
+
+    if !listeners.contains_key(HTTP_LISTENER) {
+        listeners.insert(HTTP_LISTENER.to_string(), bind(get_exposed_address()?)?);
+    }
+
+    for listener in listeners.values() {
+        listener.set_nonblocking(true)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn take(name: &str) -> anyhow::Result<tokio::net::TcpListener> {
+    let listener = LISTENERS
+        .lock()
+        .unwrap()
+        .get(name)
+        .context(format!("No {name} listener prepared"))?
+        .try_clone()?;
+
+    Ok(tokio::net::TcpListener::from_std(listener)?)
+}
+
+pub(crate) fn drop_privileges() -> anyhow::Result<()> {
+    if CONFIG.run_as_user.is_none() && CONFIG.run_as_group.is_none() {
+        return Ok(());
+    }
+
+    let user = CONFIG
+        .run_as_user
+        .as_deref()
+        .map(|name| User::from_name(name)?.context(format!("Unknown user {name}")))
+        .transpose()?;
+
+    let gid = match &CONFIG.run_as_group {
+        Some(name) => {
+            Group::from_name(name)?
+                .context(format!("Unknown group {name}"))?
+                .gid
+        }
+        None => user
+            .as_ref()
+            .map(|user| user.gid)
+            .context("No group to run as")?,
+    };
+
+    // The group has to change first, the user wouldn't be allowed to afterwards.
+    unistd::setgroups(&[gid]).context("Unable to drop supplementary groups")?;
+    unistd::setgid(gid).context(format!("Unable to switch to group {gid}"))?;
+
+    if let Some(user) = user {
+        unistd::setuid(user.uid).context(format!("Unable to switch to user {}", user.name))?;
+    }
+
+    logging::info!(
+        "Running as uid {} gid {}",
+        unistd::getuid(),
+        unistd::getgid()
+    );
+
+    Ok(())
+}
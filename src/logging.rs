@@ -0,0 +1,212 @@
+use std::{collections::BTreeMap, os::unix::net::UnixDatagram, sync::RwLock};
+
+use axum::{
+    middleware,
+    routing::{get, put},
+    Json, Router,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{credentials, error::AppResult, events, CONFIG};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+pub(crate) const SYSLOG_IDENTIFIER: &str = "k3s-proxmox-helper";
+
+static LEVELS: Lazy<RwLock<LogLevels>> = Lazy::new(|| {
+    RwLock::new(LogLevels::parse(&CONFIG.log_levels).unwrap_or_else(|err| {
+        println!("Ignoring invalid log levels {}: {err}", CONFIG.log_levels);
+        LogLevels::default()
+    }))
+});
+
+// Only set up when systemd connected stdout to the journal, the output stays plain otherwise.
+static JOURNAL: Lazy<Option<UnixDatagram>> = Lazy::new(|| {
+    std::env::var_os("JOURNAL_STREAM")?;
+
+    let socket = UnixDatagram::unbound().ok()?;
+    socket.connect(JOURNAL_SOCKET).ok()?;
+
+    Some(socket)
+});
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn parse(level: &str) -> anyhow::Result<Self> {
+        Ok(match level.trim().to_lowercase().as_str() {
+            "error" => Level::Error,
+            "warn" | "warning" => Level::Warn,
+            "info" => Level::Info,
+            "debug" => Level::Debug,
+            level => anyhow::bail!("Unknown log level {level}"),
+        })
+    }
+
+    // syslog(3) priorities, as expected by the journal.
+    fn priority(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug => 7,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LogLevels {
+    pub default: Level,
+    #[serde(default)]
+    pub modules: BTreeMap<String, Level>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        LogLevels {
+            default: Level::Info,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+impl LogLevels {
+    // `proxy=debug,cluster=info`, a bare level sets the default of every other module.
+    fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut levels = LogLevels::default();
+
+        for directive in spec
+            .split(',')
+            .filter(|directive| !directive.trim().is_empty())
+        {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    levels
+                        .modules
+                        .insert(module.trim().to_string(), Level::parse(level)?);
+                }
+                None => levels.default = Level::parse(directive)?,
+            }
+        }
+
+        Ok(levels)
+    }
+
+    fn level(&self, module: &str) -> Level {
+        self.modules.get(module).copied().unwrap_or(self.default)
+    }
+}
+
+fn short_module(module_path: &str) -> &str {
+    module_path
+        .split_once("::")
+        .map(|(_, module)| module)
+        .unwrap_or(module_path)
+}
+
+pub(crate) fn enabled(module_path: &str, level: Level) -> bool {
+    level <= LEVELS.read().unwrap().level(short_module(module_path))
+}
+
+// Native journal protocol, values containing a newline are sent with their length.
+fn journal_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+pub(crate) fn write(module_path: &str, level: Level, message: String) {
+    let module = short_module(module_path);
+
+    if let Some(journal) = JOURNAL.as_ref() {
+        let mut datagram = vec![];
+
+        journal_field(&mut datagram, "MESSAGE", &message);
+        journal_field(&mut datagram, "PRIORITY", &level.priority().to_string());
+        journal_field(&mut datagram, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+        journal_field(&mut datagram, "CODE_MODULE", module);
+
+        if journal.send(&datagram).is_ok() {
+            return;
+        }
+    }
+
+    match level {
+        Level::Error => eprintln!("{message}"),
+        _ => println!("{message}"),
+    }
+}
+
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::logging::enabled(module_path!(), $level) {
+            $crate::logging::write(module_path!(), $level, format!($($arg)*));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Error, $($arg)*) };
+}
+
+// Named apart from the builtin `warn` attribute, re-exported under the usual name below.
+macro_rules! warn_ {
+    ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Warn, $($arg)*) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Info, $($arg)*) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::logging::log!($crate::logging::Level::Debug, $($arg)*) };
+}
+
+pub(crate) use {debug, error, info, log, warn_ as warn};
+
+#[derive(Deserialize)]
+pub(crate) struct SetLogLevelsRequest {
+    levels: String,
+}
+
+async fn get_levels() -> AppResult<Json<LogLevels>> {
+    Ok(Json(LEVELS.read().unwrap().clone()))
+}
+
+async fn set_levels(Json(request): Json<SetLogLevelsRequest>) -> AppResult<Json<LogLevels>> {
+    let levels = LogLevels::parse(&request.levels)?;
+
+    *LEVELS.write().unwrap() = levels.clone();
+
+    events::record(
+        "audit",
+        None,
+        format!("Log levels set to {}", request.levels),
+        None,
+    );
+
+    Ok(Json(levels))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    // Anyone may read the levels, only the administrator may change them, e.g. to silence them.
+    Router::new().route("/levels", get(get_levels)).route(
+        "/levels",
+        put(set_levels).layer(middleware::from_fn(credentials::require_admin)),
+    )
+}
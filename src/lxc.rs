@@ -0,0 +1,307 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{extract::State, response::Response, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::NodeRole,
+    clusters, discovery,
+    error::AppResult,
+    events,
+    jobs::{self, JobHandle},
+    logging, proxmox,
+    ssh::{self, SshTarget},
+    tasks, token_rotation, CONFIG,
+};
+
+const CREATE_TIMEOUT: Duration = Duration::from_secs(1800);
+const BOOT_TIMEOUT: Duration = Duration::from_secs(600);
+
+// The Proxmox API refuses raw `lxc.*` keys, they are appended to the container's config on the host.
+// k3s needs the host's /proc and /sys, every device and all the capabilities.
+const RAW_LXC_CONFIG: &[&str] = &[
+    "lxc.apparmor.profile: unconfined",
+    "lxc.cgroup2.devices.allow: a",
+    "lxc.cap.drop:",
+    "lxc.mount.auto: proc:rw sys:rw",
+];
+
+#[derive(Deserialize)]
+pub(crate) struct LxcRequest {
+    node: String,
+    hostname: String,
+    vnet: String,
+    cluster: Option<String>,
+    // A privileged LXC template to clone, the container is created from `ostemplate` otherwise.
+    template: Option<String>,
+    ostemplate: Option<String>,
+    storage: Option<String>,
+    disk_size: Option<u32>,
+    cores: Option<u32>,
+    memory: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LxcResult {
+    pub vmid: String,
+    pub node: String,
+    pub hostname: String,
+    pub ip: String,
+    pub steps: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ClusterStatusEntry {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    ip: Option<String>,
+}
+
+pub(crate) async fn node_target(client: &reqwest::Client, node: &str) -> anyhow::Result<SshTarget> {
+    let entries: Vec<ClusterStatusEntry> = proxmox::get(client, "/cluster/status").await?;
+
+    let ip = entries
+        .into_iter()
+        .find(|entry| entry.kind == "node" && entry.name == node)
+        .and_then(|entry| entry.ip)
+        .context(format!("No address known for Proxmox node {node}"))?;
+
+    // Host keys are pinned per target, Proxmox nodes get their own namespace next to the vmids.
+    Ok(SshTarget {
+        vmid: format!("node-{node}"),
+        ip,
+    })
+}
+
+async fn create_container(
+    client: &reqwest::Client,
+    request: &LxcRequest,
+    cluster: &clusters::K3sCluster,
+    vmid: &str,
+) -> anyhow::Result<String> {
+    let pool = cluster
+        .proxmox_pool()
+        .map(|pool| ("pool", pool.to_string()));
+
+    if let Some(template) = &request.template {
+        let mut params = vec![
+            ("newid", vmid.to_string()),
+            ("hostname", request.hostname.clone()),
+            ("full", "1".to_string()),
+        ];
+        params.extend(pool);
+
+        return proxmox::post(
+            client,
+            &format!("/nodes/{}/lxc/{template}/clone", request.node),
+            &params,
+        )
+        .await;
+    }
+
+    let ostemplate = request
+        .ostemplate
+        .as_ref()
+        .or(CONFIG.lxc_ostemplate.as_ref())
+        .context("No template or ostemplate given and lxc_ostemplate is not configured")?;
+
+    let storage = request.storage.as_ref().unwrap_or(&CONFIG.lxc_storage);
+    let disk_size = request.disk_size.unwrap_or(CONFIG.lxc_disk_size);
+
+    let mut params = vec![
+        ("vmid", vmid.to_string()),
+        ("ostemplate", ostemplate.clone()),
+        ("hostname", request.hostname.clone()),
+        ("storage", storage.clone()),
+        ("rootfs", format!("{storage}:{disk_size}")),
+        ("cores", request.cores.unwrap_or(2).to_string()),
+        ("memory", request.memory.unwrap_or(2048).to_string()),
+        // The kubelet refuses to start with swap.
+        ("swap", "0".to_string()),
+        ("unprivileged", "0".to_string()),
+        ("features", "nesting=1,keyctl=1".to_string()),
+        ("onboot", "1".to_string()),
+        ("net0", format!("name=eth0,bridge={},ip=dhcp", request.vnet)),
+    ];
+    params.extend(pool);
+
+    proxmox::post(client, &format!("/nodes/{}/lxc", request.node), &params).await
+}
+
+async fn wait_for_ip(client: &reqwest::Client, node: &str, vmid: &str) -> anyhow::Result<String> {
+    let deadline = tokio::time::Instant::now() + BOOT_TIMEOUT;
+
+    loop {
+        let ip = discovery::discover_node_ipams(client, node)
+            .await?
+            .into_iter()
+            .find(|ipam| ipam.vmid.as_deref() == Some(vmid))
+            .map(|ipam| ipam.ip);
+
+        if let Some(ip) = ip {
+            return Ok(ip);
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("Container {vmid} got no address from the IPAM");
+        }
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+// Containers share the host's kernel: the kubelet can't tune the conntrack table, overlayfs may
+// not be available on the container's storage and /dev/kmsg doesn't exist.
+fn agent_config(server_url: &str, token: &str) -> String {
+    [
+        format!("server: {server_url}"),
+        format!("token: {token}"),
+        "snapshotter: native".to_string(),
+        "kube-proxy-arg:".to_string(),
+        "  - conntrack-max-per-core=0".to_string(),
+        "kubelet-arg:".to_string(),
+        "  - fail-swap-on=false".to_string(),
+    ]
+    .join("\n")
+}
+
+async fn install_agent(
+    client: &reqwest::Client,
+    host: &SshTarget,
+    vmid: &str,
+    cluster: &clusters::K3sCluster,
+) -> anyhow::Result<()> {
+    let token = token_rotation::cluster_token(client, &cluster.name).await?;
+    let server_url = cluster.server_url()?;
+    let config_dir = format!("/etc/rancher/{}", cluster.distro.as_str());
+
+    let script = [
+        "ln -sf /dev/console /dev/kmsg".to_string(),
+        "echo 'L /dev/kmsg - - - - /dev/console' > /etc/tmpfiles.d/kmsg.conf".to_string(),
+        format!("mkdir -p {config_dir}"),
+        format!(
+            "printf '%s\\n' {} > {config_dir}/config.yaml",
+            ssh::shell_quote(&agent_config(&server_url, &token))
+        ),
+        cluster.distro.install_command(NodeRole::Agent),
+    ]
+    .join(" && ");
+
+    ssh::run(
+        client,
+        host,
+        &format!("pct exec {vmid} -- sh -c {}", ssh::shell_quote(&script)),
+    )
+    .await
+    .context(format!(
+        "Unable to install {} in container {vmid}",
+        cluster.distro.as_str()
+    ))?;
+
+    Ok(())
+}
+
+async fn provision(
+    client: reqwest::Client,
+    request: LxcRequest,
+    cluster: &'static clusters::K3sCluster,
+    job: JobHandle,
+) -> anyhow::Result<LxcResult> {
+    let host = node_target(&client, &request.node).await?;
+    let vmid: String = proxmox::get(&client, "/cluster/nextid").await?;
+    let mut steps = vec![];
+
+    let upid = create_container(&client, &request, cluster, &vmid).await?;
+    tasks::wait_for_task(&client, &request.node, &upid, CREATE_TIMEOUT).await?;
+    job.step(
+        &mut steps,
+        format!(
+            "Created privileged container {vmid} ({}) on {}",
+            request.hostname, request.node
+        ),
+    );
+
+    ssh::run(
+        &client,
+        &host,
+        &format!(
+            "printf '%s\\n' {} >> /etc/pve/lxc/{vmid}.conf",
+            ssh::shell_quote(&RAW_LXC_CONFIG.join("\n"))
+        ),
+    )
+    .await?;
+    job.step(
+        &mut steps,
+        "Applied the LXC settings required by k3s".to_string(),
+    );
+
+    let upid: String = proxmox::post(
+        &client,
+        &format!("/nodes/{}/lxc/{vmid}/status/start", request.node),
+        &[("vmid", vmid.as_str())],
+    )
+    .await?;
+    tasks::wait_for_task(&client, &request.node, &upid, BOOT_TIMEOUT).await?;
+
+    let ip = wait_for_ip(&client, &request.node, &vmid).await?;
+    job.step(
+        &mut steps,
+        format!("Container {vmid} started with address {ip}"),
+    );
+
+    install_agent(&client, &host, &vmid, cluster).await?;
+    job.step(
+        &mut steps,
+        format!(
+            "Installed the {} agent and joined cluster {}",
+            cluster.distro.as_str(),
+            cluster.name
+        ),
+    );
+
+    logging::info!(
+        "Provisioned container {vmid} ({}) as an agent of {}",
+        request.hostname,
+        cluster.name
+    );
+
+    events::record(
+        "lxc-provisioned",
+        Some(&vmid),
+        format!(
+            "Provisioned container {vmid} ({}) as an agent of cluster {}",
+            request.hostname, cluster.name
+        ),
+        None,
+    );
+
+    Ok(LxcResult {
+        vmid,
+        node: request.node,
+        hostname: request.hostname,
+        ip,
+        steps,
+    })
+}
+
+// Privileged containers with raw LXC keys can only be created with root@pam, the helper's Proxmox
+// user needs it for this path.
+pub(crate) async fn provision_lxc(
+    State(client): State<reqwest::Client>,
+    Json(request): Json<LxcRequest>,
+) -> AppResult<Response> {
+    let cluster_name = request
+        .cluster
+        .clone()
+        .unwrap_or_else(clusters::default_cluster_name);
+    let cluster =
+        clusters::find(&cluster_name).context(format!("Unknown cluster {cluster_name}"))?;
+
+    Ok(jobs::accepted(jobs::spawn(
+        "lxc",
+        Some(cluster.name.clone()),
+        |job| provision(client, request, cluster, job),
+    )?))
+}
@@ -1,23 +1,137 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+// Helpers shared by the optional subsystems are left unused by slim builds.
+#![cfg_attr(
+    not(all(
+        feature = "proxy",
+        feature = "pki",
+        feature = "operator",
+        feature = "provisioning"
+    )),
+    allow(dead_code, unused_imports, unused_macros)
+)]
+
+use std::{net::SocketAddr, time::Duration};
 
 use anyhow::Context;
-use axum::{routing::get, Router};
-use clap::Parser;
-use cluster::IpamEntry;
+use axum::{middleware, routing::get, Router};
 use config::Config;
-use models::ProxmoxData;
 use network_interface::NetworkInterfaceConfig;
 use once_cell::sync::Lazy;
-use reqwest::cookie::Jar;
-use serde::Deserialize;
-use tokio::{net::TcpStream, sync::watch};
+use state::StateStore;
+mod access;
+mod alerts;
+#[cfg(feature = "operator")]
+mod annotations;
+#[cfg(feature = "operator")]
+mod backup_policy;
+#[cfg(feature = "operator")]
+mod backups;
+#[cfg(feature = "provisioning")]
+mod bootstrap;
+mod bus;
+mod capacity;
+#[cfg(feature = "pki")]
+mod certificate_expiry;
+#[cfg(feature = "pki")]
 mod certificates;
+mod cli;
+mod client_certificates;
+#[cfg(feature = "provisioning")]
+mod cloud_init;
 mod cluster;
+#[cfg(feature = "operator")]
+mod cluster_restore;
+mod clusters;
+mod commands;
 mod config;
+mod config_file;
+mod connectivity;
+mod credentials;
+mod dashboard;
+mod discovery;
+mod dns_cache;
+mod drain;
 mod error;
+mod error_reporting;
+mod etag;
+#[cfg(feature = "operator")]
+mod etcd;
+#[cfg(feature = "operator")]
+mod etcd_snapshots;
+mod events;
+#[cfg(feature = "fault-injection")]
+mod faults;
+mod forwarded;
+#[cfg(feature = "operator")]
+mod guest_agent;
+mod ha;
+mod health;
+mod hooks;
+#[cfg(feature = "provisioning")]
+mod ignition;
+mod inventory;
+mod ipam_gc;
+#[cfg(feature = "provisioning")]
+mod ipxe;
+mod jobs;
+#[cfg(feature = "operator")]
+mod k3s_certificates;
+#[cfg(feature = "operator")]
+mod kube;
+#[cfg(feature = "proxy")]
+mod lb_export;
+mod listeners;
+mod logging;
+#[cfg(feature = "provisioning")]
+mod lxc;
+mod maintenance;
+mod metrics;
 mod models;
-
-static CONFIG: Lazy<Config> = Lazy::new(|| Config::parse());
+#[cfg(feature = "provisioning")]
+mod nocloud;
+mod node_deltas;
+mod node_history;
+#[cfg(feature = "operator")]
+mod node_reaper;
+mod permissions;
+mod placement;
+#[cfg(feature = "operator")]
+mod preflight;
+mod prometheus_sd;
+mod proxmox;
+mod proxmox_auth;
+mod proxmox_cache;
+mod proxmox_tls;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod registrations;
+mod response_cache;
+#[cfg(feature = "operator")]
+mod restore;
+mod roles;
+mod route_limits;
+#[cfg(feature = "operator")]
+mod s3;
+mod sdn;
+mod secrets;
+#[cfg(feature = "pki")]
+mod signer;
+#[cfg(feature = "operator")]
+mod ssh;
+mod state;
+mod status;
+mod support_bundle;
+mod tasks;
+#[cfg(feature = "operator")]
+mod tls_san;
+#[cfg(feature = "operator")]
+mod token_rotation;
+#[cfg(feature = "operator")]
+mod vm_lifecycle;
+mod vms;
+
+static CONFIG: Lazy<Config> = Lazy::new(config_file::parse_config);
+static STATE: Lazy<StateStore> =
+    Lazy::new(|| StateStore::open(&CONFIG.state_path).expect("Unable to open state store"));
 
 fn get_exposed_address() -> anyhow::Result<(std::net::IpAddr, u16)> {
     let network_interfaces = network_interface::NetworkInterface::show()?;
@@ -40,193 +154,230 @@ fn get_exposed_address() -> anyhow::Result<(std::net::IpAddr, u16)> {
     Ok((address_to_listen, CONFIG.port))
 }
 
-#[derive(Clone, Deserialize)]
-struct ProxmoxTicket {
-    #[serde(rename = "username")]
-    _username: String,
-    ticket: String,
-    #[serde(rename = "CSRFPreventionToken")]
-    _csrf_prevention_token: String,
-}
-
-async fn generate_pve_ticket() -> anyhow::Result<ProxmoxData<ProxmoxTicket>> {
-    let mut params = HashMap::new();
-
-    params.insert("username", &CONFIG.proxmox_api_user);
-    params.insert("password", &CONFIG.proxmox_api_password);
-
-    let response = reqwest::Client::new()
-        .post(format!(
-            "{}/api2/json/access/ticket",
-            &CONFIG.proxmox_api_url
-        ))
-        .form(&params)
-        .send()
-        .await?
-        .error_for_status()?;
+fn proxmox_client_builder() -> anyhow::Result<reqwest::ClientBuilder> {
+    // Bounds every attempt, the retries of `proxmox::send` come on top.
+    let mut builder = proxmox_tls::configure(
+        reqwest::ClientBuilder::new().timeout(Duration::from_secs(CONFIG.proxmox_timeout)),
+    )?;
+
+    if let Some(proxy_url) = &CONFIG.proxmox_http_proxy {
+        let proxy = reqwest::Proxy::all(proxy_url)?.no_proxy(
+            CONFIG
+                .proxmox_no_proxy
+                .as_deref()
+                .and_then(reqwest::NoProxy::from_string),
+        );
+
+        builder = builder.proxy(proxy);
+    }
 
-    Ok(response.json().await?)
+    Ok(builder)
 }
 
-async fn renew_ticket(ticket: &ProxmoxData<ProxmoxTicket>) -> anyhow::Result<()> {
-    println!("Renewing ticket");
-
-    let mut params = HashMap::new();
+async fn serve(app: Router) -> anyhow::Result<()> {
+    let listener = listeners::take(listeners::HTTP_LISTENER)?;
 
-    params.insert("username", &CONFIG.proxmox_api_user);
-    params.insert("password", &ticket.data.ticket);
+    logging::info!("Listening on {}", listener.local_addr()?);
 
-    reqwest::Client::new()
-        .post(format!(
-            "{}/api2/json/access/ticket",
-            &CONFIG.proxmox_api_url
-        ))
-        .form(&params)
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok(())
+    Ok(axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?)
 }
 
 async fn setup_webserver(client: reqwest::Client) -> anyhow::Result<()> {
-    let address_to_listen = get_exposed_address()?;
-
     let app = Router::new()
+        .nest(
+            "/admin",
+            drain::create_router().merge(support_bundle::create_router()),
+        )
         .nest("/cluster", cluster::create_router())
-        .nest("/certificates", certificates::create_router())
-        .route("/", get(|| async { "Hello, World!" }))
+        .nest("/credentials", credentials::create_router())
+        .nest("/events", events::create_router())
+        .nest("/hooks", hooks::create_router())
+        .nest("/inventory", inventory::create_router())
+        .nest("/jobs", jobs::create_router())
+        .nest("/logging", logging::create_router())
+        .nest("/sd", prometheus_sd::create_router())
+        .merge(dashboard::create_router())
+        .merge(health::create_router())
+        .merge(metrics::create_router())
+        .merge(status::create_router())
+        .route("/", get(|| async { "Hello, World!" }));
+
+    #[cfg(feature = "pki")]
+    let app = app.nest("/certificates", certificates::create_router());
+
+    #[cfg(feature = "provisioning")]
+    let app = app
+        .merge(ignition::create_router())
+        .merge(ipxe::create_router())
+        .merge(nocloud::create_router());
+
+    #[cfg(feature = "fault-injection")]
+    let app = app.nest("/faults", faults::create_router());
+
+    let app = app
+        .layer(middleware::from_fn(drain::reject_mutations))
+        .layer(middleware::from_fn(etag::etag))
+        .layer(middleware::from_fn(forwarded::resolve_client))
+        .layer(middleware::from_fn(
+            client_certificates::extract_certificate,
+        ))
         .with_state(client);
 
-    let listener = tokio::net::TcpListener::bind(address_to_listen).await?;
+    serve(app).await
+}
 
-    println!("Listening on {}", listener.local_addr()?);
+// Proxmox nodes often boot slower than the helper after a power outage, authentication is retried
+// with a backoff while the proxy keeps serving the last known good backends.
+async fn wait_for_proxmox() -> anyhow::Result<()> {
+    let mut delay = Duration::from_secs(1);
 
-    Ok(axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await?)
-}
+    let wait = async {
+        loop {
+            match proxmox_auth::authenticate().await {
+                Ok(_) => return,
+                Err(err) => {
+                    logging::warn!(
+                        "Proxmox API unavailable, retrying in {}s: {err}",
+                        delay.as_secs()
+                    );
 
-async fn synchronize_ipams(
-    tx: watch::Sender<Vec<IpamEntry>>,
-    client: reqwest::Client,
-) -> anyhow::Result<()> {
-    loop {
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-
-        let nodes = cluster::get_nodes(client.clone()).await?.data;
-
-        let mut ipams = vec![];
-
-        for node in nodes {
-            ipams.extend(
-                cluster::get_ipams_for_node(client.clone(), &node.node)
-                    .await?
-                    .data
-                    .into_iter()
-                    .filter(|ipam| {
-                        ipam.hostname
-                            .clone()
-                            .is_some_and(|hostname| hostname.starts_with("k3s-server"))
-                    }),
-            );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(Duration::from_secs(60));
+                }
+            }
         }
+    };
 
-        tx.send(ipams)?;
+    tokio::select! {
+        _ = wait => Ok(()),
+        result = serve(health::create_startup_router()) => {
+            result?;
+            anyhow::bail!("The startup web server stopped")
+        }
+        result = run_proxy() => {
+            result?;
+            anyhow::bail!("The API proxy stopped")
+        }
     }
 }
 
-async fn proxy_k8s_servers(rx: watch::Receiver<Vec<IpamEntry>>) -> anyhow::Result<()> {
-    let listener = tokio::net::TcpListener::bind(("0.0.0.0", 6443)).await?;
-
-    loop {
-        let (mut ingress, _) = listener.accept().await?;
-
-        let ipams = rx.borrow().clone();
+// Each subsystem built behind a cargo feature runs its own loops, and never returns when it isn't
+// built in.
+#[cfg(feature = "proxy")]
+async fn run_proxy() -> anyhow::Result<()> {
+    tokio::select! {
+        result = proxy::check_backends(discovery::subscribe()) => result,
+        result = proxy::proxy_k8s_servers(discovery::subscribe()) => result,
+        result = lb_export::export_lb_config() => result,
+    }
+}
 
-        tokio::spawn(async move {
-            let mut ipam_idx = 0;
+#[cfg(not(feature = "proxy"))]
+async fn run_proxy() -> anyhow::Result<()> {
+    std::future::pending().await
+}
 
-            let egress = loop {
-                if ipam_idx >= ipams.len() {
-                    break None;
-                }
+#[cfg(feature = "pki")]
+async fn run_pki() -> anyhow::Result<()> {
+    certificate_expiry::monitor_certificate_expiry().await
+}
 
-                let ipam = &ipams[ipam_idx];
+#[cfg(not(feature = "pki"))]
+async fn run_pki() -> anyhow::Result<()> {
+    std::future::pending().await
+}
 
-                if let Ok(connection) = TcpStream::connect((ipam.ip.as_str(), 6443)).await {
-                    break Some(connection);
-                } else {
-                    ipam_idx += 1;
-                }
-            };
-
-            let mut egress = if let Some(egress) = egress {
-                egress
-            } else {
-                drop(ingress);
-                panic!("Impossible to connect to any k3s-server");
-            };
-
-            match tokio::io::copy_bidirectional(&mut ingress, &mut egress).await {
-                Ok((to_egress, to_ingress)) => {
-                    println!(
-                        "Connection ended gracefully ({to_egress} bytes from client, {to_ingress} bytes from server)"
-                    );
-                }
-                Err(err) => {
-                    println!("Error while proxying: {}", err);
-                }
-            }
-        });
+#[cfg(feature = "operator")]
+async fn run_operator(client: reqwest::Client) -> anyhow::Result<()> {
+    tokio::select! {
+        result = backups::monitor_backup_freshness(client.clone()) => result,
+        result = backup_policy::run_backup_policy(client.clone()) => result,
+        result = k3s_certificates::rotate_k3s_certificates(client.clone()) => result,
+        result = node_reaper::reap_nodes(client.clone()) => result,
+        result = etcd_snapshots::ship_snapshots(client.clone()) => result,
+        result = annotations::annotate_vms(client.clone()) => result,
     }
 }
 
+#[cfg(not(feature = "operator"))]
+async fn run_operator(_client: reqwest::Client) -> anyhow::Result<()> {
+    std::future::pending().await
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
 
-    let pve_ticket = generate_pve_ticket().await?;
+    if config_file::is_schema_requested() {
+        println!("{}", serde_json::to_string_pretty(&config_file::schema())?);
+        return Ok(());
+    }
 
-    let cookie_jar = Jar::default();
-    cookie_jar.add_cookie_str(
-        &format!("PVEAuthCookie={}", pve_ticket.data.ticket),
-        &CONFIG.proxmox_api_url.parse()?,
-    );
+    // Must run before the configuration is first used, the file provides its defaults.
+    config_file::apply().await?;
 
-    let client = reqwest::ClientBuilder::new()
-        .cookie_provider(Arc::new(cookie_jar))
-        .build()?;
+    error_reporting::install_panic_hook();
 
-    let (tx, rx) = watch::channel(Vec::new());
+    // Sockets are bound while still privileged, CLI commands don't serve anything.
+    if CONFIG.command.is_none() {
+        listeners::prepare()?;
+        listeners::drop_privileges()?;
+    }
 
-    let axum_handle = setup_webserver(client.clone());
-    tokio::pin!(axum_handle);
+    Lazy::force(&clusters::CLUSTERS);
+    #[cfg(feature = "pki")]
+    Lazy::force(&signer::SIGNERS);
+    Lazy::force(&roles::ROLE_RULES);
+    maintenance::load()?;
 
-    let synchronize_ipams_handle = synchronize_ipams(tx, client.clone());
-    tokio::pin!(synchronize_ipams_handle);
+    #[cfg(feature = "proxy")]
+    if discovery::seed(proxy::restore_last_known_good()).await {
+        logging::info!("Restored the last known good proxy backends");
+    }
 
-    let proxy_k8s_servers_handle = proxy_k8s_servers(rx);
-    tokio::pin!(proxy_k8s_servers_handle);
+    if CONFIG.wait_for_proxmox {
+        wait_for_proxmox().await?;
+    } else {
+        proxmox_auth::authenticate().await?;
+    }
 
-    loop {
-        tokio::select! {
-            _ = &mut axum_handle => {
-                break;
-            }
-            _ = &mut synchronize_ipams_handle => {
-                break;
-            }
-            _ = &mut proxy_k8s_servers_handle => {
-                break;
-            }
-            _ = tokio::time::sleep(std::time::Duration::from_secs(600)) => {
-                renew_ticket(&pve_ticket).await?;
-            }
-        }
+    let client = proxmox_client_builder()?
+        .cookie_provider(proxmox_auth::cookie_provider())
+        .build()?;
+
+    if CONFIG.proxmox_insecure_skip_verify && CONFIG.proxmox_tls_fingerprint.is_none() {
+        logging::warn!("The certificate of the Proxmox API is not verified");
     }
 
-    Ok(())
+    if let Some(command) = &CONFIG.command {
+        let result = cli::run(command, CONFIG.output, &client).await;
+        events::flush();
+        return result;
+    }
+
+    jobs::mark_interrupted()?;
+
+    permissions::warn_missing(&client).await;
+    access::warn_unconfigured();
+
+    // Every loop runs for as long as the helper does, the first one to stop takes it down and its
+    // error becomes the exit status.
+    tokio::select! {
+        result = setup_webserver(client.clone()) => result,
+        result = discovery::synchronize_ipams(client.clone()) => result,
+        result = discovery::record_changes() => result,
+        result = events::persist_events() => result,
+        result = run_proxy() => result,
+        result = run_pki() => result,
+        result = run_operator(client.clone()) => result,
+        result = placement::track_placements(client.clone()) => result,
+        result = ha::monitor_node_conditions(client.clone()) => result,
+        result = ipam_gc::run_ipam_gc(client.clone()) => result,
+        result = proxmox_auth::reload_on_sighup() => result,
+        result = proxmox_auth::keep_session_alive() => result,
+    }
 }
@@ -0,0 +1,531 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::Json;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Timelike, Utc};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+
+use crate::{error::AppResult, CONFIG};
+
+// Loaded at startup, an invalid window is a configuration error rather than a panic later on.
+static WINDOWS: OnceCell<BTreeMap<OperationClass, Window>> = OnceCell::new();
+
+static PENDING: Lazy<Mutex<BTreeMap<OperationClass, Vec<String>>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+// Openings are searched day by day. Any expression that matches at all does so again within 8
+// years, February 29th is skipped on century years.
+const SEARCH_DAYS: i64 = 8 * 366;
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum OperationClass {
+    Backup,
+    CertificateRotation,
+    EtcdSnapshot,
+    IpamGc,
+    NodeReaper,
+    ServerRestart,
+    TokenRotation,
+}
+
+impl OperationClass {
+    const ALL: [OperationClass; 7] = [
+        OperationClass::Backup,
+        OperationClass::CertificateRotation,
+        OperationClass::EtcdSnapshot,
+        OperationClass::IpamGc,
+        OperationClass::NodeReaper,
+        OperationClass::ServerRestart,
+        OperationClass::TokenRotation,
+    ];
+
+    fn configured_window(&self) -> Option<&'static String> {
+        match self {
+            OperationClass::Backup => CONFIG.maintenance_window_backup.as_ref(),
+            OperationClass::CertificateRotation => {
+                CONFIG.maintenance_window_certificate_rotation.as_ref()
+            }
+            OperationClass::EtcdSnapshot => CONFIG.maintenance_window_etcd_snapshot.as_ref(),
+            OperationClass::IpamGc => CONFIG.maintenance_window_ipam_gc.as_ref(),
+            OperationClass::NodeReaper => CONFIG.maintenance_window_node_reaper.as_ref(),
+            OperationClass::ServerRestart => CONFIG.maintenance_window_server_restart.as_ref(),
+            OperationClass::TokenRotation => CONFIG.maintenance_window_token_rotation.as_ref(),
+        }
+    }
+}
+
+// `minute hour day-of-month month day-of-week`, with `*`, lists, ranges and steps. Every minute the
+// expression matches opens the window.
+#[derive(Debug)]
+pub(crate) struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Like cron, a restricted day of month and day of week match either one.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+// Every window is in UTC, whatever the timezone of the host. Either a daily range like the k3s
+// certificate rotation window, e.g. `22:00-04:00`, or a cron expression followed by how long the
+// window stays open, e.g. `0 22 * * 6 8h` for Saturday nights.
+#[derive(Debug)]
+pub(crate) enum Window {
+    Cron {
+        expression: String,
+        schedule: Schedule,
+        duration: chrono::Duration,
+    },
+    Daily {
+        start: NaiveTime,
+        end: NaiveTime,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct MaintenanceStatus {
+    pub class: OperationClass,
+    pub window: Option<String>,
+    pub open: bool,
+    pub next_window: Option<i64>,
+    // Disruptive actions deferred by the last pass, they run once the window opens.
+    pub pending: Vec<String>,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let mut bits = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>()?)),
+            None => (part, None),
+        };
+
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (start.parse()?, end.parse()?),
+            None => {
+                let start = range.parse()?;
+                (start, if step.is_some() { max } else { start })
+            }
+        };
+
+        if start < min || end > max || start > end || step == Some(0) {
+            anyhow::bail!("{part} is out of range {min}-{max}");
+        }
+
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+impl Schedule {
+    fn parse(expression: &str) -> anyhow::Result<Self> {
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            anyhow::bail!("expected 5 fields, got {}", fields.len());
+        };
+
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+
+        // Both 0 and 7 are Sunday.
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+
+        Ok(Schedule {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+
+        self.months & (1 << date.month()) != 0 && day_matches
+    }
+
+    fn matches_minute(&self, minute: u32) -> bool {
+        self.hours & (1 << (minute / 60)) != 0 && self.minutes & (1 << (minute % 60)) != 0
+    }
+
+    // The first opening strictly after `from`.
+    fn next_after(&self, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let first_minute = from.hour() * 60 + from.minute() + 1;
+
+        (0..SEARCH_DAYS).find_map(|days| {
+            let date = from.date_naive() + chrono::Duration::days(days);
+            let start = if days == 0 { first_minute } else { 0 };
+
+            if !self.matches_day(date) {
+                return None;
+            }
+
+            (start..MINUTES_PER_DAY)
+                .find(|minute| self.matches_minute(*minute))
+                .and_then(|minute| at_minute(date, minute))
+        })
+    }
+
+    // The last opening at or before `at`, as long as it is strictly after `after`.
+    fn last_until(&self, at: DateTime<Utc>, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let last_minute = at.hour() * 60 + at.minute();
+        let days = (at.date_naive() - after.date_naive()).num_days();
+
+        (0..=days)
+            .find_map(|days| {
+                let date = at.date_naive() - chrono::Duration::days(days);
+                let end = if days == 0 {
+                    last_minute
+                } else {
+                    MINUTES_PER_DAY - 1
+                };
+
+                if !self.matches_day(date) {
+                    return None;
+                }
+
+                (0..=end)
+                    .rev()
+                    .find(|minute| self.matches_minute(*minute))
+                    .and_then(|minute| at_minute(date, minute))
+            })
+            .filter(|opening| *opening > after)
+    }
+}
+
+fn at_minute(date: NaiveDate, minute: u32) -> Option<DateTime<Utc>> {
+    Some(date.and_hms_opt(minute / 60, minute % 60, 0)?.and_utc())
+}
+
+// A number of days, hours and minutes, e.g. `2h` or `1h30m`.
+fn parse_duration(duration: &str) -> anyhow::Result<chrono::Duration> {
+    let mut total = chrono::Duration::zero();
+    let mut number = String::new();
+
+    for character in duration.chars() {
+        if character.is_ascii_digit() {
+            number.push(character);
+            continue;
+        }
+
+        let value = number
+            .parse::<i64>()
+            .context(format!("Invalid duration {duration}"))?;
+        number.clear();
+
+        total += match character {
+            'd' => chrono::Duration::days(value),
+            'h' => chrono::Duration::hours(value),
+            'm' => chrono::Duration::minutes(value),
+            _ => anyhow::bail!("Invalid duration {duration}, expected e.g. 2h or 1h30m"),
+        };
+    }
+
+    if !number.is_empty() || total <= chrono::Duration::zero() {
+        anyhow::bail!("Invalid duration {duration}, expected e.g. 2h or 1h30m");
+    }
+
+    Ok(total)
+}
+
+fn parse_daily(window: &str) -> anyhow::Result<Window> {
+    let (start, end) = window
+        .split_once('-')
+        .context(format!("Invalid window {window}, expected HH:MM-HH:MM"))?;
+
+    Ok(Window::Daily {
+        start: NaiveTime::parse_from_str(start.trim(), "%H:%M")?,
+        end: NaiveTime::parse_from_str(end.trim(), "%H:%M")?,
+    })
+}
+
+fn parse_window(window: &str) -> anyhow::Result<Window> {
+    if window.contains(':') {
+        return parse_daily(window);
+    }
+
+    let (expression, duration) =
+        window
+            .trim()
+            .rsplit_once(char::is_whitespace)
+            .context(format!(
+                "Invalid window {window}, expected a cron expression followed by a duration"
+            ))?;
+
+    let schedule = Schedule::parse(expression)?;
+
+    if schedule.next_after(Utc::now()).is_none() {
+        anyhow::bail!("{expression} never matches");
+    }
+
+    Ok(Window::Cron {
+        expression: expression.trim().to_string(),
+        schedule,
+        duration: parse_duration(duration)?,
+    })
+}
+
+impl Window {
+    fn expression(&self) -> String {
+        match self {
+            Window::Cron {
+                expression,
+                duration,
+                ..
+            } => format!("{expression} for {}m UTC", duration.num_minutes()),
+            Window::Daily { start, end } => {
+                format!("{}-{} UTC", start.format("%H:%M"), end.format("%H:%M"))
+            }
+        }
+    }
+
+    fn contains(&self, at: DateTime<Utc>) -> bool {
+        match self {
+            Window::Cron {
+                schedule, duration, ..
+            } => schedule.last_until(at, at - *duration).is_some(),
+            Window::Daily { start, end } => {
+                let now = at.time();
+
+                if start <= end {
+                    *start <= now && now < *end
+                } else {
+                    // The window wraps around midnight.
+                    now >= *start || now < *end
+                }
+            }
+        }
+    }
+
+    // `from` itself when the window is already open.
+    fn next_opening(&self, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.contains(from) {
+            return Some(from);
+        }
+
+        match self {
+            Window::Cron { schedule, .. } => schedule.next_after(from),
+            Window::Daily { start, .. } => {
+                let today = from.date_naive().and_time(*start).and_utc();
+
+                Some(if today > from {
+                    today
+                } else {
+                    today + chrono::Duration::days(1)
+                })
+            }
+        }
+    }
+}
+
+pub(crate) fn load() -> anyhow::Result<()> {
+    let mut windows = BTreeMap::new();
+
+    for class in OperationClass::ALL {
+        if let Some(window) = class.configured_window() {
+            windows.insert(
+                class,
+                parse_window(window).context(format!("Invalid maintenance window {window}"))?,
+            );
+        }
+    }
+
+    if let Entry::Vacant(entry) = windows.entry(OperationClass::CertificateRotation) {
+        entry.insert(
+            parse_daily(&CONFIG.k3s_certificate_rotation_window)
+                .context("Invalid k3s_certificate_rotation_window")?,
+        );
+    }
+
+    WINDOWS
+        .set(windows)
+        .map_err(|_| anyhow::anyhow!("The maintenance windows are already loaded"))
+}
+
+fn windows() -> &'static BTreeMap<OperationClass, Window> {
+    WINDOWS
+        .get()
+        .expect("The maintenance windows are loaded at startup")
+}
+
+// Classes without a window may run at any time.
+pub(crate) fn is_open(class: OperationClass) -> bool {
+    windows()
+        .get(&class)
+        .is_none_or(|window| window.contains(Utc::now()))
+}
+
+// For operations started on request, they are refused rather than deferred.
+pub(crate) fn ensure_open(class: OperationClass) -> anyhow::Result<()> {
+    let now = Utc::now();
+
+    match windows().get(&class) {
+        Some(window) if !window.contains(now) => anyhow::bail!(
+            "Outside of the maintenance window {}, it next opens at {}",
+            window.expression(),
+            window
+                .next_opening(now)
+                .map_or("an unknown time".to_string(), |opening| opening
+                    .to_rfc3339())
+        ),
+        _ => Ok(()),
+    }
+}
+
+// Replaces what the class deferred on its previous pass.
+pub(crate) fn defer(class: OperationClass, actions: Vec<String>) {
+    PENDING.lock().unwrap().insert(class, actions);
+}
+
+// Loops that deferred something wake up when the window opens rather than after their interval.
+pub(crate) fn next_pass_in(class: OperationClass, interval: Duration) -> Duration {
+    let now = Utc::now();
+
+    let has_pending = PENDING
+        .lock()
+        .unwrap()
+        .get(&class)
+        .is_some_and(|actions| !actions.is_empty());
+
+    windows()
+        .get(&class)
+        .filter(|_| has_pending)
+        .and_then(|window| window.next_opening(now))
+        .and_then(|opening| (opening - now).to_std().ok())
+        .filter(|until| !until.is_zero())
+        .map_or(interval, |until| until.min(interval))
+}
+
+pub(crate) fn status() -> Vec<MaintenanceStatus> {
+    let now = Utc::now();
+    let pending = PENDING.lock().unwrap();
+
+    OperationClass::ALL
+        .into_iter()
+        .map(|class| {
+            let window = windows().get(&class);
+
+            MaintenanceStatus {
+                class,
+                window: window.map(Window::expression),
+                open: window.is_none_or(|window| window.contains(now)),
+                next_window: window
+                    .and_then(|window| window.next_opening(now))
+                    .map(|opening| opening.timestamp()),
+                pending: pending.get(&class).cloned().unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+pub(crate) async fn get_maintenance() -> AppResult<Json<Vec<MaintenanceStatus>>> {
+    Ok(Json(status()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2024-06-01 is a Saturday.
+        Utc.with_ymd_and_hms(2024, 6, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("2h").unwrap(), chrono::Duration::hours(2));
+        assert_eq!(
+            parse_duration("1h30m").unwrap(),
+            chrono::Duration::minutes(90)
+        );
+        assert_eq!(parse_duration("1d").unwrap(), chrono::Duration::days(1));
+        assert!(parse_duration("0m").is_err());
+        assert!(parse_duration("2").is_err());
+        assert!(parse_duration("2s").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_windows() {
+        assert!(parse_window("0 22 * * 6").is_err());
+        assert!(parse_window("0 22 * * 6 soon").is_err());
+        assert!(parse_window("60 22 * * 6 2h").is_err());
+        assert!(parse_window("0 22 31 2 * 2h").is_err());
+        assert!(parse_window("22:00").is_err());
+        assert!(parse_window("22:00-25:00").is_err());
+    }
+
+    #[test]
+    fn cron_windows_stay_open_for_their_duration() {
+        let window = parse_window("0 22 * * 6 8h").unwrap();
+
+        assert!(!window.contains(at(1, 21, 59)));
+        assert!(window.contains(at(1, 22, 0)));
+        assert!(window.contains(at(2, 5, 59)));
+        assert!(!window.contains(at(2, 6, 0)));
+        assert!(!window.contains(at(3, 22, 0)));
+    }
+
+    #[test]
+    fn cron_windows_open_at_the_next_match() {
+        let window = parse_window("30 1 * * 1-5 1h").unwrap();
+
+        // Saturday, the next weekday is Monday the 3rd.
+        assert_eq!(window.next_opening(at(1, 12, 0)), Some(at(3, 1, 30)));
+        assert_eq!(window.next_opening(at(3, 1, 45)), Some(at(3, 1, 45)));
+        assert_eq!(window.next_opening(at(3, 2, 30)), Some(at(4, 1, 30)));
+    }
+
+    #[test]
+    fn cron_days_match_either_the_day_of_month_or_of_week() {
+        let window = parse_window("0 0 15 * 0 1h").unwrap();
+
+        assert!(window.contains(at(2, 0, 30)));
+        assert!(window.contains(at(15, 0, 30)));
+        assert!(!window.contains(at(14, 0, 30)));
+    }
+
+    #[test]
+    fn daily_windows_wrap_around_midnight() {
+        let window = parse_window("22:00-04:00").unwrap();
+
+        assert!(window.contains(at(1, 23, 0)));
+        assert!(window.contains(at(2, 3, 59)));
+        assert!(!window.contains(at(2, 4, 0)));
+        assert_eq!(window.next_opening(at(2, 12, 0)), Some(at(2, 22, 0)));
+    }
+
+    #[test]
+    fn daily_windows_open_the_next_day_once_passed() {
+        let window = parse_window("02:00-05:00").unwrap();
+
+        assert_eq!(window.next_opening(at(1, 6, 0)), Some(at(2, 2, 0)));
+        assert_eq!(window.expression(), "02:00-05:00 UTC");
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+use axum::{routing::get, Router};
+use once_cell::sync::Lazy;
+
+static METRICS: Lazy<Mutex<BTreeMap<String, Metric>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+pub(crate) const DURATION_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+struct Histogram {
+    buckets: Vec<f64>,
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+struct Metric {
+    kind: &'static str,
+    help: &'static str,
+    samples: BTreeMap<String, f64>,
+    histograms: BTreeMap<String, Histogram>,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Self {
+        Self {
+            buckets: buckets.to_vec(),
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+fn format_label_pairs(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{name}=\"{}\"",
+                value.replace('\\', r"\\").replace('"', "\\\"")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    format!("{{{}}}", format_label_pairs(labels))
+}
+
+fn with_metric<F: FnOnce(&mut Metric)>(name: &str, kind: &'static str, help: &'static str, f: F) {
+    let mut metrics = METRICS.lock().unwrap();
+
+    let metric = metrics.entry(name.to_string()).or_insert_with(|| Metric {
+        kind,
+        help,
+        samples: BTreeMap::new(),
+        histograms: BTreeMap::new(),
+    });
+
+    f(metric);
+}
+
+pub(crate) fn set_gauge(name: &str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+    with_metric(name, "gauge", help, |metric| {
+        metric.samples.insert(format_labels(labels), value);
+    });
+}
+
+pub(crate) fn clear_gauge(name: &str) {
+    if let Some(metric) = METRICS.lock().unwrap().get_mut(name) {
+        metric.samples.clear();
+        metric.histograms.clear();
+    }
+}
+
+pub(crate) fn increment_counter(name: &str, help: &'static str, labels: &[(&str, &str)]) {
+    with_metric(name, "counter", help, |metric| {
+        *metric.samples.entry(format_labels(labels)).or_default() += 1.0;
+    });
+}
+
+pub(crate) fn observe_histogram(
+    name: &str,
+    help: &'static str,
+    buckets: &[f64],
+    labels: &[(&str, &str)],
+    value: f64,
+) {
+    with_metric(name, "histogram", help, |metric| {
+        metric
+            .histograms
+            .entry(format_label_pairs(labels))
+            .or_insert_with(|| Histogram::new(buckets))
+            .observe(value);
+    });
+}
+
+async fn get_metrics() -> String {
+    let metrics = METRICS.lock().unwrap();
+
+    let mut output = String::new();
+
+    for (name, metric) in metrics.iter() {
+        let _ = writeln!(output, "# HELP {name} {}", metric.help);
+        let _ = writeln!(output, "# TYPE {name} {}", metric.kind);
+
+        for (labels, value) in &metric.samples {
+            let _ = writeln!(output, "{name}{labels} {value}");
+        }
+
+        for (labels, histogram) in &metric.histograms {
+            let separator = if labels.is_empty() { "" } else { "," };
+
+            for (bucket, count) in histogram.buckets.iter().zip(&histogram.counts) {
+                let _ = writeln!(
+                    output,
+                    "{name}_bucket{{{labels}{separator}le=\"{bucket}\"}} {count}"
+                );
+            }
+
+            let _ = writeln!(
+                output,
+                "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}",
+                histogram.count
+            );
+
+            let labels = if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{{{labels}}}")
+            };
+
+            let _ = writeln!(output, "{name}_sum{labels} {}", histogram.sum);
+            let _ = writeln!(output, "{name}_count{labels} {}", histogram.count);
+        }
+    }
+
+    output
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new().route("/metrics", get(get_metrics))
+}
@@ -0,0 +1,249 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Path},
+    routing::get,
+    Router,
+};
+
+use crate::{
+    cluster::{IpamEntry, NodeRole},
+    clusters, discovery,
+    error::AppResult,
+    get_exposed_address, registrations,
+    roles::NodeOs,
+    CONFIG,
+};
+
+// cloudbase-init runs user-data starting with this marker as a PowerShell script.
+const WINDOWS_USER_DATA: &str = r#"#ps1_sysnative
+$ErrorActionPreference = "Stop"
+$token = Invoke-RestMethod -Uri "{{helper_url}}/cluster/{{server_vmid}}/token"
+New-Item -ItemType Directory -Force -Path (Split-Path "{{config_path}}") | Out-Null
+Set-Content -Path "{{config_path}}" -Value "server: {{server_url}}`ntoken: $token`nnode-name: {{node_name}}"
+{{install_command}}
+Start-Service {{distro}}
+"#;
+
+// Bare-metal members come from the static entries and have no vmid, they are matched by MAC.
+pub(crate) fn find_member(ip: Option<&str>, mac: Option<&str>) -> anyhow::Result<IpamEntry> {
+    discovery::subscribe()
+        .borrow()
+        .iter()
+        .filter(|ipam| ipam.assignment.is_some())
+        .find(|ipam| match (mac, ip) {
+            (Some(mac), _) => ipam
+                .mac
+                .as_ref()
+                .is_some_and(|ipam_mac| ipam_mac.eq_ignore_ascii_case(mac)),
+            (None, Some(ip)) => ipam.ip == ip,
+            (None, None) => false,
+        })
+        .cloned()
+        .context(format!(
+            "No cluster member found for {}",
+            mac.or(ip).unwrap_or_default()
+        ))
+}
+
+// Static entries are declared by the operator, only discovered VMs go through the approval.
+fn ensure_approved(ipam: &IpamEntry) -> anyhow::Result<()> {
+    match &ipam.vmid {
+        Some(vmid) => registrations::ensure_approved(vmid),
+        None => Ok(()),
+    }
+}
+
+fn variables(ipam: &IpamEntry) -> anyhow::Result<Vec<(&'static str, String)>> {
+    let assignment = ipam.assignment.as_ref().context("Caller has no role")?;
+    let cluster = clusters::find(&assignment.cluster)
+        .context(format!("Unknown cluster {}", assignment.cluster))?;
+    let (helper_ip, helper_port) = get_exposed_address()?;
+
+    let (install_command, config_path) = match assignment.os {
+        NodeOs::Linux => (
+            cluster.distro.install_command(assignment.role),
+            format!("/etc/rancher/{}/config.yaml", cluster.distro.as_str()),
+        ),
+        NodeOs::Windows => (
+            cluster.distro.windows_install_command()?,
+            cluster.distro.windows_config_path(),
+        ),
+    };
+
+    // The join token is read from a server over SSH.
+    let server_vmid = discovery::subscribe()
+        .borrow()
+        .iter()
+        .filter(|member| {
+            member.assignment.as_ref().is_some_and(|member| {
+                member.cluster == assignment.cluster && member.role == NodeRole::Server
+            })
+        })
+        .find_map(|member| member.vmid.clone())
+        .unwrap_or_default();
+
+    Ok(vec![
+        ("vmid", ipam.vmid.clone().unwrap_or_default()),
+        ("hostname", ipam.hostname.clone().unwrap_or_default()),
+        // Kubernetes only accepts lowercase node names, Windows hostnames are uppercase.
+        (
+            "node_name",
+            ipam.hostname.clone().unwrap_or_default().to_lowercase(),
+        ),
+        ("os", assignment.os.as_str().to_string()),
+        ("ip", ipam.ip.clone()),
+        ("mac", ipam.mac.clone().unwrap_or_default()),
+        ("cluster", assignment.cluster.clone()),
+        ("role", assignment.role.as_str().to_string()),
+        ("pool", assignment.pool.clone().unwrap_or_default()),
+        ("helper_url", format!("http://{helper_ip}:{helper_port}")),
+        ("distro", cluster.distro.as_str().to_string()),
+        ("install_command", install_command),
+        ("config_path", config_path),
+        ("token_path", cluster.distro.token_path()),
+        ("server_vmid", server_vmid),
+        ("kubeconfig_path", cluster.distro.kubeconfig_path()),
+        ("server_url", cluster.server_url()?),
+        ("api_host", cluster.api_host()?),
+        (
+            "tls_san",
+            cluster.api_fqdn().unwrap_or_default().to_string(),
+        ),
+    ])
+}
+
+// `{{hostname}}` and the like are replaced by the member's values.
+pub(crate) fn render_template(template: String, ipam: &IpamEntry) -> anyhow::Result<String> {
+    Ok(variables(ipam)?
+        .into_iter()
+        .fold(template, |rendered, (variable, value)| {
+            rendered.replace(&format!("{{{{{variable}}}}}"), &value)
+        }))
+}
+
+// `user-data.server` is preferred over `user-data` for servers, and likewise for every role. Windows
+// nodes only use `user-data.windows`, the Linux templates can't apply to them.
+pub(crate) fn template(name: &str, ipam: &IpamEntry) -> anyhow::Result<Option<String>> {
+    let directory = PathBuf::from(
+        CONFIG
+            .nocloud_templates_path
+            .as_ref()
+            .context("nocloud_templates_path is not configured")?,
+    );
+
+    let (role, os) = ipam
+        .assignment
+        .as_ref()
+        .map(|assignment| (assignment.role.as_str(), assignment.os))
+        .unwrap_or_default();
+
+    let paths = match os {
+        NodeOs::Linux => vec![
+            directory.join(format!("{name}.{role}")),
+            directory.join(name),
+        ],
+        NodeOs::Windows => vec![directory.join(format!("{name}.windows"))],
+    };
+
+    for path in paths {
+        if path.exists() {
+            return Ok(Some(std::fs::read_to_string(&path).context(format!(
+                "Unable to read NoCloud template {}",
+                path.display()
+            ))?));
+        }
+    }
+
+    Ok(None)
+}
+
+fn render(name: &str, ipam: &IpamEntry) -> anyhow::Result<Option<String>> {
+    ensure_approved(ipam)?;
+
+    template(name, ipam)?
+        .map(|template| render_template(template, ipam))
+        .transpose()
+}
+
+fn meta_data(ipam: &IpamEntry) -> anyhow::Result<String> {
+    if let Some(meta_data) = render("meta-data", ipam)? {
+        return Ok(meta_data);
+    }
+
+    let instance_id = ipam
+        .vmid
+        .clone()
+        .or_else(|| ipam.mac.as_ref().map(|mac| mac.replace(':', "")))
+        .unwrap_or_else(|| ipam.ip.clone());
+
+    Ok(format!(
+        "instance-id: iid-{instance_id}\nlocal-hostname: {}\n",
+        ipam.hostname.clone().unwrap_or_default()
+    ))
+}
+
+// Windows nodes get a PowerShell join script unless a template replaces it.
+fn user_data(ipam: &IpamEntry) -> anyhow::Result<String> {
+    if let Some(user_data) = render("user-data", ipam)? {
+        return Ok(user_data);
+    }
+
+    match ipam.assignment.as_ref().map(|assignment| assignment.os) {
+        Some(NodeOs::Windows) => {
+            ensure_approved(ipam)?;
+            render_template(WINDOWS_USER_DATA.to_string(), ipam)
+        }
+        _ => anyhow::bail!("No user-data template"),
+    }
+}
+
+// Optional, cloud-init is fine with an empty document.
+fn vendor_data(ipam: &IpamEntry) -> anyhow::Result<String> {
+    Ok(render("vendor-data", ipam)?.unwrap_or_default())
+}
+
+fn by_address(addr: SocketAddr) -> anyhow::Result<IpamEntry> {
+    find_member(Some(&addr.ip().to_string()), None)
+}
+
+fn by_mac(mac: &str) -> anyhow::Result<IpamEntry> {
+    find_member(None, Some(mac))
+}
+
+async fn get_meta_data(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> AppResult<String> {
+    Ok(meta_data(&by_address(addr)?)?)
+}
+
+async fn get_user_data(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> AppResult<String> {
+    Ok(user_data(&by_address(addr)?)?)
+}
+
+async fn get_vendor_data(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> AppResult<String> {
+    Ok(vendor_data(&by_address(addr)?)?)
+}
+
+async fn get_mac_meta_data(Path(mac): Path<String>) -> AppResult<String> {
+    Ok(meta_data(&by_mac(&mac)?)?)
+}
+
+async fn get_mac_user_data(Path(mac): Path<String>) -> AppResult<String> {
+    Ok(user_data(&by_mac(&mac)?)?)
+}
+
+async fn get_mac_vendor_data(Path(mac): Path<String>) -> AppResult<String> {
+    Ok(vendor_data(&by_mac(&mac)?)?)
+}
+
+// Served at the root so `ds=nocloud;s=http://<helper>:<port>/` works as the seed URL, machines
+// installing from the network can't rely on their address yet and use `/nocloud/<mac>/` instead.
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/meta-data", get(get_meta_data))
+        .route("/user-data", get(get_user_data))
+        .route("/vendor-data", get(get_vendor_data))
+        .route("/nocloud/:mac/meta-data", get(get_mac_meta_data))
+        .route("/nocloud/:mac/user-data", get(get_mac_user_data))
+        .route("/nocloud/:mac/vendor-data", get(get_mac_vendor_data))
+}
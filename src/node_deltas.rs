@@ -0,0 +1,130 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use axum::{
+    http::HeaderValue,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::cluster::IpamEntry;
+
+// Enough for every caller of a bootstrap storm to find the snapshot it got on its previous poll.
+const MAX_SNAPSHOTS: usize = 64;
+
+const RESOURCE_VERSION_HEADER: &str = "X-Resource-Version";
+
+// Recent snapshots handed out by `/cluster/nodes`, oldest first. Every caller sees a different
+// list, they are keyed by the hash of their content rather than by a global counter.
+static SNAPSHOTS: Lazy<Mutex<VecDeque<Snapshot>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+type Snapshot = (String, Vec<IpamEntry>);
+
+#[derive(Deserialize)]
+pub(crate) struct NodesQuery {
+    #[serde(rename = "resourceVersion")]
+    resource_version: Option<String>,
+}
+
+// `reset` is set when the given version is unknown, e.g. after a restart of the helper, `added`
+// holds the whole list then and the client starts over from it.
+#[derive(Serialize)]
+pub struct NodesDelta {
+    pub resource_version: String,
+    pub reset: bool,
+    pub added: Vec<IpamEntry>,
+    pub changed: Vec<IpamEntry>,
+    pub removed: Vec<String>,
+}
+
+fn resource_version(ipams: &[IpamEntry]) -> anyhow::Result<String> {
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .encode(&Sha256::digest(serde_json::to_vec(ipams)?)[..12]))
+}
+
+// A known snapshot moves to the back so that versions still being polled aren't evicted.
+fn remember(version: &str, ipams: &[IpamEntry]) {
+    let mut snapshots = SNAPSHOTS.lock().unwrap();
+
+    snapshots.retain(|(known, _)| known != version);
+    snapshots.push_back((version.to_string(), ipams.to_vec()));
+
+    while snapshots.len() > MAX_SNAPSHOTS {
+        snapshots.pop_front();
+    }
+}
+
+fn previous(version: &str) -> Option<Vec<IpamEntry>> {
+    SNAPSHOTS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(known, _)| known == version)
+        .map(|(_, snapshot)| snapshot.clone())
+}
+
+fn delta(
+    resource_version: String,
+    previous: Option<Vec<IpamEntry>>,
+    ipams: Vec<IpamEntry>,
+) -> NodesDelta {
+    let Some(previous) = previous else {
+        return NodesDelta {
+            resource_version,
+            reset: true,
+            added: ipams,
+            changed: vec![],
+            removed: vec![],
+        };
+    };
+
+    let mut added = vec![];
+    let mut changed = vec![];
+
+    for ipam in &ipams {
+        match previous.iter().find(|known| known.id == ipam.id) {
+            None => added.push(ipam.clone()),
+            Some(known) if serde_json::to_value(known).ok() != serde_json::to_value(ipam).ok() => {
+                changed.push(ipam.clone())
+            }
+            Some(_) => {}
+        }
+    }
+
+    let removed = previous
+        .iter()
+        .filter(|known| !ipams.iter().any(|ipam| ipam.id == known.id))
+        .map(|known| known.id.clone())
+        .collect();
+
+    NodesDelta {
+        resource_version,
+        reset: false,
+        added,
+        changed,
+        removed,
+    }
+}
+
+// Without a version the full list is returned as before, its version is passed along in a header
+// for the next poll.
+pub(crate) fn respond(query: NodesQuery, ipams: Vec<IpamEntry>) -> anyhow::Result<Response> {
+    let version = resource_version(&ipams)?;
+
+    let since = query.resource_version.as_deref().and_then(previous);
+    remember(&version, &ipams);
+
+    let mut response = match query.resource_version {
+        Some(_) => Json(delta(version.clone(), since, ipams)).into_response(),
+        None => Json(ipams).into_response(),
+    };
+
+    response
+        .headers_mut()
+        .insert(RESOURCE_VERSION_HEADER, HeaderValue::from_str(&version)?);
+
+    Ok(response)
+}
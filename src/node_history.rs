@@ -0,0 +1,151 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::Context;
+use axum::{extract::Path, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{cluster::IpamEntry, error::AppResult, logging, STATE};
+
+const NODE_HISTORY_KEY: &str = "node_history";
+const MAX_ENTRIES: usize = 100;
+// The synchronizer sees every node every few seconds, last seen doesn't need to be that precise.
+const LAST_SEEN_RESOLUTION: i64 = 300;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub timestamp: i64,
+    pub kind: String,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NodeHistory {
+    pub vmid: String,
+    pub hostname: Option<String>,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub joined_at: Option<i64>,
+    pub joins: u32,
+    pub drains: u32,
+    pub rebuilds: u32,
+    pub left_at: Option<i64>,
+    pub entries: VecDeque<HistoryEntry>,
+}
+
+impl NodeHistory {
+    fn push(&mut self, kind: &str, message: String) {
+        let now = chrono::Utc::now().timestamp();
+
+        match kind {
+            "joined" => {
+                self.joined_at = Some(now);
+                self.joins += 1;
+                self.left_at = None;
+            }
+            "drained" => self.drains += 1,
+            "rebuilt" => self.rebuilds += 1,
+            "left" => self.left_at = Some(now),
+            _ => {}
+        }
+
+        self.entries.push_back(HistoryEntry {
+            timestamp: now,
+            kind: kind.to_string(),
+            message,
+        });
+
+        while self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+    }
+}
+
+fn update<F: FnOnce(&mut BTreeMap<String, NodeHistory>)>(f: F) {
+    if let Err(err) = STATE.update(NODE_HISTORY_KEY, f) {
+        logging::warn!("Unable to persist the node history: {err}");
+    }
+}
+
+// Called after every synchronization, only writes the store when a node shows up or its last seen
+// time is due.
+pub(crate) fn seen(ipams: &[IpamEntry]) {
+    let now = chrono::Utc::now().timestamp();
+    let history: BTreeMap<String, NodeHistory> = STATE.get(NODE_HISTORY_KEY).unwrap_or_default();
+
+    let members = ipams
+        .iter()
+        .filter(|ipam| ipam.assignment.is_some())
+        .filter_map(|ipam| Some((ipam.vmid.clone()?, ipam.hostname.clone())))
+        .filter(|(vmid, hostname)| {
+            history.get(vmid).is_none_or(|node| {
+                now - node.last_seen >= LAST_SEEN_RESOLUTION || &node.hostname != hostname
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if members.is_empty() {
+        return;
+    }
+
+    update(|history| {
+        for (vmid, hostname) in members {
+            let node = history.entry(vmid.clone()).or_insert_with(|| NodeHistory {
+                vmid: vmid.clone(),
+                first_seen: now,
+                ..Default::default()
+            });
+
+            if node.entries.is_empty() {
+                node.push(
+                    "first-seen",
+                    format!("VM {vmid} discovered as a cluster member"),
+                );
+            }
+
+            if node.hostname.is_some() && node.hostname != hostname {
+                node.push(
+                    "renamed",
+                    format!(
+                        "Renamed from {} to {}",
+                        node.hostname.as_deref().unwrap_or_default(),
+                        hostname.as_deref().unwrap_or_default()
+                    ),
+                );
+            }
+
+            node.hostname = hostname;
+            node.last_seen = now;
+        }
+    });
+}
+
+pub(crate) fn record<S: Into<String>>(vmid: &str, kind: &str, message: S) {
+    update(|history| {
+        if let Some(node) = history.get_mut(vmid) {
+            node.push(kind, message.into());
+        }
+    });
+}
+
+// For callers that only know the Kubernetes node name, the most recently seen VM with that
+// hostname gets the entry.
+pub(crate) fn record_hostname<S: Into<String>>(hostname: &str, kind: &str, message: S) {
+    update(|history| {
+        if let Some(node) = history
+            .values_mut()
+            .filter(|node| node.hostname.as_deref() == Some(hostname))
+            .max_by_key(|node| node.last_seen)
+        {
+            node.push(kind, message.into());
+        }
+    });
+}
+
+pub(crate) async fn get_node_history(Path(vmid): Path<String>) -> AppResult<Json<NodeHistory>> {
+    Ok(Json(
+        STATE
+            .get::<BTreeMap<String, NodeHistory>>(NODE_HISTORY_KEY)
+            .and_then(|mut history| history.remove(&vmid))
+            .context(format!("No history recorded for VM {vmid}"))?,
+    ))
+}
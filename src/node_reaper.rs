@@ -0,0 +1,208 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    cluster::get_vm_resources,
+    clusters, discovery, etcd, events, kube, logging,
+    maintenance::{self, OperationClass},
+    node_history, status, CONFIG,
+};
+
+const REAPER_INTERVAL: Duration = Duration::from_secs(120);
+const ETCD_ROLE_LABEL: &str = "node-role.kubernetes.io/etcd";
+
+#[derive(Deserialize)]
+struct NodeList {
+    items: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    metadata: NodeMetadata,
+    #[serde(default)]
+    spec: NodeSpec,
+}
+
+#[derive(Deserialize)]
+struct NodeMetadata {
+    name: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeSpec {
+    #[serde(rename = "providerID")]
+    provider_id: Option<String>,
+}
+
+// Nodes registered by the Proxmox cloud provider carry the vmid in their provider ID
+// (`proxmox://<region>/<vmid>`), k3s ones only have their hostname.
+fn has_backing_vm(node: &Node, vmids: &HashSet<String>, names: &HashSet<String>) -> bool {
+    match node
+        .spec
+        .provider_id
+        .as_deref()
+        .and_then(|provider_id| provider_id.strip_prefix("proxmox://"))
+    {
+        Some(path) => path
+            .rsplit('/')
+            .next()
+            .is_some_and(|vmid| vmids.contains(vmid)),
+        None => names.contains(&node.metadata.name.to_lowercase()),
+    }
+}
+
+async fn reap_cluster(
+    client: &reqwest::Client,
+    cluster: &str,
+    vmids: &HashSet<String>,
+    names: &HashSet<String>,
+    missing_since: &mut HashMap<String, i64>,
+    deferred: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let nodes: NodeList = serde_json::from_str(
+        &kube::kubectl(client, cluster, &["get", "nodes", "-o", "json"]).await?,
+    )
+    .context("Invalid node list")?;
+
+    let now = chrono::Utc::now().timestamp();
+
+    let ghosts = nodes
+        .items
+        .iter()
+        .filter(|node| !has_backing_vm(node, vmids, names))
+        .map(|node| {
+            (
+                node.metadata.name.clone(),
+                node.metadata.labels.contains_key(ETCD_ROLE_LABEL),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    missing_since.retain(|name, _| ghosts.contains_key(name));
+
+    for (name, etcd_member) in ghosts {
+        let since = *missing_since.entry(name.clone()).or_insert(now);
+
+        if now - since < CONFIG.node_reaper_grace_period {
+            continue;
+        }
+
+        if !maintenance::is_open(OperationClass::NodeReaper) {
+            deferred.push(format!(
+                "Delete Kubernetes node {name} of cluster {cluster}, its VM no longer exists"
+            ));
+            continue;
+        }
+
+        // The VM is gone for good, its etcd member can only hurt the quorum from now on.
+        if etcd_member {
+            if let Err(err) = etcd::remove_member(client, cluster, &name, "", true).await {
+                logging::warn!("Unable to remove the etcd member of {name}: {err}");
+            }
+        }
+
+        match kube::kubectl(client, cluster, &["delete", "node", &name]).await {
+            Ok(_) => {
+                events::record(
+                    "node-reaper",
+                    None,
+                    format!("Deleted Kubernetes node {name}, its VM no longer exists"),
+                    None,
+                );
+                node_history::record_hostname(
+                    &name,
+                    "left",
+                    "Kubernetes node deleted, its VM no longer exists",
+                );
+                missing_since.remove(&name);
+            }
+            Err(err) => logging::warn!("Unable to delete Kubernetes node {name}: {err}"),
+        }
+    }
+
+    Ok(())
+}
+
+// Node names are only unique within a cluster, the ghosts are tracked per cluster.
+async fn reap(
+    client: &reqwest::Client,
+    missing_since: &mut HashMap<String, HashMap<String, i64>>,
+) -> anyhow::Result<()> {
+    let resources = get_vm_resources(client).await?;
+
+    // An empty listing is far more likely an API hiccup than every VM being gone.
+    if resources.is_empty() {
+        anyhow::bail!("Proxmox returned no VM at all, skipping this pass");
+    }
+
+    let vmids = resources
+        .iter()
+        .map(|resource| resource.vmid.to_string())
+        .collect::<HashSet<_>>();
+
+    // Node names are the lowercase hostnames, which Windows keeps in uppercase.
+    let mut names = resources
+        .into_iter()
+        .filter_map(|resource| resource.name.map(|name| name.to_lowercase()))
+        .collect::<HashSet<_>>();
+
+    // Members living outside of Proxmox never have a backing VM.
+    names.extend(
+        discovery::static_entries()?
+            .into_iter()
+            .filter_map(|entry| entry.hostname.map(|hostname| hostname.to_lowercase())),
+    );
+
+    let mut deferred = vec![];
+    let mut failures = vec![];
+
+    for cluster in clusters::CLUSTERS.iter() {
+        if let Err(err) = reap_cluster(
+            client,
+            &cluster.name,
+            &vmids,
+            &names,
+            missing_since.entry(cluster.name.clone()).or_default(),
+            &mut deferred,
+        )
+        .await
+        {
+            failures.push(format!("cluster {}: {err:#}", cluster.name));
+        }
+    }
+
+    maintenance::defer(OperationClass::NodeReaper, deferred);
+
+    if !failures.is_empty() {
+        anyhow::bail!("{}", failures.join("; "));
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn reap_nodes(client: reqwest::Client) -> anyhow::Result<()> {
+    if !CONFIG.node_reaper {
+        return std::future::pending().await;
+    }
+
+    let mut missing_since = HashMap::new();
+
+    loop {
+        let result = reap(&client, &mut missing_since).await;
+        status::record_job("node-reaper", &result);
+
+        if let Err(err) = result {
+            logging::warn!("Unable to reap Kubernetes nodes: {err}");
+        }
+
+        tokio::time::sleep(REAPER_INTERVAL).await;
+    }
+}
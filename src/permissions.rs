@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{clusters::CLUSTERS, logging, proxmox, CONFIG};
+
+// One privilege the helper needs on a Proxmox ACL path, for the feature needing it.
+#[derive(Serialize)]
+pub struct PermissionCheck {
+    pub privilege: &'static str,
+    pub path: String,
+    pub required_by: &'static str,
+    pub granted: bool,
+}
+
+// Only what the built-in and configured features actually call is required.
+fn requirements() -> Vec<(&'static str, String, &'static str)> {
+    let mut requirements = vec![
+        ("Sys.Audit", "/nodes".to_string(), "discovery"),
+        ("VM.Audit", "/vms".to_string(), "discovery"),
+        ("SDN.Audit", "/sdn".to_string(), "discovery"),
+    ];
+
+    if CONFIG.ipam_gc && CONFIG.ipam_gc_delete {
+        requirements.push(("SDN.Allocate", "/sdn".to_string(), "ipam-gc"));
+    }
+
+    if cfg!(feature = "operator") {
+        let backup_storage = format!("/storage/{}", CONFIG.backup_storage);
+
+        requirements.extend([
+            ("VM.PowerMgmt", "/vms".to_string(), "restore"),
+            ("VM.Allocate", "/vms".to_string(), "restore"),
+            ("VM.Backup", "/vms".to_string(), "backups"),
+            ("Datastore.AllocateSpace", backup_storage.clone(), "backups"),
+        ]);
+
+        if CONFIG.backup_policy {
+            requirements.push(("Datastore.Allocate", backup_storage, "backup-policy"));
+        }
+
+        if CONFIG.annotate_vms {
+            requirements.push(("VM.Config.Options", "/vms".to_string(), "annotations"));
+        }
+    }
+
+    if cfg!(feature = "provisioning") {
+        requirements.extend([
+            ("VM.Clone", "/vms".to_string(), "provisioning"),
+            ("VM.Allocate", "/vms".to_string(), "provisioning"),
+            (
+                "Datastore.AllocateSpace",
+                "/storage".to_string(),
+                "provisioning",
+            ),
+            ("SDN.Use", "/sdn".to_string(), "provisioning"),
+        ]);
+    }
+
+    let mut pools = CLUSTERS
+        .iter()
+        .filter_map(|cluster| cluster.proxmox_pool())
+        .collect::<Vec<_>>();
+    pools.sort();
+    pools.dedup();
+
+    for pool in pools {
+        requirements.push(("Pool.Audit", format!("/pool/{pool}"), "pool-scoping"));
+
+        if cfg!(feature = "provisioning") {
+            requirements.push(("Pool.Allocate", format!("/pool/{pool}"), "provisioning"));
+        }
+    }
+
+    requirements
+}
+
+// Proxmox resolves inheritance, group membership and the privilege separation of tokens itself
+// when asked about a single path.
+async fn privileges(client: &reqwest::Client, path: &str) -> anyhow::Result<Vec<String>> {
+    let permissions: HashMap<String, HashMap<String, u8>> =
+        proxmox::get_with_query(client, "/access/permissions", &[("path", path)]).await?;
+
+    Ok(permissions
+        .into_values()
+        .flatten()
+        .filter(|(_, granted)| *granted != 0)
+        .map(|(privilege, _)| privilege)
+        .collect())
+}
+
+pub(crate) async fn check(client: &reqwest::Client) -> anyhow::Result<Vec<PermissionCheck>> {
+    let mut granted = HashMap::new();
+    let mut checks = vec![];
+
+    for (privilege, path, required_by) in requirements() {
+        if !granted.contains_key(&path) {
+            granted.insert(path.clone(), privileges(client, &path).await?);
+        }
+
+        checks.push(PermissionCheck {
+            privilege,
+            granted: granted[&path].iter().any(|known| known == privilege),
+            path,
+            required_by,
+        });
+    }
+
+    Ok(checks)
+}
+
+// Reported at startup so a missing privilege shows up before the first operation fails with a
+// bare 403.
+pub(crate) async fn warn_missing(client: &reqwest::Client) {
+    match check(client).await {
+        Ok(checks) => {
+            for check in checks.iter().filter(|check| !check.granted) {
+                logging::warn!(
+                    "Missing Proxmox privilege {} on {}, required by {}",
+                    check.privilege,
+                    check.path,
+                    check.required_by
+                );
+            }
+        }
+        Err(err) => logging::warn!("Unable to check the Proxmox permissions: {err}"),
+    }
+}
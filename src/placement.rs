@@ -0,0 +1,67 @@
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+
+use crate::{cluster::get_vm_resources, events, logging, status};
+
+const PLACEMENT_INTERVAL: Duration = Duration::from_secs(15);
+
+// Last known Proxmox node of every VM, by vmid.
+static PLACEMENTS: Lazy<RwLock<HashMap<String, String>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+async fn current_placements(client: &reqwest::Client) -> anyhow::Result<HashMap<String, String>> {
+    Ok(get_vm_resources(client)
+        .await?
+        .into_iter()
+        .filter(|resource| resource.kind == "qemu")
+        .map(|resource| (resource.vmid.to_string(), resource.node))
+        .collect())
+}
+
+// Records the placements and emits an event for every VM found on another node than last time.
+fn update(placements: HashMap<String, String>) {
+    let previous = std::mem::replace(&mut *PLACEMENTS.write().unwrap(), placements.clone());
+
+    for (vmid, node) in &placements {
+        match previous.get(vmid) {
+            Some(previous_node) if previous_node != node => events::record(
+                "migration",
+                Some(vmid),
+                format!("VM {vmid} moved from {previous_node} to {node}"),
+                Some(serde_json::json!({ "from": previous_node, "to": node })),
+            ),
+            _ => {}
+        }
+    }
+}
+
+// Node-scoped calls must go through this rather than a node remembered earlier, the VM may have been
+// live-migrated since.
+pub(crate) async fn resolve_node(client: &reqwest::Client, vmid: &str) -> anyhow::Result<String> {
+    let placements = current_placements(client).await?;
+    let node = placements.get(vmid).cloned();
+
+    update(placements);
+
+    node.context(format!("VM {vmid} not found"))
+}
+
+pub(crate) fn last_known_node(vmid: &str) -> Option<String> {
+    PLACEMENTS.read().unwrap().get(vmid).cloned()
+}
+
+pub(crate) async fn track_placements(client: reqwest::Client) -> anyhow::Result<()> {
+    loop {
+        let result = current_placements(&client).await;
+        status::record_job("placements", &result);
+
+        match result {
+            Ok(placements) => update(placements),
+            Err(err) => logging::warn!("Unable to track VM placements: {err}"),
+        }
+
+        tokio::time::sleep(PLACEMENT_INTERVAL).await;
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{cluster::get_vm_resources, error::AppResult, proxmox, vms, CONFIG};
+
+#[derive(Deserialize)]
+struct ReplicationJob {
+    id: String,
+    target: Option<String>,
+    last_sync: Option<i64>,
+    #[serde(default)]
+    fail_count: u32,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StorageStatus {
+    active: Option<u8>,
+    enabled: Option<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreflightReport {
+    pub vmid: String,
+    pub node: String,
+    pub safe: bool,
+    pub issues: Vec<String>,
+}
+
+async fn replication_issues(
+    client: &reqwest::Client,
+    node: &str,
+    vmid: &str,
+) -> anyhow::Result<Vec<String>> {
+    let jobs: Vec<ReplicationJob> = proxmox::get_with_query(
+        client,
+        &format!("/nodes/{node}/replication"),
+        &[("guest", vmid)],
+    )
+    .await?;
+
+    let now = chrono::Utc::now().timestamp();
+    let mut issues = vec![];
+
+    for job in jobs {
+        let target = job.target.as_deref().unwrap_or("unknown node");
+
+        if let Some(error) = &job.error {
+            issues.push(format!(
+                "Replication job {} to {target} failed {} times: {error}",
+                job.id, job.fail_count
+            ));
+        }
+
+        match job.last_sync {
+            None | Some(0) => issues.push(format!(
+                "Replication job {} to {target} never synchronized",
+                job.id
+            )),
+            Some(last_sync) if now - last_sync > CONFIG.replication_max_lag => {
+                issues.push(format!(
+                    "Replication job {} to {target} last synchronized {} seconds ago",
+                    job.id,
+                    now - last_sync
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(issues)
+}
+
+async fn storage_issues(
+    client: &reqwest::Client,
+    node: &str,
+    path: &str,
+) -> anyhow::Result<Vec<String>> {
+    let config: Map<String, Value> = proxmox::get(client, &format!("{path}/config")).await?;
+    let mut issues = vec![];
+
+    for storage in vms::disk_storages(&config) {
+        let status: StorageStatus =
+            proxmox::get(client, &format!("/nodes/{node}/storage/{storage}/status")).await?;
+
+        if status.enabled == Some(0) {
+            issues.push(format!("Storage {storage} is disabled on {node}"));
+        } else if status.active != Some(1) {
+            issues.push(format!("Storage {storage} is not active on {node}"));
+        }
+    }
+
+    Ok(issues)
+}
+
+// Replication lagging behind or a storage in trouble may leave the disk about to be replaced or
+// moved as the only up-to-date copy. A check that can't run counts as an issue.
+pub(crate) async fn check_vm(
+    client: &reqwest::Client,
+    node: &str,
+    kind: &str,
+    vmid: &str,
+) -> PreflightReport {
+    let mut issues = match replication_issues(client, node, vmid).await {
+        Ok(issues) => issues,
+        Err(err) => vec![format!("Unable to read the replication status: {err}")],
+    };
+
+    let path = format!("/nodes/{node}/{kind}/{vmid}");
+
+    match storage_issues(client, node, &path).await {
+        Ok(storage_issues) => issues.extend(storage_issues),
+        Err(err) => issues.push(format!("Unable to read the storage status: {err}")),
+    }
+
+    PreflightReport {
+        vmid: vmid.to_string(),
+        node: node.to_string(),
+        safe: issues.is_empty(),
+        issues,
+    }
+}
+
+// Refuses the operation unless forced, a forced operation gets the issues back as warnings.
+pub(crate) async fn ensure_safe(
+    client: &reqwest::Client,
+    node: &str,
+    kind: &str,
+    vmid: &str,
+    force: bool,
+) -> anyhow::Result<Vec<String>> {
+    let report = check_vm(client, node, kind, vmid).await;
+
+    if !report.safe && !force {
+        anyhow::bail!(
+            "Refusing to touch VM {vmid}, pass force to override: {}",
+            report.issues.join("; ")
+        );
+    }
+
+    Ok(report.issues)
+}
+
+pub(crate) async fn get_preflight(
+    Path(vmid): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<PreflightReport>> {
+    let resource = get_vm_resources(&client)
+        .await?
+        .into_iter()
+        .find(|resource| resource.vmid.to_string() == vmid)
+        .context(format!("VM {vmid} not found"))?;
+
+    Ok(Json(
+        check_vm(&client, &resource.node, &resource.kind, &vmid).await,
+    ))
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::Query, middleware, routing::get, Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    credentials::{self, ClusterScope},
+    discovery,
+    error::AppResult,
+    placement, response_cache,
+};
+
+const NODE_EXPORTER_PORT: u16 = 9100;
+const KUBELET_PORT: u16 = 10250;
+
+#[derive(Deserialize)]
+pub(crate) struct TargetQuery {
+    // `node-exporter` or `kubelet`, both are listed when unset.
+    exporter: Option<String>,
+    cluster: Option<String>,
+}
+
+// One target group per node and exporter, as expected by Prometheus' `http_sd_configs`.
+#[derive(Serialize)]
+pub struct TargetGroup {
+    pub targets: Vec<String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+async fn get_prometheus_targets(
+    scope: Option<Extension<ClusterScope>>,
+    Query(query): Query<TargetQuery>,
+) -> AppResult<Json<Vec<TargetGroup>>> {
+    let exporters = [
+        ("node-exporter", NODE_EXPORTER_PORT),
+        ("kubelet", KUBELET_PORT),
+    ]
+    .into_iter()
+    .filter(|(exporter, _)| {
+        query
+            .exporter
+            .as_deref()
+            .is_none_or(|name| name == *exporter)
+    })
+    .collect::<Vec<_>>();
+
+    let mut groups = vec![];
+
+    for ipam in discovery::subscribe().borrow().iter() {
+        let Some(assignment) = &ipam.assignment else {
+            continue;
+        };
+
+        if query
+            .cluster
+            .as_ref()
+            .is_some_and(|cluster| cluster != &assignment.cluster)
+            || credentials::authorize(&scope, &assignment.cluster).is_err()
+        {
+            continue;
+        }
+
+        let mut labels = BTreeMap::from([
+            ("k3s_cluster".to_string(), assignment.cluster.clone()),
+            ("k3s_role".to_string(), assignment.role.as_str().to_string()),
+            ("zone".to_string(), ipam.zone.clone()),
+            ("vnet".to_string(), ipam.vnet.clone()),
+        ]);
+
+        if let Some(hostname) = &ipam.hostname {
+            labels.insert("node".to_string(), hostname.clone());
+        }
+
+        if let Some(vmid) = &ipam.vmid {
+            labels.insert("vmid".to_string(), vmid.clone());
+
+            if let Some(node) = placement::last_known_node(vmid) {
+                labels.insert("proxmox_node".to_string(), node);
+            }
+        }
+
+        if let Some(pool) = &assignment.pool {
+            labels.insert("k3s_pool".to_string(), pool.clone());
+        }
+
+        for (exporter, port) in &exporters {
+            let mut labels = labels.clone();
+            labels.insert("exporter".to_string(), exporter.to_string());
+
+            groups.push(TargetGroup {
+                targets: vec![format!("{}:{port}", ipam.ip)],
+                labels,
+            });
+        }
+    }
+
+    Ok(Json(groups))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route(
+            "/prometheus",
+            get(get_prometheus_targets).layer(middleware::from_fn(response_cache::cache)),
+        )
+        .route_layer(middleware::from_fn(credentials::require_credentials))
+}
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use reqwest::{header, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "fault-injection")]
+use crate::faults;
+use crate::{logging, models::ProxmoxData, proxmox_auth, proxmox_cache, CONFIG};
+
+fn api_url(path: &str) -> String {
+    format!("{}/api2/json{path}", &CONFIG.proxmox_api_url)
+}
+
+// Transient failures are retried after a jittered exponential delay, doubling from
+// `proxmox_retry_initial_delay_ms` up to this.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+fn retry_delay(attempt: u32) -> Duration {
+    let delay = Duration::from_millis(CONFIG.proxmox_retry_initial_delay_ms)
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RETRY_DELAY);
+
+    // Full jitter spreads out the retries of the concurrent requests failing together.
+    delay.mul_f64(rand::random::<f64>())
+}
+
+fn is_transient(result: &reqwest::Result<reqwest::Response>, idempotent: bool) -> bool {
+    match result {
+        Ok(response) => {
+            idempotent
+                && (response.status().is_server_error()
+                    || response.status() == StatusCode::TOO_MANY_REQUESTS)
+        }
+        // A request that never reached Proxmox can't have been applied.
+        Err(err) => err.is_connect() || (idempotent && (err.is_timeout() || err.is_request())),
+    }
+}
+
+// Transient failures are retried up to `proxmox_retry_max_attempts` times, but a request Proxmox
+// may already have received is only retried when it is idempotent. A request rejected because the
+// ticket expired is sent once more after logging in again. The CSRF token changes along with the
+// ticket and the API token may be reloaded, so both are set again on every attempt.
+async fn send<T: DeserializeOwned>(
+    path: &str,
+    idempotent: bool,
+    request: impl Fn(String) -> reqwest::RequestBuilder,
+) -> anyhow::Result<T> {
+    #[cfg(feature = "fault-injection")]
+    faults::inject_proxmox_fault(path).await?;
+
+    let attempt = || {
+        let mut request = request(api_url(path))
+            .header("CSRFPreventionToken", proxmox_auth::csrf_prevention_token());
+
+        if let Some(authorization) = proxmox_auth::authorization() {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+
+        request.send()
+    };
+
+    let max_attempts = CONFIG.proxmox_retry_max_attempts.max(1);
+    let mut attempts = 1;
+    let mut recovered = false;
+
+    let response = loop {
+        let generation = proxmox_auth::session_generation();
+        let result = attempt().await;
+
+        if let Ok(response) = &result {
+            if response.status() == StatusCode::UNAUTHORIZED
+                && !recovered
+                && proxmox_auth::recover_session(generation).await?
+            {
+                recovered = true;
+                continue;
+            }
+        }
+
+        if attempts >= max_attempts || !is_transient(&result, idempotent) {
+            break result?;
+        }
+
+        let delay = retry_delay(attempts - 1);
+
+        logging::debug!(
+            "Retrying {path} in {}ms after attempt {attempts}/{max_attempts}: {}",
+            delay.as_millis(),
+            match &result {
+                Ok(response) => response.status().to_string(),
+                Err(err) => err.to_string(),
+            }
+        );
+
+        tokio::time::sleep(delay).await;
+        attempts += 1;
+    };
+
+    let response: ProxmoxData<T> = response.error_for_status()?.json().await?;
+
+    Ok(response.data)
+}
+
+// Even a failed change may have been partly applied, nothing read before it is trusted afterwards.
+async fn mutate<T: DeserializeOwned>(
+    path: &str,
+    idempotent: bool,
+    request: impl Fn(String) -> reqwest::RequestBuilder,
+) -> anyhow::Result<T> {
+    let result = send(path, idempotent, request).await;
+
+    proxmox_cache::invalidate();
+
+    result
+}
+
+pub(crate) async fn get<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    path: &str,
+) -> anyhow::Result<T> {
+    send(path, true, |url| client.get(url)).await
+}
+
+pub(crate) async fn get_with_query<T: DeserializeOwned, Q: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    path: &str,
+    query: &Q,
+) -> anyhow::Result<T> {
+    send(path, true, |url| client.get(url).query(query)).await
+}
+
+pub(crate) async fn post<T: DeserializeOwned, F: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    path: &str,
+    form: &F,
+) -> anyhow::Result<T> {
+    mutate(path, false, |url| client.post(url).form(form)).await
+}
+
+pub(crate) async fn put<T: DeserializeOwned, F: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    path: &str,
+    form: &F,
+) -> anyhow::Result<T> {
+    mutate(path, true, |url| client.put(url).form(form)).await
+}
+
+pub(crate) async fn delete<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    path: &str,
+) -> anyhow::Result<T> {
+    mutate(path, true, |url| client.delete(url)).await
+}
+
+pub(crate) async fn delete_with_query<T: DeserializeOwned, Q: Serialize + ?Sized>(
+    client: &reqwest::Client,
+    path: &str,
+    query: &Q,
+) -> anyhow::Result<T> {
+    mutate(path, true, |url| client.delete(url).query(query)).await
+}
@@ -0,0 +1,409 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::Json;
+use once_cell::sync::Lazy;
+use reqwest::{
+    cookie::Jar,
+    header::{self, HeaderValue},
+};
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{
+    error::AppResult, events, logging, models::ProxmoxData, proxmox_client_builder, secrets,
+    status, CONFIG,
+};
+
+// Proxmox tickets are valid for two hours, they are renewed halfway through.
+const TICKET_LIFETIME: i64 = 7200;
+const RENEWAL_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+static SESSION: Lazy<ProxmoxSession> = Lazy::new(ProxmoxSession::default);
+
+#[derive(Clone, Deserialize)]
+struct ProxmoxTicket {
+    username: String,
+    ticket: String,
+    #[serde(rename = "CSRFPreventionToken")]
+    csrf_prevention_token: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Authentication {
+    pub username: String,
+    pub authenticated_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct TicketStatus {
+    pub username: Option<String>,
+    pub authenticated_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub valid: bool,
+}
+
+#[derive(Default)]
+struct SessionState {
+    ticket: Option<ProxmoxTicket>,
+    authentication: Option<Authentication>,
+    api_token: Option<HeaderValue>,
+}
+
+// Owns the cookie jar shared by every client built from `cookie_provider`, replacing the ticket
+// here re-authenticates them all without rebuilding them. The cookie and the CSRF token are swapped
+// under the same lock, and every swap bumps the generation so a request rejected with an old ticket
+// doesn't trigger a second renewal. An API token is read by every request the same way, a reloaded
+// one takes effect right away.
+#[derive(Default)]
+pub(crate) struct ProxmoxSession {
+    cookies: Arc<Jar>,
+    state: RwLock<SessionState>,
+    generation: AtomicU64,
+    renewal: tokio::sync::Mutex<()>,
+}
+
+impl ProxmoxSession {
+    fn store(&self, ticket: ProxmoxTicket) -> anyhow::Result<Authentication> {
+        let url = CONFIG.proxmox_api_url.parse()?;
+
+        let authentication = Authentication {
+            username: ticket.username.clone(),
+            authenticated_at: chrono::Utc::now().timestamp(),
+        };
+
+        let mut state = self.state.write().unwrap();
+
+        self.cookies
+            .add_cookie_str(&format!("PVEAuthCookie={}", ticket.ticket), &url);
+        state.ticket = Some(ticket);
+        state.authentication = Some(authentication.clone());
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        Ok(authentication)
+    }
+
+    fn store_token(&self, authentication: Authentication, api_token: HeaderValue) {
+        let mut state = self.state.write().unwrap();
+
+        state.authentication = Some(authentication);
+        state.api_token = Some(api_token);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn csrf_prevention_token(&self) -> String {
+        self.state
+            .read()
+            .unwrap()
+            .ticket
+            .as_ref()
+            .map(|ticket| ticket.csrf_prevention_token.clone())
+            .unwrap_or_default()
+    }
+
+    fn authentication(&self) -> Option<Authentication> {
+        self.state.read().unwrap().authentication.clone()
+    }
+
+    // A valid ticket stands in for the password to get a new one, an expired one can't.
+    async fn renew(&self) -> anyhow::Result<Authentication> {
+        let _renewal = self.renewal.lock().await;
+
+        let ticket = {
+            let state = self.state.read().unwrap();
+
+            state
+                .ticket
+                .as_ref()
+                .zip(state.authentication.as_ref())
+                .filter(|(_, authentication)| {
+                    authentication.authenticated_at + TICKET_LIFETIME
+                        > chrono::Utc::now().timestamp()
+                })
+                .map(|(ticket, _)| ticket.ticket.clone())
+        };
+
+        match ticket {
+            Some(ticket) => self.store(request_ticket(&ticket).await?),
+            None => self.store(request_ticket(&password()?).await?),
+        }
+    }
+
+    // Only the first request rejected with a given ticket logs in again, the others wait for it
+    // and retry with the new one.
+    async fn recover(&self, generation: u64) -> anyhow::Result<()> {
+        let _renewal = self.renewal.lock().await;
+
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return Ok(());
+        }
+
+        logging::warn!("Proxmox rejected the ticket, logging in again");
+
+        self.store(request_ticket(&password()?).await?)?;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn cookie_provider() -> Arc<Jar> {
+    SESSION.cookies.clone()
+}
+
+// Mutating API calls authenticated with a ticket cookie also require the CSRF token.
+pub(crate) fn csrf_prevention_token() -> String {
+    SESSION.csrf_prevention_token()
+}
+
+// The Authorization header of the last verified API token, none when logged in with a ticket.
+pub(crate) fn authorization() -> Option<HeaderValue> {
+    SESSION.state.read().unwrap().api_token.clone()
+}
+
+pub(crate) fn session_generation() -> u64 {
+    SESSION.generation.load(Ordering::SeqCst)
+}
+
+// Called when Proxmox answered 401 to a request sent during the given generation. API tokens can't
+// be renewed, a rejected one stays rejected.
+pub(crate) async fn recover_session(generation: u64) -> anyhow::Result<bool> {
+    if uses_api_token() {
+        return Ok(false);
+    }
+
+    SESSION.recover(generation).await?;
+
+    Ok(true)
+}
+
+// Files are read on every authentication so a rotated password is picked up.
+fn password() -> anyhow::Result<String> {
+    if let Some(password) = secrets::credential("proxmox_api_password") {
+        return Ok(password);
+    }
+
+    match &CONFIG.proxmox_api_password_file {
+        Some(path) => Ok(std::fs::read_to_string(path)
+            .context(format!("Unable to read the Proxmox password from {path}"))?
+            .trim()
+            .to_string()),
+        None => CONFIG
+            .proxmox_api_password
+            .clone()
+            .context("Either proxmox_api_password or proxmox_api_password_file is required"),
+    }
+}
+
+// `user@realm!tokenid=secret`, used instead of a ticket when configured.
+fn api_token() -> anyhow::Result<Option<String>> {
+    let token = match (
+        secrets::credential("proxmox_api_token"),
+        &CONFIG.proxmox_api_token_file,
+    ) {
+        (Some(token), _) => token,
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .context(format!("Unable to read the Proxmox API token from {path}"))?
+            .trim()
+            .to_string(),
+        (None, None) => match &CONFIG.proxmox_api_token {
+            Some(token) => token.clone(),
+            None => return Ok(None),
+        },
+    };
+
+    if !token.contains('!') || !token.contains('=') {
+        anyhow::bail!("The Proxmox API token must look like user@realm!tokenid=secret");
+    }
+
+    Ok(Some(token))
+}
+
+pub(crate) fn uses_api_token() -> bool {
+    CONFIG.proxmox_api_token.is_some()
+        || CONFIG.proxmox_api_token_file.is_some()
+        || secrets::credential("proxmox_api_token").is_some()
+}
+
+fn api_token_header(token: &str) -> anyhow::Result<HeaderValue> {
+    let mut value = HeaderValue::from_str(&format!("PVEAPIToken={token}"))
+        .context("Invalid Proxmox API token")?;
+    value.set_sensitive(true);
+
+    Ok(value)
+}
+
+// Proxmox answers the version to any valid token, whatever its privileges. Only a verified token
+// replaces the one sent with the requests, the current one stays in place otherwise.
+async fn verify_api_token(token: &str) -> anyhow::Result<Authentication> {
+    let api_token = api_token_header(token)?;
+
+    proxmox_client_builder()?
+        .build()?
+        .get(format!("{}/api2/json/version", &CONFIG.proxmox_api_url))
+        .header(header::AUTHORIZATION, api_token.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let authentication = Authentication {
+        username: token
+            .split_once('=')
+            .map(|(id, _)| id.to_string())
+            .unwrap_or_default(),
+        authenticated_at: chrono::Utc::now().timestamp(),
+    };
+
+    SESSION.store_token(authentication.clone(), api_token);
+
+    Ok(authentication)
+}
+
+async fn request_ticket(password: &str) -> anyhow::Result<ProxmoxTicket> {
+    let mut params = HashMap::new();
+
+    params.insert(
+        "username",
+        CONFIG
+            .proxmox_api_user
+            .as_deref()
+            .context("proxmox_api_user is required without an API token")?,
+    );
+    params.insert("password", password);
+
+    let response: ProxmoxData<ProxmoxTicket> = proxmox_client_builder()?
+        .build()?
+        .post(format!(
+            "{}/api2/json/access/ticket",
+            &CONFIG.proxmox_api_url
+        ))
+        .form(&params)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(response.data)
+}
+
+pub(crate) fn ticket_status() -> TicketStatus {
+    let authentication = SESSION.authentication();
+
+    if uses_api_token() {
+        return TicketStatus {
+            username: authentication
+                .as_ref()
+                .map(|authentication| authentication.username.clone()),
+            authenticated_at: authentication
+                .as_ref()
+                .map(|authentication| authentication.authenticated_at),
+            expires_at: None,
+            valid: authentication.is_some(),
+        };
+    }
+
+    let expires_at = authentication
+        .as_ref()
+        .map(|authentication| authentication.authenticated_at + TICKET_LIFETIME);
+
+    TicketStatus {
+        username: authentication
+            .as_ref()
+            .map(|authentication| authentication.username.clone()),
+        authenticated_at: authentication.map(|authentication| authentication.authenticated_at),
+        expires_at,
+        valid: expires_at.is_some_and(|expires_at| expires_at > chrono::Utc::now().timestamp()),
+    }
+}
+
+pub(crate) async fn authenticate() -> anyhow::Result<Authentication> {
+    match api_token()? {
+        Some(token) => verify_api_token(&token).await,
+        None => SESSION.store(request_ticket(&password()?).await?),
+    }
+}
+
+pub(crate) async fn renew_ticket() -> anyhow::Result<()> {
+    logging::info!("Renewing ticket");
+
+    let result = SESSION.renew().await;
+
+    status::record_job("ticket-renewal", &result);
+
+    result.map(|_| ())
+}
+
+// Renews the ticket halfway through its lifetime, a failed renewal is retried until it works.
+pub(crate) async fn keep_session_alive() -> anyhow::Result<()> {
+    // API tokens don't expire, there is no ticket to renew.
+    if uses_api_token() {
+        return std::future::pending().await;
+    }
+
+    loop {
+        let renew_at = SESSION
+            .authentication()
+            .map(|authentication| authentication.authenticated_at + TICKET_LIFETIME / 2)
+            .unwrap_or_default();
+        let delay = (renew_at - chrono::Utc::now().timestamp()).max(0) as u64;
+
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+
+        if let Err(err) = renew_ticket().await {
+            logging::warn!(
+                "Unable to renew the Proxmox ticket, retrying in {}s: {err}",
+                RENEWAL_RETRY_DELAY.as_secs()
+            );
+
+            tokio::time::sleep(RENEWAL_RETRY_DELAY).await;
+        }
+    }
+}
+
+async fn reauthenticate(trigger: &str) -> anyhow::Result<Authentication> {
+    let result = authenticate().await;
+
+    match &result {
+        Ok(authentication) => events::record(
+            "proxmox-credentials",
+            None,
+            format!(
+                "Re-authenticated to Proxmox as {} ({trigger})",
+                authentication.username
+            ),
+            None,
+        ),
+        Err(err) => events::record(
+            "proxmox-credentials",
+            None,
+            format!("Unable to re-authenticate to Proxmox ({trigger}): {err}"),
+            None,
+        ),
+    }
+
+    result
+}
+
+pub(crate) async fn reload_on_sighup() -> anyhow::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    while hangup.recv().await.is_some() {
+        // The current ticket stays in place when the new credentials are rejected.
+        if let Err(err) = reauthenticate("SIGHUP").await {
+            logging::warn!("Unable to reload the Proxmox credentials: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn reload_credentials() -> AppResult<Json<Authentication>> {
+    Ok(Json(reauthenticate("API").await?))
+}
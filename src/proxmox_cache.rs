@@ -0,0 +1,86 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    cluster::{IpamEntry, NodeEntry, VirtualMachineEntry},
+    metrics, CONFIG,
+};
+
+pub(crate) static NODES: Lazy<TtlCache<Vec<NodeEntry>>> = Lazy::new(|| TtlCache::new("nodes"));
+pub(crate) static IPAMS: Lazy<TtlCache<Vec<IpamEntry>>> = Lazy::new(|| TtlCache::new("ipams"));
+pub(crate) static VMS: Lazy<TtlCache<Vec<VirtualMachineEntry>>> =
+    Lazy::new(|| TtlCache::new("vms"));
+pub(crate) static CONTAINERS: Lazy<TtlCache<Vec<VirtualMachineEntry>>> =
+    Lazy::new(|| TtlCache::new("containers"));
+
+// The latest answer of Proxmox for one resource, keyed by node, shared by the handlers, the proxy's
+// synchronization and the background jobs so they don't each fan out to every node.
+pub(crate) struct TtlCache<T> {
+    resource: &'static str,
+    entries: Mutex<HashMap<String, (Instant, T)>>,
+}
+
+impl<T: Clone> TtlCache<T> {
+    fn new(resource: &'static str) -> Self {
+        TtlCache {
+            resource,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn record(&self, result: &'static str) {
+        metrics::increment_counter(
+            "k3s_helper_proxmox_cache_requests_total",
+            "Reads of Proxmox nodes, IPAMs and guests answered from the cache or not",
+            &[("resource", self.resource), ("result", result)],
+        );
+    }
+
+    // Concurrent misses all reach Proxmox, the last answer wins.
+    pub(crate) async fn get_or_fetch<F: Future<Output = anyhow::Result<T>>>(
+        &self,
+        key: &str,
+        fetch: impl FnOnce() -> F,
+    ) -> anyhow::Result<T> {
+        let ttl = Duration::from_secs(CONFIG.proxmox_cache_ttl);
+
+        if let Some((fetched_at, value)) = self.entries.lock().unwrap().get(key) {
+            if fetched_at.elapsed() < ttl {
+                self.record("hit");
+                return Ok(value.clone());
+            }
+        }
+
+        self.record("miss");
+
+        let value = fetch().await?;
+
+        if !ttl.is_zero() {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), (Instant::now(), value.clone()));
+        }
+
+        Ok(value)
+    }
+
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+// Anything changed through the API is read back from Proxmox rather than from a snapshot taken
+// before the change.
+pub(crate) fn invalidate() {
+    NODES.clear();
+    IPAMS.clear();
+    VMS.clear();
+    CONTAINERS.clear();
+}
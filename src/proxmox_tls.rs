@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+
+use crate::CONFIG;
+
+// Trusts the one certificate whose SHA-256 fingerprint was pinned, whoever issued it, as shown by
+// `pvenode cert info`.
+#[derive(Debug)]
+struct PinnedCertificate {
+    fingerprint: String,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").to_lowercase()
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Sha256::digest(end_entity.as_ref())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        if fingerprint != self.fingerprint {
+            return Err(rustls::Error::General(format!(
+                "Proxmox certificate fingerprint {fingerprint} doesn't match the pinned one"
+            )));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+fn pinned_config(fingerprint: &str) -> anyhow::Result<rustls::ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+
+    let verifier = PinnedCertificate {
+        fingerprint: normalize_fingerprint(fingerprint),
+        algorithms: provider.signature_verification_algorithms,
+    };
+
+    Ok(rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+// A pinned fingerprint takes precedence over the CA, skipping the verification entirely is only
+// meant for labs.
+pub(crate) fn configure(builder: reqwest::ClientBuilder) -> anyhow::Result<reqwest::ClientBuilder> {
+    if let Some(fingerprint) = &CONFIG.proxmox_tls_fingerprint {
+        return Ok(builder.use_preconfigured_tls(pinned_config(fingerprint)?));
+    }
+
+    if CONFIG.proxmox_insecure_skip_verify {
+        return Ok(builder.danger_accept_invalid_certs(true));
+    }
+
+    match &CONFIG.proxmox_ca_cert {
+        Some(path) => {
+            let pem =
+                std::fs::read(path).context(format!("Unable to read the Proxmox CA {path}"))?;
+
+            Ok(builder.add_root_certificate(
+                reqwest::Certificate::from_pem(&pem)
+                    .context(format!("Invalid Proxmox CA {path}"))?,
+            ))
+        }
+        None => Ok(builder),
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::{net::TcpStream, sync::watch, task::JoinSet};
+
+use crate::{
+    cluster::IpamEntry,
+    clusters::{K3sCluster, CLUSTERS, K8S_API_PORT},
+    discovery::is_proxy_member,
+    dns_cache, drain, ha, lb_export, listeners, logging, CONFIG, STATE,
+};
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+const LAST_KNOWN_GOOD_KEY: &str = "proxy_backends";
+
+static BACKEND_HEALTH: Lazy<RwLock<HashMap<String, BackendHealth>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+static LISTENING: Lazy<RwLock<HashSet<String>>> = Lazy::new(|| RwLock::new(HashSet::new()));
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BackendHealth {
+    pub healthy: bool,
+    pub published: bool,
+    pub consecutive_successes: u32,
+    pub consecutive_failures: u32,
+    pub last_check: i64,
+    pub last_error: Option<String>,
+}
+
+impl BackendHealth {
+    // Backends only join the pool after `backend_rise` successful checks in a row and leave it
+    // after `backend_fall` failed ones, so a flapping or bootstrapping server doesn't get traffic.
+    fn record(&mut self, result: &anyhow::Result<()>) {
+        self.healthy = result.is_ok();
+        self.last_check = chrono::Utc::now().timestamp();
+        self.last_error = result.as_ref().err().map(|err| err.to_string());
+
+        if self.healthy {
+            self.consecutive_successes += 1;
+            self.consecutive_failures = 0;
+
+            if self.consecutive_successes >= CONFIG.backend_rise {
+                self.published = true;
+            }
+        } else {
+            self.consecutive_failures += 1;
+            self.consecutive_successes = 0;
+
+            if self.consecutive_failures >= CONFIG.backend_fall {
+                self.published = false;
+            }
+        }
+    }
+}
+
+fn build_readyz_client() -> anyhow::Result<Option<reqwest::Client>> {
+    let (Some(cert_path), Some(key_path)) = (&CONFIG.k8s_client_cert, &CONFIG.k8s_client_key)
+    else {
+        return Ok(None);
+    };
+
+    let mut identity = std::fs::read(cert_path)?;
+    identity.extend(std::fs::read(key_path)?);
+
+    let mut builder = reqwest::ClientBuilder::new()
+        .use_rustls_tls()
+        .identity(reqwest::Identity::from_pem(&identity)?)
+        .timeout(HEALTH_CHECK_TIMEOUT);
+
+    builder = match &CONFIG.k8s_ca_cert {
+        Some(ca_path) => builder
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(reqwest::Certificate::from_pem(&std::fs::read(ca_path)?)?),
+        // Still better than a bare TCP connect, but anyone on the network can answer the probe.
+        None => {
+            logging::warn!(
+                "k8s_ca_cert is not set, the certificates of the readiness probes are not verified"
+            );
+            builder.danger_accept_invalid_certs(true)
+        }
+    };
+
+    Ok(Some(builder.build()?))
+}
+
+// Backends given by name go through the resolver cache, each of their addresses is tried in turn.
+async fn connect_backend(host: &str, port: u16) -> anyhow::Result<TcpStream> {
+    let mut last_error = anyhow::Error::msg(format!("{host} has no address"));
+
+    for ip in dns_cache::resolve(host).await? {
+        match TcpStream::connect((ip, port)).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = err.into(),
+        }
+    }
+
+    Err(last_error)
+}
+
+async fn probe_backend(readyz_client: Option<&reqwest::Client>, ip: &str) -> anyhow::Result<()> {
+    match readyz_client {
+        Some(client) => {
+            client
+                .get(format!("https://{ip}:{K8S_API_PORT}/readyz"))
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        None => {
+            tokio::time::timeout(HEALTH_CHECK_TIMEOUT, connect_backend(ip, K8S_API_PORT)).await??;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn backend_health() -> HashMap<String, BackendHealth> {
+    BACKEND_HEALTH.read().unwrap().clone()
+}
+
+pub(crate) fn is_listening(cluster: &K3sCluster) -> bool {
+    LISTENING
+        .read()
+        .unwrap()
+        .contains(&listeners::api_listener(cluster))
+}
+
+pub(crate) fn is_backend_published(ip: &str) -> bool {
+    BACKEND_HEALTH
+        .read()
+        .unwrap()
+        .get(ip)
+        .is_some_and(|health| health.published)
+}
+
+// The VM is going away, its backend leaves the pool right away instead of after `backend_fall`
+// failed checks and has to rise again like a new one.
+pub(crate) fn unpublish_backend(ip: &str) {
+    if let Some(health) = BACKEND_HEALTH.write().unwrap().get_mut(ip) {
+        health.published = false;
+        health.healthy = false;
+        health.consecutive_successes = 0;
+    }
+}
+
+// Restores the backends published before the last restart so the proxy can serve connections
+// before the first synchronization with Proxmox.
+pub(crate) fn restore_last_known_good() -> Vec<IpamEntry> {
+    let backends: Vec<IpamEntry> = STATE.get(LAST_KNOWN_GOOD_KEY).unwrap_or_default();
+
+    let mut health = BACKEND_HEALTH.write().unwrap();
+
+    for backend in &backends {
+        health.insert(
+            backend.ip.clone(),
+            BackendHealth {
+                published: true,
+                ..Default::default()
+            },
+        );
+    }
+
+    backends
+}
+
+fn persist_last_known_good(backends: &[IpamEntry]) {
+    if let Err(err) = STATE.update(LAST_KNOWN_GOOD_KEY, |persisted: &mut Vec<IpamEntry>| {
+        *persisted = backends.to_vec();
+    }) {
+        logging::warn!("Unable to persist the proxy backends: {err}");
+    }
+}
+
+pub(crate) async fn check_backends(rx: watch::Receiver<Vec<IpamEntry>>) -> anyhow::Result<()> {
+    let readyz_client = build_readyz_client()?;
+
+    let mut last_known_good: Vec<String> = vec![];
+
+    loop {
+        let ipams: Vec<IpamEntry> = rx
+            .borrow()
+            .iter()
+            .filter(|ipam| is_proxy_member(ipam))
+            .cloned()
+            .collect();
+
+        let previous_health = backend_health();
+        let mut health = HashMap::new();
+        let mut published = vec![];
+
+        // A slow backend only delays the pass by one timeout, not one per backend.
+        let results = join_all(
+            ipams
+                .iter()
+                .map(|ipam| probe_backend(readyz_client.as_ref(), &ipam.ip)),
+        )
+        .await;
+
+        for (ipam, result) in ipams.into_iter().zip(results) {
+            #[cfg(feature = "fault-injection")]
+            let result = crate::faults::inject_backend_fault(&ipam.ip, result);
+
+            let mut backend = previous_health.get(&ipam.ip).cloned().unwrap_or_default();
+            let was_published = backend.published;
+
+            backend.record(&result);
+
+            if backend.published != was_published {
+                if backend.published {
+                    logging::info!("Backend {} published to the proxy pool", ipam.ip);
+                } else {
+                    logging::warn!(
+                        "Backend {} demoted from the proxy pool: {}",
+                        ipam.ip,
+                        backend.last_error.as_deref().unwrap_or_default()
+                    );
+                }
+            }
+
+            if backend.published {
+                published.push(ipam.clone());
+            }
+
+            health.insert(ipam.ip, backend);
+        }
+
+        *BACKEND_HEALTH.write().unwrap() = health;
+
+        let published_ips = published
+            .iter()
+            .map(|ipam| ipam.ip.clone())
+            .collect::<Vec<_>>();
+
+        // An empty pool is never persisted, the previous set is more useful after a restart.
+        if !published.is_empty() && published_ips != last_known_good {
+            persist_last_known_good(&published);
+            last_known_good = published_ips;
+        }
+
+        tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+    }
+}
+
+#[derive(Serialize)]
+struct AccessLogEntry {
+    timestamp: i64,
+    cluster: String,
+    client: String,
+    backend: Option<String>,
+    connect_latency_ms: Option<u128>,
+    bytes_from_client: u64,
+    bytes_from_server: u64,
+    duration_ms: u128,
+    termination: String,
+}
+
+static ACCESS_LOG_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Failed connections are always logged, successful ones only one out of
+// `proxy_access_log_sample_every`.
+fn log_access(entry: AccessLogEntry) {
+    if !CONFIG.proxy_access_log {
+        return;
+    }
+
+    let sampled = ACCESS_LOG_COUNTER
+        .fetch_add(1, Ordering::Relaxed)
+        .is_multiple_of(CONFIG.proxy_access_log_sample_every.max(1));
+
+    if entry.termination != "closed" || sampled {
+        if let Ok(line) = serde_json::to_string(&entry) {
+            logging::info!("{line}");
+        }
+    }
+}
+
+fn is_cluster_member(ipam: &IpamEntry, cluster: &K3sCluster) -> bool {
+    ipam.assignment
+        .as_ref()
+        .is_some_and(|assignment| assignment.cluster == cluster.name)
+}
+
+async fn proxy_cluster(
+    rx: watch::Receiver<Vec<IpamEntry>>,
+    cluster: &'static K3sCluster,
+    listener_name: String,
+    backend_port: u16,
+) -> anyhow::Result<()> {
+    let listener = listeners::take(&listener_name)?;
+
+    LISTENING.write().unwrap().insert(listener_name);
+
+    loop {
+        let (mut ingress, client_addr) = listener.accept().await?;
+
+        #[cfg(feature = "fault-injection")]
+        if crate::faults::should_drop_connection() {
+            drop(ingress);
+
+            log_access(AccessLogEntry {
+                timestamp: chrono::Utc::now().timestamp(),
+                cluster: cluster.name.clone(),
+                client: client_addr.to_string(),
+                backend: None,
+                connect_latency_ms: None,
+                bytes_from_client: 0,
+                bytes_from_server: 0,
+                duration_ms: 0,
+                termination: "fault_injected".to_string(),
+            });
+            continue;
+        }
+
+        if drain::is_draining() {
+            drop(ingress);
+
+            log_access(AccessLogEntry {
+                timestamp: chrono::Utc::now().timestamp(),
+                cluster: cluster.name.clone(),
+                client: client_addr.to_string(),
+                backend: None,
+                connect_latency_ms: None,
+                bytes_from_client: 0,
+                bytes_from_server: 0,
+                duration_ms: 0,
+                termination: "draining".to_string(),
+            });
+            continue;
+        }
+
+        let mut ipams: Vec<IpamEntry> = rx
+            .borrow()
+            .iter()
+            .filter(|ipam| is_cluster_member(ipam, cluster))
+            .filter(|ipam| is_proxy_member(ipam) && is_backend_published(&ipam.ip))
+            .cloned()
+            .collect();
+
+        // Servers hosted on a node in maintenance or with a degraded HA status are only used when
+        // nothing else answers.
+        ipams.sort_by_key(ha::backend_condition);
+
+        let connection = drain::track_connection();
+
+        tokio::spawn(async move {
+            let _connection = connection;
+            let started_at = Instant::now();
+
+            let mut entry = AccessLogEntry {
+                timestamp: chrono::Utc::now().timestamp(),
+                cluster: cluster.name.clone(),
+                client: client_addr.to_string(),
+                backend: None,
+                connect_latency_ms: None,
+                bytes_from_client: 0,
+                bytes_from_server: 0,
+                duration_ms: 0,
+                termination: "closed".to_string(),
+            };
+
+            let mut egress = None;
+
+            for ipam in &ipams {
+                if let Ok(connection) = connect_backend(&ipam.ip, backend_port).await {
+                    entry.backend = Some(ipam.ip.clone());
+                    entry.connect_latency_ms = Some(started_at.elapsed().as_millis());
+                    egress = Some(connection);
+                    break;
+                }
+            }
+
+            let Some(mut egress) = egress else {
+                drop(ingress);
+                logging::warn!("Impossible to connect to any k3s-server");
+
+                entry.duration_ms = started_at.elapsed().as_millis();
+                entry.termination = "no_backend".to_string();
+                log_access(entry);
+                return;
+            };
+
+            match tokio::io::copy_bidirectional(&mut ingress, &mut egress).await {
+                Ok((to_egress, to_ingress)) => {
+                    if !CONFIG.proxy_access_log {
+                        logging::debug!(
+                            "Connection ended gracefully ({to_egress} bytes from client, {to_ingress} bytes from server)"
+                        );
+                    }
+
+                    entry.bytes_from_client = to_egress;
+                    entry.bytes_from_server = to_ingress;
+                }
+                Err(err) => {
+                    logging::warn!("Error while proxying: {}", err);
+
+                    entry.termination = format!("error: {err}");
+                }
+            }
+
+            entry.duration_ms = started_at.elapsed().as_millis();
+            log_access(entry);
+        });
+    }
+}
+
+// Every cluster gets its own listener and only ever reaches its own servers.
+pub(crate) async fn proxy_k8s_servers(rx: watch::Receiver<Vec<IpamEntry>>) -> anyhow::Result<()> {
+    if lb_export::replaces_proxy() {
+        return std::future::pending().await;
+    }
+
+    let mut proxies = JoinSet::new();
+
+    for cluster in CLUSTERS.iter() {
+        proxies.spawn(proxy_cluster(
+            rx.clone(),
+            cluster,
+            listeners::api_listener(cluster),
+            K8S_API_PORT,
+        ));
+
+        if let Some(backend_port) = cluster.distro.supervisor_backend_port() {
+            proxies.spawn(proxy_cluster(
+                rx.clone(),
+                cluster,
+                listeners::supervisor_listener(cluster),
+                backend_port,
+            ));
+        }
+    }
+
+    while let Some(result) = proxies.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use axum::{extract::Path, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{cluster::IpamEntry, discovery, error::AppResult, events, logging, CONFIG, STATE};
+
+const REGISTRATIONS_KEY: &str = "registrations";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationStatus {
+    Pending,
+    Approved,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Registration {
+    pub vmid: String,
+    pub hostname: Option<String>,
+    pub ip: String,
+    pub status: RegistrationStatus,
+    pub discovered_at: i64,
+    pub approved_at: Option<i64>,
+    pub approved_by: Option<String>,
+}
+
+fn auto_approval(ipam: &IpamEntry) -> Option<String> {
+    let tag = CONFIG.registration_auto_approve_tag.as_ref()?;
+
+    ipam.tags
+        .as_deref()
+        .is_some_and(|tags| {
+            tags.split([';', ',', ' '])
+                .any(|candidate| candidate == tag)
+        })
+        .then(|| format!("policy:tag={tag}"))
+}
+
+// Proxmox hands out the VM IDs of deleted VMs again, an approval also holds the VM's name so it
+// doesn't carry over to an unrelated VM reusing the ID.
+fn is_same_vm(registration: &Registration, ipam: &IpamEntry) -> bool {
+    registration.hostname == ipam.hostname
+}
+
+// Records the k3s members seen by the synchronizer, unknown ones start out pending unless a policy
+// approves them right away. A VM ID now used by another VM starts over.
+pub(crate) fn register(ipams: &[IpamEntry]) -> anyhow::Result<()> {
+    if !CONFIG.registration_approval {
+        return Ok(());
+    }
+
+    let mut registered = vec![];
+
+    STATE.update(
+        REGISTRATIONS_KEY,
+        |registrations: &mut BTreeMap<String, Registration>| {
+            for ipam in ipams.iter().filter(|ipam| ipam.assignment.is_some()) {
+                let Some(vmid) = &ipam.vmid else {
+                    continue;
+                };
+
+                if registrations
+                    .get(vmid)
+                    .is_some_and(|registration| is_same_vm(registration, ipam))
+                {
+                    continue;
+                }
+
+                let now = chrono::Utc::now().timestamp();
+                let approved_by = auto_approval(ipam);
+
+                let registration = Registration {
+                    vmid: vmid.clone(),
+                    hostname: ipam.hostname.clone(),
+                    ip: ipam.ip.clone(),
+                    status: if approved_by.is_some() {
+                        RegistrationStatus::Approved
+                    } else {
+                        RegistrationStatus::Pending
+                    },
+                    discovered_at: now,
+                    approved_at: approved_by.as_ref().map(|_| now),
+                    approved_by,
+                };
+
+                registrations.insert(vmid.clone(), registration.clone());
+                registered.push(registration);
+            }
+        },
+    )?;
+
+    for registration in registered {
+        events::record(
+            "registration",
+            Some(&registration.vmid),
+            match &registration.approved_by {
+                Some(approved_by) => format!(
+                    "VM {} discovered and approved by {approved_by}",
+                    registration.vmid
+                ),
+                None => format!("VM {} discovered, waiting for approval", registration.vmid),
+            },
+            None,
+        );
+    }
+
+    Ok(())
+}
+
+pub(crate) fn ensure_approved(vmid: &str) -> anyhow::Result<()> {
+    if !CONFIG.registration_approval {
+        return Ok(());
+    }
+
+    let registration = STATE
+        .get::<BTreeMap<String, Registration>>(REGISTRATIONS_KEY)
+        .and_then(|mut registrations| registrations.remove(vmid));
+
+    // The VM may have been replaced since the last synchronization.
+    let approved = registration.is_some_and(|registration| {
+        registration.status == RegistrationStatus::Approved
+            && discovery::subscribe()
+                .borrow()
+                .iter()
+                .filter(|ipam| ipam.vmid.as_deref() == Some(vmid))
+                .all(|ipam| is_same_vm(&registration, ipam))
+    });
+
+    if !approved {
+        anyhow::bail!("VM {vmid} has not been approved to join the cluster");
+    }
+
+    Ok(())
+}
+
+// Called once the VM is deleted, its ID may be handed out again.
+pub(crate) fn forget(vmid: &str) {
+    if let Err(err) = STATE.update(
+        REGISTRATIONS_KEY,
+        |registrations: &mut BTreeMap<String, Registration>| {
+            registrations.remove(vmid);
+        },
+    ) {
+        logging::warn!("Unable to forget the registration of VM {vmid}: {err}");
+    }
+}
+
+pub(crate) async fn get_registrations() -> AppResult<Json<Vec<Registration>>> {
+    Ok(Json(
+        STATE
+            .get::<BTreeMap<String, Registration>>(REGISTRATIONS_KEY)
+            .unwrap_or_default()
+            .into_values()
+            .collect(),
+    ))
+}
+
+pub(crate) async fn approve(Path(vmid): Path<String>) -> AppResult<Json<Registration>> {
+    let mut approved = None;
+
+    STATE.update(
+        REGISTRATIONS_KEY,
+        |registrations: &mut BTreeMap<String, Registration>| {
+            if let Some(registration) = registrations.get_mut(&vmid) {
+                registration.status = RegistrationStatus::Approved;
+                registration.approved_at = Some(chrono::Utc::now().timestamp());
+                registration.approved_by = Some("admin".to_string());
+
+                approved = Some(registration.clone());
+            }
+        },
+    )?;
+
+    let Some(registration) = approved else {
+        return Err(anyhow::Error::msg(format!("No registration found for VM {vmid}")).into());
+    };
+
+    events::record(
+        "registration",
+        Some(&vmid),
+        format!("VM {vmid} approved to join the cluster"),
+        None,
+    );
+
+    Ok(Json(registration))
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{OriginalUri, Request},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+
+use crate::{credentials::ClusterScope, discovery, logging, CONFIG};
+
+// Only a handful of routes and query strings are cached, the oldest entry makes room past this.
+const MAX_ENTRIES: usize = 256;
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+static CACHE: Lazy<Mutex<HashMap<String, CachedResponse>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone)]
+struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    // The IPAM synchronization the response was computed from.
+    synchronization: Option<i64>,
+    refreshing: bool,
+}
+
+enum Freshness {
+    Fresh,
+    Stale,
+    Expired,
+}
+
+impl CachedResponse {
+    fn freshness(&self) -> Freshness {
+        let age = self.stored_at.elapsed();
+        let max_age = Duration::from_secs(CONFIG.response_cache_max_age);
+        let stale = Duration::from_secs(CONFIG.response_cache_stale_while_revalidate);
+
+        if age < max_age && self.synchronization == discovery::last_synchronization() {
+            Freshness::Fresh
+        } else if age < max_age + stale {
+            Freshness::Stale
+        } else {
+            Freshness::Expired
+        }
+    }
+
+    fn response(&self, cache: &'static str) -> Response {
+        let mut response = Response::new(Body::from(self.body.clone()));
+
+        *response.headers_mut() = self.headers.clone();
+        response
+            .headers_mut()
+            .insert("X-Cache", HeaderValue::from_static(cache));
+
+        response
+    }
+}
+
+fn cache_control() -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "max-age={}, stale-while-revalidate={}",
+        CONFIG.response_cache_max_age, CONFIG.response_cache_stale_while_revalidate
+    ))
+    .unwrap_or_else(|_| HeaderValue::from_static("no-cache"))
+}
+
+// Only successful responses are kept, an error is never served from the cache.
+async fn store(key: String, response: Response) -> Response {
+    if response.status() != StatusCode::OK {
+        if let Some(entry) = CACHE.lock().unwrap().get_mut(&key) {
+            entry.refreshing = false;
+        }
+
+        return response;
+    }
+
+    let synchronization = discovery::last_synchronization();
+    let (mut parts, body) = response.into_parts();
+
+    let Ok(body) = axum::body::to_bytes(body, MAX_BODY_SIZE).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    parts.headers.insert(header::CACHE_CONTROL, cache_control());
+
+    let mut cache = CACHE.lock().unwrap();
+
+    if cache.len() >= MAX_ENTRIES && !cache.contains_key(&key) {
+        if let Some(oldest) = cache
+            .iter()
+            .min_by_key(|(_, entry)| entry.stored_at)
+            .map(|(key, _)| key.clone())
+        {
+            cache.remove(&oldest);
+        }
+    }
+
+    cache.insert(
+        key,
+        CachedResponse {
+            headers: parts.headers.clone(),
+            body: body.clone(),
+            stored_at: Instant::now(),
+            synchronization,
+            refreshing: false,
+        },
+    );
+
+    let mut response = Response::from_parts(parts, Body::from(body));
+    response
+        .headers_mut()
+        .insert("X-Cache", HeaderValue::from_static("MISS"));
+
+    response
+}
+
+// Read endpoints fanning out to Proxmox answer from the cache. Past `response_cache_max_age`, or
+// once a newer IPAM snapshot is published, the cached response is still served right away while a
+// single request refreshes it in the background, for up to `response_cache_stale_while_revalidate`.
+pub(crate) async fn cache(request: Request, next: Next) -> Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+
+    // Nested routers only see the end of the path. Answers filtered for a scoped credential are
+    // kept apart from the others.
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.0.to_string())
+        .unwrap_or_else(|| request.uri().to_string());
+
+    let key = match request.extensions().get::<ClusterScope>() {
+        Some(ClusterScope(cluster)) => format!("{cluster}:{uri}"),
+        None => uri,
+    };
+
+    let cached = {
+        let mut cache = CACHE.lock().unwrap();
+
+        match cache.get_mut(&key) {
+            Some(entry) => match entry.freshness() {
+                Freshness::Fresh => Some((entry.response("HIT"), false)),
+                Freshness::Stale => {
+                    let refresh = !entry.refreshing;
+                    entry.refreshing = true;
+
+                    Some((entry.response("STALE"), refresh))
+                }
+                Freshness::Expired => None,
+            },
+            None => None,
+        }
+    };
+
+    match cached {
+        Some((response, refresh)) => {
+            if refresh {
+                tokio::spawn(async move {
+                    let response = store(key.clone(), next.run(request).await).await;
+
+                    if response.status() != StatusCode::OK {
+                        logging::debug!(
+                            "Unable to refresh the cached {key}: {}",
+                            response.status()
+                        );
+                    }
+                });
+            }
+
+            response
+        }
+        None => store(key, next.run(request).await).await,
+    }
+}
@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::find_vm,
+    clusters,
+    error::AppResult,
+    events,
+    jobs::{self, JobHandle},
+    kube, node_history, preflight, proxmox, roles, tasks, vms, CONFIG,
+};
+
+#[derive(Deserialize)]
+pub(crate) struct RestoreRequest {
+    archive: String,
+    confirm: String,
+    target_vmid: Option<String>,
+    storage: Option<String>,
+    start: Option<bool>,
+    // Restores in place even when the preflight checks find an issue.
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreResult {
+    pub vmid: String,
+    pub target_vmid: String,
+    pub node: String,
+    pub upid: String,
+    pub steps: Vec<String>,
+}
+
+async fn run_restore(
+    client: reqwest::Client,
+    vm_id: String,
+    request: RestoreRequest,
+    job: JobHandle,
+) -> anyhow::Result<RestoreResult> {
+    let (node, vm) = find_vm(client.clone(), &vm_id).await?;
+
+    let target_vmid = request.target_vmid.clone().unwrap_or_else(|| vm_id.clone());
+    let in_place = target_vmid == vm_id;
+    let cluster = roles::assign_vm(&vm).map(|assignment| assignment.cluster);
+
+    let mut steps = vec![];
+
+    events::record(
+        "restore-started",
+        Some(&vm_id),
+        format!(
+            "Restoring VM {vm_id} from {} into VM {target_vmid}",
+            request.archive
+        ),
+        None,
+    );
+
+    if in_place {
+        for warning in preflight::ensure_safe(&client, &node, "qemu", &vm_id, request.force).await?
+        {
+            job.step(&mut steps, format!("Preflight check overridden: {warning}"));
+        }
+
+        if let Some(cluster) = &cluster {
+            match kube::cordon_and_drain(&client, cluster, &vm.name).await {
+                Ok(()) => {
+                    node_history::record(&vm_id, "drained", "Drained before an in-place restore");
+                    job.step(&mut steps, format!("Cordoned and drained node {}", vm.name));
+                }
+                Err(err) => job.step(
+                    &mut steps,
+                    format!("Unable to drain node {}, continuing anyway: {err}", vm.name),
+                ),
+            }
+        }
+
+        if vm.status == "running" {
+            vms::change_vm_status(&client, &node, &vm_id, "stop").await?;
+            job.step(&mut steps, format!("Stopped VM {vm_id}"));
+        }
+    }
+
+    let mut params = vec![
+        ("vmid", target_vmid.clone()),
+        ("archive", request.archive.clone()),
+    ];
+
+    if in_place {
+        params.push(("force", "1".to_string()));
+    } else {
+        // A copy running next to the original must not reuse its MAC addresses.
+        params.push(("unique", "1".to_string()));
+    }
+
+    if let Some(storage) = &request.storage {
+        params.push(("storage", storage.clone()));
+    }
+
+    let upid: String = proxmox::post(&client, &format!("/nodes/{node}/qemu"), &params).await?;
+
+    if let Err(err) = tasks::wait_for_task(
+        &client,
+        &node,
+        &upid,
+        Duration::from_secs(CONFIG.backup_timeout),
+    )
+    .await
+    {
+        events::record(
+            "restore-failed",
+            Some(&vm_id),
+            format!("Restore of VM {vm_id} failed: {err}"),
+            Some(serde_json::json!({ "upid": upid })),
+        );
+
+        return Err(err);
+    }
+
+    job.step(
+        &mut steps,
+        format!("Restored {} into VM {target_vmid}", request.archive),
+    );
+
+    if in_place {
+        node_history::record(
+            &vm_id,
+            "rebuilt",
+            format!("Restored in place from {}", request.archive),
+        );
+    }
+
+    if request.start.unwrap_or(in_place) {
+        vms::change_vm_status(&client, &node, &target_vmid, "start").await?;
+        job.step(&mut steps, format!("Started VM {target_vmid}"));
+    }
+
+    if let Some(cluster) = cluster.as_deref().filter(|_| in_place) {
+        match kube::wait_for_node_ready(&client, cluster, &vm.name, Duration::from_secs(600)).await
+        {
+            Ok(()) => {
+                kube::uncordon(&client, cluster, &vm.name).await?;
+                job.step(
+                    &mut steps,
+                    format!("Node {} is ready and uncordoned", vm.name),
+                );
+            }
+            Err(err) => job.step(
+                &mut steps,
+                format!(
+                    "Node {} left cordoned, it did not become ready: {err}",
+                    vm.name
+                ),
+            ),
+        }
+    }
+
+    events::record(
+        "restore-completed",
+        Some(&vm_id),
+        format!("Restored VM {vm_id} from {}", request.archive),
+        Some(serde_json::json!({
+            "upid": upid,
+            "target_vmid": target_vmid,
+            "steps": steps,
+        })),
+    );
+
+    Ok(RestoreResult {
+        vmid: vm_id,
+        target_vmid,
+        node,
+        upid,
+        steps,
+    })
+}
+
+pub(crate) async fn restore_vm(
+    Path(vm_id): Path<String>,
+    State(client): State<reqwest::Client>,
+    Json(request): Json<RestoreRequest>,
+) -> AppResult<Response> {
+    if request.confirm != vm_id {
+        return Err(anyhow::Error::msg(format!(
+            "Restoring is destructive, set \"confirm\" to \"{vm_id}\" to proceed"
+        ))
+        .into());
+    }
+
+    Ok(jobs::accepted(jobs::spawn(
+        "restore",
+        clusters::cluster_of_vmid(&vm_id),
+        |job| run_restore(client, vm_id, request, job),
+    )?))
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::{NodeRole, VirtualMachineEntry},
+    clusters, discovery, CONFIG,
+};
+
+pub(crate) static ROLE_RULES: Lazy<Vec<RoleRule>> =
+    Lazy::new(|| load_rules().expect("Unable to load the role mapping"));
+
+#[derive(Debug, Deserialize)]
+struct RoleMapping {
+    rules: Vec<RoleRule>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RoleRule {
+    cluster: Option<String>,
+    hostname: Option<String>,
+    tag: Option<String>,
+    vnet: Option<String>,
+    role: NodeRole,
+    pool: Option<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    proxy: Option<bool>,
+    #[serde(default)]
+    provisioning: Provisioning,
+    #[serde(default)]
+    os: NodeOs,
+}
+
+// How the nodes of a pool get their first-boot configuration.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provisioning {
+    #[default]
+    CloudInit,
+    Ignition,
+}
+
+// Windows nodes can only join as agents of mixed clusters.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeOs {
+    #[default]
+    Linux,
+    Windows,
+}
+
+impl NodeOs {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeOs::Linux => "linux",
+            NodeOs::Windows => "windows",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NodeAssignment {
+    #[serde(default = "clusters::default_cluster_name")]
+    pub cluster: String,
+    pub role: NodeRole,
+    pub pool: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub proxy: bool,
+    #[serde(default)]
+    pub provisioning: Provisioning,
+    #[serde(default)]
+    pub os: NodeOs,
+}
+
+fn default_rules() -> Vec<RoleRule> {
+    [
+        ("k3s-server*", NodeRole::Server),
+        ("k3s-*", NodeRole::Agent),
+    ]
+    .into_iter()
+    .map(|(hostname, role)| RoleRule {
+        cluster: None,
+        hostname: Some(hostname.to_string()),
+        tag: None,
+        vnet: None,
+        role,
+        pool: None,
+        labels: BTreeMap::new(),
+        proxy: None,
+        provisioning: Provisioning::default(),
+        os: NodeOs::default(),
+    })
+    .collect()
+}
+
+fn load_rules() -> anyhow::Result<Vec<RoleRule>> {
+    let Some(path) = &CONFIG.role_mapping_path else {
+        return Ok(default_rules());
+    };
+
+    let mapping =
+        std::fs::read_to_string(path).context(format!("Unable to read role mapping {path}"))?;
+
+    let rules = toml::from_str::<RoleMapping>(&mapping)
+        .context(format!("Invalid role mapping {path}"))?
+        .rules;
+
+    for rule in rules.iter().filter(|rule| rule.os == NodeOs::Windows) {
+        if rule.role == NodeRole::Server {
+            anyhow::bail!("Invalid role mapping {path}: Windows nodes can only be agents");
+        }
+
+        if rule.provisioning == Provisioning::Ignition {
+            anyhow::bail!("Invalid role mapping {path}: Windows nodes can't boot from Ignition");
+        }
+    }
+
+    Ok(rules)
+}
+
+// Windows nodes never run the control plane, they stay out of the API proxy whatever is asked.
+pub(crate) fn is_proxied(role: NodeRole, os: NodeOs, proxy: Option<bool>) -> bool {
+    os == NodeOs::Linux && proxy.unwrap_or(role == NodeRole::Server)
+}
+
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    match pattern.chars().next() {
+        None => value.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+
+            value
+                .char_indices()
+                .map(|(index, _)| index)
+                .chain(std::iter::once(value.len()))
+                .any(|index| glob_match(rest, &value[index..]))
+        }
+        Some(expected) => value.chars().next().is_some_and(|actual| {
+            (expected == '?' || expected == actual)
+                && glob_match(&pattern[expected.len_utf8()..], &value[actual.len_utf8()..])
+        }),
+    }
+}
+
+impl RoleRule {
+    // A rule only matches VMs which have every attribute it sets, an unknown vnet or missing tags
+    // never grant a role (and with it a place behind the proxy).
+    fn matches(&self, hostname: Option<&str>, vnet: Option<&str>, tags: Option<&str>) -> bool {
+        // Windows registers its hostname in uppercase.
+        let hostname_matches = match (&self.hostname, self.os) {
+            (Some(pattern), NodeOs::Windows) => hostname.is_some_and(|hostname| {
+                glob_match(&pattern.to_lowercase(), &hostname.to_lowercase())
+            }),
+            (Some(pattern), NodeOs::Linux) => {
+                hostname.is_some_and(|hostname| glob_match(pattern, hostname))
+            }
+            (None, _) => true,
+        };
+
+        let vnet_matches = match (&self.vnet, vnet) {
+            (Some(expected), vnet) => vnet == Some(expected.as_str()),
+            (None, _) => true,
+        };
+
+        // Proxmox separates VM tags with semicolons.
+        let tag_matches = match (&self.tag, tags) {
+            (Some(expected), tags) => {
+                tags.is_some_and(|tags| tags.split([';', ',', ' ']).any(|tag| tag == expected))
+            }
+            (None, _) => true,
+        };
+
+        hostname_matches && vnet_matches && tag_matches
+    }
+}
+
+// The first matching rule of the VM's cluster wins, VMs matching no cluster or rule are not part of
+// any k3s cluster.
+pub(crate) fn assign(
+    hostname: Option<&str>,
+    vnet: Option<&str>,
+    tags: Option<&str>,
+) -> Option<NodeAssignment> {
+    let cluster = clusters::cluster_for(hostname, vnet, tags)?;
+
+    ROLE_RULES
+        .iter()
+        .filter(|rule| {
+            rule.cluster
+                .as_ref()
+                .is_none_or(|name| *name == cluster.name)
+        })
+        .find(|rule| rule.matches(hostname, vnet, tags))
+        .map(|rule| NodeAssignment {
+            cluster: cluster.name.clone(),
+            role: rule.role,
+            pool: rule.pool.clone(),
+            labels: rule.labels.clone(),
+            proxy: is_proxied(rule.role, rule.os, rule.proxy),
+            provisioning: rule.provisioning,
+            os: rule.os,
+        })
+}
+
+pub(crate) fn assign_vm(vm: &VirtualMachineEntry) -> Option<NodeAssignment> {
+    let vmid = vm.vmid.to_string();
+
+    let vnet = discovery::subscribe()
+        .borrow()
+        .iter()
+        .find(|ipam| ipam.vmid.as_ref() == Some(&vmid))
+        .map(|ipam| ipam.vnet.clone());
+
+    assign(Some(&vm.name), vnet.as_deref(), vm.tags.as_deref())
+}
@@ -0,0 +1,100 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+use tokio::sync::Semaphore;
+
+use crate::{metrics, CONFIG};
+
+static NODES: Lazy<RouteLimit> = Lazy::new(|| RouteLimit::new("nodes"));
+static TOKEN: Lazy<RouteLimit> = Lazy::new(|| RouteLimit::new("token"));
+
+// Requests to one expensive route, running or waiting for their turn.
+struct RouteLimit {
+    route: &'static str,
+    running: Semaphore,
+    queued: AtomicUsize,
+}
+
+struct QueuedRequest<'a>(&'a RouteLimit);
+
+impl RouteLimit {
+    fn new(route: &'static str) -> Self {
+        RouteLimit {
+            route,
+            running: Semaphore::new(CONFIG.route_concurrency_limit.max(1)),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    fn report_queue_depth(&self, depth: usize) {
+        metrics::set_gauge(
+            "k3s_helper_route_queue_depth",
+            "Requests to an expensive route running or waiting for their turn",
+            &[("route", self.route)],
+            depth as f64,
+        );
+    }
+
+    fn reject(&self, reason: &'static str) -> Response {
+        metrics::increment_counter(
+            "k3s_helper_route_rejections_total",
+            "Requests to an expensive route refused because it was saturated",
+            &[("route", self.route)],
+        );
+
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, CONFIG.route_queue_timeout.to_string())],
+            reason,
+        )
+            .into_response()
+    }
+
+    // Requests beyond the concurrency limit wait in a bounded queue, those that don't fit in it or
+    // wait for too long are turned away so a herd of bootstrapping agents can't pile up.
+    async fn run(&self, request: Request, next: Next) -> Response {
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        let _request = QueuedRequest(self);
+
+        if queued > CONFIG.route_concurrency_limit + CONFIG.route_queue_size {
+            return self.reject("Too many pending requests, try again later");
+        }
+
+        self.report_queue_depth(queued);
+
+        let permit = tokio::time::timeout(
+            Duration::from_secs(CONFIG.route_queue_timeout),
+            self.running.acquire(),
+        )
+        .await;
+
+        match permit {
+            Ok(Ok(_permit)) => next.run(request).await,
+            _ => self.reject("Timed out waiting for a turn, try again later"),
+        }
+    }
+}
+
+impl Drop for QueuedRequest<'_> {
+    fn drop(&mut self) {
+        self.0
+            .report_queue_depth(self.0.queued.fetch_sub(1, Ordering::SeqCst) - 1);
+    }
+}
+
+pub(crate) async fn limit_nodes(request: Request, next: Next) -> Response {
+    NODES.run(request, next).await
+}
+
+pub(crate) async fn limit_token(request: Request, next: Next) -> Response {
+    TOKEN.run(request, next).await
+}
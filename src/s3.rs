@@ -0,0 +1,200 @@
+use anyhow::Context;
+use hmac::{Hmac, KeyInit, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// Minimal S3 client using path-style requests, which MinIO and most S3-compatible stores accept.
+pub(crate) struct S3Bucket {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct S3Object {
+    pub key: String,
+    pub last_modified: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<S3Object>,
+    is_truncated: Option<bool>,
+    next_continuation_token: Option<String>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl S3Bucket {
+    fn host(&self) -> anyhow::Result<String> {
+        let url = reqwest::Url::parse(&self.endpoint)?;
+        let host = url.host_str().context("S3 endpoint has no host")?;
+
+        Ok(match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        })
+    }
+
+    // Signs the request with AWS Signature Version 4.
+    fn request(
+        &self,
+        client: &reqwest::Client,
+        method: reqwest::Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> anyhow::Result<reqwest::RequestBuilder> {
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let path = std::iter::once(self.bucket.as_str())
+            .chain(key.split('/').filter(|segment| !segment.is_empty()))
+            .map(|segment| urlencoding::encode(segment).to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        let path = format!("/{path}");
+
+        let mut query = query
+            .iter()
+            .map(|(name, value)| (urlencoding::encode(name), urlencoding::encode(value)))
+            .collect::<Vec<_>>();
+        query.sort();
+        let query = query
+            .into_iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let host = self.host()?;
+        let payload_hash = hex(&Sha256::digest(&body));
+
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\nhost:{host}\nx-amz-content-sha256:{payload_hash}\n\
+             x-amz-date:{amz_date}\n\nhost;x-amz-content-sha256;x-amz-date\n{payload_hash}"
+        );
+
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = ["s3", "aws4_request"].iter().fold(
+            hmac(
+                &hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date),
+                &self.region,
+            ),
+            |key, part| hmac(&key, part),
+        );
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.access_key,
+            hex(&hmac(&signing_key, &string_to_sign))
+        );
+
+        let mut url = format!("{}{path}", self.endpoint.trim_end_matches('/'));
+
+        if !query.is_empty() {
+            url = format!("{url}?{query}");
+        }
+
+        Ok(client
+            .request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(reqwest::header::AUTHORIZATION, authorization)
+            .body(body))
+    }
+
+    pub(crate) async fn put(
+        &self,
+        client: &reqwest::Client,
+        key: &str,
+        body: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        self.request(client, reqwest::Method::PUT, key, &[], body)?
+            .send()
+            .await?
+            .error_for_status()
+            .context(format!("Unable to upload {key}"))?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn get(&self, client: &reqwest::Client, key: &str) -> anyhow::Result<Vec<u8>> {
+        let body = self
+            .request(client, reqwest::Method::GET, key, &[], vec![])?
+            .send()
+            .await?
+            .error_for_status()
+            .context(format!("Unable to download {key}"))?
+            .bytes()
+            .await?;
+
+        Ok(body.to_vec())
+    }
+
+    pub(crate) async fn delete(&self, client: &reqwest::Client, key: &str) -> anyhow::Result<()> {
+        self.request(client, reqwest::Method::DELETE, key, &[], vec![])?
+            .send()
+            .await?
+            .error_for_status()
+            .context(format!("Unable to delete {key}"))?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn list(
+        &self,
+        client: &reqwest::Client,
+        prefix: &str,
+    ) -> anyhow::Result<Vec<S3Object>> {
+        let mut objects = vec![];
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+
+            if let Some(token) = &continuation_token {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            let response = self
+                .request(client, reqwest::Method::GET, "", &query, vec![])?
+                .send()
+                .await?
+                .error_for_status()
+                .context(format!("Unable to list {prefix}"))?
+                .text()
+                .await?;
+
+            let result: ListBucketResult =
+                quick_xml::de::from_str(&response).context("Invalid S3 listing")?;
+
+            objects.extend(result.contents);
+
+            match (result.is_truncated, result.next_continuation_token) {
+                (Some(true), Some(token)) => continuation_token = Some(token),
+                _ => return Ok(objects),
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppResult, proxmox};
+
+#[derive(Debug, Deserialize)]
+struct ZoneEntry {
+    zone: String,
+    #[serde(rename = "type")]
+    zone_type: String,
+    dhcp: Option<String>,
+    ipam: Option<String>,
+    mtu: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VnetEntry {
+    vnet: String,
+    zone: String,
+    alias: Option<String>,
+    tag: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubnetEntry {
+    cidr: Option<String>,
+    subnet: String,
+    gateway: Option<String>,
+    snat: Option<u8>,
+    #[serde(rename = "dhcp-range", default)]
+    dhcp_range: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DhcpRange {
+    pub start_address: String,
+    pub end_address: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Subnet {
+    pub subnet: String,
+    pub cidr: String,
+    pub gateway: Option<String>,
+    pub snat: bool,
+    pub dhcp_ranges: Vec<DhcpRange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Vnet {
+    pub vnet: String,
+    pub alias: Option<String>,
+    pub tag: Option<u32>,
+    pub subnets: Vec<Subnet>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Zone {
+    pub zone: String,
+    #[serde(rename = "type")]
+    pub zone_type: String,
+    pub dhcp: Option<String>,
+    pub ipam: Option<String>,
+    pub mtu: Option<u32>,
+    pub vnets: Vec<Vnet>,
+}
+
+// Proxmox returns DHCP ranges either as property strings
+// (`start-address=10.0.0.100,end-address=10.0.0.200`) or as objects depending on the version.
+fn parse_dhcp_range(range: &serde_json::Value) -> Option<DhcpRange> {
+    let field = |name: &str| match range {
+        serde_json::Value::String(range) => range
+            .split(',')
+            .find_map(|pair| pair.trim().strip_prefix(&format!("{name}=")))
+            .map(|value| value.to_string()),
+        serde_json::Value::Object(range) => range
+            .get(name)
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string()),
+        _ => None,
+    };
+
+    Some(DhcpRange {
+        start_address: field("start-address")?,
+        end_address: field("end-address")?,
+    })
+}
+
+pub(crate) async fn get_sdn(State(client): State<reqwest::Client>) -> AppResult<Json<Vec<Zone>>> {
+    let zones: Vec<ZoneEntry> = proxmox::get(&client, "/cluster/sdn/zones").await?;
+    let vnets: Vec<VnetEntry> = proxmox::get(&client, "/cluster/sdn/vnets").await?;
+
+    let mut inventory = vec![];
+
+    for zone in zones {
+        let mut zone_vnets = vec![];
+
+        for vnet in vnets.iter().filter(|vnet| vnet.zone == zone.zone) {
+            let subnets: Vec<SubnetEntry> = proxmox::get(
+                &client,
+                &format!("/cluster/sdn/vnets/{}/subnets", vnet.vnet),
+            )
+            .await?;
+
+            zone_vnets.push(Vnet {
+                vnet: vnet.vnet.clone(),
+                alias: vnet.alias.clone(),
+                tag: vnet.tag,
+                subnets: subnets
+                    .into_iter()
+                    .map(|subnet| Subnet {
+                        cidr: subnet.cidr.unwrap_or_else(|| subnet.subnet.clone()),
+                        subnet: subnet.subnet,
+                        gateway: subnet.gateway,
+                        snat: subnet.snat == Some(1),
+                        dhcp_ranges: subnet
+                            .dhcp_range
+                            .iter()
+                            .filter_map(parse_dhcp_range)
+                            .collect(),
+                    })
+                    .collect(),
+            });
+        }
+
+        inventory.push(Zone {
+            zone: zone.zone,
+            zone_type: zone.zone_type,
+            dhcp: zone.dhcp,
+            ipam: zone.ipam,
+            mtu: zone.mtu,
+            vnets: zone_vnets,
+        });
+    }
+
+    Ok(Json(inventory))
+}
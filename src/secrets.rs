@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use crate::{logging, CONFIG};
+
+// Secrets passed by systemd with `LoadCredential=` are preferred, they never appear in the unit
+// file nor in the environment inherited by the commands the helper runs.
+pub(crate) fn credential(name: &str) -> Option<String> {
+    let directory = std::env::var_os("CREDENTIALS_DIRECTORY")?;
+    let path = Path::new(&directory).join(name);
+
+    match std::fs::read_to_string(&path) {
+        Ok(value) => Some(value.trim_end_matches(['\r', '\n']).to_string()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            logging::warn!("Unable to read credential {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+// Falls back to the configured value when systemd provides no credential of that name.
+pub(crate) fn secret(name: &str, configured: &Option<String>) -> Option<String> {
+    credential(name).or_else(|| configured.clone())
+}
+
+// Compares every byte whatever the first difference, the timing doesn't reveal the secret.
+pub(crate) fn secrets_match(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+// Every secret currently configured, scrubbed from anything the helper sends out.
+pub(crate) fn configured_secrets() -> Vec<String> {
+    let password_file = CONFIG
+        .proxmox_api_password_file
+        .as_ref()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .map(|password| password.trim().to_string());
+
+    let token_file = CONFIG
+        .proxmox_api_token_file
+        .as_ref()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .map(|token| token.trim().to_string());
+
+    [
+        secret("admin_token", &CONFIG.admin_token),
+        secret("proxmox_api_password", &CONFIG.proxmox_api_password),
+        password_file,
+        secret("proxmox_api_token", &CONFIG.proxmox_api_token),
+        token_file,
+        secret("alert_webhook_url", &CONFIG.alert_webhook_url),
+        secret("config_age_key", &CONFIG.config_age_key),
+        secret(
+            "etcd_snapshot_s3_access_key",
+            &CONFIG.etcd_snapshot_s3_access_key,
+        ),
+        secret(
+            "etcd_snapshot_s3_secret_key",
+            &CONFIG.etcd_snapshot_s3_secret_key,
+        ),
+        secret("error_report_webhook_url", &CONFIG.error_report_webhook_url),
+        secret("proxmox_hook_secret", &CONFIG.proxmox_hook_secret),
+        secret("sentry_dsn", &CONFIG.sentry_dsn),
+        secret("vault_token", &CONFIG.vault_token),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|secret| !secret.is_empty())
+    .collect()
+}
+
+// Configured secrets and bearer tokens never leave the host, whatever the message embedded.
+pub(crate) fn redact(message: &str) -> String {
+    let mut message = configured_secrets()
+        .iter()
+        .fold(message.to_string(), |message, secret| {
+            message.replace(secret, "[redacted]")
+        });
+
+    while let Some(start) = message.find("Bearer ") {
+        let token_start = start + "Bearer ".len();
+        let token_end = message[token_start..]
+            .find(|c: char| c.is_whitespace() || c == '"')
+            .map(|end| token_start + end)
+            .unwrap_or(message.len());
+
+        message.replace_range(start..token_end, "[redacted]");
+    }
+
+    message
+}
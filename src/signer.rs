@@ -0,0 +1,639 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mktemp::Temp;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{process::Command, sync::Mutex};
+
+use crate::{
+    clusters::{K3sCluster, CLUSTERS},
+    commands, logging, metrics, secrets, CONFIG,
+};
+
+static SIGNING_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+static SIGNING_QUEUE: AtomicUsize = AtomicUsize::new(0);
+
+// Every cluster has its own CA.
+pub(crate) static SIGNERS: Lazy<BTreeMap<String, Box<dyn Signer>>> = Lazy::new(|| {
+    CLUSTERS
+        .iter()
+        .map(|cluster| Ok((cluster.name.clone(), build_signer(cluster.ca_path())?)))
+        .collect::<anyhow::Result<_>>()
+        .expect("Unable to configure the certificate signer")
+});
+
+struct QueuedRequest;
+
+impl QueuedRequest {
+    fn enter() -> anyhow::Result<Self> {
+        let queued = SIGNING_QUEUE.fetch_add(1, Ordering::SeqCst) + 1;
+        let request = QueuedRequest;
+
+        if queued > CONFIG.signing_queue_size {
+            anyhow::bail!("Too many pending certificate requests, try again later");
+        }
+
+        report_queue_depth(queued);
+
+        Ok(request)
+    }
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        report_queue_depth(SIGNING_QUEUE.fetch_sub(1, Ordering::SeqCst) - 1);
+    }
+}
+
+fn report_queue_depth(depth: usize) {
+    metrics::set_gauge(
+        "k3s_helper_certificate_sign_queue_depth",
+        "Certificate requests being signed or waiting for their turn",
+        &[],
+        depth as f64,
+    );
+}
+
+// Issuance runs one request at a time so bursts (e.g. a whole cluster bootstrapping) can't corrupt
+// the CA state, the requests that don't fit in the queue or wait for too long fail instead of
+// piling up openssl processes.
+pub(crate) async fn serialized<T, F: Future<Output = anyhow::Result<T>>>(
+    signing: F,
+) -> anyhow::Result<T> {
+    let _request = QueuedRequest::enter()?;
+
+    tokio::time::timeout(Duration::from_secs(CONFIG.signing_timeout), async {
+        let _guard = SIGNING_LOCK.lock().await;
+        signing.await
+    })
+    .await
+    .context("Certificate signing timed out")?
+}
+
+pub(crate) enum CertificateUsage {
+    Ca,
+    // Subject alternative names use the openssl notation, e.g. `IP:10.0.0.1` or `DNS:host`.
+    Leaf { subject_alt_names: Vec<String> },
+    // Client authentication only, Kubernetes identifies the holder by its subject.
+    Client,
+}
+
+pub(crate) struct CaCertificate {
+    pub name: String,
+    pub pem: String,
+}
+
+#[async_trait]
+pub(crate) trait Signer: Send + Sync {
+    // Signs the CSR and returns the PEM encoded certificate.
+    async fn sign(
+        &self,
+        csr: &str,
+        usage: &CertificateUsage,
+        not_before: DateTime<Utc>,
+        not_after: DateTime<Utc>,
+    ) -> anyhow::Result<String>;
+
+    // The issuing CA comes first and the root CA last.
+    async fn ca_chain(&self) -> anyhow::Result<Vec<CaCertificate>>;
+
+    fn ca_index_path(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+pub(crate) fn signer(cluster: &str) -> anyhow::Result<&'static dyn Signer> {
+    SIGNERS
+        .get(cluster)
+        .map(|signer| signer.as_ref())
+        .context(format!("No certificate signer for cluster {cluster}"))
+}
+
+fn build_signer(ca_path: PathBuf) -> anyhow::Result<Box<dyn Signer>> {
+    Ok(match CONFIG.signer.as_str() {
+        "local" => Box::new(OpensslCaSigner {
+            ca_path: ca_path.clone(),
+            key: CaKey::File(ca_path.join("intermediate-ca.key")),
+        }),
+        "hsm" => Box::new(OpensslCaSigner {
+            ca_path,
+            key: CaKey::Engine {
+                engine: CONFIG.signer_hsm_engine.clone(),
+                key: CONFIG
+                    .signer_hsm_key
+                    .clone()
+                    .context("signer_hsm_key is required by the hsm signer")?,
+            },
+        }),
+        "vault" => Box::new(VaultSigner {
+            client: reqwest::Client::new(),
+            addr: CONFIG
+                .vault_addr
+                .clone()
+                .context("vault_addr is required by the vault signer")?,
+            token: secrets::secret("vault_token", &CONFIG.vault_token)
+                .context("vault_token is required by the vault signer")?,
+            mount: CONFIG.vault_pki_mount.clone(),
+            role: CONFIG
+                .vault_pki_role
+                .clone()
+                .context("vault_pki_role is required by the vault signer")?,
+        }),
+        "mock" => Box::new(MockSigner::new()?),
+        signer => anyhow::bail!("Unknown signer {signer}, expected local, hsm, vault or mock"),
+    })
+}
+
+pub(crate) async fn openssl_output(args: &[&str]) -> anyhow::Result<String> {
+    let output = commands::output(Command::new("openssl").args(args))
+        .await
+        .context(format!("openssl {} failed", args.first().unwrap_or(&"")))?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn path_string(path: PathBuf) -> String {
+    path.as_path().display().to_string()
+}
+
+fn openssl_extensions(usage: &CertificateUsage) -> String {
+    match usage {
+        CertificateUsage::Ca => "basicConstraints=critical,CA:TRUE\n\
+             keyUsage=critical,keyCertSign,cRLSign\n\
+             subjectKeyIdentifier=hash\n\
+             authorityKeyIdentifier=keyid:always"
+            .to_string(),
+        CertificateUsage::Leaf { subject_alt_names } => format!(
+            "basicConstraints=critical,CA:FALSE\n\
+             keyUsage=critical,digitalSignature,keyEncipherment,keyAgreement\n\
+             extendedKeyUsage=serverAuth,clientAuth\n\
+             subjectAltName=critical,{}",
+            subject_alt_names.join(",")
+        ),
+        CertificateUsage::Client => "basicConstraints=critical,CA:FALSE\n\
+             keyUsage=critical,digitalSignature,keyEncipherment\n\
+             extendedKeyUsage=clientAuth"
+            .to_string(),
+    }
+}
+
+fn split_pem_chain(chain: &str) -> Vec<String> {
+    chain
+        .split_inclusive("-----END CERTIFICATE-----")
+        .map(|pem| pem.trim())
+        .filter(|pem| !pem.is_empty())
+        .map(|pem| format!("{pem}\n"))
+        .collect()
+}
+
+enum CaKey {
+    File(PathBuf),
+    // The private key never leaves the HSM, openssl reaches it through an engine (e.g. pkcs11).
+    Engine { engine: String, key: String },
+}
+
+// Signs with `openssl ca` against the intermediate CA kept in the cluster CA directory.
+struct OpensslCaSigner {
+    ca_path: PathBuf,
+    key: CaKey,
+}
+
+// The openssl CA keeps its serial and index in plain files, signing must never run concurrently.
+static OPENSSL_CA_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[async_trait]
+impl Signer for OpensslCaSigner {
+    async fn sign(
+        &self,
+        csr: &str,
+        usage: &CertificateUsage,
+        not_before: DateTime<Utc>,
+        not_after: DateTime<Utc>,
+    ) -> anyhow::Result<String> {
+        let temp_dir = Temp::new_dir()?;
+
+        let csr_path = path_string(temp_dir.join("certificate.csr"));
+        let certificate_path = path_string(temp_dir.join("certificate.pem"));
+
+        std::fs::write(&csr_path, csr)?;
+
+        let mut extension_args = vec![];
+
+        match usage {
+            CertificateUsage::Ca => {
+                extension_args.extend(["-extensions".to_string(), "v3_ca".to_string()])
+            }
+            CertificateUsage::Leaf { .. } | CertificateUsage::Client => {
+                let extensions_path = path_string(temp_dir.join("extensions.cnf"));
+
+                std::fs::write(
+                    &extensions_path,
+                    format!("[extensions]\n{}\n", openssl_extensions(usage)),
+                )?;
+
+                extension_args.extend([
+                    "-extfile".to_string(),
+                    extensions_path,
+                    "-extensions".to_string(),
+                    "extensions".to_string(),
+                ]);
+            }
+        }
+
+        let key_args = match &self.key {
+            CaKey::File(path) => vec!["-keyfile".to_string(), path_string(path.clone())],
+            CaKey::Engine { engine, key } => vec![
+                "-engine".to_string(),
+                engine.clone(),
+                "-keyform".to_string(),
+                "engine".to_string(),
+                "-keyfile".to_string(),
+                key.clone(),
+            ],
+        };
+
+        let _guard = OPENSSL_CA_LOCK.lock().await;
+
+        commands::output(
+            Command::new("openssl")
+                .args([
+                    "ca",
+                    "-batch",
+                    "-notext",
+                    "-startdate",
+                    &not_before.format("%Y%m%d%H%M%SZ").to_string(),
+                    "-enddate",
+                    &not_after.format("%Y%m%d%H%M%SZ").to_string(),
+                    "-in",
+                    &csr_path,
+                    "-out",
+                    &certificate_path,
+                    "-cert",
+                    &path_string(self.ca_path.join("intermediate-ca.pem")),
+                    "-config",
+                    &path_string(self.ca_path.join(".ca").join("config")),
+                ])
+                .args(&key_args)
+                .args(&extension_args),
+        )
+        .await?;
+
+        Ok(std::fs::read_to_string(&certificate_path)?)
+    }
+
+    async fn ca_chain(&self) -> anyhow::Result<Vec<CaCertificate>> {
+        let mut chain = vec![];
+
+        for name in ["intermediate-ca", "root-ca"] {
+            let path = self.ca_path.join(format!("{name}.pem"));
+
+            chain.push(CaCertificate {
+                name: name.to_string(),
+                pem: std::fs::read_to_string(&path)
+                    .context(format!("Unable to read {}", path.display()))?,
+            });
+        }
+
+        Ok(chain)
+    }
+
+    fn ca_index_path(&self) -> Option<PathBuf> {
+        Some(self.ca_path.join(".ca").join("index.txt"))
+    }
+}
+
+// Same layout as k3s' generate-custom-ca-certs.sh, which the local signer expects: a long-lived
+// root CA kept next to the intermediate that signs everything.
+fn openssl_ca_config(ca_dir: &Path) -> String {
+    format!(
+        "[ca]\n\
+         default_ca = ca_default\n\n\
+         [ca_default]\n\
+         dir = {}\n\
+         database = $dir/index.txt\n\
+         serial = $dir/serial\n\
+         new_certs_dir = $dir/certs\n\
+         default_md = sha256\n\
+         policy = policy_anything\n\
+         unique_subject = no\n\n\
+         [policy_anything]\n\
+         commonName = supplied\n\n\
+         [req]\n\
+         distinguished_name = req_distinguished_name\n\n\
+         [req_distinguished_name]\n\n\
+         [v3_ca]\n\
+         {}\n",
+        ca_dir.display(),
+        openssl_extensions(&CertificateUsage::Ca)
+    )
+}
+
+// Only the local signer keeps its CA on disk, an existing CA is never replaced.
+pub(crate) async fn bootstrap_ca(cluster: &K3sCluster) -> anyhow::Result<bool> {
+    let ca_path = cluster.ca_path();
+
+    if CONFIG.signer != "local" || ca_path.join("intermediate-ca.pem").exists() {
+        return Ok(false);
+    }
+
+    let ca_dir = ca_path.join(".ca");
+    std::fs::create_dir_all(ca_dir.join("certs"))
+        .context(format!("Unable to create {}", ca_dir.display()))?;
+    std::fs::write(ca_dir.join("config"), openssl_ca_config(&ca_dir))?;
+    std::fs::write(ca_dir.join("index.txt"), "")?;
+    std::fs::write(
+        ca_dir.join("serial"),
+        format!("{:016x}\n", rand::random::<u64>()),
+    )?;
+
+    let config = path_string(ca_dir.join("config"));
+    let timestamp = Utc::now().timestamp();
+
+    openssl_output(&[
+        "req",
+        "-x509",
+        "-new",
+        "-nodes",
+        "-newkey",
+        "ec",
+        "-pkeyopt",
+        "ec_paramgen_curve:prime256v1",
+        "-config",
+        &config,
+        "-extensions",
+        "v3_ca",
+        "-subj",
+        &format!("/CN=k3s-root-ca@{timestamp}"),
+        "-days",
+        "7300",
+        "-keyout",
+        &path_string(ca_path.join("root-ca.key")),
+        "-out",
+        &path_string(ca_path.join("root-ca.pem")),
+    ])
+    .await?;
+
+    openssl_output(&[
+        "req",
+        "-new",
+        "-nodes",
+        "-newkey",
+        "ec",
+        "-pkeyopt",
+        "ec_paramgen_curve:prime256v1",
+        "-config",
+        &config,
+        "-subj",
+        &format!("/CN=k3s-intermediate-ca@{timestamp}"),
+        "-keyout",
+        &path_string(ca_path.join("intermediate-ca.key")),
+        "-out",
+        &path_string(ca_dir.join("intermediate-ca.csr")),
+    ])
+    .await?;
+
+    openssl_output(&[
+        "ca",
+        "-batch",
+        "-notext",
+        "-config",
+        &config,
+        "-extensions",
+        "v3_ca",
+        "-days",
+        "3650",
+        "-cert",
+        &path_string(ca_path.join("root-ca.pem")),
+        "-keyfile",
+        &path_string(ca_path.join("root-ca.key")),
+        "-in",
+        &path_string(ca_dir.join("intermediate-ca.csr")),
+        "-out",
+        &path_string(ca_path.join("intermediate-ca.pem")),
+    ])
+    .await?;
+
+    logging::info!(
+        "Created the CA of cluster {} in {}",
+        cluster.name,
+        ca_path.display()
+    );
+
+    Ok(true)
+}
+
+#[derive(Deserialize)]
+struct VaultResponse<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct VaultSignedCertificate {
+    certificate: String,
+}
+
+// Signs through the PKI secrets engine of a HashiCorp Vault server. The CSR is signed verbatim so
+// the subject and subject alternative names chosen by the helper are kept.
+struct VaultSigner {
+    client: reqwest::Client,
+    addr: String,
+    token: String,
+    mount: String,
+    role: String,
+}
+
+impl VaultSigner {
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/v1/{}/{path}",
+            self.addr.trim_end_matches('/'),
+            self.mount.trim_matches('/')
+        )
+    }
+}
+
+#[async_trait]
+impl Signer for VaultSigner {
+    async fn sign(
+        &self,
+        csr: &str,
+        usage: &CertificateUsage,
+        _not_before: DateTime<Utc>,
+        not_after: DateTime<Utc>,
+    ) -> anyhow::Result<String> {
+        let ttl = format!("{}s", (not_after - Utc::now()).num_seconds());
+
+        let (path, body) = match usage {
+            CertificateUsage::Ca => (
+                "root/sign-intermediate".to_string(),
+                json!({ "csr": csr, "ttl": ttl, "use_csr_values": true }),
+            ),
+            CertificateUsage::Leaf { .. } => (
+                format!("sign-verbatim/{}", self.role),
+                json!({
+                    "csr": csr,
+                    "ttl": ttl,
+                    "key_usage": ["DigitalSignature", "KeyEncipherment", "KeyAgreement"],
+                    "ext_key_usage": ["ServerAuth", "ClientAuth"],
+                }),
+            ),
+            CertificateUsage::Client => (
+                format!("sign-verbatim/{}", self.role),
+                json!({
+                    "csr": csr,
+                    "ttl": ttl,
+                    "key_usage": ["DigitalSignature", "KeyEncipherment"],
+                    "ext_key_usage": ["ClientAuth"],
+                }),
+            ),
+        };
+
+        let response: VaultResponse<VaultSignedCertificate> = self
+            .client
+            .post(self.url(&path))
+            .header("X-Vault-Token", &self.token)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(format!("{}\n", response.data.certificate.trim()))
+    }
+
+    async fn ca_chain(&self) -> anyhow::Result<Vec<CaCertificate>> {
+        let chain = self
+            .client
+            .get(self.url("ca_chain"))
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let pems = split_pem_chain(&chain);
+        let last = pems.len().saturating_sub(1);
+
+        Ok(pems
+            .into_iter()
+            .enumerate()
+            .map(|(index, pem)| CaCertificate {
+                name: match index {
+                    index if index == last => "root-ca".to_string(),
+                    0 => "intermediate-ca".to_string(),
+                    index => format!("intermediate-ca-{index}"),
+                },
+                pem,
+            })
+            .collect())
+    }
+}
+
+// Throwaway self-signed CA generated at startup, for development and testing only.
+struct MockSigner {
+    temp_dir: Temp,
+    root_ca_pem: String,
+}
+
+impl MockSigner {
+    fn new() -> anyhow::Result<Self> {
+        let temp_dir = Temp::new_dir()?;
+
+        let output = std::process::Command::new("openssl")
+            .args([
+                "req",
+                "-x509",
+                "-nodes",
+                "-newkey",
+                "ec",
+                "-pkeyopt",
+                "ec_paramgen_curve:prime256v1",
+                "-subj",
+                "/CN=k3s-proxmox-helper mock CA",
+                "-days",
+                "3650",
+                "-keyout",
+                &path_string(temp_dir.join("root-ca.key")),
+                "-out",
+                &path_string(temp_dir.join("root-ca.pem")),
+            ])
+            .output()?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Unable to create the mock CA: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        logging::warn!("Using a mock certificate signer, issued certificates are not trustworthy");
+
+        Ok(Self {
+            root_ca_pem: std::fs::read_to_string(temp_dir.join("root-ca.pem"))?,
+            temp_dir,
+        })
+    }
+}
+
+#[async_trait]
+impl Signer for MockSigner {
+    async fn sign(
+        &self,
+        csr: &str,
+        usage: &CertificateUsage,
+        _not_before: DateTime<Utc>,
+        not_after: DateTime<Utc>,
+    ) -> anyhow::Result<String> {
+        let temp_dir = Temp::new_dir()?;
+
+        let csr_path = path_string(temp_dir.join("certificate.csr"));
+        let extensions_path = path_string(temp_dir.join("extensions.cnf"));
+
+        std::fs::write(&csr_path, csr)?;
+        std::fs::write(
+            &extensions_path,
+            format!("[extensions]\n{}\n", openssl_extensions(usage)),
+        )?;
+
+        let days = ((not_after - Utc::now()).num_seconds() + 86399) / 86400;
+
+        openssl_output(&[
+            "x509",
+            "-req",
+            "-in",
+            &csr_path,
+            "-CA",
+            &path_string(self.temp_dir.join("root-ca.pem")),
+            "-CAkey",
+            &path_string(self.temp_dir.join("root-ca.key")),
+            "-set_serial",
+            &Utc::now().timestamp_micros().to_string(),
+            "-days",
+            &days.max(1).to_string(),
+            "-extfile",
+            &extensions_path,
+            "-extensions",
+            "extensions",
+        ])
+        .await
+    }
+
+    async fn ca_chain(&self) -> anyhow::Result<Vec<CaCertificate>> {
+        Ok(vec![CaCertificate {
+            name: "root-ca".to_string(),
+            pem: self.root_ca_pem.clone(),
+        }])
+    }
+}
@@ -0,0 +1,308 @@
+use std::{collections::HashMap, fmt, process::Output, time::Duration};
+
+use anyhow::Context;
+use mktemp::Temp;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::{
+    commands::{self, CommandError},
+    discovery, guest_agent, logging, placement, proxmox,
+    roles::NodeOs,
+    CONFIG, STATE,
+};
+
+const KNOWN_HOSTS_KEY: &str = "ssh_known_hosts";
+const HOST_KEY_PATH: &str = "/etc/ssh/ssh_host_ed25519_key.pub";
+
+#[derive(Clone, Debug)]
+pub(crate) struct SshTarget {
+    pub vmid: String,
+    pub ip: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PinnedHostKey {
+    pub ip: String,
+    pub key: String,
+    pub source: String,
+    pub pinned_at: i64,
+}
+
+#[derive(Debug)]
+pub(crate) struct HostKeyMismatch {
+    pub vmid: String,
+    pub ip: String,
+}
+
+impl fmt::Display for HostKeyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SSH host key mismatch for VM {} ({}), remove the pinned key if the VM was legitimately rebuilt",
+            self.vmid, self.ip
+        )
+    }
+}
+
+impl std::error::Error for HostKeyMismatch {}
+
+#[derive(Debug, Deserialize)]
+struct GuestAgentFileContent {
+    content: String,
+}
+
+fn normalize_host_key(line: &str) -> Option<String> {
+    let mut fields = line.split_whitespace();
+
+    match (fields.next(), fields.next()) {
+        (Some(key_type), Some(key)) if key_type.starts_with("ssh-") => {
+            Some(format!("{key_type} {key}"))
+        }
+        _ => None,
+    }
+}
+
+async fn read_host_key_from_guest_agent(
+    client: &reqwest::Client,
+    target: &SshTarget,
+) -> anyhow::Result<String> {
+    let node = placement::resolve_node(client, &target.vmid).await?;
+
+    let content: GuestAgentFileContent = proxmox::get_with_query(
+        client,
+        &format!("/nodes/{node}/qemu/{}/agent/file-read", target.vmid),
+        &[("file", HOST_KEY_PATH)],
+    )
+    .await?;
+
+    normalize_host_key(&content.content).context("Invalid host key returned by guest agent")
+}
+
+async fn scan_host_key(target: &SshTarget) -> anyhow::Result<String> {
+    let output = commands::output(
+        Command::new("ssh-keyscan").args(["-T", "5", "-t", "ed25519", &target.ip]),
+    )
+    .await?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| {
+            line.split_once(' ')
+                .and_then(|(_, key)| normalize_host_key(key))
+        })
+        .context(format!("Unable to scan SSH host key of {}", target.ip))
+}
+
+pub(crate) fn get_pinned_host_key(vmid: &str) -> Option<PinnedHostKey> {
+    STATE
+        .get::<HashMap<String, PinnedHostKey>>(KNOWN_HOSTS_KEY)
+        .and_then(|known_hosts| known_hosts.get(vmid).cloned())
+}
+
+pub(crate) fn forget_host_key(vmid: &str) -> anyhow::Result<Option<PinnedHostKey>> {
+    let mut removed = None;
+
+    STATE.update(
+        KNOWN_HOSTS_KEY,
+        |known_hosts: &mut HashMap<String, PinnedHostKey>| {
+            removed = known_hosts.remove(vmid);
+        },
+    )?;
+
+    Ok(removed)
+}
+
+// Returns the pinned host key of the target, learning it on first contact. The guest agent is
+// preferred since it doesn't go over the network, ssh-keyscan is only a trust-on-first-use fallback.
+async fn ensure_host_key(client: &reqwest::Client, target: &SshTarget) -> anyhow::Result<String> {
+    if let Some(pinned) = get_pinned_host_key(&target.vmid) {
+        return Ok(pinned.key);
+    }
+
+    let (key, source) = match read_host_key_from_guest_agent(client, target).await {
+        Ok(key) => (key, "guest-agent"),
+        Err(err) => {
+            logging::warn!(
+                "Unable to read host key of VM {} through the guest agent ({err}), falling back to ssh-keyscan",
+                target.vmid
+            );
+
+            (scan_host_key(target).await?, "keyscan")
+        }
+    };
+
+    logging::info!(
+        "Pinning SSH host key of VM {} ({}) from {source}: {key}",
+        target.vmid,
+        target.ip
+    );
+
+    STATE.update(
+        KNOWN_HOSTS_KEY,
+        |known_hosts: &mut HashMap<String, PinnedHostKey>| {
+            known_hosts.insert(
+                target.vmid.clone(),
+                PinnedHostKey {
+                    ip: target.ip.clone(),
+                    key: key.clone(),
+                    source: source.to_string(),
+                    pinned_at: chrono::Utc::now().timestamp(),
+                },
+            );
+        },
+    )?;
+
+    Ok(key)
+}
+
+async fn run_with_pinned_key(
+    client: &reqwest::Client,
+    target: &SshTarget,
+    program: &str,
+    args: &[&str],
+) -> anyhow::Result<Output> {
+    // Windows agents are reached through WinRM if at all, not as root over SSH.
+    let windows = discovery::subscribe().borrow().iter().any(|ipam| {
+        ipam.ip == target.ip
+            && ipam
+                .assignment
+                .as_ref()
+                .is_some_and(|assignment| assignment.os == NodeOs::Windows)
+    });
+
+    if windows {
+        anyhow::bail!(
+            "VM {} ({}) is a Windows node, the helper doesn't manage it over SSH",
+            target.vmid,
+            target.ip
+        );
+    }
+
+    let key = ensure_host_key(client, target).await?;
+
+    let temp = Temp::new_dir()?;
+    let known_hosts_path = temp.join("known_hosts").as_path().display().to_string();
+
+    std::fs::write(&known_hosts_path, format!("{} {key}\n", target.ip))?;
+
+    // Giving up on the command, on a timeout or because the request waiting for it went away, kills
+    // it and removes the temporary known_hosts along with it.
+    let result = tokio::time::timeout(
+        Duration::from_secs(CONFIG.ssh_timeout),
+        commands::output(
+            Command::new(program)
+                .args([
+                    "-o",
+                    "StrictHostKeyChecking=yes",
+                    "-o",
+                    &format!("UserKnownHostsFile={known_hosts_path}"),
+                    "-o",
+                    "GlobalKnownHostsFile=/dev/null",
+                ])
+                .args(args),
+        ),
+    )
+    .await
+    .unwrap_or_else(|_| {
+        Err(anyhow::Error::msg(format!(
+            "{program} to {} timed out after {}s",
+            target.ip, CONFIG.ssh_timeout
+        )))
+    });
+
+    let host_key_mismatch = result.as_ref().err().is_some_and(|err| {
+        err.downcast_ref::<CommandError>().is_some_and(|err| {
+            err.stderr
+                .contains("REMOTE HOST IDENTIFICATION HAS CHANGED")
+                || err.stderr.contains("Host key verification failed")
+        })
+    });
+
+    if host_key_mismatch {
+        logging::error!(
+            "!!! SSH host key of VM {} ({}) does not match the pinned key {key}, refusing to connect !!!",
+            target.vmid, target.ip
+        );
+
+        return Err(HostKeyMismatch {
+            vmid: target.vmid.clone(),
+            ip: target.ip.clone(),
+        }
+        .into());
+    }
+
+    result
+}
+
+pub(crate) async fn scp_from(
+    client: &reqwest::Client,
+    target: &SshTarget,
+    remote_path: &str,
+    local_path: &str,
+) -> anyhow::Result<Output> {
+    run_with_pinned_key(
+        client,
+        target,
+        "scp",
+        &[&format!("root@{}:{remote_path}", target.ip), local_path],
+    )
+    .await
+}
+
+// Falls back to the guest agent when the guest can't be reached over SSH, a host key mismatch is
+// still refused.
+pub(crate) async fn scp_to(
+    client: &reqwest::Client,
+    target: &SshTarget,
+    local_path: &str,
+    remote_path: &str,
+) -> anyhow::Result<()> {
+    let err = match run_with_pinned_key(
+        client,
+        target,
+        "scp",
+        &[local_path, &format!("root@{}:{remote_path}", target.ip)],
+    )
+    .await
+    {
+        Ok(_) => return Ok(()),
+        Err(err) if err.is::<HostKeyMismatch>() => return Err(err),
+        Err(err) => err,
+    };
+
+    logging::warn!(
+        "Unable to copy {local_path} to VM {} over SSH, using the guest agent: {err}",
+        target.vmid
+    );
+
+    let content = tokio::fs::read(local_path)
+        .await
+        .context(format!("Unable to read {local_path}"))?;
+
+    guest_agent::write_file(client, &target.vmid, remote_path, &content)
+        .await
+        .context(format!(
+            "Unable to copy {local_path} to VM {} over SSH or the guest agent",
+            target.vmid
+        ))
+}
+
+pub(crate) async fn run(
+    client: &reqwest::Client,
+    target: &SshTarget,
+    command: &str,
+) -> anyhow::Result<Output> {
+    run_with_pinned_key(
+        client,
+        target,
+        "ssh",
+        &[&format!("root@{}", target.ip), command],
+    )
+    .await
+}
+
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
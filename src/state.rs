@@ -0,0 +1,89 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub(crate) struct StateStore {
+    path: PathBuf,
+    data: Mutex<HashMap<String, serde_json::Value>>,
+}
+
+impl StateStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let data = if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .context(format!("Unable to read state store {}", path.display()))?;
+
+            serde_json::from_str(&content)
+                .context(format!("Unable to parse state store {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            path,
+            data: Mutex::new(data),
+        })
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let data = self.data.lock().unwrap();
+
+        data.get(key)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+
+    pub fn update<T, F>(&self, key: &str, f: F) -> anyhow::Result<T>
+    where
+        T: Serialize + DeserializeOwned + Default,
+        F: FnOnce(&mut T),
+    {
+        let mut data = self.data.lock().unwrap();
+
+        // A value that no longer parses is an error, defaulting it would overwrite what is stored.
+        let mut value: T = match data.get(key) {
+            Some(value) => serde_json::from_value(value.clone())
+                .context(format!("Unable to parse {key} in state store"))?,
+            None => T::default(),
+        };
+
+        f(&mut value);
+
+        data.insert(key.to_string(), serde_json::to_value(&value)?);
+
+        self.persist(&data)?;
+
+        Ok(value)
+    }
+
+    fn persist(&self, data: &HashMap<String, serde_json::Value>) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Write to a sibling file first so a crash never leaves a truncated store behind. It holds
+        // job results and credential hashes, only the helper may read it.
+        let temp_path = self.path.with_extension("tmp");
+
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp_path)?;
+
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(&serde_json::to_vec_pretty(data)?)?;
+        std::fs::rename(&temp_path, &self.path)?;
+
+        Ok(())
+    }
+}
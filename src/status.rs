@@ -0,0 +1,213 @@
+use std::{collections::BTreeMap, sync::RwLock};
+
+use axum::{middleware, routing::get, Extension, Json, Router};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+#[cfg(feature = "pki")]
+use crate::signer;
+use crate::{
+    clusters::CLUSTERS,
+    credentials::{self, ClusterScope},
+    discovery,
+    error::AppResult,
+    proxmox_auth,
+};
+#[cfg(feature = "proxy")]
+use crate::{lb_export, proxy};
+
+static JOBS: Lazy<RwLock<BTreeMap<&'static str, JobStatus>>> =
+    Lazy::new(|| RwLock::new(BTreeMap::new()));
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct JobStatus {
+    pub name: String,
+    pub healthy: bool,
+    pub last_run: i64,
+    pub last_success: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SynchronizationStatus {
+    pub last_synchronization: Option<i64>,
+    pub data_age: Option<i64>,
+    pub stale: bool,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ProxyStatus {
+    pub cluster: String,
+    pub port: u16,
+    pub listening: bool,
+    pub backends: usize,
+    pub healthy_backends: usize,
+    pub published_backends: usize,
+}
+
+#[derive(Serialize)]
+pub struct CertificateStatus {
+    pub cluster: String,
+    pub ready: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HelperStatus {
+    pub status: &'static str,
+    pub version: &'static str,
+    pub proxmox: proxmox_auth::TicketStatus,
+    pub synchronization: SynchronizationStatus,
+    pub proxies: Vec<ProxyStatus>,
+    pub certificates: Vec<CertificateStatus>,
+    pub jobs: Vec<JobStatus>,
+}
+
+// Called by the background loops after every pass, the last error is kept until a pass succeeds.
+pub(crate) fn record_job<T>(name: &'static str, result: &anyhow::Result<T>) {
+    let now = chrono::Utc::now().timestamp();
+    let mut jobs = JOBS.write().unwrap();
+
+    let job = jobs.entry(name).or_insert_with(|| JobStatus {
+        name: name.to_string(),
+        ..Default::default()
+    });
+
+    job.healthy = result.is_ok();
+    job.last_run = now;
+
+    match result {
+        Ok(_) => {
+            job.last_success = Some(now);
+            job.last_error = None;
+        }
+        Err(err) => job.last_error = Some(err.to_string()),
+    }
+}
+
+fn job(name: &str) -> Option<JobStatus> {
+    JOBS.read().unwrap().get(name).cloned()
+}
+
+#[cfg(feature = "proxy")]
+fn proxies() -> Vec<ProxyStatus> {
+    let health = proxy::backend_health();
+    let ipams = discovery::subscribe().borrow().clone();
+
+    CLUSTERS
+        .iter()
+        .map(|cluster| {
+            let backends = ipams
+                .iter()
+                .filter(|ipam| {
+                    discovery::is_proxy_member(ipam)
+                        && ipam
+                            .assignment
+                            .as_ref()
+                            .is_some_and(|assignment| assignment.cluster == cluster.name)
+                })
+                .filter_map(|ipam| health.get(&ipam.ip))
+                .collect::<Vec<_>>();
+
+            ProxyStatus {
+                cluster: cluster.name.clone(),
+                port: cluster.proxy_port,
+                listening: proxy::is_listening(cluster),
+                backends: backends.len(),
+                healthy_backends: backends.iter().filter(|backend| backend.healthy).count(),
+                published_backends: backends.iter().filter(|backend| backend.published).count(),
+            }
+        })
+        .collect()
+}
+
+// Subsystems that aren't built in have nothing to report.
+#[cfg(not(feature = "proxy"))]
+fn proxies() -> Vec<ProxyStatus> {
+    vec![]
+}
+
+#[cfg(feature = "pki")]
+async fn certificates() -> Vec<CertificateStatus> {
+    let mut certificates = vec![];
+
+    for cluster in CLUSTERS.iter() {
+        let result = match signer::signer(&cluster.name) {
+            Ok(signer) => signer.ca_chain().await.map(|_| ()),
+            Err(err) => Err(err),
+        };
+
+        certificates.push(CertificateStatus {
+            cluster: cluster.name.clone(),
+            ready: result.is_ok(),
+            error: result.err().map(|err| err.to_string()),
+        });
+    }
+
+    certificates
+}
+
+#[cfg(not(feature = "pki"))]
+async fn certificates() -> Vec<CertificateStatus> {
+    vec![]
+}
+
+pub(crate) async fn helper_status() -> HelperStatus {
+    let proxmox = proxmox_auth::ticket_status();
+
+    let synchronization = SynchronizationStatus {
+        last_synchronization: discovery::last_synchronization(),
+        data_age: discovery::data_age(),
+        stale: discovery::is_stale(),
+        last_error: job("ipam-sync").and_then(|job| job.last_error),
+    };
+
+    let proxies = proxies();
+    let certificates = certificates().await;
+    let jobs = JOBS.read().unwrap().values().cloned().collect::<Vec<_>>();
+
+    let listening = proxies.iter().all(|proxy| proxy.listening);
+    #[cfg(feature = "proxy")]
+    let listening = listening || lb_export::replaces_proxy();
+
+    let healthy = proxmox.valid
+        && !synchronization.stale
+        && listening
+        && certificates.iter().all(|certificate| certificate.ready)
+        && jobs.iter().all(|job| job.healthy);
+
+    HelperStatus {
+        status: if healthy { "ok" } else { "degraded" },
+        version: env!("CARGO_PKG_VERSION"),
+        proxmox,
+        synchronization,
+        proxies,
+        certificates,
+        jobs,
+    }
+}
+
+async fn get_status(scope: Option<Extension<ClusterScope>>) -> AppResult<Json<HelperStatus>> {
+    let mut status = helper_status().await;
+
+    // The background loops work across every cluster, their errors are for the administrator.
+    if scope.is_some() {
+        status
+            .proxies
+            .retain(|proxy| credentials::authorize(&scope, &proxy.cluster).is_ok());
+        status
+            .certificates
+            .retain(|certificate| credentials::authorize(&scope, &certificate.cluster).is_ok());
+        status.synchronization.last_error = None;
+        status.jobs.clear();
+    }
+
+    Ok(Json(status))
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/status", get(get_status))
+        .route_layer(middleware::from_fn(credentials::require_credentials))
+}
@@ -0,0 +1,310 @@
+use std::{path::Path, time::Instant};
+
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::header,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::post,
+    Router,
+};
+use mktemp::Temp;
+use serde::Serialize;
+use serde_json::json;
+use tokio::process::Command;
+
+#[cfg(feature = "proxy")]
+use crate::proxy;
+use crate::{
+    commands, credentials,
+    error::AppResult,
+    events, logging,
+    permissions::{self, PermissionCheck},
+    proxmox,
+    proxmox_auth::{self, TicketStatus},
+    secrets, status, CONFIG,
+};
+
+// Enough history to cover the incident being reported without making the bundle unwieldy.
+const LOG_HISTORY: &str = "-24h";
+
+#[derive(Serialize)]
+struct ProxmoxDiagnostics {
+    api_url: String,
+    reachable: bool,
+    latency_ms: Option<u128>,
+    version: Option<serde_json::Value>,
+    error: Option<String>,
+    authentication: TicketStatus,
+    permissions: Vec<PermissionCheck>,
+    permissions_error: Option<String>,
+}
+
+async fn proxmox_diagnostics(client: &reqwest::Client) -> ProxmoxDiagnostics {
+    let started_at = Instant::now();
+    let version = proxmox::get::<serde_json::Value>(client, "/version").await;
+    let latency_ms = started_at.elapsed().as_millis();
+
+    let (permissions, permissions_error) = match permissions::check(client).await {
+        Ok(permissions) => (permissions, None),
+        Err(err) => (vec![], Some(err.to_string())),
+    };
+
+    ProxmoxDiagnostics {
+        api_url: CONFIG.proxmox_api_url.clone(),
+        reachable: version.is_ok(),
+        latency_ms: version.is_ok().then_some(latency_ms),
+        error: version.as_ref().err().map(|err| err.to_string()),
+        version: version.ok(),
+        authentication: proxmox_auth::ticket_status(),
+        permissions,
+        permissions_error,
+    }
+}
+
+async fn recent_logs() -> String {
+    let output = Command::new("journalctl")
+        .args([
+            "--identifier",
+            logging::SYSLOG_IDENTIFIER,
+            "--since",
+            LOG_HISTORY,
+            "--output",
+            "short-iso",
+            "--no-pager",
+        ])
+        .kill_on_drop(true)
+        .output()
+        .await;
+
+    match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+        Ok(output) => format!(
+            "Unable to read the journal: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(err) => format!("Unable to read the journal: {err}"),
+    }
+}
+
+// Only settings known to hold no secret are included, anything added to the configuration stays out
+// of the bundle until it is listed here.
+fn config_summary() -> serde_json::Map<String, serde_json::Value> {
+    [
+        ("annotate_vms", json!(CONFIG.annotate_vms)),
+        ("backend_fall", json!(CONFIG.backend_fall)),
+        ("backend_rise", json!(CONFIG.backend_rise)),
+        ("backup_max_age", json!(CONFIG.backup_max_age)),
+        ("backup_policy", json!(CONFIG.backup_policy)),
+        ("backup_storage", json!(CONFIG.backup_storage)),
+        ("backup_timeout", json!(CONFIG.backup_timeout)),
+        ("certificate_types", json!(CONFIG.certificate_types)),
+        ("certificates_path", json!(CONFIG.certificates_path)),
+        (
+            "client_certificate_header",
+            json!(CONFIG.client_certificate_header),
+        ),
+        (
+            "cloud_init_snippets_storage",
+            json!(CONFIG.cloud_init_snippets_storage),
+        ),
+        ("cluster_api_fqdn", json!(CONFIG.cluster_api_fqdn)),
+        ("cluster_subnets", json!(CONFIG.cluster_subnets)),
+        ("clusters_path", json!(CONFIG.clusters_path)),
+        ("crl_path", json!(CONFIG.crl_path)),
+        ("etcd_min_members", json!(CONFIG.etcd_min_members)),
+        (
+            "etcd_snapshot_interval",
+            json!(CONFIG.etcd_snapshot_interval),
+        ),
+        (
+            "etcd_snapshot_retention",
+            json!(CONFIG.etcd_snapshot_retention),
+        ),
+        (
+            "etcd_snapshot_s3_bucket",
+            json!(CONFIG.etcd_snapshot_s3_bucket),
+        ),
+        (
+            "etcd_snapshot_s3_endpoint",
+            json!(CONFIG.etcd_snapshot_s3_endpoint),
+        ),
+        (
+            "etcd_snapshot_s3_folder",
+            json!(CONFIG.etcd_snapshot_s3_folder),
+        ),
+        (
+            "etcd_snapshot_s3_region",
+            json!(CONFIG.etcd_snapshot_s3_region),
+        ),
+        ("ipam_gc", json!(CONFIG.ipam_gc)),
+        ("ipam_gc_delete", json!(CONFIG.ipam_gc_delete)),
+        ("ipam_gc_grace_period", json!(CONFIG.ipam_gc_grace_period)),
+        ("ipam_max_age", json!(CONFIG.ipam_max_age)),
+        (
+            "k3s_certificate_rotation",
+            json!(CONFIG.k3s_certificate_rotation),
+        ),
+        (
+            "k3s_certificate_rotation_window",
+            json!(CONFIG.k3s_certificate_rotation_window),
+        ),
+        (
+            "k3s_internal_network_interface",
+            json!(CONFIG.k3s_internal_network_interface),
+        ),
+        ("lb_export_format", json!(CONFIG.lb_export_format)),
+        ("log_levels", json!(CONFIG.log_levels)),
+        (
+            "maintenance_window_backup",
+            json!(CONFIG.maintenance_window_backup),
+        ),
+        (
+            "maintenance_window_certificate_rotation",
+            json!(CONFIG.maintenance_window_certificate_rotation),
+        ),
+        (
+            "maintenance_window_etcd_snapshot",
+            json!(CONFIG.maintenance_window_etcd_snapshot),
+        ),
+        (
+            "maintenance_window_ipam_gc",
+            json!(CONFIG.maintenance_window_ipam_gc),
+        ),
+        (
+            "maintenance_window_node_reaper",
+            json!(CONFIG.maintenance_window_node_reaper),
+        ),
+        (
+            "maintenance_window_server_restart",
+            json!(CONFIG.maintenance_window_server_restart),
+        ),
+        (
+            "maintenance_window_token_rotation",
+            json!(CONFIG.maintenance_window_token_rotation),
+        ),
+        ("node_reaper", json!(CONFIG.node_reaper)),
+        (
+            "node_reaper_grace_period",
+            json!(CONFIG.node_reaper_grace_period),
+        ),
+        ("port", json!(CONFIG.port)),
+        ("proxmox_api_url", json!(CONFIG.proxmox_api_url)),
+        ("proxmox_api_user", json!(CONFIG.proxmox_api_user)),
+        ("proxmox_cache_ttl", json!(CONFIG.proxmox_cache_ttl)),
+        (
+            "proxmox_insecure_skip_verify",
+            json!(CONFIG.proxmox_insecure_skip_verify),
+        ),
+        ("proxmox_pool", json!(CONFIG.proxmox_pool)),
+        (
+            "proxmox_retry_initial_delay_ms",
+            json!(CONFIG.proxmox_retry_initial_delay_ms),
+        ),
+        (
+            "proxmox_retry_max_attempts",
+            json!(CONFIG.proxmox_retry_max_attempts),
+        ),
+        ("proxmox_timeout", json!(CONFIG.proxmox_timeout)),
+        ("proxy_access_log", json!(CONFIG.proxy_access_log)),
+        ("registration_approval", json!(CONFIG.registration_approval)),
+        ("replication_max_lag", json!(CONFIG.replication_max_lag)),
+        ("role_mapping_path", json!(CONFIG.role_mapping_path)),
+        ("scoped_credentials", json!(CONFIG.scoped_credentials)),
+        ("signer", json!(CONFIG.signer)),
+        ("spiffe_trust_domain", json!(CONFIG.spiffe_trust_domain)),
+        ("ssh_timeout", json!(CONFIG.ssh_timeout)),
+        ("state_path", json!(CONFIG.state_path)),
+        ("trusted_proxies", json!(CONFIG.trusted_proxies)),
+        ("vault_addr", json!(CONFIG.vault_addr)),
+        ("wait_for_proxmox", json!(CONFIG.wait_for_proxmox)),
+        (
+            "configured_secrets",
+            json!(secrets::configured_secrets().len()),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+fn write_file(directory: &Path, name: &str, content: &str) -> anyhow::Result<()> {
+    std::fs::write(directory.join(name), secrets::redact(content))
+        .context(format!("Unable to write {name} to the support bundle"))
+}
+
+fn write_json<T: Serialize>(directory: &Path, name: &str, value: &T) -> anyhow::Result<()> {
+    write_file(directory, name, &serde_json::to_string_pretty(value)?)
+}
+
+// Every file is redacted before being written, the bundle is meant to be attached to bug reports.
+pub(crate) async fn create(client: &reqwest::Client) -> anyhow::Result<(String, Vec<u8>)> {
+    let name = format!(
+        "k3s-proxmox-helper-support-{}",
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+
+    let temp_dir = Temp::new_dir()?;
+    let directory = temp_dir.as_path().join(&name);
+    std::fs::create_dir(&directory)?;
+
+    write_json(&directory, "status.json", &status::helper_status().await)?;
+    write_json(&directory, "config.json", &config_summary())?;
+    write_json(&directory, "events.json", &events::list())?;
+    write_file(&directory, "logs.txt", &recent_logs().await)?;
+    write_json(
+        &directory,
+        "proxmox.json",
+        &proxmox_diagnostics(client).await,
+    )?;
+
+    #[cfg(feature = "proxy")]
+    write_json(&directory, "proxy.json", &proxy::backend_health())?;
+
+    let archive = temp_dir.as_path().join(format!("{name}.tar.gz"));
+
+    commands::output(
+        Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(temp_dir.as_path())
+            .arg(&name),
+    )
+    .await
+    .context("Unable to create the support bundle")?;
+
+    Ok((format!("{name}.tar.gz"), std::fs::read(&archive)?))
+}
+
+async fn create_support_bundle(State(client): State<reqwest::Client>) -> AppResult<Response> {
+    let (file_name, archive) = create(&client).await?;
+
+    events::record(
+        "audit",
+        None,
+        format!("Support bundle {file_name} generated"),
+        None,
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/gzip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        archive,
+    )
+        .into_response())
+}
+
+pub(crate) fn create_router() -> Router<reqwest::Client> {
+    Router::new()
+        .route("/support-bundle", post(create_support_bundle))
+        .route_layer(middleware::from_fn(credentials::require_admin))
+}
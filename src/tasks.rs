@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{cluster::get_nodes, error::AppResult, proxmox};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskStatus {
+    pub upid: String,
+    pub node: String,
+    pub status: String,
+    pub exitstatus: Option<String>,
+    #[serde(rename = "type")]
+    pub task_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TaskLogLine {
+    pub n: i64,
+    pub t: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskDetails {
+    #[serde(flatten)]
+    pub status: TaskStatus,
+    pub log: Vec<TaskLogLine>,
+}
+
+impl TaskStatus {
+    pub fn is_running(&self) -> bool {
+        self.status == "running"
+    }
+
+    pub fn is_successful(&self) -> bool {
+        self.exitstatus.as_deref() == Some("OK")
+    }
+}
+
+pub(crate) async fn get_task_status(
+    client: &reqwest::Client,
+    node: &str,
+    upid: &str,
+) -> anyhow::Result<TaskStatus> {
+    proxmox::get(
+        client,
+        &format!("/nodes/{node}/tasks/{}/status", urlencoding::encode(upid)),
+    )
+    .await
+}
+
+pub(crate) async fn get_task_log(
+    client: &reqwest::Client,
+    node: &str,
+    upid: &str,
+) -> anyhow::Result<Vec<TaskLogLine>> {
+    proxmox::get_with_query(
+        client,
+        &format!("/nodes/{node}/tasks/{}/log", urlencoding::encode(upid)),
+        &[("limit", "5000")],
+    )
+    .await
+}
+
+pub(crate) async fn wait_for_task(
+    client: &reqwest::Client,
+    node: &str,
+    upid: &str,
+    timeout: Duration,
+) -> anyhow::Result<TaskStatus> {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let status = get_task_status(client, node, upid).await?;
+
+        if !status.is_running() {
+            if !status.is_successful() {
+                anyhow::bail!(
+                    "Task {upid} failed: {}",
+                    status
+                        .exitstatus
+                        .unwrap_or_else(|| "unknown error".to_string())
+                );
+            }
+
+            return Ok(status);
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("Timed out waiting for task {upid}");
+        }
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+// `UPID:<node>:<pid>:<pstart>:<starttime>:<type>:<id>:<user>:`, the node running the task is part
+// of its id.
+fn node_of(upid: &str) -> anyhow::Result<&str> {
+    upid.strip_prefix("UPID:")
+        .and_then(|rest| rest.split(':').next())
+        .filter(|node| !node.is_empty())
+        .context(format!("Invalid task id {upid}"))
+}
+
+// The node comes from the caller, it must name a Proxmox node before it ends up in an API path.
+pub(crate) async fn get_task(
+    Path(upid): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<TaskDetails>> {
+    let node = node_of(&upid)?;
+
+    if !get_nodes(client.clone())
+        .await?
+        .data
+        .iter()
+        .any(|known| known.node == node)
+    {
+        return Err(anyhow::Error::msg(format!("Unknown Proxmox node {node}")).into());
+    }
+
+    Ok(Json(TaskDetails {
+        status: get_task_status(&client, node, &upid).await?,
+        log: get_task_log(&client, node, &upid).await?,
+    }))
+}
@@ -0,0 +1,344 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{extract::State, response::Response, Extension, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clusters::{self, K3sCluster},
+    credentials::{self, ClusterScope},
+    error::AppResult,
+    events, get_exposed_address,
+    jobs::{self, JobHandle},
+    kube,
+    maintenance::{self, OperationClass},
+    ssh::{self, SshTarget},
+};
+
+const SERVING_CERTIFICATE: &str = "server/tls/serving-kube-apiserver.crt";
+// Appended to the server's own tls-san rather than replacing it.
+const DROP_IN: &str = "config.yaml.d/90-k3s-proxmox-helper.yaml";
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerTlsSans {
+    pub cluster: String,
+    pub hostname: String,
+    pub vmid: String,
+    pub ip: String,
+    // `--tls-san` entries of the service and its configuration files.
+    pub configured: Vec<String>,
+    // Names the serving certificate is actually valid for.
+    pub certificate: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+// kubectl reaches the servers through the helper, by its address or the cluster's API name.
+pub(crate) fn cluster_names(cluster: &K3sCluster) -> anyhow::Result<Vec<String>> {
+    let mut names = vec![get_exposed_address()?.0.to_string()];
+
+    if let Some(fqdn) = cluster.api_fqdn() {
+        names.push(fqdn.to_string());
+    }
+
+    Ok(names)
+}
+
+pub(crate) fn required_names(target: &SshTarget) -> anyhow::Result<Vec<String>> {
+    cluster_names(clusters::cluster_for_ip(&target.ip))
+}
+
+fn missing(target: &SshTarget, certificate: &[String]) -> anyhow::Result<Vec<String>> {
+    Ok(required_names(target)?
+        .into_iter()
+        .filter(|name| {
+            !certificate
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(name))
+        })
+        .collect())
+}
+
+fn parse_certificate_names(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.contains("Subject Alternative Name"))
+        .skip(1)
+        .flat_map(|line| line.split(','))
+        .filter_map(|name| {
+            let name = name.trim();
+
+            name.strip_prefix("DNS:")
+                .or_else(|| name.strip_prefix("IP Address:"))
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+pub(crate) async fn certificate_names(
+    client: &reqwest::Client,
+    target: &SshTarget,
+) -> anyhow::Result<Vec<String>> {
+    let output = ssh::run(
+        client,
+        target,
+        &format!(
+            "openssl x509 -noout -ext subjectAltName -in {}/{SERVING_CERTIFICATE}",
+            clusters::distro_of_ip(&target.ip).data_dir()
+        ),
+    )
+    .await
+    .context(format!("Unable to read k3s certificate on {}", target.ip))?;
+
+    Ok(parse_certificate_names(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+fn yaml_names(value: Option<&serde_yaml::Value>) -> Vec<String> {
+    match value {
+        Some(serde_yaml::Value::String(names)) => names
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect(),
+        Some(serde_yaml::Value::Sequence(names)) => names
+            .iter()
+            .filter_map(|name| name.as_str().map(str::to_string))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// The install script writes each argument of the unit on its own line, e.g. `'--tls-san' \`.
+fn unit_names(unit: &str) -> Vec<String> {
+    let arguments = unit
+        .split_whitespace()
+        .map(|argument| argument.trim_matches(|c| c == '\'' || c == '"' || c == '\\'))
+        .filter(|argument| !argument.is_empty())
+        .collect::<Vec<_>>();
+
+    arguments
+        .iter()
+        .enumerate()
+        .filter_map(
+            |(index, argument)| match argument.strip_prefix("--tls-san") {
+                Some("") => arguments.get(index + 1).map(|name| name.to_string()),
+                Some(name) => name.strip_prefix('=').map(str::to_string),
+                None => None,
+            },
+        )
+        .collect()
+}
+
+pub(crate) async fn configured_names(
+    client: &reqwest::Client,
+    target: &SshTarget,
+) -> anyhow::Result<Vec<String>> {
+    let distro = clusters::distro_of_ip(&target.ip);
+    let config_dir = format!("/etc/rancher/{}", distro.as_str());
+
+    let unit = ssh::run(
+        client,
+        target,
+        &format!("systemctl cat {}", distro.server_service()),
+    )
+    .await?;
+    let mut names = unit_names(&String::from_utf8_lossy(&unit.stdout));
+
+    let output = ssh::run(
+        client,
+        target,
+        &format!(
+            "for file in {config_dir}/config.yaml {config_dir}/config.yaml.d/*.yaml; do \
+             [ -f \"$file\" ] && echo --- && cat \"$file\"; done; true"
+        ),
+    )
+    .await
+    .context(format!(
+        "Unable to read the k3s configuration of {}",
+        target.ip
+    ))?;
+
+    for document in serde_yaml::Deserializer::from_str(&String::from_utf8_lossy(&output.stdout)) {
+        let config = serde_yaml::Value::deserialize(document).unwrap_or_default();
+
+        names.extend(yaml_names(config.get("tls-san")));
+        names.extend(yaml_names(config.get("tls-san+")));
+    }
+
+    names.sort();
+    names.dedup();
+
+    Ok(names)
+}
+
+// Names kubectl reaches the server by that its serving certificate isn't valid for yet.
+pub(crate) async fn missing_names(
+    client: &reqwest::Client,
+    target: &SshTarget,
+) -> anyhow::Result<Vec<String>> {
+    missing(target, &certificate_names(client, target).await?)
+}
+
+// Every required name is written, not only the missing ones, so the file stays complete. The
+// serving certificate is only regenerated with the new names when the server restarts.
+pub(crate) async fn add_required_names(
+    client: &reqwest::Client,
+    target: &SshTarget,
+) -> anyhow::Result<()> {
+    let names = required_names(target)?;
+    let distro = clusters::distro_of_ip(&target.ip);
+    let path = format!("/etc/rancher/{}/{DROP_IN}", distro.as_str());
+
+    let lines = names
+        .iter()
+        .map(|name| ssh::shell_quote(&format!("  - {name}")))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    ssh::run(
+        client,
+        target,
+        &format!("mkdir -p \"$(dirname {path})\" && printf '%s\\n' 'tls-san+:' {lines} > {path}"),
+    )
+    .await
+    .context(format!(
+        "Unable to add {} to the tls-san of {}",
+        names.join(", "),
+        target.ip
+    ))?;
+
+    Ok(())
+}
+
+async fn server_status(
+    client: &reqwest::Client,
+    cluster: &str,
+    hostname: String,
+    target: &SshTarget,
+) -> anyhow::Result<ServerTlsSans> {
+    let certificate = certificate_names(client, target).await?;
+
+    Ok(ServerTlsSans {
+        cluster: cluster.to_string(),
+        hostname,
+        vmid: target.vmid.clone(),
+        ip: target.ip.clone(),
+        configured: configured_names(client, target).await?,
+        missing: missing(target, &certificate)?,
+        certificate,
+    })
+}
+
+// Credentials scoped to a cluster only see and change the servers of their own cluster.
+fn visible_clusters(scope: &Option<Extension<ClusterScope>>) -> Vec<String> {
+    clusters::CLUSTERS
+        .iter()
+        .filter(|cluster| credentials::authorize(scope, &cluster.name).is_ok())
+        .map(|cluster| cluster.name.clone())
+        .collect()
+}
+
+pub(crate) async fn get_tls_sans(
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<Vec<ServerTlsSans>>> {
+    let mut servers = vec![];
+
+    for cluster in visible_clusters(&scope) {
+        for (hostname, target) in kube::find_servers(&client, &cluster).await? {
+            servers.push(server_status(&client, &cluster, hostname, &target).await?);
+        }
+    }
+
+    Ok(Json(servers))
+}
+
+// Servers are restarted one at a time so the control plane keeps its quorum, the first one that
+// doesn't come back with the names stops the rollout.
+async fn add_to_servers(
+    client: reqwest::Client,
+    clusters: Vec<String>,
+    job: JobHandle,
+) -> anyhow::Result<Vec<ServerTlsSans>> {
+    let mut servers = vec![];
+
+    for cluster in clusters {
+        servers.extend(add_to_cluster(&client, &cluster, &job).await?);
+    }
+
+    Ok(servers)
+}
+
+async fn add_to_cluster(
+    client: &reqwest::Client,
+    cluster: &str,
+    job: &JobHandle,
+) -> anyhow::Result<Vec<ServerTlsSans>> {
+    let mut servers = vec![];
+
+    for (hostname, target) in kube::find_servers(client, cluster).await? {
+        let status = server_status(client, cluster, hostname.clone(), &target).await?;
+
+        if status.missing.is_empty() {
+            job.log(format!("{hostname} already serves every required name"));
+            servers.push(status);
+            continue;
+        }
+
+        job.log(format!(
+            "Adding {} to the tls-san of {hostname}",
+            status.missing.join(", ")
+        ));
+
+        // A rollout still going when the window closes stops before the next restart.
+        maintenance::ensure_open(OperationClass::ServerRestart)?;
+
+        add_required_names(client, &target).await?;
+
+        ssh::run(
+            client,
+            &target,
+            &format!(
+                "systemctl restart {}",
+                clusters::distro_of_ip(&target.ip).server_service()
+            ),
+        )
+        .await
+        .context(format!("Unable to restart {hostname}"))?;
+
+        kube::wait_for_node_ready(client, cluster, &hostname, Duration::from_secs(600)).await?;
+
+        let status = server_status(client, cluster, hostname.clone(), &target).await?;
+
+        if !status.missing.is_empty() {
+            anyhow::bail!(
+                "{hostname} restarted but its serving certificate still lacks {}",
+                status.missing.join(", ")
+            );
+        }
+
+        events::record(
+            "tls-san",
+            Some(&target.vmid),
+            format!("{hostname} now serves {}", status.certificate.join(", ")),
+            None,
+        );
+
+        servers.push(status);
+    }
+
+    Ok(servers)
+}
+
+pub(crate) async fn add_tls_sans(
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Response> {
+    let clusters = visible_clusters(&scope);
+
+    maintenance::ensure_open(OperationClass::ServerRestart)?;
+
+    Ok(jobs::accepted(jobs::spawn("tls-san", None, |job| {
+        add_to_servers(client, clusters, job)
+    })?))
+}
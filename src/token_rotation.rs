@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use axum::{extract::State, Extension, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::{IpamEntry, NodeRole},
+    clusters,
+    credentials::{self, ClusterScope},
+    discovery,
+    error::AppResult,
+    events, kube, logging,
+    maintenance::{self, OperationClass},
+    signer::openssl_output,
+    ssh::{self, SshTarget},
+    STATE,
+};
+
+const TOKEN_ROTATION_KEY: &str = "token_rotation";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AgentRotationStatus {
+    pub vmid: Option<String>,
+    pub ip: String,
+    pub token_fetched_at: Option<i64>,
+    pub last_heartbeat: Option<i64>,
+    pub rotated: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TokenRotation {
+    // Rotations recorded before clusters were introduced belong to the first one.
+    #[serde(default = "clusters::default_cluster_name")]
+    pub cluster: String,
+    pub started_at: i64,
+    pub completed_at: Option<i64>,
+    pub agents: BTreeMap<String, AgentRotationStatus>,
+}
+
+#[derive(Default, Deserialize)]
+pub(crate) struct RotateTokenRequest {
+    cluster: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TokenRotationReport {
+    #[serde(flatten)]
+    pub rotation: TokenRotation,
+    pub stragglers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct LeaseList {
+    items: Vec<Lease>,
+}
+
+#[derive(Deserialize)]
+struct Lease {
+    metadata: LeaseMetadata,
+    spec: LeaseSpec,
+}
+
+#[derive(Deserialize)]
+struct LeaseMetadata {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaseSpec {
+    renew_time: Option<String>,
+}
+
+fn is_agent_of(ipam: &IpamEntry, cluster: &str) -> bool {
+    ipam.assignment.as_ref().is_some_and(|assignment| {
+        assignment.cluster == cluster && assignment.role == NodeRole::Agent
+    })
+}
+
+// Called whenever an agent fetches the join token, which it does when re-registering.
+pub(crate) fn record_join(caller: &IpamEntry) {
+    let Some(hostname) = &caller.hostname else {
+        return;
+    };
+
+    let result = STATE.update(
+        TOKEN_ROTATION_KEY,
+        |rotation: &mut Option<TokenRotation>| {
+            if let Some(agent) = rotation
+                .as_mut()
+                .filter(|rotation| rotation.completed_at.is_none())
+                .and_then(|rotation| rotation.agents.get_mut(hostname))
+            {
+                agent.token_fetched_at = Some(chrono::Utc::now().timestamp());
+            }
+        },
+    );
+
+    if let Err(err) = result {
+        logging::warn!("Unable to record the token fetch of {hostname}: {err}");
+    }
+}
+
+async fn node_heartbeats(
+    client: &reqwest::Client,
+    cluster: &str,
+) -> anyhow::Result<BTreeMap<String, i64>> {
+    let output = kube::kubectl(
+        client,
+        cluster,
+        &["get", "leases", "-n", "kube-node-lease", "-o", "json"],
+    )
+    .await?;
+
+    let leases: LeaseList = serde_json::from_str(&output).context("Invalid node lease list")?;
+
+    Ok(leases
+        .items
+        .into_iter()
+        .filter_map(|lease| {
+            let renew_time = chrono::DateTime::parse_from_rfc3339(&lease.spec.renew_time?).ok()?;
+
+            Some((lease.metadata.name, renew_time.timestamp()))
+        })
+        .collect())
+}
+
+// An agent counts as re-registered once it fetched the new token and its kubelet renewed its lease
+// afterwards, i.e. it came back with the new token.
+async fn refresh(client: &reqwest::Client) -> anyhow::Result<Option<TokenRotation>> {
+    let current = STATE
+        .get::<Option<TokenRotation>>(TOKEN_ROTATION_KEY)
+        .flatten();
+
+    let Some(cluster) = current
+        .as_ref()
+        .filter(|rotation| rotation.completed_at.is_none())
+        .map(|rotation| rotation.cluster.clone())
+    else {
+        return Ok(current);
+    };
+
+    let heartbeats = node_heartbeats(client, &cluster).await?;
+
+    let mut completed = false;
+
+    let rotation = STATE.update(
+        TOKEN_ROTATION_KEY,
+        |rotation: &mut Option<TokenRotation>| {
+            let Some(rotation) = rotation.as_mut() else {
+                return;
+            };
+
+            if rotation.completed_at.is_some() {
+                return;
+            }
+
+            for (hostname, agent) in &mut rotation.agents {
+                agent.last_heartbeat = heartbeats.get(hostname).copied();
+                agent.rotated = agent
+                    .token_fetched_at
+                    .zip(agent.last_heartbeat)
+                    .is_some_and(|(fetched_at, heartbeat)| heartbeat >= fetched_at);
+            }
+
+            if rotation.agents.values().all(|agent| agent.rotated) {
+                rotation.completed_at = Some(chrono::Utc::now().timestamp());
+                completed = true;
+            }
+        },
+    )?;
+
+    if completed {
+        events::record(
+            "token-rotation",
+            None,
+            format!("Every agent of cluster {cluster} re-registered with the new k3s token"),
+            None,
+        );
+    }
+
+    Ok(rotation)
+}
+
+// Read from one of the cluster's servers, whichever answers first.
+pub(crate) async fn cluster_token(
+    client: &reqwest::Client,
+    cluster: &str,
+) -> anyhow::Result<String> {
+    let servers = discovery::subscribe()
+        .borrow()
+        .iter()
+        .filter(|ipam| {
+            ipam.assignment.as_ref().is_some_and(|assignment| {
+                assignment.cluster == cluster && assignment.role == NodeRole::Server
+            })
+        })
+        .filter_map(|ipam| {
+            Some(SshTarget {
+                vmid: ipam.vmid.clone()?,
+                ip: ipam.ip.clone(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut last_error = anyhow::Error::msg(format!("No k3s server found in cluster {cluster}"));
+
+    for server in servers {
+        let token_path = clusters::distro_of_ip(&server.ip).token_path();
+
+        match ssh::run(client, &server, &format!("cat {token_path}")).await {
+            Ok(output) => return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            Err(err) => last_error = err,
+        }
+    }
+
+    Err(last_error)
+}
+
+pub(crate) async fn rotate_token(
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+    request: Option<Json<RotateTokenRequest>>,
+) -> AppResult<Json<TokenRotation>> {
+    let cluster = request
+        .and_then(|Json(request)| request.cluster)
+        .unwrap_or_else(clusters::default_cluster_name);
+
+    credentials::authorize(&scope, &cluster)?;
+    maintenance::ensure_open(OperationClass::TokenRotation)?;
+
+    let (hostname, server) = kube::find_servers(&client, &cluster)
+        .await?
+        .into_iter()
+        .next()
+        .context(format!("No k3s server found in cluster {cluster}"))?;
+
+    let new_token = openssl_output(&["rand", "-hex", "32"]).await?;
+    let distro = clusters::distro_of_ip(&server.ip);
+
+    ssh::run(
+        &client,
+        &server,
+        &format!(
+            "{} token rotate --token \"$(cat {})\" --new-token {}",
+            distro.as_str(),
+            distro.token_path(),
+            ssh::shell_quote(new_token.trim())
+        ),
+    )
+    .await
+    .context(format!("Unable to rotate the k3s token on {hostname}"))?;
+
+    let agents = discovery::subscribe()
+        .borrow()
+        .iter()
+        .filter(|ipam| is_agent_of(ipam, &cluster))
+        .filter_map(|ipam| {
+            Some((
+                ipam.hostname.clone()?,
+                AgentRotationStatus {
+                    vmid: ipam.vmid.clone(),
+                    ip: ipam.ip.clone(),
+                    ..Default::default()
+                },
+            ))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let rotation = TokenRotation {
+        cluster: cluster.clone(),
+        started_at: chrono::Utc::now().timestamp(),
+        completed_at: None,
+        agents,
+    };
+
+    STATE.update(TOKEN_ROTATION_KEY, |current: &mut Option<TokenRotation>| {
+        *current = Some(rotation.clone());
+    })?;
+
+    events::record(
+        "token-rotation",
+        None,
+        format!(
+            "Rotated the k3s token of cluster {cluster} on {hostname}, waiting for {} agents to re-register",
+            rotation.agents.len()
+        ),
+        None,
+    );
+
+    Ok(Json(rotation))
+}
+
+pub(crate) async fn get_rotation_status(
+    scope: Option<Extension<ClusterScope>>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<Option<TokenRotationReport>>> {
+    let rotation = refresh(&client).await?;
+
+    if let Some(rotation) = &rotation {
+        credentials::authorize(&scope, &rotation.cluster)?;
+    }
+
+    Ok(Json(rotation.map(|rotation| {
+        TokenRotationReport {
+            stragglers: rotation
+                .agents
+                .iter()
+                .filter(|(_, agent)| !agent.rotated)
+                .map(|(hostname, _)| hostname.clone())
+                .collect(),
+            rotation,
+        }
+    })))
+}
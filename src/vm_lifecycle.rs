@@ -0,0 +1,366 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "provisioning")]
+use crate::{cloud_init, roles};
+use crate::{
+    cluster::{get_vm_resources, VmResource},
+    clusters::{self, K3sCluster},
+    discovery,
+    error::AppResult,
+    events,
+    jobs::{self, JobHandle},
+    proxmox, registrations, tasks,
+    vms::{self, Guest},
+};
+
+const CLONE_TIMEOUT: Duration = Duration::from_secs(1800);
+const DESTROY_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Deserialize)]
+pub(crate) struct CloneRequest {
+    template: u32,
+    // The template's node by default.
+    node: Option<String>,
+    name: String,
+    // Picked by the role mapping from the name and the template's tags by default.
+    cluster: Option<String>,
+    #[serde(default)]
+    start: bool,
+    // Generates the node's user-data and attaches it as the VM's cicustom snippet.
+    #[cfg(feature = "provisioning")]
+    #[serde(default)]
+    cloud_init: bool,
+    #[cfg(feature = "provisioning")]
+    #[serde(default)]
+    ssh_keys: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LifecycleResult {
+    pub vmid: String,
+    pub node: String,
+    pub name: Option<String>,
+    pub status: Option<String>,
+    pub steps: Vec<String>,
+}
+
+async fn find_resource(client: &reqwest::Client, vmid: &str) -> anyhow::Result<VmResource> {
+    get_vm_resources(client)
+        .await?
+        .into_iter()
+        .find(|resource| resource.vmid.to_string() == vmid)
+        .context(format!("VM {vmid} not found"))
+}
+
+// Only qemu guests the role mapping picks up, within their cluster's pool, are powered or deleted,
+// anything else on the Proxmox cluster is left alone.
+async fn managed_vm(client: &reqwest::Client, vmid: &str) -> anyhow::Result<Guest> {
+    let resource = find_resource(client, vmid).await?;
+
+    if resource.kind != "qemu" {
+        anyhow::bail!("Guest {vmid} is a {}, not a qemu VM", resource.kind);
+    }
+
+    let guest = vms::guest(resource, &discovery::subscribe().borrow());
+
+    if guest.template {
+        anyhow::bail!("VM {vmid} is a template");
+    }
+
+    if guest.assignment.is_none() {
+        anyhow::bail!("VM {vmid} isn't a member of any k3s cluster");
+    }
+
+    Ok(guest)
+}
+
+pub(crate) async fn find_template(
+    client: &reqwest::Client,
+    template: u32,
+) -> anyhow::Result<VmResource> {
+    let resource = find_resource(client, &template.to_string()).await?;
+
+    if resource.kind != "qemu" || resource.template != Some(1) {
+        anyhow::bail!("Guest {template} isn't a qemu template");
+    }
+
+    Ok(resource)
+}
+
+// Full clone into the cluster's pool, the new VM is left stopped.
+pub(crate) async fn clone_template(
+    client: &reqwest::Client,
+    template: &VmResource,
+    node: &str,
+    name: &str,
+    cluster: &K3sCluster,
+) -> anyhow::Result<String> {
+    let vmid: String = proxmox::get(client, "/cluster/nextid").await?;
+
+    let mut params = vec![
+        ("newid", vmid.clone()),
+        ("name", name.to_string()),
+        ("full", "1".to_string()),
+    ];
+
+    // Proxmox only clones onto another node from shared storage.
+    if node != template.node {
+        params.push(("target", node.to_string()));
+    }
+
+    if let Some(pool) = cluster.proxmox_pool() {
+        params.push(("pool", pool.to_string()));
+    }
+
+    let upid: String = proxmox::post(
+        client,
+        &format!("/nodes/{}/qemu/{}/clone", template.node, template.vmid),
+        &params,
+    )
+    .await?;
+    tasks::wait_for_task(client, &template.node, &upid, CLONE_TIMEOUT).await?;
+
+    Ok(vmid)
+}
+
+async fn clone(
+    client: reqwest::Client,
+    request: CloneRequest,
+    job: JobHandle,
+) -> anyhow::Result<LifecycleResult> {
+    let template = find_template(&client, request.template).await?;
+
+    let cluster = match &request.cluster {
+        Some(name) => clusters::find(name).context(format!("Unknown cluster {name}"))?,
+        None => clusters::cluster_for(Some(&request.name), None, template.tags.as_deref())
+            .context(format!(
+                "The role mapping doesn't assign {} to any cluster",
+                request.name
+            ))?,
+    };
+
+    let node = request.node.clone().unwrap_or(template.node.clone());
+    let mut steps = vec![];
+
+    let vmid = clone_template(&client, &template, &node, &request.name, cluster).await?;
+    job.step(
+        &mut steps,
+        format!(
+            "Cloned template {} into VM {vmid} ({}) on {node} for cluster {}",
+            request.template, request.name, cluster.name
+        ),
+    );
+
+    #[cfg(feature = "provisioning")]
+    if request.cloud_init {
+        let assignment = roles::assign(
+            Some(&request.name),
+            Some(cluster.vnet()),
+            template.tags.as_deref(),
+        )
+        .filter(|assignment| assignment.cluster == cluster.name)
+        .context(format!(
+            "The role mapping gives {} no role in cluster {}",
+            request.name, cluster.name
+        ))?;
+
+        let join = cloud_init::join_existing(&client, cluster).await?;
+        let volume = cloud_init::attach(
+            &client,
+            &node,
+            &vmid,
+            &assignment,
+            &request.name,
+            &request.ssh_keys,
+            &join,
+        )
+        .await?;
+        job.step(
+            &mut steps,
+            format!(
+                "Attached the {} user-data {volume} to VM {vmid}",
+                assignment.role.as_str()
+            ),
+        );
+    }
+
+    if request.start {
+        vms::change_vm_status(&client, &node, &vmid, "start").await?;
+        job.step(&mut steps, format!("Started VM {vmid}"));
+    }
+
+    events::record(
+        "vm-lifecycle",
+        Some(&vmid),
+        format!(
+            "Cloned template {} into VM {vmid} ({})",
+            request.template, request.name
+        ),
+        None,
+    );
+
+    Ok(LifecycleResult {
+        vmid,
+        node,
+        name: Some(request.name),
+        status: Some(if request.start { "running" } else { "stopped" }.to_string()),
+        steps,
+    })
+}
+
+pub(crate) async fn clone_vm(
+    State(client): State<reqwest::Client>,
+    Json(request): Json<CloneRequest>,
+) -> AppResult<Response> {
+    Ok(jobs::accepted(jobs::spawn(
+        "vm-clone",
+        request.cluster.clone(),
+        |job| clone(client, request, job),
+    )?))
+}
+
+async fn change_power(
+    client: reqwest::Client,
+    vmid: String,
+    action: &'static str,
+    job: JobHandle,
+) -> anyhow::Result<LifecycleResult> {
+    let guest = managed_vm(&client, &vmid).await?;
+    let mut steps = vec![];
+
+    let task = vms::change_vm_status(&client, &guest.node, &vmid, action).await?;
+    job.step(
+        &mut steps,
+        format!("VM {vmid} {action} task {} completed", task.upid),
+    );
+
+    events::record(
+        "vm-lifecycle",
+        Some(&vmid),
+        format!("VM {vmid} {action} requested through the API"),
+        None,
+    );
+
+    let status = find_resource(&client, &vmid).await?.status;
+
+    Ok(LifecycleResult {
+        vmid,
+        node: guest.node,
+        name: guest.name,
+        status,
+        steps,
+    })
+}
+
+fn power(client: reqwest::Client, vmid: String, action: &'static str) -> AppResult<Response> {
+    Ok(jobs::accepted(jobs::spawn(
+        "vm-power",
+        clusters::cluster_of_vmid(&vmid),
+        move |job| change_power(client, vmid, action, job),
+    )?))
+}
+
+pub(crate) async fn start_vm(
+    Path(vmid): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Response> {
+    power(client, vmid, "start")
+}
+
+pub(crate) async fn stop_vm(
+    Path(vmid): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Response> {
+    power(client, vmid, "stop")
+}
+
+pub(crate) async fn reboot_vm(
+    Path(vmid): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Response> {
+    power(client, vmid, "reboot")
+}
+
+// A VM is only deleted once it's stopped and no longer a Kubernetes node, i.e. after it was
+// drained and removed from the cluster.
+async fn destroy(
+    client: reqwest::Client,
+    vmid: String,
+    job: JobHandle,
+) -> anyhow::Result<LifecycleResult> {
+    let guest = managed_vm(&client, &vmid).await?;
+    let mut steps = vec![];
+
+    if guest.status.as_deref() != Some("stopped") {
+        anyhow::bail!("VM {vmid} must be stopped before it is deleted");
+    }
+
+    if let (Some(name), Some(assignment)) = (&guest.name, &guest.assignment) {
+        match vms::kubernetes_node(&client, &assignment.cluster, &name.to_lowercase()).await {
+            Ok(_) => {
+                anyhow::bail!("VM {vmid} is still the Kubernetes node {name}, remove it first")
+            }
+            Err(err) if err.to_string().contains("NotFound") => {}
+            Err(err) => {
+                return Err(err.context(format!("Unable to check that {name} left the cluster")))
+            }
+        }
+    }
+
+    #[cfg(feature = "provisioning")]
+    let snippet = cloud_init::is_attached(&client, &guest.node, &vmid).await?;
+
+    let upid: String = proxmox::delete_with_query(
+        &client,
+        &format!("/nodes/{}/qemu/{vmid}", guest.node),
+        &[("purge", "1"), ("destroy-unreferenced-disks", "1")],
+    )
+    .await?;
+    tasks::wait_for_task(&client, &guest.node, &upid, DESTROY_TIMEOUT).await?;
+    job.step(&mut steps, format!("Deleted VM {vmid} from {}", guest.node));
+
+    #[cfg(feature = "provisioning")]
+    if snippet {
+        cloud_init::remove(&client, &guest.node, &vmid).await?;
+        job.step(&mut steps, format!("Removed the user-data of VM {vmid}"));
+    }
+
+    registrations::forget(&vmid);
+
+    events::record(
+        "vm-lifecycle",
+        Some(&vmid),
+        format!(
+            "Deleted decommissioned VM {vmid} ({})",
+            guest.name.clone().unwrap_or_default()
+        ),
+        None,
+    );
+
+    Ok(LifecycleResult {
+        vmid,
+        node: guest.node,
+        name: guest.name,
+        status: None,
+        steps,
+    })
+}
+
+pub(crate) async fn delete_vm(
+    Path(vmid): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Response> {
+    Ok(jobs::accepted(jobs::spawn(
+        "vm-delete",
+        clusters::cluster_of_vmid(&vmid),
+        |job| destroy(client, vmid, job),
+    )?))
+}
@@ -0,0 +1,314 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    cluster::{get_vm_resources, IpamEntry, VmResource},
+    clusters, discovery,
+    error::AppResult,
+    logging, proxmox,
+    roles::{self, NodeAssignment},
+    tasks::{self, TaskStatus},
+};
+
+#[cfg(feature = "operator")]
+use crate::kube;
+
+const POWER_TASK_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Serialize)]
+pub struct Guest {
+    pub vmid: u32,
+    pub name: Option<String>,
+    pub kind: String,
+    pub node: String,
+    pub status: Option<String>,
+    pub template: bool,
+    pub tags: Vec<String>,
+    pub ips: Vec<String>,
+    pub assignment: Option<NodeAssignment>,
+}
+
+#[derive(Serialize)]
+pub struct GuestDevice {
+    pub name: String,
+    pub spec: String,
+}
+
+#[derive(Serialize)]
+pub struct GuestConfig {
+    pub cores: Option<u64>,
+    pub sockets: Option<u64>,
+    pub memory: Option<u64>,
+    pub disks: Vec<GuestDevice>,
+    pub nics: Vec<GuestDevice>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct KubernetesNodeCondition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status: String,
+}
+
+#[derive(Serialize)]
+pub struct KubernetesNode {
+    pub name: String,
+    pub ready: bool,
+    pub kubelet_version: Option<String>,
+    pub conditions: Vec<KubernetesNodeCondition>,
+}
+
+#[derive(Serialize)]
+pub struct GuestDetail {
+    #[serde(flatten)]
+    pub guest: Guest,
+    pub uptime: Option<u64>,
+    pub config: GuestConfig,
+    pub ipams: Vec<IpamEntry>,
+    pub kubernetes: Option<KubernetesNode>,
+}
+
+#[derive(Deserialize)]
+struct GuestStatus {
+    uptime: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct NodeObject {
+    metadata: NodeObjectMetadata,
+    status: NodeObjectStatus,
+}
+
+#[derive(Deserialize)]
+struct NodeObjectMetadata {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeObjectStatus {
+    #[serde(default)]
+    conditions: Vec<KubernetesNodeCondition>,
+    node_info: Option<NodeInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeInfo {
+    kubelet_version: String,
+}
+
+pub(crate) async fn change_vm_status(
+    client: &reqwest::Client,
+    node: &str,
+    vmid: &str,
+    action: &str,
+) -> anyhow::Result<TaskStatus> {
+    let upid: String = proxmox::post(
+        client,
+        &format!("/nodes/{node}/qemu/{vmid}/status/{action}"),
+        &[("vmid", vmid)],
+    )
+    .await?;
+
+    tasks::wait_for_task(client, node, &upid, POWER_TASK_TIMEOUT).await
+}
+
+pub(crate) fn guest(resource: VmResource, ipams: &[IpamEntry]) -> Guest {
+    let vmid = resource.vmid.to_string();
+
+    let vm_ipams = ipams
+        .iter()
+        .filter(|ipam| ipam.vmid.as_ref() == Some(&vmid))
+        .collect::<Vec<_>>();
+
+    let assignment = roles::assign(
+        resource.name.as_deref(),
+        vm_ipams.first().map(|ipam| ipam.vnet.as_str()),
+        resource.tags.as_deref(),
+    )
+    .filter(|assignment| {
+        clusters::find(&assignment.cluster)
+            .is_none_or(|cluster| cluster.includes_pool(resource.pool.as_deref()))
+    });
+
+    Guest {
+        vmid: resource.vmid,
+        name: resource.name,
+        kind: resource.kind,
+        node: resource.node,
+        status: resource.status,
+        template: resource.template == Some(1),
+        tags: resource
+            .tags
+            .iter()
+            .flat_map(|tags| tags.split([';', ',', ' ']))
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect(),
+        ips: vm_ipams.iter().map(|ipam| ipam.ip.clone()).collect(),
+        assignment,
+    }
+}
+
+// Every qemu and LXC guest, whether or not the role mapping picks it up, so adoption candidates and
+// mistyped names or tags stand out.
+pub(crate) async fn get_vms(State(client): State<reqwest::Client>) -> AppResult<Json<Vec<Guest>>> {
+    let ipams = discovery::subscribe().borrow().clone();
+
+    let mut guests = get_vm_resources(&client)
+        .await?
+        .into_iter()
+        .map(|resource| guest(resource, &ipams))
+        .collect::<Vec<_>>();
+
+    guests.sort_by_key(|guest| guest.vmid);
+
+    Ok(Json(guests))
+}
+
+fn is_disk(key: &str) -> bool {
+    let bus = key.trim_end_matches(|c: char| c.is_ascii_digit());
+
+    (bus != key && ["ide", "sata", "scsi", "virtio", "mp"].contains(&bus))
+        || ["efidisk0", "tpmstate0", "rootfs"].contains(&key)
+}
+
+fn is_nic(key: &str) -> bool {
+    key.strip_prefix("net")
+        .is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn devices(config: &Map<String, Value>, filter: fn(&str) -> bool) -> Vec<GuestDevice> {
+    config
+        .iter()
+        .filter(|(key, _)| filter(key))
+        .filter_map(|(key, value)| {
+            Some(GuestDevice {
+                name: key.clone(),
+                spec: value.as_str()?.to_string(),
+            })
+        })
+        // CD-ROM drives are listed with the disks by Proxmox.
+        .filter(|device| !device.spec.contains("media=cdrom"))
+        .collect()
+}
+
+// Storages holding the disks of a guest, e.g. `local-lvm` for `local-lvm:vm-100-disk-0,size=32G`.
+// Disks passed through from the host have no storage.
+pub(crate) fn disk_storages(config: &Map<String, Value>) -> Vec<String> {
+    let mut storages = devices(config, is_disk)
+        .into_iter()
+        .filter_map(|device| {
+            let volume = device.spec.split(',').next()?;
+            let (storage, _) = volume.split_once(':')?;
+            Some(storage.to_string())
+        })
+        .collect::<Vec<_>>();
+
+    storages.sort();
+    storages.dedup();
+    storages
+}
+
+// Proxmox returns numbers as strings for some guest types.
+fn number(config: &Map<String, Value>, key: &str) -> Option<u64> {
+    match config.get(key)? {
+        Value::Number(value) => value.as_u64(),
+        Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "operator")]
+pub(crate) async fn kubernetes_node(
+    client: &reqwest::Client,
+    cluster: &str,
+    name: &str,
+) -> anyhow::Result<KubernetesNode> {
+    let node: NodeObject = serde_json::from_str(
+        &kube::kubectl(client, cluster, &["get", "node", name, "-o", "json"]).await?,
+    )?;
+
+    Ok(KubernetesNode {
+        name: node.metadata.name,
+        ready: node
+            .status
+            .conditions
+            .iter()
+            .any(|condition| condition.kind == "Ready" && condition.status == "True"),
+        kubelet_version: node.status.node_info.map(|info| info.kubelet_version),
+        conditions: node.status.conditions,
+    })
+}
+
+// kubectl only comes with the operator.
+#[cfg(not(feature = "operator"))]
+pub(crate) async fn kubernetes_node(
+    _client: &reqwest::Client,
+    _cluster: &str,
+    _name: &str,
+) -> anyhow::Result<KubernetesNode> {
+    anyhow::bail!("Kubernetes nodes are only looked up when built with the operator feature")
+}
+
+// Everything the helper knows about a single guest, to answer "what is this node?" in one call.
+pub(crate) async fn get_vm(
+    Path(vmid): Path<String>,
+    State(client): State<reqwest::Client>,
+) -> AppResult<Json<GuestDetail>> {
+    let resource = get_vm_resources(&client)
+        .await?
+        .into_iter()
+        .find(|resource| resource.vmid.to_string() == vmid)
+        .context(format!("VM {vmid} not found"))?;
+
+    let path = format!("/nodes/{}/{}/{vmid}", resource.node, resource.kind);
+
+    let config: Map<String, Value> = proxmox::get(&client, &format!("{path}/config")).await?;
+    let status: GuestStatus = proxmox::get(&client, &format!("{path}/status/current")).await?;
+
+    let ipams = discovery::subscribe()
+        .borrow()
+        .iter()
+        .filter(|ipam| ipam.vmid.as_ref() == Some(&vmid))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let guest = guest(resource, &ipams);
+
+    // Only guests the role mapping picks up can have joined, a missing node isn't an error.
+    let kubernetes = match (&guest.assignment, &guest.name) {
+        (Some(assignment), Some(name)) => {
+            match kubernetes_node(&client, &assignment.cluster, &name.to_lowercase()).await {
+                Ok(node) => Some(node),
+                Err(err) => {
+                    logging::debug!("No Kubernetes node for VM {vmid}: {err}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    Ok(Json(GuestDetail {
+        uptime: status.uptime,
+        config: GuestConfig {
+            cores: number(&config, "cores"),
+            sockets: number(&config, "sockets"),
+            memory: number(&config, "memory"),
+            disks: devices(&config, is_disk),
+            nics: devices(&config, is_nic),
+        },
+        ipams,
+        kubernetes,
+        guest,
+    }))
+}